@@ -17,15 +17,92 @@ use tokio::{process, task};
 use tracing::{debug, error, info, info_span, warn, Instrument};
 use walkdir::WalkDir;
 
-use crate::{
-    ABBS_REPO_LOCK, ALL_ARCH, AMD64, ARM64, COMMITS_COUNT_LIMIT, LOONGARCH64, LOONGSON3, NOARCH,
-    PPC64EL, RISCV64,
-};
+use crate::{find_arch, Arch, ABBS_REPO_LOCK, ALL_ARCH, ARCHS, COMMITS_COUNT_LIMIT};
+
+/// Built-in PR body template, see `PrTemplate`
+pub const DEFAULT_PR_TEMPLATE: &str = "Topic Description\n-----------------\n\n{description}\n\nPackage(s) Affected\n-------------------\n\n{packages_affected}\n\nSecurity Update?\n----------------\n\n{security_update}\n\nBuild Order\n-----------\n\n```\n{build_order}\n```\n\nTest Build(s) Done\n------------------\n\n{test_builds}";
+
+/// Placeholders that must appear in a PR body template
+const PR_TEMPLATE_PLACEHOLDERS: &[&str] = &[
+    "{description}",
+    "{packages_affected}",
+    "{security_update}",
+    "{build_order}",
+    "{test_builds}",
+];
+
+/// PR body template with placeholders listed in `PR_TEMPLATE_PLACEHOLDERS`
+#[derive(Debug, Clone)]
+pub struct PrTemplate(String);
+
+impl Default for PrTemplate {
+    fn default() -> Self {
+        PrTemplate(DEFAULT_PR_TEMPLATE.to_string())
+    }
+}
 
-macro_rules! PR {
-    () => {
-        "Topic Description\n-----------------\n\n{}\n\nPackage(s) Affected\n-------------------\n\n{}\n\nSecurity Update?\n----------------\n\nNo\n\nBuild Order\n-----------\n\n```\n{}\n```\n\nTest Build(s) Done\n------------------\n\n{}"
-    };
+impl PrTemplate {
+    pub fn new(template: String) -> anyhow::Result<Self> {
+        let missing: Vec<&str> = PR_TEMPLATE_PLACEHOLDERS
+            .iter()
+            .filter(|placeholder| !template.contains(*placeholder))
+            .copied()
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "PR template is missing placeholders: {}",
+                missing.join(", ")
+            );
+        }
+        Ok(PrTemplate(template))
+    }
+
+    /// Load template from file, or use the built-in one if not specified
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        match path {
+            Some(path) => Self::new(
+                fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read PR template {}", path.display()))?,
+            ),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn render(
+        &self,
+        description: &str,
+        packages_affected: &str,
+        security_update: bool,
+        build_order: &str,
+        test_builds: &str,
+    ) -> String {
+        let values = [
+            description,
+            packages_affected,
+            if security_update { "Yes" } else { "No" },
+            build_order,
+            test_builds,
+        ];
+
+        // replace in a single pass, so that placeholders in values are kept as is
+        let mut res = String::new();
+        let mut rest = self.0.as_str();
+        'outer: while let Some(pos) = rest.find('{') {
+            res.push_str(&rest[..pos]);
+            rest = &rest[pos..];
+            for (placeholder, value) in PR_TEMPLATE_PLACEHOLDERS.iter().zip(values) {
+                if let Some(after) = rest.strip_prefix(placeholder) {
+                    res.push_str(value);
+                    rest = after;
+                    continue 'outer;
+                }
+            }
+            res.push('{');
+            rest = &rest[1..];
+        }
+        res.push_str(rest);
+        res
+    }
 }
 
 struct OpenPR<'a> {
@@ -39,6 +116,10 @@ struct OpenPR<'a> {
     pkg_affected: &'a [String],
     tags: Option<&'a [String]>,
     archs: &'a [&'a str],
+    template: &'a PrTemplate,
+    security: bool,
+    github_org: &'a str,
+    github_repo: &'a str,
 }
 
 #[derive(Debug)]
@@ -50,6 +131,13 @@ pub struct OpenPRRequest<'a> {
     pub tags: Option<Vec<String>>,
     /// If None, automatically deduced via `get_archs()`
     pub archs: Option<Vec<&'a str>>,
+    /// Mark as security update, also implied by the `security` label
+    pub security: bool,
+    /// Path to PR body template, the built-in one is used if None
+    pub pr_template: Option<PathBuf>,
+    /// GitHub organization and repository of the ABBS tree, e.g. `AOSC-Dev` and `aosc-os-abbs`
+    pub github_org: String,
+    pub github_repo: String,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -85,8 +173,14 @@ pub async fn open_pr(
         mut title,
         tags,
         archs,
+        security,
+        pr_template,
+        github_org,
+        github_repo,
     } = openpr_request;
 
+    let template = PrTemplate::load(pr_template.as_deref())?;
+
     let _lock = ABBS_REPO_LOCK.lock().await;
 
     update_abbs(&git_ref, &abbs_path, false).await?;
@@ -146,6 +240,10 @@ pub async fn open_pr(
         pkg_affected: &pkg_affected,
         tags: tags.as_deref(),
         archs: &archs,
+        template: &template,
+        security,
+        github_org: &github_org,
+        github_repo: &github_repo,
     })
     .await?;
 
@@ -315,11 +413,21 @@ fn get_commits(path: &Path) -> anyhow::Result<Vec<Commit>> {
 }
 
 /// Update ABBS tree commit logs
-#[tracing::instrument(skip(abbs_path))]
 pub async fn update_abbs<P: AsRef<Path>>(
     git_ref: &str,
     abbs_path: P,
     skip_git_fetch: bool,
+) -> anyhow::Result<()> {
+    update_abbs_from_remote("origin", git_ref, abbs_path, skip_git_fetch).await
+}
+
+/// Same as `update_abbs`, but fetch `git_ref` from `remote`, e.g. clone url of a fork
+#[tracing::instrument(skip(abbs_path))]
+pub async fn update_abbs_from_remote<P: AsRef<Path>>(
+    remote: &str,
+    git_ref: &str,
+    abbs_path: P,
+    skip_git_fetch: bool,
 ) -> anyhow::Result<()> {
     info!("Running git checkout -b stable ...");
 
@@ -351,11 +459,11 @@ pub async fn update_abbs<P: AsRef<Path>>(
     if skip_git_fetch {
         info!("Skippping git fetch ...")
     } else {
-        info!("Running git fetch origin {git_ref} ...");
+        info!("Running git fetch {remote} {git_ref} ...");
 
         let output = process::Command::new("git")
             .arg("fetch")
-            .arg("origin")
+            .arg(remote)
             .arg(git_ref)
             .current_dir(abbs_path)
             .output()
@@ -365,7 +473,7 @@ pub async fn update_abbs<P: AsRef<Path>>(
         print_stdout_and_stderr(&output);
 
         if !output.status.success() {
-            bail!("Failed to fetch origin git-ref: {git_ref}");
+            bail!("Failed to fetch {remote} git-ref: {git_ref}");
         }
     }
 
@@ -437,6 +545,23 @@ pub fn print_stdout_and_stderr(output: &Output) {
     info!(" {}", String::from_utf8_lossy(&output.stderr));
 }
 
+/// Make sure the ABBS tree is a git repo with a stable branch, for checking configuration at startup
+pub fn check_abbs_repo(path: &Path) -> anyhow::Result<()> {
+    if !path.is_dir() {
+        bail!("ABBS tree {} does not exist", path.display());
+    }
+    let repo = get_repo(path)
+        .with_context(|| format!("ABBS tree {} is not a git repo", path.display()))?;
+    if repo.try_find_reference("refs/heads/stable")?.is_none()
+        && repo
+            .try_find_reference("refs/remotes/origin/stable")?
+            .is_none()
+    {
+        bail!("ABBS tree {} has no stable branch", path.display());
+    }
+    Ok(())
+}
+
 pub fn get_repo(path: &Path) -> anyhow::Result<Repository> {
     let mut git_open_opts_map = sec::trust::Mapping::<gix::open::Options>::default();
 
@@ -487,6 +612,10 @@ async fn open_pr_inner(pr: OpenPR<'_>) -> Result<PullRequest, octocrab::Error> {
         pkg_affected,
         tags,
         archs,
+        template,
+        security,
+        github_org,
+        github_repo,
     } = pr;
 
     let crab = octocrab::Octocrab::builder()
@@ -494,15 +623,6 @@ async fn open_pr_inner(pr: OpenPR<'_>) -> Result<PullRequest, octocrab::Error> {
         .user_access_token(access_token)
         .build()?;
 
-    // pr body
-    let body = format!(
-        PR!(),
-        desc,
-        pkg_affected.join("\n"),
-        format!("#buildit {}", packages.replace(',', " ")),
-        format_archs(archs)
-    );
-
     // pr tags
     let tags = if let Some(tags) = tags {
         Cow::Borrowed(tags)
@@ -510,14 +630,23 @@ async fn open_pr_inner(pr: OpenPR<'_>) -> Result<PullRequest, octocrab::Error> {
         Cow::Owned(auto_add_label(title))
     };
 
+    // pr body
+    let body = template.render(
+        desc,
+        &format_pkg_affected(pkg_affected),
+        security || tags.iter().any(|tag| tag == "security"),
+        &format!("#buildit {}", packages.replace(',', " ")),
+        &format_archs(archs),
+    );
+
     // check if there are existing open pr
 
     let page = crab
-        .pulls("AOSC-Dev", "aosc-os-abbs")
+        .pulls(github_org, github_repo)
         .list()
         // Optional Parameters
         .state(params::State::Open)
-        .head(format!("AOSC-Dev:{}", head))
+        .head(format!("{github_org}:{head}"))
         .base("stable")
         // Send the request
         .send()
@@ -529,7 +658,7 @@ async fn open_pr_inner(pr: OpenPR<'_>) -> Result<PullRequest, octocrab::Error> {
 
             // update existing pr
             let pr = crab
-                .pulls("AOSC-Dev", "aosc-os-abbs")
+                .pulls(github_org, github_repo)
                 .update(old_pr.number)
                 .title(title)
                 .body(&body)
@@ -537,7 +666,7 @@ async fn open_pr_inner(pr: OpenPR<'_>) -> Result<PullRequest, octocrab::Error> {
                 .await?;
 
             if !tags.is_empty() {
-                crab.issues("AOSC-Dev", "aosc-os-abbs")
+                crab.issues(github_org, github_repo)
                     .add_labels(pr.number, &tags)
                     .await?;
             }
@@ -548,7 +677,7 @@ async fn open_pr_inner(pr: OpenPR<'_>) -> Result<PullRequest, octocrab::Error> {
 
     // create a new pr
     let pr = crab
-        .pulls("AOSC-Dev", "aosc-os-abbs")
+        .pulls(github_org, github_repo)
         .create(title, head, "stable")
         .draft(true)
         .maintainer_can_modify(true)
@@ -557,7 +686,7 @@ async fn open_pr_inner(pr: OpenPR<'_>) -> Result<PullRequest, octocrab::Error> {
         .await?;
 
     if !tags.is_empty() {
-        crab.issues("AOSC-Dev", "aosc-os-abbs")
+        crab.issues(github_org, github_repo)
             .add_labels(pr.number, &tags)
             .await?;
     }
@@ -635,56 +764,80 @@ fn auto_add_label(title: &str) -> Vec<String> {
     res
 }
 
-fn format_archs(archs: &[&str]) -> String {
-    let mut s = "".to_string();
-
-    let mut map = HashMap::new();
-    map.insert("amd64", AMD64);
-    map.insert("arm64", ARM64);
-    map.insert("noarch", NOARCH);
-    map.insert("loongarch64", LOONGARCH64);
-    map.insert("loongson3", LOONGSON3);
-    map.insert("ppc64el", PPC64EL);
-    map.insert("riscv64", RISCV64);
-
-    let mut newline = false;
-
-    // Primary Architectures
-    if archs.contains(&"amd64")
-        || archs.contains(&"arm64")
-        || archs.contains(&"loongarch64")
-        || archs.contains(&"noarch")
-    {
-        s.push_str("**Primary Architectures**\n\n");
-        newline = true;
-    }
+/// Max packages listed under "Package(s) Affected" of PR body, GitHub limits body to 65536 chars
+const PR_BODY_PACKAGES_LIMIT: usize = 500;
 
-    for i in ["amd64", "arm64", "loongarch64", "noarch"] {
-        if archs.contains(&i) {
-            s.push_str(&format!("- [ ] {}\n", map[i]));
-        }
+fn format_pkg_affected(pkg_affected: &[String]) -> String {
+    let mut s = pkg_affected
+        .iter()
+        .take(PR_BODY_PACKAGES_LIMIT)
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join("\n");
+    if pkg_affected.len() > PR_BODY_PACKAGES_LIMIT {
+        s.push_str(&format!(
+            "\n... and {} more",
+            pkg_affected.len() - PR_BODY_PACKAGES_LIMIT
+        ));
     }
+    s
+}
 
-    // Secondary Architectures
-    if archs.contains(&"loongson3") || archs.contains(&"ppc64el") || archs.contains(&"riscv64") {
-        if newline {
-            s.push('\n');
-        }
-        s.push_str("**Secondary Architectures**\n\n");
-    }
+fn format_archs(archs: &[&str]) -> String {
+    let archs: Vec<&Arch> = ARCHS
+        .iter()
+        .filter(|arch| archs.contains(&arch.id))
+        .collect();
+    let sections = [
+        (
+            "Primary",
+            archs
+                .iter()
+                .filter(|arch| arch.is_primary)
+                .collect::<Vec<_>>(),
+        ),
+        (
+            "Secondary",
+            archs.iter().filter(|arch| arch.is_secondary).collect(),
+        ),
+    ];
 
-    for i in ["loongson3", "ppc64el", "riscv64"] {
-        if archs.contains(&i) {
-            s.push_str(&format!("- [ ] {}\n", map[i]));
+    let mut res = vec![];
+    for (title, section) in sections {
+        if section.is_empty() {
+            continue;
         }
+        let mut s = format!("**{title} Architectures**\n\n");
+        for arch in section {
+            s.push_str(&format!("- [ ] {}\n", arch.display_name));
+        }
+        res.push(s);
     }
+    res.join("\n")
+}
 
-    s
+/// Name of the arch in PR checklist, `None` if unsupported
+pub fn arch_checklist_name(arch: &str) -> Option<&'static str> {
+    find_arch(arch).map(|arch| arch.display_name)
+}
+
+/// Tick or untick the arch in PR checklist written by `format_archs`,
+/// `None` if the arch is unsupported. Jobs are identified by their own arch,
+/// e.g. noarch jobs tick the noarch entry although built by amd64 workers.
+pub fn update_arch_checklist(body: &str, arch: &str, success: bool) -> Option<String> {
+    let name = arch_checklist_name(arch)?;
+    Some(if success {
+        body.replace(&format!("- [ ] {name}"), &format!("- [x] {name}"))
+    } else {
+        body.replace(&format!("- [x] {name}"), &format!("- [ ] {name}"))
+    })
 }
 
 pub fn strip_modifiers(pkg: &str) -> &str {
-    match pkg.split_once(":") {
-        Some((prefix, _suffix)) => prefix,
+    // colon of group slice is not a modifier, e.g. groups/kde[0:20]
+    let start = pkg.find(']').unwrap_or(0);
+    match pkg[start..].find(':') {
+        Some(idx) => &pkg[..start + idx],
         None => pkg,
     }
 }
@@ -777,7 +930,119 @@ pub fn get_archs<'a>(p: &'a Path, packages: &'a [String]) -> Vec<&'static str> {
     }
 }
 
+/// Packages among `packages` (no groups nor modifiers) whose defines all have `ABHOST=noarch`
+pub fn noarch_packages(p: &Path, packages: &[String]) -> Vec<String> {
+    let mut res = vec![];
+    for_each_abbs(p, |pkg, path| {
+        if !packages.contains(&pkg.to_string()) {
+            return;
+        }
+
+        let defines_list = locate_defines(path);
+        let is_noarch = !defines_list.is_empty()
+            && defines_list.iter().all(|i| {
+                std::fs::read_to_string(i)
+                    .map(|defines| {
+                        read_ab_with_apml(&defines)
+                            .get("ABHOST")
+                            .is_some_and(|x| x == "noarch")
+                    })
+                    .unwrap_or(false)
+            });
+        if is_noarch {
+            res.push(pkg.to_string());
+        }
+    });
+    res
+}
+
+/// Packages to build on each arch, building noarch packages only once: `noarch`
+/// (from `noarch_packages`) among `packages` (groups expanded) are split into a
+/// single noarch job and removed from jobs of the requested archs
+pub fn split_noarch_packages<'a>(
+    archs: &[&'a str],
+    packages: &[String],
+    noarch: &[String],
+) -> Vec<(&'a str, Vec<String>)> {
+    let (noarch_pkgs, arch_pkgs): (Vec<String>, Vec<String>) = packages
+        .iter()
+        .cloned()
+        .partition(|pkg| noarch.iter().any(|name| name == strip_modifiers(pkg)));
+
+    let mut res: Vec<(&str, Vec<String>)> = vec![];
+    if !arch_pkgs.is_empty() {
+        for arch in archs {
+            if *arch != "noarch" {
+                res.push((arch, arch_pkgs.clone()));
+            }
+        }
+    }
+    if !noarch_pkgs.is_empty() {
+        res.push(("noarch", noarch_pkgs));
+    }
+    res
+}
+
+/// Adjust archs deduced by `get_archs` according to PR labels. `rules` is a
+/// comma-separated list of `label=arch+arch`, which restricts the archs to the
+/// listed ones, or `label=+arch+arch`, which adds the listed archs,
+/// e.g. `cip-pilot=+loongson3+ppc64el,wip=amd64+arm64`. Falls back to the
+/// deduced archs if the rules leave nothing to build.
+pub fn apply_label_archs(archs: &[&str], labels: &[&str], rules: &str) -> Vec<String> {
+    let mut res: Vec<String> = archs.iter().map(|arch| arch.to_string()).collect();
+    // noarch packages are built once anyway
+    if archs == ["noarch"] {
+        return res;
+    }
+
+    for rule in rules.split(',') {
+        let Some((label, rule_archs)) = rule.split_once('=') else {
+            warn!("Invalid label arch rule: {rule}");
+            continue;
+        };
+        if !labels.contains(&label.trim()) {
+            continue;
+        }
+
+        let (add, rule_archs) = match rule_archs.trim().strip_prefix('+') {
+            Some(rule_archs) => (true, rule_archs),
+            None => (false, rule_archs.trim()),
+        };
+        let rule_archs: Vec<&str> = rule_archs
+            .split('+')
+            .filter(|arch| {
+                let known = ALL_ARCH.contains(arch);
+                if !known {
+                    warn!("Unknown arch {arch} in label arch rule: {rule}");
+                }
+                known
+            })
+            .collect();
+
+        if add {
+            for arch in rule_archs {
+                if !res.iter().any(|a| a == arch) {
+                    res.push(arch.to_string());
+                }
+            }
+        } else {
+            res.retain(|arch| rule_archs.contains(&arch.as_str()));
+        }
+    }
+
+    if res.is_empty() {
+        warn!("Label arch rules {rules} leave no arch of {archs:?} for labels {labels:?}, ignoring them");
+        return archs.iter().map(|arch| arch.to_string()).collect();
+    }
+    res
+}
+
 pub fn read_ab_with_apml(file: &str) -> HashMap<String, String> {
+    try_read_ab_with_apml(file).0
+}
+
+/// Like `read_ab_with_apml`, also returns whether the fallback line parser was used
+pub fn try_read_ab_with_apml(file: &str) -> (HashMap<String, String>, bool) {
     let mut context = HashMap::new();
 
     // Try to set some ab3 flags to reduce the chance of returning errors
@@ -789,7 +1054,7 @@ pub fn read_ab_with_apml(file: &str) -> HashMap<String, String> {
         let e: Vec<String> = e.iter().map(|e| e.to_string()).collect();
         anyhow!(e.join("; "))
     }) {
-        Ok(()) => (),
+        Ok(()) => (context, false),
         Err(e) => {
             error!("{e}, buildit will use fallback method to parse file");
             for line in file.split('\n') {
@@ -798,10 +1063,9 @@ pub fn read_ab_with_apml(file: &str) -> HashMap<String, String> {
                     context.insert(name.to_string(), value.replace('\"', ""));
                 }
             }
+            (context, true)
         }
-    };
-
-    context
+    }
 }
 
 pub fn get_spec(path: &Path, pkgname: &str) -> anyhow::Result<(String, PathBuf)> {
@@ -879,26 +1143,140 @@ pub fn fail_arch_regex(expr: &str) -> anyhow::Result<Regex> {
 
 // strip modifiers and expand groups
 pub fn resolve_packages(pkgs: &[String], p: &Path) -> anyhow::Result<Vec<String>> {
+    let excluded = excluded_packages(pkgs);
     let mut req_pkgs = vec![];
     for i in pkgs {
+        if i.starts_with('!') {
+            continue;
+        }
         // strip modifiers: e.g. llvm:+stage2 becomes llvm
         let i = strip_modifiers(i);
         if i.starts_with("groups/") {
-            let f = fs::File::open(p.join(i))?;
-            let lines = BufReader::new(f).lines();
-
-            for i in lines {
-                let i = i?;
-                let pkg = i.split('/').next_back().unwrap_or(&i);
-                req_pkgs.push(pkg.to_string());
-            }
+            req_pkgs.extend(read_group(i, p)?);
         } else {
             req_pkgs.push(i.to_string());
         }
     }
+    req_pkgs.retain(|pkg| !excluded.contains(&pkg.as_str()));
     Ok(req_pkgs)
 }
 
+/// Packages in `pkgs` (already resolved) that have no spec directory in the tree
+pub fn unknown_packages(pkgs: &[String], p: &Path) -> Vec<String> {
+    let mut known = HashSet::new();
+    for_each_abbs(p, |pkg, _| {
+        known.insert(pkg.to_string());
+    });
+
+    let mut res = vec![];
+    for pkg in pkgs {
+        if !known.contains(pkg) && !res.contains(pkg) {
+            res.push(pkg.clone());
+        }
+    }
+    res
+}
+
+/// Expand groups if some packages are excluded via `!pkg`, e.g. `groups/kde,!kwin`,
+/// so that only the remaining members are built. Modifiers of other packages are kept.
+pub fn expand_excluded_packages(pkgs: &[String], p: &Path) -> anyhow::Result<Vec<String>> {
+    let excluded = excluded_packages(pkgs);
+    if excluded.is_empty() {
+        return Ok(pkgs.to_vec());
+    }
+
+    let mut res = expand_groups(pkgs, p)?;
+    res.retain(|pkg| !excluded.contains(&strip_modifiers(pkg)));
+    Ok(res)
+}
+
+/// Replace groups in `pkgs` with their members, keeping modifiers of other packages
+pub fn expand_groups(pkgs: &[String], p: &Path) -> anyhow::Result<Vec<String>> {
+    let mut res = vec![];
+    for i in pkgs {
+        if i.starts_with('!') {
+            continue;
+        }
+        let name = strip_modifiers(i);
+        if name.starts_with("groups/") {
+            res.extend(read_group(name, p)?);
+        } else {
+            res.push(i.to_string());
+        }
+    }
+    Ok(res)
+}
+
+fn excluded_packages(pkgs: &[String]) -> Vec<&str> {
+    pkgs.iter()
+        .filter_map(|pkg| pkg.strip_prefix('!'))
+        .collect()
+}
+
+// read package names listed in group file
+fn read_group(group: &str, p: &Path) -> anyhow::Result<Vec<String>> {
+    let (name, slice) = GroupSlice::parse(group)?;
+    let f = fs::File::open(p.join(name)).with_context(|| format!("Unknown group: {name}"))?;
+    let mut res = vec![];
+    for i in BufReader::new(f).lines() {
+        let i = i?;
+        let pkg = i.split('/').next_back().unwrap_or(&i);
+        res.push(pkg.to_string());
+    }
+    match slice {
+        Some(slice) => slice.apply(group, res),
+        None => Ok(res),
+    }
+}
+
+/// Members of a group selected with slice notation, e.g. `groups/kde[0:20]` for the first 20
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GroupSlice {
+    pub start: usize,
+    pub end: Option<usize>,
+}
+
+impl GroupSlice {
+    /// Split `groups/foo[start:end]` into group file and slice, both bounds are optional
+    pub fn parse(group: &str) -> anyhow::Result<(&str, Option<GroupSlice>)> {
+        if !group.contains(['[', ']']) {
+            return Ok((group, None));
+        }
+        let malformed =
+            || anyhow!("Malformed group slice: {group}, expected e.g. groups/foo[0:20]");
+        let (name, range) = group
+            .strip_suffix(']')
+            .and_then(|group| group.split_once('['))
+            .ok_or_else(malformed)?;
+        let (start, end) = range.split_once(':').ok_or_else(malformed)?;
+        let parse_bound = |bound: &str| -> anyhow::Result<Option<usize>> {
+            if bound.is_empty() {
+                Ok(None)
+            } else {
+                bound.parse().map(Some).map_err(|_| malformed())
+            }
+        };
+        let start = parse_bound(start)?.unwrap_or(0);
+        let end = parse_bound(end)?;
+        if end.is_some_and(|end| end < start) {
+            bail!("Malformed group slice: {group}, end is before start");
+        }
+        Ok((name, Some(GroupSlice { start, end })))
+    }
+
+    /// Members of `group` within the slice
+    pub fn apply(&self, group: &str, members: Vec<String>) -> anyhow::Result<Vec<String>> {
+        let end = self.end.unwrap_or(members.len());
+        if end > members.len() || self.start > members.len() {
+            bail!(
+                "Slice of {group} out of bounds, the group has {} packages",
+                members.len()
+            );
+        }
+        Ok(members[self.start..end].to_vec())
+    }
+}
+
 #[derive(Debug, Clone, Copy, Default)]
 pub struct EnvironmentRequirement {
     pub min_core: Option<i32>,
@@ -907,6 +1285,66 @@ pub struct EnvironmentRequirement {
     pub min_disk: Option<i64>,
 }
 
+impl EnvironmentRequirement {
+    /// Raise each requirement to at least the one in `other`
+    pub fn merge(&mut self, other: &EnvironmentRequirement) {
+        self.min_core = self.min_core.max(other.min_core);
+        self.min_total_mem = self.min_total_mem.max(other.min_total_mem);
+        self.min_total_mem_per_core =
+            match (self.min_total_mem_per_core, other.min_total_mem_per_core) {
+                (Some(a), Some(b)) => Some(f32::max(a, b)),
+                (a, b) => a.or(b),
+            };
+        self.min_disk = self.min_disk.max(other.min_disk);
+    }
+
+    /// Replace requirements that are set in `other`
+    pub fn override_with(&mut self, other: &EnvironmentRequirement) {
+        self.min_core = other.min_core.or(self.min_core);
+        self.min_total_mem = other.min_total_mem.or(self.min_total_mem);
+        self.min_total_mem_per_core = other.min_total_mem_per_core.or(self.min_total_mem_per_core);
+        self.min_disk = other.min_disk.or(self.min_disk);
+    }
+}
+
+/// Parse requirements in ENVREQ syntax, e.g. `core=8 total_mem=16`,
+/// `mem` is accepted as an alias of `total_mem`
+pub fn parse_environment_requirement(s: &str) -> anyhow::Result<EnvironmentRequirement> {
+    let mut res = EnvironmentRequirement::default();
+    for req in s.split_whitespace() {
+        let (key, value) = req
+            .split_once('=')
+            .with_context(|| format!("Invalid environment requirement: {req}"))?;
+        let val = value
+            .parse::<f32>()
+            .with_context(|| format!("Invalid environment requirement: {req}"))?;
+        let item = match key {
+            "core" => EnvironmentRequirement {
+                min_core: Some(val as i32),
+                ..Default::default()
+            },
+            // unit: GiB -> B
+            "total_mem" | "mem" => EnvironmentRequirement {
+                min_total_mem: Some((val as i64) * 1024 * 1024 * 1024),
+                ..Default::default()
+            },
+            // unit: GiB
+            "total_mem_per_core" => EnvironmentRequirement {
+                min_total_mem_per_core: Some(val * 1024.0 * 1024.0 * 1024.0),
+                ..Default::default()
+            },
+            // unit: GB -> B
+            "disk" => EnvironmentRequirement {
+                min_disk: Some((val as i64) * 1000 * 1000 * 1000),
+                ..Default::default()
+            },
+            _ => bail!("Unsupported environment requirement: {req}"),
+        };
+        res.merge(&item);
+    }
+    Ok(res)
+}
+
 /// `packages` should have no groups nor modifiers
 /// Return one ENVREQ for each arch
 #[tracing::instrument(skip(p))]
@@ -933,35 +1371,10 @@ pub fn get_environment_requirement(
                     .or_else(|| spec.get("ENVREQ"))
                 {
                     for req in env_req.split(" ") {
-                        if let Some((key, value)) = req.split_once("=") {
-                            let val = value.parse::<f32>();
-                            match (key, val) {
-                                ("core", Ok(val)) => {
-                                    *res_arch.min_core.get_or_insert(0) =
-                                        std::cmp::max(res_arch.min_core.unwrap_or(0), val as i32);
-                                }
-                                ("total_mem", Ok(val)) => {
-                                    // unit: GiB -> B
-                                    *res_arch.min_total_mem.get_or_insert(0) = std::cmp::max(
-                                        res_arch.min_total_mem.unwrap_or(0),
-                                        (val as i64) * 1024 * 1024 * 1024,
-                                    );
-                                }
-                                ("total_mem_per_core", Ok(val)) => {
-                                    // unit: GiB
-                                    *res_arch.min_total_mem_per_core.get_or_insert(0.0) = f32::max(
-                                        res_arch.min_total_mem_per_core.unwrap_or(0.0),
-                                        val * 1024.0 * 1024.0 * 1024.0,
-                                    );
-                                }
-                                ("disk", Ok(val)) => {
-                                    // unit: GB -> B
-                                    *res_arch.min_disk.get_or_insert(0) = std::cmp::max(
-                                        res_arch.min_disk.unwrap_or(0),
-                                        (val as i64) * 1000 * 1000 * 1000,
-                                    );
-                                }
-                                _ => {
+                        if req.contains('=') {
+                            match parse_environment_requirement(req) {
+                                Ok(req) => res_arch.merge(&req),
+                                Err(_) => {
                                     warn!("Unsupported environment requirement: {}", req);
                                 }
                             }
@@ -975,6 +1388,30 @@ pub fn get_environment_requirement(
     res
 }
 
+/// `packages` should have no groups nor modifiers
+/// Return extra `ciel build` flags requested by BUILDIT_CIEL_FLAGS in spec
+#[tracing::instrument(skip(p))]
+pub fn get_ciel_flags(p: &Path, packages: &[String]) -> Vec<String> {
+    let mut res = vec![];
+
+    for_each_abbs(p, |pkg, path| {
+        if !packages.contains(&pkg.to_string()) {
+            return;
+        }
+
+        if let Ok(spec) = std::fs::read_to_string(path.join("spec")) {
+            let spec = read_ab_with_apml(&spec);
+            if let Some(flags) = spec.get("BUILDIT_CIEL_FLAGS") {
+                res.extend(flags.split_whitespace().map(|flag| flag.to_string()));
+            }
+        }
+    });
+
+    res.sort();
+    res.dedup();
+    res
+}
+
 #[test]
 fn test_get_archs() {
     let binding = ["autobuild3".to_owned(), "autobuild4".to_owned()];
@@ -993,6 +1430,150 @@ fn test_get_archs() {
     );
 }
 
+#[test]
+fn test_pr_template() {
+    let body =
+        PrTemplate::default().render("desc {build_order}", "fd: 9.0.0", true, "#buildit fd", "");
+    assert_eq!(body, "Topic Description\n-----------------\n\ndesc {build_order}\n\nPackage(s) Affected\n-------------------\n\nfd: 9.0.0\n\nSecurity Update?\n----------------\n\nYes\n\nBuild Order\n-----------\n\n```\n#buildit fd\n```\n\nTest Build(s) Done\n------------------\n\n");
+
+    assert!(PrTemplate::new("{description} {packages_affected}".to_string()).is_err());
+}
+
+#[test]
+fn test_split_noarch_packages() {
+    let pkgs = |list: &[&str]| list.iter().map(|pkg| pkg.to_string()).collect::<Vec<_>>();
+
+    // noarch package requested on amd64 and other archs
+    assert_eq!(
+        split_noarch_packages(&["amd64"], &pkgs(&["fonts"]), &pkgs(&["fonts"])),
+        vec![("noarch", pkgs(&["fonts"]))]
+    );
+    assert_eq!(
+        split_noarch_packages(&["amd64", "arm64"], &pkgs(&["fonts"]), &pkgs(&["fonts"])),
+        vec![("noarch", pkgs(&["fonts"]))]
+    );
+    assert_eq!(
+        split_noarch_packages(&["amd64", "arm64"], &pkgs(&["bash", "fish"]), &[]),
+        vec![
+            ("amd64", pkgs(&["bash", "fish"])),
+            ("arm64", pkgs(&["bash", "fish"]))
+        ]
+    );
+    // mixed: noarch packages go to their own job, with modifiers kept
+    assert_eq!(
+        split_noarch_packages(
+            &["amd64", "arm64"],
+            &pkgs(&["bash", "fonts:+stage2", "fish", "docs"]),
+            &pkgs(&["docs", "fonts"])
+        ),
+        vec![
+            ("amd64", pkgs(&["bash", "fish"])),
+            ("arm64", pkgs(&["bash", "fish"])),
+            ("noarch", pkgs(&["fonts:+stage2", "docs"]))
+        ]
+    );
+    // noarch requested explicitly
+    assert_eq!(
+        split_noarch_packages(
+            &["amd64", "noarch"],
+            &pkgs(&["bash", "docs"]),
+            &pkgs(&["docs"])
+        ),
+        vec![("amd64", pkgs(&["bash"])), ("noarch", pkgs(&["docs"]))]
+    );
+}
+
+#[test]
+fn test_apply_label_archs() {
+    let rules = "cip-pilot=+loongson3+ppc64el,wip=amd64+arm64";
+    assert_eq!(
+        apply_label_archs(&["amd64", "arm64"], &[], rules),
+        vec!["amd64", "arm64"]
+    );
+    assert_eq!(
+        apply_label_archs(&["amd64", "arm64", "ppc64el"], &["cip-pilot"], rules),
+        vec!["amd64", "arm64", "ppc64el", "loongson3"]
+    );
+    assert_eq!(
+        apply_label_archs(&["amd64", "riscv64"], &["wip"], rules),
+        vec!["amd64"]
+    );
+    assert_eq!(
+        apply_label_archs(&["noarch"], &["cip-pilot"], rules),
+        vec!["noarch"]
+    );
+
+    // rules leaving nothing to build are ignored
+    assert_eq!(
+        apply_label_archs(&["riscv64", "ppc64el"], &["wip"], rules),
+        vec!["riscv64", "ppc64el"]
+    );
+    assert_eq!(
+        apply_label_archs(&["amd64"], &["wip"], "wip=amd46"),
+        vec!["amd64"]
+    );
+}
+
+#[test]
+fn test_parse_environment_requirement() {
+    let req = parse_environment_requirement("core=8 mem=16 disk=100").unwrap();
+    assert_eq!(req.min_core, Some(8));
+    assert_eq!(req.min_total_mem, Some(16 * 1024 * 1024 * 1024));
+    assert_eq!(req.min_total_mem_per_core, None);
+    assert_eq!(req.min_disk, Some(100 * 1000 * 1000 * 1000));
+
+    assert!(parse_environment_requirement("core").is_err());
+    assert!(parse_environment_requirement("cpu=8").is_err());
+    assert!(parse_environment_requirement("core=many").is_err());
+
+    // overrides take precedence, unset ones fall back to spec
+    let mut spec = parse_environment_requirement("core=32 total_mem=64").unwrap();
+    spec.override_with(&parse_environment_requirement("mem=16").unwrap());
+    assert_eq!(spec.min_core, Some(32));
+    assert_eq!(spec.min_total_mem, Some(16 * 1024 * 1024 * 1024));
+}
+
+#[test]
+fn test_update_arch_checklist() {
+    let body = format_archs(&["amd64", "noarch", "riscv64"]);
+    let ticked = update_arch_checklist(&body, "noarch", true).unwrap();
+    assert!(ticked.contains("- [x] Architecture-independent `noarch`"));
+    assert!(ticked.contains("- [ ] AMD64 `amd64`"));
+    assert!(ticked.contains("- [ ] RISC-V 64-bit `riscv64`"));
+
+    let unticked = update_arch_checklist(&ticked, "noarch", false).unwrap();
+    assert_eq!(unticked, body);
+
+    assert_eq!(update_arch_checklist(&body, "optenv32", true), None);
+}
+
+#[test]
+fn test_format_archs() {
+    assert_eq!(
+        format_archs(&["riscv64", "noarch", "amd64"]),
+        "**Primary Architectures**\n\n- [ ] AMD64 `amd64`\n- [ ] Architecture-independent `noarch`\n\n**Secondary Architectures**\n\n- [ ] RISC-V 64-bit `riscv64`\n"
+    );
+    assert_eq!(
+        format_archs(&["ppc64el"]),
+        "**Secondary Architectures**\n\n- [ ] PowerPC 64-bit (Little Endian) `ppc64el`\n"
+    );
+    assert_eq!(format_archs(&[]), "");
+
+    // every arch shows up in exactly one section
+    for arch in ARCHS {
+        assert!(arch.is_primary != arch.is_secondary, "{}", arch.id);
+    }
+}
+
+#[test]
+fn test_arch_checklist_name() {
+    for arch in ALL_ARCH.iter().chain(&["noarch"]) {
+        assert!(arch_checklist_name(arch).is_some(), "{arch} has no name");
+    }
+    assert!(!ALL_ARCH.contains(&"mips64r6el"));
+    assert_eq!(arch_checklist_name("mips64r6el"), None);
+}
+
 #[test]
 fn test_auto_add_label() {
     let title = "266: update to 114514";
@@ -1014,3 +1595,97 @@ fn test_auto_add_label() {
         ]
     );
 }
+
+#[test]
+fn test_format_pkg_affected() {
+    let mut pkgs = (0..PR_BODY_PACKAGES_LIMIT)
+        .map(|i| format!("pkg{i}: 1.0"))
+        .collect::<Vec<_>>();
+    let s = format_pkg_affected(&pkgs);
+    assert_eq!(s.lines().count(), PR_BODY_PACKAGES_LIMIT);
+    assert!(!s.contains("more"));
+
+    pkgs.push("extra: 1.0".to_string());
+    let s = format_pkg_affected(&pkgs);
+    assert_eq!(s.lines().count(), PR_BODY_PACKAGES_LIMIT + 1);
+    assert!(s.ends_with(&format!(
+        "pkg{}: 1.0\n... and 1 more",
+        PR_BODY_PACKAGES_LIMIT - 1
+    )));
+}
+
+#[test]
+fn test_unknown_packages() {
+    let p = std::env::temp_dir().join(format!("buildit-unknown-packages-{}", std::process::id()));
+    fs::create_dir_all(p.join("app-utils/fd")).unwrap();
+    fs::create_dir_all(p.join("core-libs/glibc")).unwrap();
+
+    let pkgs = ["fd", "nonexistent", "glibc", "nonexistent"].map(String::from);
+    assert_eq!(unknown_packages(&pkgs, &p), vec!["nonexistent".to_string()]);
+    assert!(unknown_packages(&pkgs[..1], &p).is_empty());
+
+    fs::remove_dir_all(&p).unwrap();
+}
+
+#[test]
+fn test_group_slice() {
+    assert_eq!(strip_modifiers("groups/kde[0:20]"), "groups/kde[0:20]");
+    assert_eq!(
+        strip_modifiers("groups/kde[0:20]:+stage2"),
+        "groups/kde[0:20]"
+    );
+    assert_eq!(strip_modifiers("llvm:+stage2"), "llvm");
+
+    assert_eq!(
+        GroupSlice::parse("groups/kde").unwrap(),
+        ("groups/kde", None)
+    );
+    assert_eq!(
+        GroupSlice::parse("groups/kde[2:4]").unwrap(),
+        (
+            "groups/kde",
+            Some(GroupSlice {
+                start: 2,
+                end: Some(4)
+            })
+        )
+    );
+    assert_eq!(
+        GroupSlice::parse("groups/kde[:]").unwrap().1,
+        Some(GroupSlice {
+            start: 0,
+            end: None
+        })
+    );
+    for malformed in [
+        "groups/kde[",
+        "groups/kde]",
+        "groups/kde[1]",
+        "groups/kde[a:2]",
+        "groups/kde[-1:2]",
+        "groups/kde[3:2]",
+        "groups/kde[0:1]x",
+    ] {
+        assert!(GroupSlice::parse(malformed).is_err(), "{malformed}");
+    }
+
+    let members = ["a", "b", "c"].map(String::from).to_vec();
+    let slice = |group| GroupSlice::parse(group).unwrap().1.unwrap();
+    assert_eq!(
+        slice("groups/x[1:]")
+            .apply("groups/x", members.clone())
+            .unwrap(),
+        vec!["b", "c"]
+    );
+    assert_eq!(
+        slice("groups/x[:2]")
+            .apply("groups/x", members.clone())
+            .unwrap(),
+        vec!["a", "b"]
+    );
+    assert!(slice("groups/x[3:3]")
+        .apply("groups/x", members.clone())
+        .unwrap()
+        .is_empty());
+    assert!(slice("groups/x[0:4]").apply("groups/x", members).is_err());
+}
@@ -15,32 +15,153 @@ use tracing::{error, info, warn};
 
 pub mod github;
 
-pub const AMD64: &str = "AMD64 `amd64`";
-pub const ARM64: &str = "AArch64 `arm64`";
-pub const NOARCH: &str = "Architecture-independent `noarch`";
-pub const LOONGARCH64: &str = "LoongArch 64-bit `loongarch64`";
-pub const LOONGSON3: &str = "Loongson 3 `loongson3`";
-pub const PPC64EL: &str = "PowerPC 64-bit (Little Endian) `ppc64el`";
-pub const RISCV64: &str = "RISC-V 64-bit `riscv64`";
 pub const COMMITS_COUNT_LIMIT: usize = 10;
 
-pub(crate) const ALL_ARCH: &[&str] = &[
-    "amd64",
-    "arm64",
-    "loongarch64",
-    "loongson3",
-    "ppc64el",
-    "riscv64",
+/// Architecture known to buildit, everything arch-dependent is derived from `ARCHS`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Arch {
+    /// Name used by jobs and workers, e.g. `amd64`
+    pub id: &'static str,
+    /// Name in PR checklists
+    pub display_name: &'static str,
+    pub is_primary: bool,
+    pub is_secondary: bool,
+    /// Built by workers of its own, noarch packages are built by amd64 workers
+    pub is_mainline: bool,
+}
+
+// follow https://github.com/AOSC-Dev/autobuild3/blob/master/sets/arch_groups/mainline
+// mips64r6el has been dropped from mainline, and is no longer supported
+pub const ARCHS: &[Arch] = &[
+    Arch {
+        id: "amd64",
+        display_name: "AMD64 `amd64`",
+        is_primary: true,
+        is_secondary: false,
+        is_mainline: true,
+    },
+    Arch {
+        id: "arm64",
+        display_name: "AArch64 `arm64`",
+        is_primary: true,
+        is_secondary: false,
+        is_mainline: true,
+    },
+    Arch {
+        id: "loongarch64",
+        display_name: "LoongArch 64-bit `loongarch64`",
+        is_primary: true,
+        is_secondary: false,
+        is_mainline: true,
+    },
+    Arch {
+        id: "noarch",
+        display_name: "Architecture-independent `noarch`",
+        is_primary: true,
+        is_secondary: false,
+        is_mainline: false,
+    },
+    Arch {
+        id: "loongson3",
+        display_name: "Loongson 3 `loongson3`",
+        is_primary: false,
+        is_secondary: true,
+        is_mainline: true,
+    },
+    Arch {
+        id: "ppc64el",
+        display_name: "PowerPC 64-bit (Little Endian) `ppc64el`",
+        is_primary: false,
+        is_secondary: true,
+        is_mainline: true,
+    },
+    Arch {
+        id: "riscv64",
+        display_name: "RISC-V 64-bit `riscv64`",
+        is_primary: false,
+        is_secondary: true,
+        is_mainline: true,
+    },
 ];
 
+/// Look up an arch in `ARCHS`
+pub fn find_arch(id: &str) -> Option<&'static Arch> {
+    ARCHS.iter().find(|arch| arch.id == id)
+}
+
+const MAINLINE_ARCH_COUNT: usize = {
+    let mut count = 0;
+    let mut i = 0;
+    while i < ARCHS.len() {
+        if ARCHS[i].is_mainline {
+            count += 1;
+        }
+        i += 1;
+    }
+    count
+};
+
+const fn mainline_archs() -> [&'static str; MAINLINE_ARCH_COUNT] {
+    let mut res = [""; MAINLINE_ARCH_COUNT];
+    let mut count = 0;
+    let mut i = 0;
+    while i < ARCHS.len() {
+        if ARCHS[i].is_mainline {
+            res[count] = ARCHS[i].id;
+            count += 1;
+        }
+        i += 1;
+    }
+    res
+}
+
+/// Ids of mainline archs in `ARCHS`, i.e. archs with workers of their own
+pub const ALL_ARCH: &[&str] = &mainline_archs();
+
 pub static ABBS_REPO_LOCK: Lazy<tokio::sync::Mutex<()>> = Lazy::new(|| tokio::sync::Mutex::new(()));
 
+/// Scratch directories `acbs-build` used to create in the tree, never to be committed
+const ACBS_SCRATCH_DIRS: &[&str] = &["acbs-log", "acbs-cache", "acbs-temp"];
+
+/// Local branch bumps are committed to before knowing the final branch name
+const BUMP_WORK_BRANCH: &str = "buildit-bump";
+
+/// Files a version bump is expected to modify
+const BUMP_FILES: &[&str] = &["spec", "defines"];
+
 pub struct FindUpdate {
     pub package: String,
     pub branch: String,
     pub title: String,
 }
 
+pub struct BatchUpdate {
+    /// Branch with all updated packages, None if no package was updated
+    pub update: Option<FindUpdate>,
+    /// Packages failed to update, e.g. having no update
+    pub failed: Vec<(String, anyhow::Error)>,
+}
+
+pub struct UpdatePreview {
+    pub package: String,
+    pub old_version: String,
+    pub new_version: String,
+    /// Unified diff of the ABBS tree
+    pub diff: String,
+}
+
+/// Staged files that a version bump should not touch
+fn unexpected_bump_files(staged: &[String]) -> Vec<&str> {
+    staged
+        .iter()
+        .map(|file| file.as_str())
+        .filter(|file| {
+            let name = file.rsplit('/').next().unwrap_or(file);
+            !BUMP_FILES.contains(&name)
+        })
+        .collect()
+}
+
 fn update_version<P: AsRef<Path>>(
     new: &str,
     spec: P,
@@ -71,18 +192,201 @@ fn update_version<P: AsRef<Path>>(
     Ok(())
 }
 
+/// Bump several packages on a single branch with one commit per package, holding the
+/// ABBS tree lock once. Packages without update or failing to update are skipped.
 #[tracing::instrument(skip(abbs_path))]
-pub async fn find_update_and_update_checksum(
+pub async fn find_updates_and_update_checksums(
+    pkgs: &[&str],
+    abbs_path: &Path,
+    coauthor: &str,
+    manual_update: Option<&str>,
+    scratch_dir: &Path,
+) -> anyhow::Result<BatchUpdate> {
+    let _lock = ABBS_REPO_LOCK.lock().await;
+
+    // switch to stable branch
+    update_abbs("stable", &abbs_path, false).await?;
+
+    // commit to a work branch, renamed once new versions are known
+    Command::new("git")
+        .arg("checkout")
+        .arg("-B")
+        .arg(BUMP_WORK_BRANCH)
+        .arg("stable")
+        .current_dir(abbs_path)
+        .output()
+        .context("Checking out to the work branch")?;
+
+    let mut updated = vec![];
+    let mut failed = vec![];
+    for pkg in pkgs {
+        match commit_update(pkg, abbs_path, coauthor, manual_update, scratch_dir).await {
+            Ok(ver) => updated.push((pkg.to_string(), ver)),
+            Err(err) => {
+                // drop partial changes, keeping commits of other packages
+                git_reset(abbs_path)?;
+                failed.push((pkg.to_string(), err));
+            }
+        }
+    }
+    if updated.is_empty() {
+        return Ok(BatchUpdate {
+            update: None,
+            failed,
+        });
+    }
+
+    let (branch, title) = batch_branch_and_title(&updated);
+    Command::new("git")
+        .arg("branch")
+        .arg("-M")
+        .arg(&branch)
+        .current_dir(abbs_path)
+        .output()
+        .context("Renaming work branch")?;
+    Command::new("git")
+        .arg("push")
+        .arg("--set-upstream")
+        .arg("origin")
+        .arg(&branch)
+        .arg("--force")
+        .current_dir(abbs_path)
+        .output()
+        .context("Pushing new commit to GitHub")?;
+
+    Ok(BatchUpdate {
+        update: Some(FindUpdate {
+            package: updated
+                .iter()
+                .map(|(pkg, _)| pkg.as_str())
+                .collect::<Vec<_>>()
+                .join(","),
+            branch,
+            title,
+        }),
+        failed,
+    })
+}
+
+/// Branch name and PR title of updated packages and their new versions
+fn batch_branch_and_title(updated: &[(String, String)]) -> (String, String) {
+    let branch = updated
+        .iter()
+        .map(|(pkg, ver)| format!("{pkg}-{ver}"))
+        .collect::<Vec<_>>()
+        .join("+");
+    let pkgs = updated
+        .iter()
+        .map(|(pkg, _)| pkg.as_str())
+        .collect::<Vec<_>>();
+    let vers = updated
+        .iter()
+        .map(|(_, ver)| ver.as_str())
+        .collect::<Vec<_>>();
+    let title = format!("{}: update to {}", pkgs.join(", "), vers.join(", "));
+    (branch, title)
+}
+
+/// Update the package on the checked out branch and commit, returning the new version
+async fn commit_update(
     pkg: &str,
     abbs_path: &Path,
     coauthor: &str,
     manual_update: Option<&str>,
-) -> anyhow::Result<FindUpdate> {
+    scratch_dir: &Path,
+) -> anyhow::Result<String> {
+    apply_update(pkg, abbs_path, manual_update, scratch_dir).await?;
+    let ver = package_version(pkg, abbs_path)?;
+    let title = format!("{pkg}: update to {ver}");
+
+    Command::new("git")
+        .arg("add")
+        .arg("--")
+        .arg(".")
+        .args(ACBS_SCRATCH_DIRS.iter().map(|dir| format!(":!{dir}")))
+        .current_dir(abbs_path)
+        .output()
+        .context("Staging modified files")?;
+
+    // refuse to commit stray files left in the tree
+    let staged = Command::new("git")
+        .arg("diff")
+        .arg("--cached")
+        .arg("--name-only")
+        .current_dir(abbs_path)
+        .output()
+        .context("Listing staged files")?;
+    let staged: Vec<String> = BufReader::new(&*staged.stdout)
+        .lines()
+        .map_while(Result::ok)
+        .collect();
+    let unexpected = unexpected_bump_files(&staged);
+    if !unexpected.is_empty() {
+        bail!(
+            "Refusing to commit unexpected files: {}",
+            unexpected.join(", ")
+        );
+    }
+    Command::new("git")
+        .arg("commit")
+        .arg("-m")
+        .arg(format!("{}\n\nCo-authored-by: {}", title, coauthor))
+        .current_dir(abbs_path)
+        .output()
+        .context("Creating git commit")?;
+
+    Ok(ver)
+}
+
+/// Find update and checksum like `find_updates_and_update_checksums`,
+/// but only report the changes instead of committing and pushing them
+#[tracing::instrument(skip(abbs_path))]
+pub async fn preview_update(
+    pkg: &str,
+    abbs_path: &Path,
+    manual_update: Option<&str>,
+    scratch_dir: &Path,
+) -> anyhow::Result<UpdatePreview> {
     let _lock = ABBS_REPO_LOCK.lock().await;
 
     // switch to stable branch
     update_abbs("stable", &abbs_path, false).await?;
 
+    let old_version = package_version(pkg, abbs_path)?;
+    let res: anyhow::Result<UpdatePreview> = async {
+        apply_update(pkg, abbs_path, manual_update, scratch_dir).await?;
+        let new_version = package_version(pkg, abbs_path)?;
+
+        let diff = Command::new("git")
+            .arg("diff")
+            .arg("--")
+            .arg(".")
+            .args(ACBS_SCRATCH_DIRS.iter().map(|dir| format!(":!{dir}")))
+            .current_dir(abbs_path)
+            .output()
+            .context("Diffing modified files")?;
+
+        Ok(UpdatePreview {
+            package: pkg.to_string(),
+            old_version,
+            new_version,
+            diff: String::from_utf8_lossy(&diff.stdout).to_string(),
+        })
+    }
+    .await;
+
+    // nothing is committed, do not leak changes to the next user of the tree
+    git_reset(abbs_path)?;
+    res
+}
+
+/// Write new version and checksum of the package to the ABBS tree, without committing
+async fn apply_update(
+    pkg: &str,
+    abbs_path: &Path,
+    manual_update: Option<&str>,
+    scratch_dir: &Path,
+) -> anyhow::Result<()> {
     match manual_update {
         Some(version) => {
             info!("manual version: {version}");
@@ -152,99 +456,67 @@ pub async fn find_update_and_update_checksum(
         .output()
         .context("Finding modified files using git")?;
 
-    let status = BufReader::new(&*status.stdout).lines().flatten().next();
-
-    if let Some(status) = status {
-        let split_status = status.trim().split_once(" ");
-        if let Some((status, _)) = split_status {
-            if status != "M" {
-                bail!("{pkg} has no update");
-            }
-
-            let absolute_abbs_path = std::fs::canonicalize(abbs_path)?;
-            let pkg_shared = pkg.to_owned();
-
-            info!("Writting new checksum ...");
-            let res = write_new_spec(absolute_abbs_path, pkg_shared).await;
+    // scratch directories of earlier acbs-build runs may show up as untracked
+    let modified = BufReader::new(&*status.stdout)
+        .lines()
+        .map_while(Result::ok)
+        .any(|status| {
+            status
+                .trim()
+                .split_once(' ')
+                .is_some_and(|(status, _)| status == "M")
+        });
+    if !modified {
+        bail!("{pkg} has no update");
+    }
 
-            if let Err(e) = res {
-                // cleanup repo
-                Command::new("git")
-                    .arg("reset")
-                    .arg("HEAD")
-                    .arg("--hard")
-                    .current_dir(&abbs_path)
-                    .output()
-                    .context("Reset git repo status")?;
+    let absolute_abbs_path = std::fs::canonicalize(abbs_path)?;
+    let pkg_shared = pkg.to_owned();
 
-                bail!("Failed to run acbs-build to update checksum: {}", e);
-            }
+    info!("Writting new checksum ...");
+    let res = write_new_spec(absolute_abbs_path, pkg_shared, scratch_dir).await;
 
-            let ver = find_version_by_packages(&[pkg.to_string()], &abbs_path)
-                .into_iter()
-                .next();
+    if let Err(e) = res {
+        // cleanup repo
+        git_reset(abbs_path)?;
 
-            let mut ver = ver
-                .context(format!("Failed to find pkg version: {}", pkg))?
-                .1;
+        bail!("Failed to run acbs-build to update checksum: {}", e);
+    }
 
-            // skip epoch
-            if let Some((_prefix, suffix)) = ver.split_once(':') {
-                ver = suffix.to_string();
-            }
+    Ok(())
+}
 
-            let branch = format!("{pkg}-{ver}");
-            let title = format!("{pkg}: update to {ver}");
+/// Version of the package in the ABBS tree, without epoch
+fn package_version(pkg: &str, abbs_path: &Path) -> anyhow::Result<String> {
+    let ver = find_version_by_packages(&[pkg.to_string()], abbs_path)
+        .into_iter()
+        .next();
 
-            Command::new("git")
-                .arg("branch")
-                .arg("-f")
-                .arg(&branch)
-                .arg("stable")
-                .current_dir(&abbs_path)
-                .output()
-                .context("Point new branch at stable")?;
-            Command::new("git")
-                .arg("checkout")
-                .arg(&branch)
-                .current_dir(&abbs_path)
-                .output()
-                .context("Checking out to the new branch")?;
-            Command::new("git")
-                .arg("add")
-                .arg(".")
-                .current_dir(&abbs_path)
-                .output()
-                .context("Staging modified files")?;
-            Command::new("git")
-                .arg("commit")
-                .arg("-m")
-                .arg(format!("{}\n\nCo-authored-by: {}", title, coauthor))
-                .current_dir(&abbs_path)
-                .output()
-                .context("Creating git commit")?;
-            Command::new("git")
-                .arg("push")
-                .arg("--set-upstream")
-                .arg("origin")
-                .arg(&branch)
-                .arg("--force")
-                .current_dir(&abbs_path)
-                .output()
-                .context("Pushing new commit to GitHub")?;
+    let mut ver = ver
+        .context(format!("Failed to find pkg version: {}", pkg))?
+        .1;
 
-            return Ok(FindUpdate {
-                package: pkg.to_string(),
-                branch,
-                title,
-            });
-        }
+    // skip epoch
+    if let Some((_prefix, suffix)) = ver.split_once(':') {
+        ver = suffix.to_string();
     }
 
-    bail!("{pkg} has no update")
+    Ok(ver)
+}
+
+/// Drop uncommitted changes of the ABBS tree
+fn git_reset(abbs_path: &Path) -> anyhow::Result<()> {
+    Command::new("git")
+        .arg("reset")
+        .arg("HEAD")
+        .arg("--hard")
+        .current_dir(abbs_path)
+        .output()
+        .context("Reset git repo status")?;
+    Ok(())
 }
 
-async fn write_new_spec(abbs_path: PathBuf, pkg: String) -> anyhow::Result<()> {
+async fn write_new_spec(abbs_path: PathBuf, pkg: String, scratch_dir: &Path) -> anyhow::Result<()> {
     let pkg_shared = pkg.clone();
     let abbs_path_shared = abbs_path.clone();
     let (mut spec, p) = spawn_blocking(move || get_spec(&abbs_path_shared, &pkg_shared)).await??;
@@ -262,7 +534,7 @@ async fn write_new_spec(abbs_path: PathBuf, pkg: String) -> anyhow::Result<()> {
             Err(e) => {
                 if let Some(e) = e.downcast_ref::<ParseErrors>() {
                     warn!("{e}, try use acbs-build fallback to get new checksum ...");
-                    acbs_build_gw(&pkg, &abbs_path).await?;
+                    acbs_build_gw(&pkg, &abbs_path, scratch_dir).await?;
                 } else {
                     error!("Failed to get new spec: {e}");
                     if i == 5 {
@@ -276,22 +548,33 @@ async fn write_new_spec(abbs_path: PathBuf, pkg: String) -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn acbs_build_gw(pkg_shared: &str, abbs_path_shared: &Path) -> anyhow::Result<()> {
+async fn acbs_build_gw(
+    pkg_shared: &str,
+    abbs_path_shared: &Path,
+    scratch_dir: &Path,
+) -> anyhow::Result<()> {
+    // keep acbs-build files out of the tree, so that they are never committed
+    let scratch_dir = scratch_dir.join(format!("buildit-acbs-{pkg_shared}"));
     let output = tokio::process::Command::new("acbs-build")
         .arg("-gw")
         .arg(pkg_shared)
         .arg("--log-dir")
-        .arg(&abbs_path_shared.join("acbs-log"))
+        .arg(scratch_dir.join("log"))
         .arg("--cache-dir")
-        .arg(&abbs_path_shared.join("acbs-cache"))
+        .arg(scratch_dir.join("cache"))
         .arg("--temp-dir")
-        .arg(&abbs_path_shared.join("acbs-temp"))
+        .arg(scratch_dir.join("temp"))
         .arg("--tree-dir")
         .arg(abbs_path_shared)
         .current_dir(abbs_path_shared)
         .output()
         .await
-        .context("Running acbs-build to update checksums")?;
+        .context("Running acbs-build to update checksums");
+
+    if let Err(e) = fs::remove_dir_all(&scratch_dir).await {
+        warn!("Failed to clean up {}: {e}", scratch_dir.display());
+    }
+    let output = output?;
 
     print_stdout_and_stderr(&output);
 
@@ -304,3 +587,37 @@ async fn acbs_build_gw(pkg_shared: &str, abbs_path_shared: &Path) -> anyhow::Res
 
     Ok(())
 }
+
+#[test]
+fn test_unexpected_bump_files() {
+    let staged = vec![
+        "app-utils/fd/spec".to_string(),
+        "app-utils/fd/autobuild/defines".to_string(),
+        "acbs-cache/fd-9.0.0.tar.gz".to_string(),
+    ];
+    assert_eq!(
+        unexpected_bump_files(&staged),
+        vec!["acbs-cache/fd-9.0.0.tar.gz"]
+    );
+}
+
+#[test]
+fn test_batch_branch_and_title() {
+    let updated = vec![("fd".to_string(), "10.2.0".to_string())];
+    assert_eq!(
+        batch_branch_and_title(&updated),
+        ("fd-10.2.0".to_string(), "fd: update to 10.2.0".to_string())
+    );
+
+    let updated = vec![
+        ("libfoo".to_string(), "1.2".to_string()),
+        ("libbar".to_string(), "3.4".to_string()),
+    ];
+    assert_eq!(
+        batch_branch_and_title(&updated),
+        (
+            "libfoo-1.2+libbar-3.4".to_string(),
+            "libfoo, libbar: update to 1.2, 3.4".to_string()
+        )
+    );
+}
@@ -1,10 +1,13 @@
 use std::{
+    collections::BTreeMap,
     fs,
     path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
 use buildit_utils::github::{get_repo, open_pr, OpenPRRequest};
 use clap::{Parser, Subcommand};
+use common::api::{JobInfoResponse, PipelineInfoResponse, PipelineNewRequest, PipelineNewResponse};
 use eyre::{bail, eyre};
 use serde::Deserialize;
 
@@ -14,7 +17,23 @@ pub struct Args {
     #[clap(subcommand)]
     pub subcommand: BiCommand,
     #[arg(short, long)]
-    pub abbs_path: PathBuf,
+    pub abbs_path: Option<PathBuf>,
+    /// buildit server url
+    #[arg(
+        long,
+        default_value = "https://buildit.aosc.io",
+        env = "BUILDIT_SERVER"
+    )]
+    pub server: String,
+    /// API token, sent as bearer token to buildit server
+    #[arg(long, env = "BUILDIT_API_TOKEN")]
+    pub token: Option<String>,
+    /// GitHub organization of the ABBS repo
+    #[arg(long, default_value = "AOSC-Dev", env = "BUILDIT_GITHUB_ORG")]
+    pub github_org: String,
+    /// GitHub repository name of the ABBS repo
+    #[arg(long, default_value = "aosc-os-abbs", env = "BUILDIT_GITHUB_REPO")]
+    pub github_repo: String,
 }
 
 #[derive(Subcommand, Debug)]
@@ -29,9 +48,40 @@ pub enum BiCommand {
         packages: Vec<String>,
         #[arg(long)]
         tags: Option<Vec<String>>,
+        /// Mark as security update
+        #[arg(long)]
+        security: bool,
+        /// PR body template, the built-in one is used if not specified
+        #[arg(long)]
+        pr_template: Option<PathBuf>,
     },
     /// Login to Github
     Login,
+    /// Start a build job on buildit server
+    Build {
+        #[arg(short, long)]
+        git_branch: String,
+        /// Packages to build, separated by comma
+        #[arg(short, long)]
+        packages: String,
+        /// Architectures to build, separated by comma
+        #[arg(long)]
+        archs: String,
+    },
+    /// Show status of jobs in pipeline
+    Status {
+        #[arg(long)]
+        pipeline_id: i32,
+        /// Poll until all jobs finish
+        #[arg(long)]
+        watch: bool,
+        /// Polling interval in seconds
+        #[arg(long, default_value_t = 30)]
+        interval: u64,
+        /// Give up watching after the timeout in seconds
+        #[arg(long, default_value_t = 86400)]
+        timeout: u64,
+    },
 }
 
 #[derive(Deserialize, Debug)]
@@ -50,6 +100,70 @@ struct Config {
     id: String,
 }
 
+async fn get_json<T: serde::de::DeserializeOwned>(
+    client: &reqwest::Client,
+    args: &Args,
+    url: &str,
+) -> eyre::Result<T> {
+    let mut req = client.get(url);
+    if let Some(token) = &args.token {
+        req = req.bearer_auth(token);
+    }
+    let resp = req.send().await?;
+    if !resp.status().is_success() {
+        bail!("{}: {}", resp.status(), resp.text().await?);
+    }
+    Ok(resp.json().await?)
+}
+
+/// Fetch latest job of each arch in the pipeline
+async fn pipeline_jobs(
+    client: &reqwest::Client,
+    args: &Args,
+    pipeline_id: i32,
+) -> eyre::Result<Vec<JobInfoResponse>> {
+    let server = args.server.trim_end_matches('/');
+    let pipeline: PipelineInfoResponse = get_json(
+        client,
+        args,
+        &format!("{server}/api/pipeline/info?pipeline_id={pipeline_id}"),
+    )
+    .await?;
+
+    // restarted jobs have larger ids
+    let mut latest = BTreeMap::new();
+    for job in pipeline.jobs {
+        let job_id = latest.entry(job.arch).or_insert(job.job_id);
+        *job_id = (*job_id).max(job.job_id);
+    }
+
+    let mut res = vec![];
+    for job_id in latest.into_values() {
+        res.push(
+            get_json(
+                client,
+                args,
+                &format!("{server}/api/job/info?job_id={job_id}"),
+            )
+            .await?,
+        );
+    }
+    Ok(res)
+}
+
+fn print_jobs(jobs: &[JobInfoResponse]) {
+    println!("{:<12} {:>8} {:<10} LOG", "ARCH", "JOB", "STATUS");
+    for job in jobs {
+        println!(
+            "{:<12} {:>8} {:<10} {}",
+            job.arch,
+            job.job_id,
+            job.status,
+            job.log_url.as_deref().unwrap_or("")
+        );
+    }
+}
+
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
     let args = Args::parse();
@@ -59,7 +173,12 @@ async fn main() -> eyre::Result<()> {
             git_ref,
             packages,
             tags,
+            security,
+            pr_template,
         } => {
+            let abbs_path = args
+                .abbs_path
+                .ok_or_else(|| eyre!("Please specify ABBS path with --abbs-path"))?;
             let login = dirs_next::data_dir()
                 .ok_or_else(|| eyre!("no data dir found!"))?
                 .join("github_login");
@@ -83,18 +202,22 @@ async fn main() -> eyre::Result<()> {
                     git_ref: if let Some(git_ref) = git_ref {
                         git_ref
                     } else {
-                        let repo = get_repo(&args.abbs_path).map_err(|e| eyre!("{e}"))?;
+                        let repo = get_repo(&abbs_path).map_err(|e| eyre!("{e}"))?;
                         repo.head_name()
                             .ok()
                             .and_then(|x| x)
                             .map(|x| x.shorten().to_string())
                             .ok_or_else(|| eyre!("Failed to get branch"))?
                     },
-                    abbs_path: args.abbs_path.clone(),
+                    abbs_path: abbs_path.clone(),
                     packages: packages.join(","),
                     title,
                     tags,
                     archs: None,
+                    security,
+                    pr_template,
+                    github_org: args.github_org.clone(),
+                    github_repo: args.github_repo.clone(),
                 },
             )
             .await
@@ -105,6 +228,83 @@ async fn main() -> eyre::Result<()> {
                 }
             }
         }
+        BiCommand::Build {
+            git_branch,
+            packages,
+            archs,
+        } => {
+            let server = args.server.trim_end_matches('/');
+            let mut req = reqwest::Client::new()
+                .post(format!("{server}/api/pipeline/new"))
+                .json(&PipelineNewRequest {
+                    git_branch,
+                    packages,
+                    archs,
+                    patch: None,
+                    verbose: false,
+                    required_tags: None,
+                    idempotency_key: None,
+                    ciel_flags: None,
+                    tag: None,
+                });
+            if let Some(token) = &args.token {
+                req = req.bearer_auth(token);
+            }
+
+            let resp = req.send().await?;
+            if !resp.status().is_success() {
+                bail!(
+                    "Failed to create pipeline: {}: {}",
+                    resp.status(),
+                    resp.text().await?
+                );
+            }
+            let pipeline: PipelineNewResponse = resp.json().await?;
+            println!("Created pipeline #{}", pipeline.id);
+            println!("{server}/pipelines/{}", pipeline.id);
+        }
+        BiCommand::Status {
+            pipeline_id,
+            watch,
+            interval,
+            timeout,
+        } => {
+            let client = reqwest::Client::new();
+            let begin = Instant::now();
+            let jobs = loop {
+                match pipeline_jobs(&client, &args, pipeline_id).await {
+                    Ok(jobs) => {
+                        let finished = jobs
+                            .iter()
+                            .all(|job| !matches!(job.status.as_str(), "created" | "running"));
+                        if !watch || finished {
+                            break jobs;
+                        }
+                        println!("{}", chrono::Local::now().format("%Y-%m-%d %H:%M:%S"));
+                        print_jobs(&jobs);
+                        println!();
+                    }
+                    Err(err) if watch => {
+                        // keep watching if the server is temporarily unavailable
+                        eprintln!("Failed to get pipeline status: {err}");
+                    }
+                    Err(err) => return Err(err),
+                }
+
+                if begin.elapsed() > Duration::from_secs(timeout) {
+                    bail!("Timed out waiting for pipeline #{pipeline_id}");
+                }
+                tokio::time::sleep(Duration::from_secs(interval)).await;
+            };
+
+            print_jobs(&jobs);
+            if jobs
+                .iter()
+                .any(|job| matches!(job.status.as_str(), "failed" | "error"))
+            {
+                bail!("Some jobs of pipeline #{pipeline_id} failed");
+            }
+        }
         BiCommand::Login => {
             println!("Please open url to login Github:");
             println!("https://github.com/login/oauth/authorize?client_id=Iv1.bf26f3e9dd7883ae&redirect_uri=https://minzhengbu.aosc.io/login_cli");
@@ -0,0 +1,314 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Requests and responses of buildit server api, shared by server and clients
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PipelineNewRequest {
+    pub git_branch: String,
+    pub packages: String,
+    pub archs: String,
+    /// Optional git patch applied on top of git_branch
+    pub patch: Option<String>,
+    /// Build with verbose output
+    #[serde(default)]
+    pub verbose: bool,
+    /// Comma-separated tags a worker must have to build the jobs
+    #[serde(default)]
+    pub required_tags: Option<String>,
+    /// Retrying with the same key within an hour returns the pipeline created first,
+    /// a new pipeline is always created if omitted
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+    /// Extra space-separated `ciel build` flags, e.g. `--stage2`
+    #[serde(default)]
+    pub ciel_flags: Option<String>,
+    /// Campaign tag of the pipeline, e.g. `perl-5.40`, for bulk retries
+    #[serde(default)]
+    pub tag: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PipelineNewResponse {
+    pub id: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PipelineInfoResponseJob {
+    pub job_id: i32,
+    pub arch: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PipelineInfoResponse {
+    // from pipeline
+    pub pipeline_id: i32,
+    pub packages: String,
+    pub archs: String,
+    pub git_branch: String,
+    pub git_sha: String,
+    pub git_ref: Option<String>,
+    pub creation_time: chrono::DateTime<chrono::Utc>,
+    pub github_pr: Option<i64>,
+
+    // related jobs
+    pub jobs: Vec<PipelineInfoResponseJob>,
+}
+
+/// A pipeline with details of all its jobs, for external status pages
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PipelineExportResponse {
+    // from pipeline
+    pub pipeline_id: i32,
+    pub packages: String,
+    pub archs: String,
+    pub git_branch: String,
+    pub git_sha: String,
+    pub git_ref: Option<String>,
+    pub creation_time: chrono::DateTime<chrono::Utc>,
+    pub github_pr: Option<i64>,
+
+    // from creator
+    pub creator_github_login: Option<String>,
+
+    // related jobs
+    pub jobs: Vec<JobInfoResponse>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JobInfoResponse {
+    // from job
+    pub job_id: i32,
+    pub pipeline_id: i32,
+    pub packages: String,
+    pub arch: String,
+    pub creation_time: chrono::DateTime<chrono::Utc>,
+    pub status: String,
+    pub build_success: Option<bool>,
+    pub pushpkg_success: Option<bool>,
+    pub successful_packages: Option<String>,
+    pub failed_package: Option<String>,
+    pub skipped_packages: Option<String>,
+    pub log_url: Option<String>,
+    pub finish_time: Option<chrono::DateTime<chrono::Utc>>,
+    pub error_message: Option<String>,
+    pub elapsed_secs: Option<i64>,
+    pub assigned_worker_id: Option<i32>,
+    pub built_by_worker_id: Option<i32>,
+    pub require_min_core: Option<i32>,
+    pub require_min_total_mem: Option<i64>,
+    pub require_min_total_mem_per_core: Option<f32>,
+    pub require_min_disk: Option<i64>,
+    /// Comma-separated tags a worker must have to build the job
+    pub required_tags: Option<String>,
+    pub assign_time: Option<chrono::DateTime<chrono::Utc>>,
+    /// The job this one was restarted from
+    pub restarted_from: Option<i32>,
+    /// The latest job restarted from this one
+    pub restarted_to: Option<i32>,
+    /// Jobs with higher priority are assigned first
+    pub priority: i32,
+    /// Seconds spent in each step, e.g. ("ciel build", 600)
+    pub step_timings: Option<Vec<(String, i64)>>,
+
+    // timing
+    pub queued_secs: Option<i64>,
+    pub build_secs: Option<i64>,
+
+    // from pipeline
+    pub git_branch: String,
+    pub git_sha: String,
+    pub github_pr: Option<i64>,
+
+    // from worker
+    pub assigned_worker_hostname: Option<String>,
+    pub built_by_worker_hostname: Option<String>,
+    /// Live log stream of the running job, for `/api/ws/viewer/:stream`
+    #[serde(default)]
+    pub live_log_stream: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PipelineListResponseJob {
+    pub job_id: i32,
+    pub arch: String,
+    pub status: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PipelineListResponseItem {
+    pub id: i32,
+    pub git_branch: String,
+    pub git_sha: String,
+    pub creation_time: chrono::DateTime<chrono::Utc>,
+    pub github_pr: Option<i64>,
+    pub packages: String,
+    pub archs: String,
+    pub status: String,
+
+    // from pipeline creator
+    pub creator_github_login: Option<String>,
+    pub creator_github_avatar_url: Option<String>,
+
+    pub jobs: Vec<PipelineListResponseJob>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PipelineListResponse {
+    pub total_items: i64,
+    pub items: Vec<PipelineListResponseItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JobListResponseItem {
+    pub id: i32,
+    pub pipeline_id: i32,
+    pub packages: String,
+    pub arch: String,
+    pub status: String,
+    pub elapsed_secs: Option<i64>,
+    pub creation_time: chrono::DateTime<chrono::Utc>,
+    pub log_url: Option<String>,
+    pub build_success: Option<bool>,
+
+    // from pipeline
+    pub git_branch: String,
+    pub git_sha: String,
+    pub github_pr: Option<i64>,
+
+    // from pipeline creator
+    pub creator_github_login: Option<String>,
+    pub creator_github_avatar_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JobListResponse {
+    pub total_items: i64,
+    pub items: Vec<JobListResponseItem>,
+    /// Pass as `before_id` (or `after_id` when paging by `after_id`) to get the next page
+    pub next_cursor: Option<i32>,
+}
+
+/// Pushed by `/api/pipeline/events` when a job of the pipeline changes state
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PipelineJobEvent {
+    pub job_id: i32,
+    pub arch: String,
+    pub status: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JobRestartRequest {
+    pub job_id: i32,
+    /// Build with verbose output
+    #[serde(default)]
+    pub verbose: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JobRestartResponse {
+    pub job_id: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PipelineRestartRequest {
+    pub pipeline_id: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PipelineRestartResponse {
+    /// Ids of the new jobs
+    pub job_ids: Vec<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WorkerListResponseItem {
+    pub id: i32,
+    pub hostname: String,
+    pub arch: String,
+    pub logical_cores: i32,
+    pub memory_bytes: i64,
+    pub disk_free_space_bytes: i64,
+    pub is_live: bool,
+    pub last_heartbeat_time: chrono::DateTime<chrono::Utc>,
+    pub internet_connectivity: bool,
+    pub tags: Vec<String>,
+    // status
+    pub running_job_id: Option<i32>,
+    pub running_job_assign_time: Option<chrono::DateTime<chrono::Utc>>,
+    // statistics
+    pub successful_job_count: i64,
+    pub failed_job_count: i64,
+    /// Ratio of successful jobs among successful and failed ones, none if neither
+    pub success_rate: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WorkerListResponse {
+    pub total_items: i64,
+    pub items: Vec<WorkerListResponseItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WorkerInfoResponse {
+    // from worker
+    pub worker_id: i32,
+    pub hostname: String,
+    pub arch: String,
+    pub git_commit: String,
+    pub memory_bytes: i64,
+    pub logical_cores: i32,
+    pub last_heartbeat_time: chrono::DateTime<chrono::Utc>,
+    pub disk_free_space_bytes: i64,
+    pub ciel_instances: Vec<String>,
+    pub tags: Vec<String>,
+    /// Websocket reconnections within the last hour, none for workers not reporting it
+    pub websocket_reconnects: Option<i32>,
+    pub websocket_last_error: Option<String>,
+
+    // status
+    pub running_job_id: Option<i32>,
+
+    // statistics
+    pub built_job_count: i64,
+    pub successful_job_count: i64,
+    pub failed_job_count: i64,
+    /// Ratio of successful jobs among successful and failed ones, none if neither
+    pub success_rate: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Hash)]
+pub struct DashboardStatusResponseByArch {
+    pub total_worker_count: i64,
+    pub live_worker_count: i64,
+    pub total_logical_cores: i64,
+    pub total_memory_bytes: bigdecimal::BigDecimal,
+
+    pub total_job_count: i64,
+    pub pending_job_count: i64,
+    pub running_job_count: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UserTokenRotateResponse {
+    /// Only returned once, the server keeps a hash of it
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Hash)]
+pub struct DashboardStatusResponse {
+    pub total_pipeline_count: i64,
+
+    pub total_job_count: i64,
+    pub pending_job_count: i64,
+    pub running_job_count: i64,
+    pub finished_job_count: i64,
+    pub cancelled_job_count: i64,
+
+    pub total_worker_count: i64,
+    pub live_worker_count: i64,
+    pub total_logical_cores: i64,
+    pub total_memory_bytes: bigdecimal::BigDecimal,
+
+    pub by_arch: BTreeMap<String, DashboardStatusResponseByArch>,
+}
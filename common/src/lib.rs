@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+pub mod api;
+
 #[derive(Serialize, Deserialize)]
 pub struct WorkerPollRequest {
     pub hostname: String,
@@ -8,6 +10,18 @@ pub struct WorkerPollRequest {
     pub memory_bytes: i64,
     pub logical_cores: i32,
     pub disk_free_space_bytes: i64,
+    /// Comma-separated worker tags, e.g. `bigdisk,gpu`
+    #[serde(default)]
+    pub tags: Option<String>,
+    /// One of `MACHINE_CLASSES`, derived from cores and memory if not set
+    #[serde(default)]
+    pub machine_class: Option<String>,
+    /// Jobs still being built by other build slots of the worker
+    #[serde(default)]
+    pub running_job_ids: Vec<i32>,
+    /// Live log stream of the polling build slot, `hostname` if not set
+    #[serde(default)]
+    pub log_stream: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -15,7 +29,28 @@ pub struct WorkerPollResponse {
     pub job_id: i32,
     pub git_branch: String,
     pub git_sha: String,
+    /// Git ref to fetch instead of git_branch, e.g. refs/pull/N/merge
+    #[serde(default)]
+    pub git_ref: Option<String>,
+    /// Git remote to fetch from: the configured ABBS repo, or e.g. the fork of a PR
+    #[serde(default)]
+    pub git_remote: Option<String>,
     pub packages: String,
+    /// Git patch to apply on top of git_sha before building
+    #[serde(default)]
+    pub patch: Option<String>,
+    /// Skip building and only run pushpkg on the output of previous build
+    #[serde(default)]
+    pub repush_only: bool,
+    /// Job that produced the output to push again, if repush_only
+    #[serde(default)]
+    pub repush_from: Option<i32>,
+    /// Pass verbose options to ciel for more detailed logs
+    #[serde(default)]
+    pub verbose: bool,
+    /// Extra space-separated `ciel build` flags, each one of `ALLOWED_CIEL_FLAGS`
+    #[serde(default)]
+    pub ciel_flags: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -29,6 +64,30 @@ pub struct WorkerHeartbeatRequest {
     pub worker_secret: String,
     pub performance: Option<i64>,
     pub internet_connectivity: Option<bool>,
+    /// Names of ciel instances available on the worker
+    pub ciel_instances: Option<Vec<String>>,
+    /// Comma-separated worker tags, e.g. `bigdisk,gpu`
+    #[serde(default)]
+    pub tags: Option<String>,
+    /// One of `MACHINE_CLASSES`, derived from cores and memory if not set
+    #[serde(default)]
+    pub machine_class: Option<String>,
+    /// Websocket reconnections within the last hour
+    #[serde(default)]
+    pub websocket_reconnects: Option<i32>,
+    /// Error of the last failed websocket connection
+    #[serde(default)]
+    pub websocket_last_error: Option<String>,
+    /// Jobs being built by the worker
+    #[serde(default)]
+    pub running_job_ids: Vec<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct WorkerHeartbeatResponse {
+    /// Running jobs cancelled on the server, the worker should stop building them
+    #[serde(default)]
+    pub cancelled_job_ids: Vec<i32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -53,9 +112,86 @@ pub struct JobOk {
     pub elapsed_secs: i64,
     /// If pushpkg succeeded
     pub pushpkg_success: bool,
+    /// Stage where the job failed, if any
+    #[serde(default)]
+    pub failure_stage: Option<FailureStage>,
+    /// Package files produced by a successful build
+    #[serde(default)]
+    pub artifacts: Option<Vec<Artifact>>,
+    /// Seconds spent in each step, e.g. ("ciel build", 600)
+    #[serde(default)]
+    pub step_timings: Vec<(String, i64)>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Artifact {
+    /// Path relative to the `debs` output directory
+    pub path: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureStage {
+    /// Failed to fetch the git branch, only reported by older workers:
+    /// git failures are now reported as `JobResult::Error`
+    GitFetch,
+    /// Failed to apply the patch on top of the git tree
+    Checkout,
+    /// acbs failed to parse spec or defines of a package
+    SpecParse,
+    /// acbs failed to fetch or verify package sources, which might be a network hiccup
+    /// of the worker or a source only used by some archs
+    SourceFetch,
+    /// ciel build failed
+    Build,
+    /// pushpkg failed
+    Pushpkg,
+}
+
+/// Errors printed by acbs when parsing spec or defines, see acbs/parser.py
+const SPEC_PARSE_ERRORS: &[&str] = &[
+    "Failed to parse spec",
+    "Failed to parse defines",
+    "Malformed spec file",
+    "Malformed defines file",
+    "Unable to parse",
+];
+
+/// Errors printed by acbs when fetching sources, see acbs/fetch.py
+const SOURCE_FETCH_ERRORS: &[&str] = &[
+    "Failed to fetch source",
+    "Unable to fetch source",
+    "Checksum mismatch",
+];
+
+impl FailureStage {
+    /// Whether the failure would happen identically on every arch.
+    /// Be conservative here: build failures might be arch-specific,
+    /// and source fetch failures might be transient or arch-specific.
+    pub fn is_arch_independent(&self) -> bool {
+        matches!(self, FailureStage::Checkout | FailureStage::SpecParse)
+    }
+
+    /// Tell the stage of a failed `ciel build` from its output
+    pub fn from_build_output(output: &str) -> FailureStage {
+        for line in output.lines() {
+            // acbs stops at the first failure, so any of these errors tells the stage
+            if !line.contains("ERROR") {
+                continue;
+            }
+            if SPEC_PARSE_ERRORS.iter().any(|err| line.contains(err)) {
+                return FailureStage::SpecParse;
+            }
+            if SOURCE_FETCH_ERRORS.iter().any(|err| line.contains(err)) {
+                return FailureStage::SourceFetch;
+            }
+        }
+        FailureStage::Build
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct WorkerJobUpdateRequest {
     pub hostname: String,
     pub arch: String,
@@ -63,3 +199,151 @@ pub struct WorkerJobUpdateRequest {
     pub result: JobResult,
     pub worker_secret: String,
 }
+
+/// Header carrying the worker secret of requests with a raw body, e.g. log upload
+pub const WORKER_SECRET_HEADER: &str = "X-Buildit-Worker-Secret";
+
+/// Largest log accepted by `/api/job/upload_log`, same as pastes to aosc.io
+pub const MAX_UPLOADED_LOG_BYTES: usize = 10 * 1024 * 1024;
+
+/// Query of `/api/job/upload_log`, the raw log is sent as request body
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JobUploadLogRequest {
+    pub hostname: String,
+    pub arch: String,
+    pub job_id: i32,
+    /// Must start with `<job_id>-` and end with `.txt`
+    pub file_name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JobUploadLogResponse {
+    pub log_url: String,
+}
+
+/// Split comma-separated tags, ignoring empty ones
+pub fn parse_tags(tags: &str) -> Vec<&str> {
+    tags.split(',')
+        .map(|tag| tag.trim())
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+/// Machine size classes, from small to large
+pub const MACHINE_CLASSES: [&str; 3] = ["medium", "large", "xlarge"];
+
+/// Rank in `MACHINE_CLASSES` by cores and by memory
+fn machine_class_ranks(logical_cores: i32, memory_bytes: i64) -> (usize, usize) {
+    let by_cores = match logical_cores {
+        32.. => 2,
+        16.. => 1,
+        _ => 0,
+    };
+    let by_memory = match memory_bytes >> 30 {
+        64.. => 2,
+        32.. => 1,
+        _ => 0,
+    };
+    (by_cores, by_memory)
+}
+
+/// Class of a worker, which must have both enough cores and memory for it
+pub fn worker_machine_class(logical_cores: i32, memory_bytes: i64) -> &'static str {
+    let (by_cores, by_memory) = machine_class_ranks(logical_cores, memory_bytes);
+    MACHINE_CLASSES[by_cores.min(by_memory)]
+}
+
+/// Class of worker preferred by a job of the minimum requirements, `None` if any worker fits
+pub fn job_machine_class(
+    min_core: Option<i32>,
+    min_total_mem: Option<i64>,
+) -> Option<&'static str> {
+    let (by_cores, by_memory) =
+        machine_class_ranks(min_core.unwrap_or(0), min_total_mem.unwrap_or(0));
+    match by_cores.max(by_memory) {
+        0 => None,
+        rank => Some(MACHINE_CLASSES[rank]),
+    }
+}
+
+/// Flags of `ciel build` a job may request, e.g. for bootstrap builds
+pub const ALLOWED_CIEL_FLAGS: &[&str] = &["--stage2", "--offline", "--always-discard"];
+
+/// First flag in space-separated `flags` not in `ALLOWED_CIEL_FLAGS`
+pub fn disallowed_ciel_flag(flags: &str) -> Option<&str> {
+    flags
+        .split_whitespace()
+        .find(|flag| !ALLOWED_CIEL_FLAGS.contains(flag))
+}
+
+/// Arguments of `ciel build` for the job, shared by worker and `/buildcmd` preview
+pub fn ciel_build_args<'a>(
+    instance: &'a str,
+    packages: &'a str,
+    verbose_options: Option<&'a str>,
+    ciel_flags: Option<&'a str>,
+) -> Vec<&'a str> {
+    let mut res = vec!["build", "-i", instance];
+    if let Some(options) = verbose_options {
+        res.extend(options.split_whitespace());
+    }
+    if let Some(flags) = ciel_flags {
+        res.extend(flags.split_whitespace());
+    }
+    res.extend(packages.split(','));
+    res
+}
+
+#[test]
+fn test_failure_stage_from_build_output() {
+    let spec = "\
+[INFO]:  Resolving dependencies...
+[\x1b[1;31mERROR\x1b[0m]: Failed to parse spec of package app-utils/fish: line 3: unexpected EOF
+[ERROR]: Build failed.
+";
+    assert_eq!(
+        FailureStage::from_build_output(spec),
+        FailureStage::SpecParse
+    );
+
+    let defines = "\
+[INFO]:  Building fish...
+[ERROR]: Malformed defines file: app-utils/fish/autobuild/defines
+";
+    assert_eq!(
+        FailureStage::from_build_output(defines),
+        FailureStage::SpecParse
+    );
+
+    let fetch = "\
+[INFO]:  Fetching required source files...
+[INFO]:  [1/1] Fetching source (tarball)...
+curl: (22) The requested URL returned error: 404
+[ERROR]: Failed to fetch source from https://example.com/fish-3.7.1.tar.xz
+";
+    assert_eq!(
+        FailureStage::from_build_output(fetch),
+        FailureStage::SourceFetch
+    );
+
+    let checksum = "\
+[INFO]:  [1/1] Fetching source (tarball)...
+[ERROR]: Checksum mismatch! Expected 614c9f5643cd0799df391395fa6bbc3649427bb839722ce3b114d3bbc1a3b250, got 0f6f8d3bd9e3dfe51e06c1d4e4a8d7a4b1c0c0b8d56ae4a2a8a7a2d8e3c4b5a6
+";
+    assert_eq!(
+        FailureStage::from_build_output(checksum),
+        FailureStage::SourceFetch
+    );
+
+    let build = "\
+[INFO]:  Building fish...
+src/fish.cpp:42:10: fatal error: ncurses.h: No such file or directory
+[ERROR]: Build error.
+Failed package:
+fish (amd64 @ 3.7.1-0)
+";
+    assert_eq!(FailureStage::from_build_output(build), FailureStage::Build);
+    assert!(!FailureStage::Build.is_arch_independent());
+    assert!(FailureStage::SpecParse.is_arch_independent());
+    assert!(!FailureStage::SourceFetch.is_arch_independent());
+}
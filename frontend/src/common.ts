@@ -1 +1,13 @@
-export const hostname = process.env.NODE_ENV === "development" ? "http://localhost:3000" : "";
\ No newline at end of file
+import axios from 'axios';
+
+export const hostname = process.env.NODE_ENV === "development" ? "http://localhost:3000" : "";
+
+let repoUrl: Promise<string> | null = null;
+
+// url of the ABBS repo on GitHub as configured on the server
+export function getRepoUrl(): Promise<string> {
+  if (repoUrl === null) {
+    repoUrl = axios.get(hostname + `/api/version`).then((resp) => resp.data.repo_url);
+  }
+  return repoUrl;
+}
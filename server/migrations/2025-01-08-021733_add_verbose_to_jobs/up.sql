@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE jobs ADD COLUMN verbose BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE jobs ADD restarted_from INTEGER REFERENCES jobs(id);
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE env_req_overrides (
+  package TEXT PRIMARY KEY,
+  envreq TEXT NOT NULL,
+  update_time TIMESTAMP WITH TIME ZONE NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE workers ADD tags TEXT;
+ALTER TABLE jobs ADD required_tags TEXT;
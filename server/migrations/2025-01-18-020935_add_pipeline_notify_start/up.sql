@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE pipelines ADD notify_start BOOLEAN NOT NULL DEFAULT false;
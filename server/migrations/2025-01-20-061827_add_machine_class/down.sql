@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE jobs DROP COLUMN preferred_machine_class;
+ALTER TABLE workers DROP COLUMN machine_class;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE workers ADD machine_class TEXT;
+ALTER TABLE jobs ADD preferred_machine_class TEXT;
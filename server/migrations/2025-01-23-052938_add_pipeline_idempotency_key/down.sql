@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE pipelines DROP CONSTRAINT unique_idempotency_key;
+ALTER TABLE pipelines DROP COLUMN idempotency_key;
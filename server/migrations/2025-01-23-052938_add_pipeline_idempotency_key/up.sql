@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE pipelines ADD idempotency_key TEXT;
+ALTER TABLE pipelines ADD CONSTRAINT unique_idempotency_key UNIQUE (idempotency_key);
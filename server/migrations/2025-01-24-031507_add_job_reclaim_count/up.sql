@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE jobs ADD reclaim_count INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE workers DROP COLUMN websocket_last_error;
+ALTER TABLE workers DROP COLUMN websocket_reconnects;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE workers ADD websocket_reconnects INTEGER;
+ALTER TABLE workers ADD websocket_last_error TEXT;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+CREATE TABLE job_last_seen (
+  job_id INTEGER PRIMARY KEY REFERENCES jobs(id) ON DELETE CASCADE,
+  last_seen_time TIMESTAMP WITH TIME ZONE NOT NULL
+);
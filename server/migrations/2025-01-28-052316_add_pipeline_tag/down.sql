@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX pipelines_tag_idx;
+ALTER TABLE pipelines DROP COLUMN tag;
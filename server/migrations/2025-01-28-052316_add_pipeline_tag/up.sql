@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE pipelines ADD tag TEXT;
+CREATE INDEX pipelines_tag_idx ON pipelines (tag);
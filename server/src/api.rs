@@ -1,25 +1,57 @@
 use crate::{
+    get_conn_with_retry,
     github::{get_crab_github_installation, get_packages_from_pr},
-    models::{Job, NewJob, NewPipeline, Pipeline, User, Worker},
-    DbPool, ALL_ARCH, ARGS,
+    github_org, github_repo, github_repo_url,
+    models::{EnvReqOverride, Job, NewJob, NewPipeline, Pipeline, User, Worker},
+    DbConn, DbPool, ALL_ARCH, ARGS, DEFAULT_MAX_PIPELINE_PACKAGES, DEFAULT_NOARCH_FALLBACK_SECS,
+    DEFAULT_QUEUE_LIMIT, DEFAULT_STUCK_JOB_SECS, HEARTBEAT_TIMEOUT,
 };
 use anyhow::Context;
 use anyhow::{anyhow, bail};
 use buildit_utils::{
-    github::{get_archs, get_environment_requirement, resolve_packages, update_abbs},
+    github::{
+        apply_label_archs, expand_excluded_packages, expand_groups, find_version_by_packages,
+        for_each_abbs, get_archs, get_ciel_flags, get_environment_requirement, locate_defines,
+        noarch_packages, parse_environment_requirement, resolve_packages, split_noarch_packages,
+        strip_modifiers, try_read_ab_with_apml, unknown_packages, update_abbs,
+        update_abbs_from_remote, EnvironmentRequirement, GroupSlice,
+    },
     ABBS_REPO_LOCK,
 };
+use common::{
+    api::PipelineListResponseJob, ciel_build_args, disallowed_ciel_flag, job_machine_class,
+    parse_tags, worker_machine_class, Artifact, MACHINE_CLASSES,
+};
 use diesel::r2d2::PoolTransactionManager;
 use diesel::{
     connection::{AnsiTransactionManager, TransactionManager},
     SelectableHelper,
 };
 use diesel::{
-    dsl::count, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl,
+    dsl::count, result::DatabaseErrorKind, BoolExpressionMethods, Connection, ExpressionMethods,
+    JoinOnDsl, NullableExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult,
+    RunQueryDsl, TextExpressionMethods,
 };
+use octocrab::models::CheckRunId;
+use octocrab::params::checks::CheckRunConclusion;
+use rand::{distributions::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
-use tracing::warn;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    process::Stdio,
+};
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info, warn};
+
+pub const MAX_PATCH_SIZE: usize = 1024 * 1024; // 1 MiB
+
+/// Priority of jobs, e.g. manual rebuilds and QA rolls
+pub const DEFAULT_JOB_PRIORITY: i32 = 0;
+/// Priority of jobs of PRs labeled `security`, assigned ahead of default ones
+pub const SECURITY_JOB_PRIORITY: i32 = 10;
+/// Submissions with the same idempotency key within this window return the same pipeline
+pub const IDEMPOTENCY_KEY_WINDOW_SECS: i64 = 3600; // 1 hour
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum JobSource {
@@ -35,7 +67,7 @@ pub enum JobSource {
 #[tracing::instrument(skip(crab))]
 async fn create_check_run(crab: octocrab::Octocrab, arch: String, git_sha: String) -> Option<u64> {
     match crab
-        .checks("AOSC-Dev", "aosc-os-abbs")
+        .checks(github_org(), github_repo())
         .create_check_run(format!("buildit {}", arch), git_sha)
         .status(octocrab::params::checks::CheckRunStatus::Queued)
         .send()
@@ -51,17 +83,96 @@ async fn create_check_run(crab: octocrab::Octocrab, arch: String, git_sha: Strin
     return None;
 }
 
+/// Optional arguments of `pipeline_new`
+#[derive(Debug, Clone, Copy)]
+pub struct PipelineOptions<'a> {
+    /// Git ref to fetch instead of git_branch, e.g. refs/pull/N/merge
+    pub git_ref: Option<&'a str>,
+    /// Git remote to fetch from instead of the configured ABBS repo, e.g. the fork of a PR
+    pub git_remote: Option<&'a str>,
+    pub github_pr: Option<u64>,
+    /// Git patch to apply on top of git_sha before building
+    pub patch: Option<&'a str>,
+    /// Comma-separated tags a worker must have to build the jobs
+    pub required_tags: Option<&'a str>,
+    /// ABBS tree is already updated by the caller
+    pub skip_git_fetch: bool,
+    pub verbose: bool,
+    /// Skip packages unchanged since the last successful build
+    pub skip_unchanged: bool,
+    /// Notify the creator when jobs start
+    pub notify_start: bool,
+    pub priority: i32,
+    pub idempotency_key: Option<&'a str>,
+    /// Extra space-separated `ciel build` flags
+    pub ciel_flags: Option<&'a str>,
+    /// Campaign tag, see `retry_campaign`
+    pub tag: Option<&'a str>,
+}
+
+impl Default for PipelineOptions<'_> {
+    fn default() -> Self {
+        Self {
+            git_ref: None,
+            git_remote: None,
+            github_pr: None,
+            patch: None,
+            required_tags: None,
+            skip_git_fetch: false,
+            verbose: false,
+            skip_unchanged: false,
+            notify_start: false,
+            priority: DEFAULT_JOB_PRIORITY,
+            idempotency_key: None,
+            ciel_flags: None,
+            tag: None,
+        }
+    }
+}
+
 #[tracing::instrument(skip(pool))]
 pub async fn pipeline_new(
     pool: DbPool,
     git_branch: &str,
     git_sha: Option<&str>,
-    github_pr: Option<u64>,
     packages: &str,
     archs: &str,
     source: JobSource,
-    skip_git_fetch: bool,
+    options: PipelineOptions<'_>,
 ) -> anyhow::Result<Pipeline> {
+    let PipelineOptions {
+        git_ref,
+        git_remote,
+        github_pr,
+        patch,
+        required_tags,
+        skip_git_fetch,
+        verbose,
+        skip_unchanged,
+        notify_start,
+        priority,
+        idempotency_key,
+        ciel_flags,
+        tag,
+    } = options;
+
+    // a retried submission returns the pipeline created by the first one
+    if let Some(key) = idempotency_key {
+        if key.is_empty() || key.len() > 128 {
+            return Err(anyhow!("Invalid idempotency key: {key}"));
+        }
+        let mut conn = pool
+            .get()
+            .context("Failed to get db connection from pool")?;
+        if let Some(pipeline) = pipeline_by_idempotency_key(&mut conn, key)? {
+            info!(
+                "Returning pipeline {} for idempotency key {key}",
+                pipeline.id
+            );
+            return Ok(pipeline);
+        }
+    }
+
     // sanitize archs arg
     let mut archs: Vec<&str> = archs.split(',').collect();
     archs.sort();
@@ -91,10 +202,43 @@ pub async fn pipeline_new(
             || ch == '+'
             || ch == ':'
             || ch == '/'
+            || ch == '!'
+            || ch == '['
+            || ch == ']'
     }) {
         return Err(anyhow!("Invalid packages: {packages}"));
     }
 
+    // sanitize required_tags arg
+    let required_tags = required_tags
+        .map(|tags| parse_tags(tags).join(","))
+        .filter(|tags| !tags.is_empty());
+    if let Some(tags) = &required_tags {
+        if !tags
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == ',' || ch == '-' || ch == '_')
+        {
+            return Err(anyhow!("Invalid tags: {tags}"));
+        }
+    }
+
+    // sanitize tag arg
+    if let Some(tag) = tag {
+        if tag.is_empty()
+            || tag.len() > 64
+            || !tag
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '.' || ch == '-' || ch == '_')
+        {
+            bail!("Invalid tag: {tag}");
+        }
+    }
+
+    // sanitize ciel_flags arg, only allow known flags to be passed to ciel
+    if let Some(flag) = ciel_flags.and_then(disallowed_ciel_flag) {
+        bail!("Unsupported ciel flag: {flag}");
+    }
+
     // sanitize git_branch arg
     if !git_branch
         .chars()
@@ -103,10 +247,31 @@ pub async fn pipeline_new(
         return Err(anyhow!("Invalid branch: {git_branch}"));
     }
 
+    // sanitize git_remote arg, only fetch from GitHub
+    if let Some(git_remote) = git_remote {
+        if !git_remote.starts_with("https://github.com/")
+            || !git_remote.chars().all(|ch| {
+                ch.is_ascii_alphanumeric()
+                    || ch == '/'
+                    || ch == ':'
+                    || ch == '.'
+                    || ch == '-'
+                    || ch == '_'
+            })
+        {
+            return Err(anyhow!("Invalid git remote: {git_remote}"));
+        }
+    }
+
     let lock = ABBS_REPO_LOCK.lock().await;
-    update_abbs(git_branch, &ARGS.abbs_path, skip_git_fetch)
-        .await
-        .context("Failed to update ABBS tree")?;
+    update_abbs_from_remote(
+        git_remote.unwrap_or("origin"),
+        git_branch,
+        &ARGS.abbs_path,
+        skip_git_fetch,
+    )
+    .await
+    .context("Failed to update ABBS tree")?;
 
     // resolve branch name to commit hash if not specified
     let git_sha = match git_sha {
@@ -114,7 +279,12 @@ pub async fn pipeline_new(
             if !git_sha.chars().all(|ch| ch.is_ascii_alphanumeric()) {
                 return Err(anyhow!("Invalid git sha: {git_sha}"));
             }
-            git_sha.to_string()
+            if git_ref.is_some() {
+                // e.g. test merge commit of PR, not on the branch
+                git_sha.to_string()
+            } else {
+                resolve_commit_on_branch(git_sha, git_branch).await?
+            }
         }
         None => {
             let output = tokio::process::Command::new("git")
@@ -128,23 +298,90 @@ pub async fn pipeline_new(
         }
     };
 
+    // make sure the patch applies cleanly
+    if let Some(patch) = patch {
+        check_patch(patch).await?;
+    }
+
+    let pkgs = packages
+        .split(",")
+        .map(|s| s.to_string())
+        .collect::<Vec<String>>();
+
+    // expand groups with excluded members, e.g. groups/kde,!kwin
+    let packages = expand_excluded_packages(&pkgs, &ARGS.abbs_path)
+        .context("Failed to exclude packages")?
+        .join(",");
+    if packages.is_empty() {
+        bail!("No packages left to build after exclusion");
+    }
+
     // find environment requirements
-    let resolved_pkgs = resolve_packages(
-        &packages
-            .split(",")
-            .map(|s| s.to_string())
-            .collect::<Vec<String>>(),
-        &ARGS.abbs_path,
-    )
-    .context("Resolve packages")?;
+    let resolved_pkgs = resolve_packages(&pkgs, &ARGS.abbs_path).context("Resolve packages")?;
+    let unknown = unknown_packages(&resolved_pkgs, &ARGS.abbs_path);
+    if !unknown.is_empty() {
+        bail!("Unknown package: {}", unknown.join(", "));
+    }
+    let max_packages = ARGS
+        .max_pipeline_packages
+        .unwrap_or(DEFAULT_MAX_PIPELINE_PACKAGES);
+    if resolved_pkgs.len() > max_packages {
+        bail!(
+            "Too many packages: {} after resolving groups, at most {max_packages} are allowed",
+            resolved_pkgs.len()
+        );
+    }
 
-    let env_req = get_environment_requirement(&ARGS.abbs_path, &resolved_pkgs);
-    drop(lock);
+    // extra ciel flags requested by packages, e.g. bootstrap builds
+    let spec_ciel_flags = get_ciel_flags(&ARGS.abbs_path, &resolved_pkgs);
+    if let Some(flag) = spec_ciel_flags
+        .iter()
+        .find(|flag| disallowed_ciel_flag(flag).is_some())
+    {
+        bail!("Unsupported ciel flag in BUILDIT_CIEL_FLAGS: {flag}");
+    }
+    let mut all_ciel_flags: Vec<&str> = ciel_flags.unwrap_or_default().split_whitespace().collect();
+    for flag in &spec_ciel_flags {
+        if !all_ciel_flags.contains(&flag.as_str()) {
+            all_ciel_flags.push(flag);
+        }
+    }
+    let ciel_flags = Some(all_ciel_flags.join(" ")).filter(|flags| !flags.is_empty());
 
     // create a new pipeline
     let mut conn = pool
         .get()
         .context("Failed to get db connection from pool")?;
+
+    let env_req = environment_requirement_with_overrides(&mut conn, &resolved_pkgs)?;
+
+    // packages to build on each arch, never building the same noarch package on several archs
+    let noarch = noarch_packages(&ARGS.abbs_path, &resolved_pkgs);
+    let pkgs: Vec<String> = packages.split(',').map(|pkg| pkg.to_string()).collect();
+    let pkgs = if noarch.is_empty() {
+        pkgs
+    } else {
+        expand_groups(&pkgs, &ARGS.abbs_path).context("Failed to expand groups")?
+    };
+    let mut arch_packages: Vec<(&str, String)> = split_noarch_packages(&archs, &pkgs, &noarch)
+        .into_iter()
+        .map(|(arch, arch_pkgs)| (arch, arch_pkgs.join(",")))
+        .collect();
+    if skip_unchanged {
+        for (arch, arch_pkgs) in &mut arch_packages {
+            let pkgs: Vec<String> = arch_pkgs.split(',').map(|pkg| pkg.to_string()).collect();
+            *arch_pkgs = changed_packages(&mut conn, &pkgs, arch, &git_sha)
+                .await?
+                .join(",");
+        }
+        arch_packages.retain(|(_, arch_pkgs)| !arch_pkgs.is_empty());
+        if arch_packages.is_empty() {
+            bail!("{packages} unchanged since last successful build, skipping");
+        }
+    }
+    let archs: Vec<&str> = arch_packages.iter().map(|(arch, _)| *arch).collect();
+    drop(lock);
+
     use crate::schema::pipelines;
     let (source, github_pr, telegram_user, creator_user_id) = match source {
         JobSource::Telegram(id) => {
@@ -159,6 +396,20 @@ pub async fn pipeline_new(
         JobSource::Github(id) => ("github", Some(id), None, None),
         JobSource::Manual => ("manual", github_pr, None, None),
     };
+
+    // refuse to enqueue more jobs for archs whose queue is full
+    for arch in &archs {
+        use crate::schema::jobs::dsl;
+        let queued = dsl::jobs
+            .filter(dsl::arch.eq(arch))
+            .filter(dsl::status.eq_any(["created", "running"]))
+            .count()
+            .get_result::<i64>(&mut conn)?;
+        if queued >= queue_limit(arch) {
+            bail!("Queue full for {arch}: {queued} jobs created or running");
+        }
+    }
+
     let new_pipeline = NewPipeline {
         packages: packages.to_string(),
         archs: archs.join(","),
@@ -169,12 +420,30 @@ pub async fn pipeline_new(
         github_pr: github_pr.map(|pr| pr as i64),
         telegram_user: telegram_user,
         creator_user_id: creator_user_id,
+        patch: patch.map(|patch| patch.to_string()),
+        git_ref: git_ref.map(|git_ref| git_ref.to_string()),
+        notify_start,
+        git_remote: git_remote.map(|git_remote| git_remote.to_string()),
+        idempotency_key: idempotency_key.map(|key| key.to_string()),
+        ciel_flags,
+        tag: tag.map(|tag| tag.to_string()),
     };
-    let pipeline = diesel::insert_into(pipelines::table)
+    let pipeline = match diesel::insert_into(pipelines::table)
         .values(&new_pipeline)
         .returning(Pipeline::as_returning())
         .get_result(&mut conn)
-        .context("Failed to create pipeline")?;
+    {
+        Ok(pipeline) => pipeline,
+        Err(diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _))
+            if idempotency_key.is_some() =>
+        {
+            // a concurrent retry with the same key got here first
+            let key = idempotency_key.unwrap_or_default();
+            return pipeline_by_idempotency_key(&mut conn, key)?
+                .context("Failed to find pipeline of the idempotency key");
+        }
+        Err(err) => return Err(anyhow::Error::new(err).context("Failed to create pipeline")),
+    };
 
     // authenticate with github app
     let crab = match get_crab_github_installation().await {
@@ -210,13 +479,13 @@ pub async fn pipeline_new(
     };
 
     // for each arch, create a new job
-    for (arch, check_run_id) in archs.iter().zip(github_check_run_ids.iter()) {
+    for ((arch, arch_pkgs), check_run_id) in arch_packages.iter().zip(github_check_run_ids.iter()) {
         // create a new job
         use crate::schema::jobs;
         let env_req_current = env_req.get(*arch).cloned().unwrap_or_default();
         let new_job = NewJob {
             pipeline_id: pipeline.id,
-            packages: packages.to_string(),
+            packages: arch_pkgs.to_string(),
             arch: arch.to_string(),
             creation_time: chrono::Utc::now(),
             status: "created".to_string(),
@@ -225,6 +494,17 @@ pub async fn pipeline_new(
             require_min_total_mem: env_req_current.min_total_mem,
             require_min_total_mem_per_core: env_req_current.min_total_mem_per_core,
             require_min_disk: env_req_current.min_disk,
+            built_by_worker_id: None,
+            repush_only: false,
+            verbose,
+            restarted_from: None,
+            required_tags: required_tags.clone(),
+            preferred_machine_class: job_machine_class(
+                env_req_current.min_core,
+                env_req_current.min_total_mem,
+            )
+            .map(String::from),
+            priority,
         };
         diesel::insert_into(jobs::table)
             .values(&new_job)
@@ -235,21 +515,181 @@ pub async fn pipeline_new(
     Ok(pipeline)
 }
 
+/// Pipeline created with the idempotency key within `IDEMPOTENCY_KEY_WINDOW_SECS`.
+/// Keys are unique, so reusing one after the window is an error
+fn pipeline_by_idempotency_key(
+    conn: &mut PgConnection,
+    key: &str,
+) -> anyhow::Result<Option<Pipeline>> {
+    let pipeline = crate::schema::pipelines::dsl::pipelines
+        .filter(crate::schema::pipelines::dsl::idempotency_key.eq(key))
+        .first::<Pipeline>(conn)
+        .optional()?;
+    let window = chrono::Duration::try_seconds(IDEMPOTENCY_KEY_WINDOW_SECS).unwrap();
+    match pipeline {
+        Some(pipeline) if pipeline.creation_time > chrono::Utc::now() - window => {
+            Ok(Some(pipeline))
+        }
+        Some(_) => bail!("Idempotency key {key} has already been used"),
+        None => Ok(None),
+    }
+}
+
+/// Tree object id of package directory at commit, changes whenever anything in it changes
+async fn package_tree_hash(git_sha: &str, package_path: &str) -> Option<String> {
+    let output = tokio::process::Command::new("git")
+        .arg("rev-parse")
+        .arg(format!("{git_sha}:{package_path}"))
+        .current_dir(&ARGS.abbs_path)
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Filter out packages whose tree is unchanged since the last successful build on arch.
+/// Groups and packages never built are always kept.
+async fn changed_packages(
+    conn: &mut PgConnection,
+    pkgs: &[String],
+    target_arch: &str,
+    git_sha: &str,
+) -> anyhow::Result<Vec<String>> {
+    let mut package_paths = BTreeMap::new();
+    for_each_abbs(&ARGS.abbs_path, |pkg, path| {
+        if pkgs.iter().any(|p| strip_modifiers(p) == pkg) {
+            if let Ok(path) = path.strip_prefix(&ARGS.abbs_path) {
+                package_paths.insert(pkg.to_string(), path.display().to_string());
+            }
+        }
+    });
+
+    let mut res = vec![];
+    for pkg in pkgs {
+        let name = strip_modifiers(pkg);
+        let Some(path) = package_paths.get(name) else {
+            res.push(pkg.clone());
+            continue;
+        };
+
+        // find commit of the last successful build, successful_packages is comma-separated
+        let last_sha = {
+            use crate::schema::{jobs, pipelines};
+            let name = escape_like(name);
+            jobs::dsl::jobs
+                .inner_join(pipelines::table)
+                .filter(jobs::dsl::status.eq("success"))
+                .filter(jobs::dsl::arch.eq(target_arch))
+                .filter(
+                    jobs::dsl::successful_packages
+                        .like(name.clone())
+                        .or(jobs::dsl::successful_packages.like(format!("{name},%")))
+                        .or(jobs::dsl::successful_packages.like(format!("%,{name}")))
+                        .or(jobs::dsl::successful_packages.like(format!("%,{name},%"))),
+                )
+                .order(jobs::dsl::id.desc())
+                .select(pipelines::dsl::git_sha)
+                .first::<String>(conn)
+                .optional()?
+        };
+
+        let unchanged = match last_sha {
+            Some(last_sha) => {
+                let old = package_tree_hash(&last_sha, path).await;
+                old.is_some() && old == package_tree_hash(git_sha, path).await
+            }
+            None => false,
+        };
+        if unchanged {
+            info!("Skipping {pkg} on {target_arch}: unchanged since last successful build");
+        } else {
+            res.push(pkg.clone());
+        }
+    }
+    Ok(res)
+}
+
+/// Escape wildcards of LIKE patterns, e.g. `_` in package names
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Lookup max number of created + running jobs of the arch from `BUILDIT_QUEUE_LIMITS`
+fn queue_limit(arch: &str) -> i64 {
+    let mut limit = DEFAULT_QUEUE_LIMIT;
+    if let Some(limits) = &ARGS.queue_limits {
+        for entry in limits.split(',') {
+            if let Some((key, value)) = entry.split_once('=') {
+                let Ok(value) = value.trim().parse::<i64>() else {
+                    warn!("Invalid queue limit: {entry}");
+                    continue;
+                };
+                match key.trim() {
+                    key if key == arch => return value,
+                    "default" => limit = value,
+                    _ => {}
+                }
+            }
+        }
+    }
+    limit
+}
+
+/// Run `git apply --check` against the ABBS tree, reporting rejects if any
+#[tracing::instrument(skip(patch))]
+async fn check_patch(patch: &str) -> anyhow::Result<()> {
+    if patch.len() > MAX_PATCH_SIZE {
+        bail!("Patch is too large: {} bytes", patch.len());
+    }
+
+    let mut child = tokio::process::Command::new("git")
+        .args(["apply", "--check", "--verbose", "-"])
+        .current_dir(&ARGS.abbs_path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run git apply")?;
+
+    // close stdin after writing, so that git can finish reading
+    let mut stdin = child.stdin.take().context("Failed to open stdin")?;
+    stdin.write_all(patch.as_bytes()).await?;
+    drop(stdin);
+
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        bail!(
+            "Patch does not apply cleanly:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(())
+}
+
 #[tracing::instrument(skip(pool))]
 pub async fn pipeline_new_pr(
     pool: DbPool,
     pr: u64,
     archs: Option<&str>,
+    merge: bool,
+    allow_fork: bool,
     source: JobSource,
+    idempotency_key: Option<&str>,
 ) -> anyhow::Result<Pipeline> {
     match octocrab::instance()
-        .pulls("AOSC-Dev", "aosc-os-abbs")
+        .pulls(github_org(), github_repo())
         .get(pr)
         .await
     {
         Ok(pr) => {
             // If the pull request has been merged,
             // build and push packages based on stable
+            let mut git_ref = None;
             let (git_branch, git_sha) = if pr.merged_at.is_some() {
                 (
                     "stable",
@@ -257,12 +697,54 @@ pub async fn pipeline_new_pr(
                         .as_ref()
                         .context("merge_commit_sha should not be None")?,
                 )
+            } else if merge {
+                // build the test merge commit computed by GitHub
+                match pr.mergeable {
+                    Some(true) => {}
+                    Some(false) => bail!(
+                        "Pull request #{} cannot be merged into stable, please resolve conflicts first",
+                        pr.number
+                    ),
+                    None => bail!(
+                        "GitHub has not computed the merge commit of pull request #{} yet, please retry later",
+                        pr.number
+                    ),
+                }
+                git_ref = Some(format!("refs/pull/{}/merge", pr.number));
+                (
+                    pr.head.ref_field.as_str(),
+                    pr.merge_commit_sha
+                        .as_ref()
+                        .context("merge_commit_sha should not be None")?,
+                )
             } else {
                 (pr.head.ref_field.as_str(), &pr.head.sha)
             };
 
+            // unmerged head of a fork is only available from the fork itself
+            let mut git_remote = None;
             if pr.head.repo.as_ref().and_then(|x| x.fork).unwrap_or(false) {
-                return Err(anyhow!("Failed to create job: Pull request is a fork"));
+                if !allow_fork {
+                    return Err(anyhow!(
+                        "Failed to create job: Pull request is a fork, add fork to build it anyway"
+                    ));
+                }
+                if pr.merged_at.is_none() {
+                    if merge {
+                        bail!("Building the test merge commit of a fork is not supported");
+                    }
+                    if git_branch == "stable" {
+                        bail!("Refusing to build branch stable of a fork");
+                    }
+                    git_remote = Some(
+                        pr.head
+                            .repo
+                            .as_ref()
+                            .and_then(|repo| repo.clone_url.as_ref())
+                            .context("Failed to get clone url of the fork")?
+                            .to_string(),
+                    );
+                }
             }
 
             // find lines starting with #buildit
@@ -275,29 +757,70 @@ pub async fn pipeline_new_pr(
                     let path = &ARGS.abbs_path;
 
                     let _lock = ABBS_REPO_LOCK.lock().await;
-                    update_abbs(git_branch, &ARGS.abbs_path, false)
-                        .await
-                        .context("Failed to update ABBS tree")?;
+                    update_abbs_from_remote(
+                        git_remote.as_deref().unwrap_or("origin"),
+                        git_branch,
+                        &ARGS.abbs_path,
+                        false,
+                    )
+                    .await
+                    .context("Failed to update ABBS tree")?;
                     // skip next git fetch in pipeline_new
                     skip_git_fetch = true;
 
                     let resolved_packages =
                         resolve_packages(&packages, path).context("Failed to resolve packages")?;
 
-                    get_archs(path, &resolved_packages).join(",")
+                    let archs = get_archs(path, &resolved_packages);
+                    match &ARGS.pr_label_archs {
+                        Some(rules) => {
+                            let labels: Vec<&str> = pr
+                                .labels
+                                .iter()
+                                .flatten()
+                                .map(|label| label.name.as_str())
+                                .collect();
+                            apply_label_archs(&archs, &labels, rules).join(",")
+                        }
+                        None => archs.join(","),
+                    }
+                };
+
+                // let security fixes jump ahead of bulk rebuilds
+                let security = pr
+                    .labels
+                    .iter()
+                    .flatten()
+                    .any(|label| label.name == "security");
+                let priority = if security {
+                    SECURITY_JOB_PRIORITY
+                } else {
+                    DEFAULT_JOB_PRIORITY
                 };
 
-                pipeline_new(
-                    pool,
+                let pipeline = pipeline_new(
+                    pool.clone(),
                     git_branch,
                     Some(git_sha),
-                    Some(pr.number),
                     &packages.join(","),
                     &archs,
                     source,
-                    skip_git_fetch,
+                    PipelineOptions {
+                        git_ref: git_ref.as_deref(),
+                        git_remote: git_remote.as_deref(),
+                        github_pr: Some(pr.number),
+                        skip_git_fetch,
+                        priority,
+                        idempotency_key,
+                        ..Default::default()
+                    },
                 )
-                .await
+                .await?;
+
+                if let Err(err) = supersede_pr_jobs(pool, pr.number, &pipeline).await {
+                    warn!("Failed to cancel superseded jobs: {:?}", err);
+                }
+                Ok(pipeline)
             } else {
                 Err(anyhow!(
                     "Please list packages to build in pr info starting with '#buildit'"
@@ -308,19 +831,384 @@ pub async fn pipeline_new_pr(
     }
 }
 
+/// Resolve `git_sha` to full commit hash, making sure it is an ancestor of
+/// the branch tip checked out in ABBS tree
+async fn resolve_commit_on_branch(git_sha: &str, git_branch: &str) -> anyhow::Result<String> {
+    let output = tokio::process::Command::new("git")
+        .arg("rev-parse")
+        .arg("--verify")
+        .arg("--quiet")
+        .arg(format!("{git_sha}^{{commit}}"))
+        .current_dir(&ARGS.abbs_path)
+        .output()
+        .await
+        .context("Failed to resolve git commit")?;
+    if !output.status.success() {
+        bail!("Commit {git_sha} not found in ABBS tree");
+    }
+    let full_sha = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    let status = tokio::process::Command::new("git")
+        .arg("merge-base")
+        .arg("--is-ancestor")
+        .arg(&full_sha)
+        .arg("HEAD")
+        .current_dir(&ARGS.abbs_path)
+        .status()
+        .await
+        .context("Failed to check git commit ancestry")?;
+    match status.code() {
+        Some(0) => Ok(full_sha),
+        Some(1) => bail!("Commit {git_sha} is not on branch {git_branch}"),
+        _ => bail!("Failed to check whether commit {git_sha} is on branch {git_branch}"),
+    }
+}
+
+/// Environment requirements of packages per arch, where runtime overrides
+/// take precedence over ENVREQ in spec of the overridden packages
+fn environment_requirement_with_overrides(
+    conn: &mut PgConnection,
+    packages: &[String],
+) -> anyhow::Result<BTreeMap<&'static str, EnvironmentRequirement>> {
+    use crate::schema::env_req_overrides::dsl;
+    let overrides = dsl::env_req_overrides
+        .filter(dsl::package.eq_any(packages))
+        .load::<EnvReqOverride>(conn)?;
+
+    let others: Vec<String> = packages
+        .iter()
+        .filter(|pkg| !overrides.iter().any(|o| &o.package == *pkg))
+        .cloned()
+        .collect();
+    let mut res = get_environment_requirement(&ARGS.abbs_path, &others);
+
+    for o in overrides {
+        let env_req = parse_environment_requirement(&o.envreq).with_context(|| {
+            format!("Invalid environment requirement override of {}", o.package)
+        })?;
+        let spec = get_environment_requirement(&ARGS.abbs_path, &[o.package]);
+        for arch in ALL_ARCH {
+            let mut req = spec.get(arch).cloned().unwrap_or_default();
+            req.override_with(&env_req);
+            res.entry(*arch).or_default().merge(&req);
+        }
+    }
+    Ok(res)
+}
+
+/// Set runtime override of environment requirements of a package in ENVREQ syntax
+#[tracing::instrument(skip(pool))]
+pub async fn env_req_override_set(
+    pool: DbPool,
+    package: &str,
+    envreq: &str,
+) -> anyhow::Result<EnvironmentRequirement> {
+    if envreq.trim().is_empty() {
+        bail!("Please specify at least one requirement, e.g. mem=16");
+    }
+    let env_req = parse_environment_requirement(envreq)?;
+
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let new_override = EnvReqOverride {
+        package: package.to_string(),
+        envreq: envreq.split_whitespace().collect::<Vec<_>>().join(" "),
+        update_time: chrono::Utc::now(),
+    };
+    diesel::insert_into(crate::schema::env_req_overrides::table)
+        .values(&new_override)
+        .on_conflict(crate::schema::env_req_overrides::dsl::package)
+        .do_update()
+        .set(&new_override)
+        .execute(&mut conn)?;
+    Ok(env_req)
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn env_req_override_get(
+    pool: DbPool,
+    package: &str,
+) -> anyhow::Result<Option<EnvReqOverride>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    Ok(crate::schema::env_req_overrides::dsl::env_req_overrides
+        .find(package)
+        .get_result::<EnvReqOverride>(&mut conn)
+        .optional()?)
+}
+
+/// Remove runtime override of environment requirements, returning whether it existed
+#[tracing::instrument(skip(pool))]
+pub async fn env_req_override_clear(pool: DbPool, package: &str) -> anyhow::Result<bool> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let count =
+        diesel::delete(crate::schema::env_req_overrides::dsl::env_req_overrides.find(package))
+            .execute(&mut conn)?;
+    Ok(count > 0)
+}
+
+/// Cancel jobs of older pipelines of the same PR that are not assigned to any worker yet.
+/// Only archs built by the new pipeline are superseded, e.g. `/pr 1234 arm64` keeps other archs.
+#[tracing::instrument(skip(pool, pipeline))]
+async fn supersede_pr_jobs(pool: DbPool, pr: u64, pipeline: &Pipeline) -> anyhow::Result<()> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    use crate::schema::{jobs, pipelines};
+    let old_pipelines = pipelines::dsl::pipelines
+        .filter(pipelines::dsl::github_pr.eq(pr as i64))
+        .filter(pipelines::dsl::id.ne(pipeline.id))
+        .select(pipelines::dsl::id);
+    let cancelled = diesel::update(
+        jobs::dsl::jobs
+            .filter(jobs::dsl::pipeline_id.eq_any(old_pipelines))
+            .filter(jobs::dsl::arch.eq_any(pipeline.archs.split(',')))
+            .filter(jobs::dsl::status.eq("created")),
+    )
+    .set((
+        jobs::dsl::status.eq("cancelled"),
+        jobs::dsl::error_message.eq(format!("Superseded by pipeline #{}", pipeline.id)),
+        jobs::dsl::finish_time.eq(chrono::Utc::now()),
+    ))
+    .returning(Job::as_returning())
+    .get_results(&mut conn)?;
+
+    cancel_check_runs(&cancelled).await
+}
+
+/// Cancel jobs of the pipeline that are not assigned to any worker yet
+#[tracing::instrument(skip(pool))]
+pub async fn pipeline_cancel_created_jobs(
+    pool: DbPool,
+    pipeline_id: i32,
+    reason: &str,
+) -> anyhow::Result<Vec<Job>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    use crate::schema::jobs::dsl;
+    let cancelled = diesel::update(
+        dsl::jobs
+            .filter(dsl::pipeline_id.eq(pipeline_id))
+            .filter(dsl::status.eq("created")),
+    )
+    .set((
+        dsl::status.eq("cancelled"),
+        dsl::error_message.eq(reason),
+        dsl::finish_time.eq(chrono::Utc::now()),
+    ))
+    .returning(Job::as_returning())
+    .get_results(&mut conn)?;
+
+    cancel_check_runs(&cancelled).await?;
+    Ok(cancelled)
+}
+
+/// Cancel created or running jobs, returning the cancelled jobs and ids of jobs
+/// skipped because they have already finished. Workers running a cancelled job
+/// are told to stop on their next heartbeat, and get their update rejected in
+/// `worker_job_update`.
+#[tracing::instrument(skip(pool))]
+pub async fn job_cancel(pool: DbPool, job_ids: &[i32]) -> anyhow::Result<(Vec<Job>, Vec<i32>)> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    use crate::schema::jobs::dsl;
+    let cancelled = conn.transaction::<Vec<Job>, diesel::result::Error, _>(|conn| {
+        diesel::update(
+            dsl::jobs
+                .filter(dsl::id.eq_any(job_ids))
+                .filter(dsl::status.eq_any(["created", "running"])),
+        )
+        .set((
+            dsl::status.eq("cancelled"),
+            dsl::assigned_worker_id.eq(None::<i32>),
+            dsl::error_message.eq("Cancelled by user"),
+            dsl::finish_time.eq(chrono::Utc::now()),
+        ))
+        .returning(Job::as_returning())
+        .get_results(conn)
+    })?;
+
+    let skipped = job_ids
+        .iter()
+        .filter(|job_id| !cancelled.iter().any(|job| job.id == **job_id))
+        .copied()
+        .collect();
+
+    cancel_check_runs(&cancelled).await?;
+    Ok((cancelled, skipped))
+}
+
+/// Package files produced by the job, None if the job predates artifact tracking
+#[tracing::instrument(skip(pool))]
+pub async fn job_artifacts(
+    pool: DbPool,
+    job_id: i32,
+) -> anyhow::Result<(Job, Pipeline, Option<Vec<Artifact>>)> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let (job, pipeline) = crate::schema::jobs::dsl::jobs
+        .find(job_id)
+        .inner_join(crate::schema::pipelines::dsl::pipelines)
+        .get_result::<(Job, Pipeline)>(&mut conn)?;
+    if job.status != "success" {
+        bail!("Job #{job_id} is {}, not successful", job.status);
+    }
+
+    let artifacts = job
+        .artifacts
+        .as_deref()
+        .map(serde_json::from_str)
+        .transpose()
+        .context("Failed to parse artifacts")?;
+    Ok((job, pipeline, artifacts))
+}
+
+/// Job and the worker it is assigned to, if any
+#[tracing::instrument(skip(pool))]
+pub async fn job_assigned_worker(
+    pool: DbPool,
+    job_id: i32,
+) -> anyhow::Result<(Job, Option<Worker>)> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let job = crate::schema::jobs::dsl::jobs
+        .find(job_id)
+        .get_result::<Job>(&mut conn)
+        .optional()?
+        .ok_or_else(|| anyhow!("Job #{job_id} not found"))?;
+    let worker = match job.assigned_worker_id {
+        Some(worker_id) => crate::schema::workers::dsl::workers
+            .find(worker_id)
+            .get_result::<Worker>(&mut conn)
+            .optional()?,
+        None => None,
+    };
+    Ok((job, worker))
+}
+
+#[derive(Debug)]
+pub struct ExpeditedJobs {
+    pub job_ids: Vec<i32>,
+    pub priority: i32,
+    /// Number of pending jobs of other pipelines on the same archs now queued behind
+    pub overtaken: i64,
+}
+
+/// Raise priority of created jobs of the pipeline above every other pending job
+#[tracing::instrument(skip(pool))]
+pub async fn pipeline_expedite(pool: DbPool, pipeline_id: i32) -> anyhow::Result<ExpeditedJobs> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    crate::schema::pipelines::dsl::pipelines
+        .find(pipeline_id)
+        .get_result::<Pipeline>(&mut conn)
+        .optional()?
+        .ok_or_else(|| anyhow!("Pipeline #{pipeline_id} not found"))?;
+
+    conn.transaction::<_, anyhow::Error, _>(|conn| {
+        use crate::schema::jobs::dsl::{arch, id, jobs, priority, status};
+
+        let new_priority = jobs
+            .filter(status.eq("created"))
+            .select(diesel::dsl::max(priority))
+            .get_result::<Option<i32>>(conn)?
+            .unwrap_or_default()
+            + 1;
+        let expedited = diesel::update(jobs)
+            .filter(crate::schema::jobs::dsl::pipeline_id.eq(pipeline_id))
+            .filter(status.eq("created"))
+            .set(priority.eq(new_priority))
+            .returning((id, arch))
+            .get_results::<(i32, String)>(conn)?;
+        if expedited.is_empty() {
+            bail!("Pipeline #{pipeline_id} has no pending jobs");
+        }
+
+        let archs = expedited
+            .iter()
+            .map(|(_, job_arch)| job_arch.as_str())
+            .collect::<BTreeSet<_>>();
+        let overtaken = jobs
+            .filter(status.eq("created"))
+            .filter(crate::schema::jobs::dsl::pipeline_id.ne(pipeline_id))
+            .filter(arch.eq_any(archs))
+            .count()
+            .get_result::<i64>(conn)?;
+
+        Ok(ExpeditedJobs {
+            job_ids: expedited.into_iter().map(|(job_id, _)| job_id).collect(),
+            priority: new_priority,
+            overtaken,
+        })
+    })
+}
+
+// mark check runs of cancelled jobs as cancelled too
+async fn cancel_check_runs(cancelled: &[Job]) -> anyhow::Result<()> {
+    let check_run_ids: Vec<i64> = cancelled
+        .iter()
+        .filter_map(|job| job.github_check_run_id)
+        .collect();
+    if !check_run_ids.is_empty() {
+        if let Some(crab) = get_crab_github_installation().await? {
+            for check_run_id in check_run_ids {
+                if let Err(err) = crab
+                    .checks(github_org(), github_repo())
+                    .update_check_run(CheckRunId(check_run_id as u64))
+                    .status(octocrab::params::checks::CheckRunStatus::Completed)
+                    .conclusion(CheckRunConclusion::Cancelled)
+                    .send()
+                    .await
+                {
+                    warn!("Failed to update check run: {}", err);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 #[derive(Serialize)]
 pub struct PipelineStatus {
     pub arch: String,
     pub pending: u64,
     pub running: u64,
     pub available_servers: u64,
+    /// Rough time until pending jobs are all started, omitted without live workers or history
+    pub estimated_wait_secs: Option<u64>,
+}
+
+/// Average build time of recently finished jobs times pending jobs per live worker
+fn estimate_wait_secs(avg_secs: Option<i64>, pending: u64, live_workers: u64) -> Option<u64> {
+    match avg_secs {
+        Some(avg_secs) if avg_secs > 0 && pending > 0 && live_workers > 0 => {
+            Some(avg_secs as u64 * pending / live_workers)
+        }
+        _ => None,
+    }
 }
 
 #[tracing::instrument(skip(pool))]
 pub async fn pipeline_status(pool: DbPool) -> anyhow::Result<Vec<PipelineStatus>> {
-    let mut conn = pool
-        .get()
-        .context("Failed to get db connection from pool")?;
+    let mut conn = get_conn_with_retry(&pool).await?;
     // find pending/running jobs
     let mut pending: BTreeMap<String, i64> = crate::schema::jobs::dsl::jobs
         .filter(crate::schema::jobs::dsl::status.eq("created"))
@@ -351,40 +1239,1295 @@ pub async fn pipeline_status(pool: DbPool) -> anyhow::Result<Vec<PipelineStatus>
         .into_iter()
         .collect();
 
-    // fold noarch into amd64
-    let pending_noarch = *pending.get("noarch").unwrap_or(&0);
-    *pending.entry("amd64".to_string()).or_default() += pending_noarch;
-    let running_noarch = *running.get("noarch").unwrap_or(&0);
-    *running.entry("amd64".to_string()).or_default() += running_noarch;
-    let pending_noarch = *pending.get("optenv32").unwrap_or(&0);
-    *pending.entry("amd64".to_string()).or_default() += pending_noarch;
-    let running_noarch = *running.get("optenv32").unwrap_or(&0);
-    *running.entry("amd64".to_string()).or_default() += running_noarch;
+    // average build time of jobs finished in the last 7 days
+    let since = chrono::Utc::now() - chrono::Duration::try_days(7).unwrap();
+    let mut elapsed: BTreeMap<String, (i64, i64)> = BTreeMap::new();
+    for (job_arch, secs) in crate::schema::jobs::dsl::jobs
+        .filter(crate::schema::jobs::dsl::finish_time.gt(since))
+        .filter(crate::schema::jobs::dsl::elapsed_secs.is_not_null())
+        .select((
+            crate::schema::jobs::dsl::arch,
+            crate::schema::jobs::dsl::elapsed_secs,
+        ))
+        .load::<(String, Option<i64>)>(&mut conn)?
+    {
+        let job_arch = match job_arch.as_str() {
+            "noarch" | "optenv32" => "amd64".to_string(),
+            _ => job_arch,
+        };
+        let entry = elapsed.entry(job_arch).or_default();
+        entry.0 += secs.unwrap_or_default();
+        entry.1 += 1;
+    }
+
+    let mut live: BTreeMap<String, u64> = BTreeMap::new();
+    for worker in live_workers(&mut conn)? {
+        if worker.visible {
+            *live.entry(worker.arch).or_default() += 1;
+        }
+    }
+
+    // fold noarch into amd64
+    let pending_noarch = *pending.get("noarch").unwrap_or(&0);
+    *pending.entry("amd64".to_string()).or_default() += pending_noarch;
+    let running_noarch = *running.get("noarch").unwrap_or(&0);
+    *running.entry("amd64".to_string()).or_default() += running_noarch;
+    let pending_noarch = *pending.get("optenv32").unwrap_or(&0);
+    *pending.entry("amd64".to_string()).or_default() += pending_noarch;
+    let running_noarch = *running.get("optenv32").unwrap_or(&0);
+    *running.entry("amd64".to_string()).or_default() += running_noarch;
+
+    let mut res = vec![];
+    for a in ALL_ARCH {
+        let arch_pending = *pending.get(*a).unwrap_or(&0) as u64;
+        res.push(PipelineStatus {
+            arch: a.to_string(),
+            pending: arch_pending,
+            running: *running.get(*a).unwrap_or(&0) as u64,
+            available_servers: *available_servers.get(*a).unwrap_or(&0) as u64,
+            estimated_wait_secs: estimate_wait_secs(
+                elapsed.get(*a).map(|(sum, cnt)| sum / cnt),
+                arch_pending,
+                *live.get(*a).unwrap_or(&0),
+            ),
+        });
+    }
+
+    Ok(res)
+}
+
+#[derive(Serialize, Debug)]
+pub struct ArchReliability {
+    pub arch: String,
+    pub success: i64,
+    /// Number of finished (success or failed) jobs
+    pub total: i64,
+}
+
+/// Count successful and finished jobs per arch since the given time
+#[tracing::instrument(skip(pool))]
+pub async fn arch_reliability(
+    pool: DbPool,
+    since: chrono::DateTime<chrono::Utc>,
+) -> anyhow::Result<Vec<ArchReliability>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    use crate::schema::jobs::dsl::*;
+    let counts = jobs
+        .filter(finish_time.gt(since))
+        .filter(status.eq_any(["success", "failed"]))
+        .group_by((arch, status))
+        .select((arch, status, count(id)))
+        .load::<(String, String, i64)>(&mut conn)?;
+
+    let mut res: BTreeMap<String, ArchReliability> = BTreeMap::new();
+    for (job_arch, job_status, cnt) in counts {
+        let entry = res
+            .entry(job_arch.clone())
+            .or_insert_with(|| ArchReliability {
+                arch: job_arch,
+                success: 0,
+                total: 0,
+            });
+        if job_status == "success" {
+            entry.success += cnt;
+        }
+        entry.total += cnt;
+    }
+    Ok(res.into_values().collect())
+}
+
+#[derive(Serialize, Debug)]
+pub struct ArchWaitTime {
+    pub arch: String,
+    pub jobs: usize,
+    pub avg_secs: i64,
+    pub p95_secs: i64,
+}
+
+/// Queue wait time, i.e. from creation to assignment to worker,
+/// of jobs finished since the given time per arch
+#[tracing::instrument(skip(pool))]
+pub async fn arch_wait_times(
+    pool: DbPool,
+    since: chrono::DateTime<chrono::Utc>,
+) -> anyhow::Result<Vec<ArchWaitTime>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    use crate::schema::jobs::dsl::*;
+    let times = jobs
+        .filter(finish_time.gt(since))
+        .filter(assign_time.is_not_null())
+        .select((arch, creation_time, assign_time))
+        .load::<(
+            String,
+            chrono::DateTime<chrono::Utc>,
+            Option<chrono::DateTime<chrono::Utc>>,
+        )>(&mut conn)?;
+
+    let mut by_arch: BTreeMap<String, Vec<i64>> = BTreeMap::new();
+    for (job_arch, created, assigned) in times {
+        if let Some(assigned) = assigned {
+            by_arch
+                .entry(job_arch)
+                .or_default()
+                .push((assigned - created).num_seconds());
+        }
+    }
+
+    Ok(by_arch
+        .into_iter()
+        .map(|(job_arch, mut secs)| {
+            secs.sort_unstable();
+            // nearest-rank percentile
+            let p95 = secs[(secs.len() * 95).div_ceil(100) - 1];
+            ArchWaitTime {
+                arch: job_arch,
+                jobs: secs.len(),
+                avg_secs: secs.iter().sum::<i64>() / secs.len() as i64,
+                p95_secs: p95,
+            }
+        })
+        .collect())
+}
+
+#[derive(Serialize, Debug)]
+pub struct StalePackage {
+    pub name: String,
+    /// Time of the last successful build on the arch, if ever
+    pub last_built: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// List packages in the stable tree that have not been built on the arch in `days`,
+/// least recently built first
+#[tracing::instrument(skip(pool))]
+pub async fn stale_packages(
+    pool: DbPool,
+    target_arch: &str,
+    days: u64,
+) -> anyhow::Result<Vec<StalePackage>> {
+    if !ALL_ARCH.contains(&target_arch) {
+        bail!("Architecture {target_arch} is not supported");
+    }
+
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    // build index of last successful build time per package,
+    // noarch packages are available on all archs
+    let built = {
+        use crate::schema::jobs::dsl::*;
+        jobs.filter(status.eq("success"))
+            .filter(arch.eq_any([target_arch, "noarch"]))
+            .filter(finish_time.is_not_null())
+            .select((successful_packages, finish_time))
+            .load::<(Option<String>, Option<chrono::DateTime<chrono::Utc>>)>(&mut conn)?
+    };
+    let mut last_built: BTreeMap<String, chrono::DateTime<chrono::Utc>> = BTreeMap::new();
+    for (pkgs, time) in built {
+        let (Some(pkgs), Some(time)) = (pkgs, time) else {
+            continue;
+        };
+        for pkg in pkgs.split(',').filter(|pkg| !pkg.is_empty()) {
+            let entry = last_built.entry(pkg.to_string()).or_insert(time);
+            *entry = (*entry).max(time);
+        }
+    }
+
+    let mut tree_pkgs = vec![];
+    {
+        let _lock = ABBS_REPO_LOCK.lock().await;
+        update_abbs("stable", &ARGS.abbs_path, false)
+            .await
+            .context("Failed to update ABBS tree")?;
+        for_each_abbs(&ARGS.abbs_path, |pkg, path| {
+            if path.join("spec").is_file() {
+                tree_pkgs.push(pkg.to_string());
+            }
+        });
+    }
+
+    let deadline = chrono::Utc::now() - chrono::Days::new(days);
+    let mut res: Vec<StalePackage> = tree_pkgs
+        .into_iter()
+        .map(|name| StalePackage {
+            last_built: last_built.get(&name).cloned(),
+            name,
+        })
+        .filter(|pkg| match pkg.last_built {
+            Some(time) => time < deadline,
+            None => true,
+        })
+        .collect();
+    // never built first, then least recently built
+    res.sort_by(|a, b| a.last_built.cmp(&b.last_built).then(a.name.cmp(&b.name)));
+    Ok(res)
+}
+
+/// Variables buildit reads from spec and defines when deducing version and arch
+const SPEC_VARIABLES: &[&str] = &["VER", "REL", "PKGEPOCH", "ABHOST", "FAIL_ARCH", "ENVREQ"];
+
+#[derive(Serialize, Debug)]
+pub struct SpecFile {
+    /// Path relative to the package directory, e.g. `spec` or `autobuild/defines`
+    pub path: String,
+    /// Whether the fallback line parser was used because apml parsing failed
+    pub fallback: bool,
+    pub variables: Vec<(String, String)>,
+}
+
+/// Parse spec and defines of package in stable branch as buildit sees them
+#[tracing::instrument]
+pub async fn package_spec_variables(package: &str) -> anyhow::Result<Vec<SpecFile>> {
+    let _lock = ABBS_REPO_LOCK.lock().await;
+    update_abbs("stable", &ARGS.abbs_path, false)
+        .await
+        .context("Failed to update ABBS tree")?;
+
+    let mut package_path = None;
+    for_each_abbs(&ARGS.abbs_path, |pkg, path| {
+        if pkg == package {
+            package_path = Some(path.to_path_buf());
+        }
+    });
+    let package_path = package_path.with_context(|| format!("{package} does not exist"))?;
+
+    let mut files = vec![package_path.join("spec")];
+    files.extend(locate_defines(&package_path));
+
+    let mut res = vec![];
+    for file in files {
+        let content = std::fs::read_to_string(&file)
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+        let (context, fallback) = try_read_ab_with_apml(&content);
+        res.push(SpecFile {
+            path: file
+                .strip_prefix(&package_path)
+                .unwrap_or(&file)
+                .display()
+                .to_string(),
+            fallback,
+            variables: SPEC_VARIABLES
+                .iter()
+                .filter_map(|name| Some((name.to_string(), context.get(*name)?.clone())))
+                .collect(),
+        });
+    }
+    Ok(res)
+}
+
+#[derive(Serialize, Debug)]
+pub struct PackageVersions {
+    pub package: String,
+    /// Version of each PKGNAME defined by the package, e.g. `("llvm", "17.0.6-1")`
+    pub versions: Vec<(String, String)>,
+    pub archs: Vec<&'static str>,
+}
+
+/// Versions and archs of packages in stable branch
+#[tracing::instrument]
+pub async fn package_versions(packages: &[String]) -> anyhow::Result<Vec<PackageVersions>> {
+    let _lock = ABBS_REPO_LOCK.lock().await;
+    update_abbs("stable", &ARGS.abbs_path, false)
+        .await
+        .context("Failed to update ABBS tree")?;
+
+    let unknown = unknown_packages(packages, &ARGS.abbs_path);
+    if !unknown.is_empty() {
+        bail!("Unknown package: {}", unknown.join(", "));
+    }
+
+    Ok(packages
+        .iter()
+        .map(|package| {
+            let package = std::slice::from_ref(package);
+            PackageVersions {
+                package: package[0].clone(),
+                versions: find_version_by_packages(package, &ARGS.abbs_path),
+                archs: get_archs(&ARGS.abbs_path, package),
+            }
+        })
+        .collect())
+}
+
+/// Number of recent successful jobs per arch used to estimate build time
+const BUILD_HISTORY_LIMIT: i64 = 1000;
+
+#[derive(Serialize, Debug)]
+pub struct PackageEstimate {
+    pub name: String,
+    /// Estimated build time from previous jobs, if the package was built before
+    pub secs: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ArchCriticalPath {
+    pub job_id: i32,
+    pub arch: String,
+    /// Sum of estimated build time of packages with known estimate
+    pub total_secs: i64,
+    /// Packages in build order
+    pub packages: Vec<PackageEstimate>,
+}
+
+fn median(mut samples: Vec<i64>) -> Option<i64> {
+    samples.sort_unstable();
+    samples.get(samples.len() / 2).copied()
+}
+
+/// Load (packages, elapsed_secs) of recent successful jobs of arch, newest first
+fn load_build_history(
+    conn: &mut PgConnection,
+    job_arch: &str,
+) -> anyhow::Result<Vec<(String, i64)>> {
+    use crate::schema::jobs::dsl::*;
+    Ok(jobs
+        .filter(status.eq("success"))
+        .filter(arch.eq(job_arch))
+        .filter(elapsed_secs.is_not_null())
+        .order(id.desc())
+        .limit(BUILD_HISTORY_LIMIT)
+        .select((packages, elapsed_secs))
+        .load::<(String, Option<i64>)>(conn)?
+        .into_iter()
+        .filter_map(|(pkgs, secs)| Some((pkgs, secs?)))
+        .collect())
+}
+
+/// Estimate build time of package as the median of previous jobs. Prefer jobs
+/// building the package alone, otherwise assume elapsed time is evenly shared
+/// by packages of the job.
+fn estimate_package_secs(history: &[(String, i64)], pkg: &str) -> Option<i64> {
+    let alone: Vec<i64> = history
+        .iter()
+        .filter(|(pkgs, _)| pkgs == pkg)
+        .map(|(_, secs)| *secs)
+        .collect();
+    if !alone.is_empty() {
+        return median(alone);
+    }
+
+    median(
+        history
+            .iter()
+            .filter_map(|(pkgs, secs)| {
+                let pkgs: Vec<&str> = pkgs.split(',').collect();
+                pkgs.contains(&pkg).then(|| secs / pkgs.len() as i64)
+            })
+            .collect(),
+    )
+}
+
+/// Estimate the critical path of a pipeline. Jobs of different archs run in
+/// parallel, while packages of a job are built one after another in
+/// dependency order, so the slowest arch dominates the wall time.
+/// Sorted by estimated time, slowest first.
+#[tracing::instrument(skip(pool))]
+pub async fn pipeline_critical_path(
+    pool: DbPool,
+    pipeline_id: i32,
+) -> anyhow::Result<Vec<ArchCriticalPath>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let pipeline_jobs = latest_pipeline_jobs(&mut conn, pipeline_id)?;
+    if pipeline_jobs.is_empty() {
+        bail!("Pipeline #{pipeline_id} has no jobs");
+    }
+
+    let mut res = vec![];
+    for job in pipeline_jobs {
+        let history = load_build_history(&mut conn, &job.arch)?;
+
+        let packages: Vec<PackageEstimate> = job
+            .packages
+            .split(',')
+            .map(|pkg| PackageEstimate {
+                name: pkg.to_string(),
+                secs: estimate_package_secs(&history, pkg),
+            })
+            .collect();
+        res.push(ArchCriticalPath {
+            job_id: job.id,
+            arch: job.arch,
+            total_secs: packages.iter().filter_map(|pkg| pkg.secs).sum(),
+            packages,
+        });
+    }
+    res.sort_by_key(|path| std::cmp::Reverse(path.total_secs));
+    Ok(res)
+}
+
+/// Predict build time of a new pipeline per arch. Packages never built on
+/// the arch fall back to the median build time of packages on the arch.
+#[tracing::instrument(skip(pool))]
+pub async fn pipeline_predict_secs(
+    pool: DbPool,
+    pipeline: &Pipeline,
+) -> anyhow::Result<BTreeMap<String, i64>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let mut res = BTreeMap::new();
+    for arch in pipeline.archs.split(',') {
+        let history = load_build_history(&mut conn, arch)?;
+        let Some(fallback) = median(
+            history
+                .iter()
+                .map(|(pkgs, secs)| secs / pkgs.split(',').count() as i64)
+                .collect(),
+        ) else {
+            // nothing built on the arch yet
+            continue;
+        };
+
+        let secs = pipeline
+            .packages
+            .split(',')
+            .map(|pkg| estimate_package_secs(&history, pkg).unwrap_or(fallback))
+            .sum();
+        res.insert(arch.to_string(), secs);
+    }
+    Ok(res)
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn worker_status(pool: DbPool) -> anyhow::Result<Vec<Worker>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let workers = crate::schema::workers::dsl::workers.load::<Worker>(&mut conn)?;
+    Ok(workers)
+}
+
+/// Return jobs assigned to the worker to the queue, returning the number of jobs released
+pub fn release_worker_jobs(conn: &mut PgConnection, worker_id: i32) -> QueryResult<usize> {
+    use crate::schema::jobs::dsl::*;
+    diesel::update(jobs.filter(assigned_worker_id.eq(worker_id)))
+        .set((status.eq("created"), assigned_worker_id.eq(None::<i32>)))
+        .execute(conn)
+}
+
+/// Clear stale assignments of workers on the host, e.g. after a crash-restart,
+/// returning the number of jobs put back to the queue
+#[tracing::instrument(skip(pool))]
+pub async fn worker_reset(pool: DbPool, worker_hostname: &str) -> anyhow::Result<usize> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let workers = crate::schema::workers::dsl::workers
+        .filter(crate::schema::workers::dsl::hostname.eq(worker_hostname))
+        .load::<Worker>(&mut conn)?;
+    if workers.is_empty() {
+        bail!("No worker found with hostname {worker_hostname}");
+    }
+
+    let mut released = 0;
+    for worker in workers {
+        let count = release_worker_jobs(&mut conn, worker.id)?;
+        if count > 0 {
+            info!(
+                "Released {count} job(s) assigned to worker {} ({})",
+                worker.id, worker.arch
+            );
+        }
+        released += count;
+    }
+    Ok(released)
+}
+
+#[derive(Serialize, Debug)]
+pub struct WorkerPackage {
+    pub name: String,
+    /// Number of successful builds on the worker
+    pub builds: usize,
+}
+
+/// Packages successfully built by workers with the hostname since the given time,
+/// most frequently built first
+#[tracing::instrument(skip(pool))]
+pub async fn worker_packages(
+    pool: DbPool,
+    worker_hostname: &str,
+    since: chrono::DateTime<chrono::Utc>,
+) -> anyhow::Result<Vec<WorkerPackage>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let worker_ids = crate::schema::workers::dsl::workers
+        .filter(crate::schema::workers::dsl::hostname.eq(worker_hostname))
+        .select(crate::schema::workers::dsl::id)
+        .load::<i32>(&mut conn)?;
+    if worker_ids.is_empty() {
+        bail!("No worker found with hostname {worker_hostname}");
+    }
+
+    use crate::schema::jobs::dsl::*;
+    let built = jobs
+        .filter(built_by_worker_id.eq_any(worker_ids))
+        .filter(finish_time.gt(since))
+        .filter(successful_packages.is_not_null())
+        .select(successful_packages)
+        .load::<Option<String>>(&mut conn)?;
+
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for pkgs in built.iter().flatten() {
+        for pkg in pkgs.split(',').filter(|pkg| !pkg.is_empty()) {
+            *counts.entry(pkg.to_string()).or_default() += 1;
+        }
+    }
+
+    let mut res: Vec<WorkerPackage> = counts
+        .into_iter()
+        .map(|(pkg, count)| WorkerPackage {
+            name: pkg,
+            builds: count,
+        })
+        .collect();
+    // stable sort keeps names in order among equal counts
+    res.sort_by_key(|pkg| std::cmp::Reverse(pkg.builds));
+    Ok(res)
+}
+
+/// Noarch jobs created before this time can be claimed by workers of any arch
+pub fn noarch_fallback_deadline() -> chrono::DateTime<chrono::Utc> {
+    let secs = ARGS
+        .noarch_fallback_secs
+        .unwrap_or(DEFAULT_NOARCH_FALLBACK_SECS);
+    chrono::Utc::now() - chrono::Duration::try_seconds(secs).unwrap_or_default()
+}
+
+fn worker_accepts_arch(worker_arch: &str, job: &Job) -> bool {
+    // noarch and optenv32 are routed to amd64, noarch falls back to any arch
+    // once it has waited long enough
+    worker_arch == job.arch
+        || (worker_arch == "amd64" && (job.arch == "noarch" || job.arch == "optenv32"))
+        || (job.arch == "noarch" && job.creation_time < noarch_fallback_deadline())
+}
+
+/// Rank in `MACHINE_CLASSES` of the explicit class, or the one derived from resources
+pub(crate) fn machine_class_rank(
+    class: Option<&str>,
+    logical_cores: i32,
+    memory_bytes: i64,
+) -> usize {
+    let class = class.unwrap_or_else(|| worker_machine_class(logical_cores, memory_bytes));
+    MACHINE_CLASSES
+        .iter()
+        .position(|c| *c == class)
+        .unwrap_or_default()
+}
+
+/// Largest machine class rank among the workers, e.g. other live workers of the arch
+pub(crate) fn max_machine_class_rank<'a>(
+    workers: impl IntoIterator<Item = &'a Worker>,
+) -> Option<usize> {
+    workers
+        .into_iter()
+        .map(|w| machine_class_rank(w.machine_class.as_deref(), w.logical_cores, w.memory_bytes))
+        .max()
+}
+
+/// Classes of jobs a worker leaves to larger live workers, jobs preferring
+/// a class no live worker has fall back to any worker
+pub(crate) fn machine_classes_left_to_others(
+    worker_rank: usize,
+    max_live_rank: Option<usize>,
+) -> &'static [&'static str] {
+    match max_live_rank {
+        Some(max_live_rank) if max_live_rank > worker_rank => {
+            &MACHINE_CLASSES[worker_rank + 1..=max_live_rank]
+        }
+        _ => &[],
+    }
+}
+
+/// Largest machine class rank among live workers of the same arch other than `worker`
+fn other_live_max_rank(worker: &Worker, live_workers: &[Worker]) -> Option<usize> {
+    max_machine_class_rank(
+        live_workers
+            .iter()
+            .filter(|w| w.arch == worker.arch && w.id != worker.id && w.visible),
+    )
+}
+
+/// Explain why the worker cannot take the job, following the filters in `worker_poll`.
+/// `max_live_rank` is the largest machine class among other live workers of the arch.
+/// An empty result means that the worker is eligible
+pub fn job_unmet_requirements(
+    job: &Job,
+    worker: &Worker,
+    max_live_rank: Option<usize>,
+) -> Vec<String> {
+    let mut res = vec![];
+
+    if !worker_accepts_arch(&worker.arch, job) {
+        res.push(format!("arch {} does not match {}", worker.arch, job.arch));
+    }
+
+    if let Some(preferred) = &job.preferred_machine_class {
+        let worker_rank = machine_class_rank(
+            worker.machine_class.as_deref(),
+            worker.logical_cores,
+            worker.memory_bytes,
+        );
+        if machine_classes_left_to_others(worker_rank, max_live_rank).contains(&preferred.as_str())
+        {
+            res.push(format!(
+                "{} machine, job left to live {preferred} workers",
+                MACHINE_CLASSES[worker_rank]
+            ));
+        }
+    }
+
+    if job.repush_only && job.built_by_worker_id != Some(worker.id) {
+        res.push("repush job can only run on the worker that built it".to_string());
+    }
+
+    if let Some(min_core) = job.require_min_core {
+        if worker.logical_cores < min_core {
+            res.push(format!(
+                "{} core(s), {} required",
+                worker.logical_cores, min_core
+            ));
+        }
+    }
+
+    if let Some(min_total_mem) = job.require_min_total_mem {
+        if worker.memory_bytes < min_total_mem {
+            res.push(format!(
+                "{} memory, {} required",
+                size::Size::from_bytes(worker.memory_bytes),
+                size::Size::from_bytes(min_total_mem)
+            ));
+        }
+    }
+
+    if let Some(min_total_mem_per_core) = job.require_min_total_mem_per_core {
+        let mem_per_core = (worker.memory_bytes as f32) / (worker.logical_cores as f32);
+        if mem_per_core < min_total_mem_per_core {
+            res.push(format!(
+                "{} memory per core, {} required",
+                size::Size::from_bytes(mem_per_core),
+                size::Size::from_bytes(min_total_mem_per_core)
+            ));
+        }
+    }
+
+    if let Some(required_tags) = &job.required_tags {
+        let worker_tags = parse_tags(worker.tags.as_deref().unwrap_or(""));
+        let missing: Vec<&str> = parse_tags(required_tags)
+            .into_iter()
+            .filter(|tag| !worker_tags.contains(tag))
+            .collect();
+        if !missing.is_empty() {
+            res.push(format!("missing tag(s) {}", missing.join(",")));
+        }
+    }
+
+    if let Some(min_disk) = job.require_min_disk {
+        if worker.disk_free_space_bytes < min_disk {
+            res.push(format!(
+                "{} free disk space, {} required",
+                size::Size::from_bytes(worker.disk_free_space_bytes),
+                size::Size::from_bytes(min_disk)
+            ));
+        }
+    }
+
+    res
+}
+
+/// Find workers that sent heartbeat recently
+fn live_workers(conn: &mut PgConnection) -> anyhow::Result<Vec<Worker>> {
+    use crate::schema::workers::dsl::*;
+    let deadline = chrono::Utc::now() - chrono::Duration::try_seconds(HEARTBEAT_TIMEOUT).unwrap();
+    Ok(workers
+        .filter(last_heartbeat_time.gt(deadline))
+        .load::<Worker>(conn)?)
+}
+
+#[derive(Serialize, Debug)]
+pub struct JobRequirements {
+    pub job_id: i32,
+    pub arch: String,
+    pub status: String,
+    pub require_min_core: Option<i32>,
+    pub require_min_total_mem: Option<i64>,
+    pub require_min_total_mem_per_core: Option<f32>,
+    pub require_min_disk: Option<i64>,
+    /// Hostnames of live workers that satisfy the requirements
+    pub satisfying_workers: Vec<String>,
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn pipeline_requirements(
+    pool: DbPool,
+    pipeline_id: i32,
+) -> anyhow::Result<Vec<JobRequirements>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let jobs = crate::schema::jobs::dsl::jobs
+        .filter(crate::schema::jobs::dsl::pipeline_id.eq(pipeline_id))
+        .order(crate::schema::jobs::dsl::id.asc())
+        .load::<Job>(&mut conn)?;
+    if jobs.is_empty() {
+        bail!("Pipeline #{pipeline_id} has no jobs");
+    }
+    let workers = live_workers(&mut conn)?;
+
+    Ok(jobs
+        .into_iter()
+        .map(|job| JobRequirements {
+            satisfying_workers: workers
+                .iter()
+                .filter(|worker| {
+                    job_unmet_requirements(&job, worker, other_live_max_rank(worker, &workers))
+                        .is_empty()
+                })
+                .map(|worker| worker.hostname.clone())
+                .collect(),
+            job_id: job.id,
+            arch: job.arch,
+            status: job.status,
+            require_min_core: job.require_min_core,
+            require_min_total_mem: job.require_min_total_mem,
+            require_min_total_mem_per_core: job.require_min_total_mem_per_core,
+            require_min_disk: job.require_min_disk,
+        })
+        .collect())
+}
+
+#[derive(Serialize, Debug)]
+pub struct WorkerEligibility {
+    pub hostname: String,
+    pub arch: String,
+    /// Empty if the worker can take the job
+    pub reasons: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct JobSchedulability {
+    pub job_id: i32,
+    pub arch: String,
+    pub status: String,
+    /// Whether any live worker can take the job
+    pub schedulable: bool,
+    pub workers: Vec<WorkerEligibility>,
+}
+
+/// Check a job against all live workers of its arch and explain why each is ineligible
+#[tracing::instrument(skip(pool))]
+pub async fn job_schedulability(pool: DbPool, job_id: i32) -> anyhow::Result<JobSchedulability> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let job = crate::schema::jobs::dsl::jobs
+        .find(job_id)
+        .get_result::<Job>(&mut conn)?;
+
+    let live = live_workers(&mut conn)?;
+    let workers: Vec<WorkerEligibility> = live
+        .iter()
+        .filter(|worker| worker_accepts_arch(&worker.arch, &job))
+        .map(|worker| WorkerEligibility {
+            reasons: job_unmet_requirements(&job, worker, other_live_max_rank(worker, &live)),
+            hostname: worker.hostname.clone(),
+            arch: worker.arch.clone(),
+        })
+        .collect();
+
+    Ok(JobSchedulability {
+        job_id: job.id,
+        schedulable: workers.iter().any(|worker| worker.reasons.is_empty()),
+        arch: job.arch,
+        status: job.status,
+        workers,
+    })
+}
+
+#[derive(Serialize, Debug)]
+pub struct PrCoverage {
+    pub pipeline_id: i32,
+    pub git_sha: String,
+    /// Latest job of each arch, sorted by arch
+    pub jobs: Vec<PipelineListResponseJob>,
+}
+
+/// Latest job of each arch in pipeline, sorted by arch
+fn latest_pipeline_jobs(conn: &mut PgConnection, pipeline_id: i32) -> anyhow::Result<Vec<Job>> {
+    let mut jobs = crate::schema::jobs::dsl::jobs
+        .filter(crate::schema::jobs::dsl::pipeline_id.eq(pipeline_id))
+        .order(crate::schema::jobs::dsl::id.desc())
+        .load::<Job>(conn)?;
+    // `dedup_by` keeps the first, i.e. latest, job of each arch
+    jobs.sort_by(|a, b| a.arch.cmp(&b.arch));
+    jobs.dedup_by(|a, b| a.arch.eq(&b.arch));
+    Ok(jobs)
+}
+
+/// Pipeline with the latest job of each arch
+#[tracing::instrument(skip(pool))]
+pub async fn pipeline_latest_jobs(
+    pool: DbPool,
+    pipeline_id: i32,
+) -> anyhow::Result<(Pipeline, Vec<Job>)> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let pipeline = crate::schema::pipelines::dsl::pipelines
+        .find(pipeline_id)
+        .first::<Pipeline>(&mut conn)
+        .optional()?
+        .with_context(|| format!("Pipeline #{pipeline_id} not found"))?;
+    let jobs = latest_pipeline_jobs(&mut conn, pipeline_id)?;
+    Ok((pipeline, jobs))
+}
+
+#[derive(Debug)]
+pub struct ArchVerification {
+    pub arch: String,
+    pub job_id: i32,
+    pub job_status: String,
+    /// Number of requested packages found in successful packages
+    pub built: usize,
+    /// Requested packages not built, with reason: failed, skipped or missing
+    pub not_built: Vec<(String, &'static str)>,
+}
+
+/// Members of group at the commit, read from git objects to leave ABBS working tree alone
+async fn read_group_at(git_sha: &str, group: &str) -> anyhow::Result<Vec<String>> {
+    let (name, slice) = GroupSlice::parse(group)?;
+    let output = tokio::process::Command::new("git")
+        .arg("show")
+        .arg(format!("{git_sha}:{name}"))
+        .current_dir(&ARGS.abbs_path)
+        .output()
+        .await
+        .context("Failed to read group")?;
+    if !output.status.success() {
+        bail!("Failed to read {name} at {git_sha}");
+    }
+    let members = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.split('/').next_back().unwrap_or(line).to_string())
+        .collect();
+    match slice {
+        Some(slice) => slice.apply(group, members),
+        None => Ok(members),
+    }
+}
+
+/// Check that each requested package of the pipeline appears in successful
+/// packages of the latest job of each arch
+#[tracing::instrument(skip(pool))]
+pub async fn pipeline_verify(
+    pool: DbPool,
+    pipeline_id: i32,
+) -> anyhow::Result<(Pipeline, Vec<ArchVerification>)> {
+    let (pipeline, jobs) = pipeline_latest_jobs(pool, pipeline_id).await?;
 
     let mut res = vec![];
-    for a in ALL_ARCH {
-        res.push(PipelineStatus {
-            arch: a.to_string(),
-            pending: *pending.get(*a).unwrap_or(&0) as u64,
-            running: *running.get(*a).unwrap_or(&0) as u64,
-            available_servers: *available_servers.get(*a).unwrap_or(&0) as u64,
+    for job in jobs {
+        // packages differ per arch when unchanged ones were skipped
+        let mut requested = vec![];
+        let excluded: Vec<&str> = job
+            .packages
+            .split(',')
+            .filter_map(|pkg| pkg.strip_prefix('!'))
+            .collect();
+        for pkg in job.packages.split(',') {
+            if pkg.starts_with('!') {
+                continue;
+            }
+            let pkg = strip_modifiers(pkg);
+            if pkg.starts_with("groups/") {
+                requested.extend(read_group_at(&pipeline.git_sha, pkg).await?);
+            } else {
+                requested.push(pkg.to_string());
+            }
+        }
+        requested.retain(|pkg| !excluded.contains(&pkg.as_str()));
+
+        let list = |s: &Option<String>| -> Vec<String> {
+            s.as_deref()
+                .unwrap_or("")
+                .split(',')
+                .filter(|pkg| !pkg.is_empty())
+                .map(|pkg| pkg.to_string())
+                .collect()
+        };
+        let successful = list(&job.successful_packages);
+        let skipped = list(&job.skipped_packages);
+
+        let mut built = 0;
+        let mut not_built = vec![];
+        for pkg in requested {
+            if successful.contains(&pkg) {
+                built += 1;
+            } else if job.failed_package.as_ref() == Some(&pkg) {
+                not_built.push((pkg, "failed"));
+            } else if skipped.contains(&pkg) {
+                not_built.push((pkg, "skipped"));
+            } else {
+                not_built.push((pkg, "missing"));
+            }
+        }
+        res.push(ArchVerification {
+            arch: job.arch,
+            job_id: job.id,
+            job_status: job.status,
+            built,
+            not_built,
         });
     }
+    Ok((pipeline, res))
+}
+
+/// Build status of each arch in the latest pipeline of PR
+#[tracing::instrument(skip(pool))]
+pub async fn pr_coverage(pool: DbPool, pr: u64) -> anyhow::Result<PrCoverage> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let pipeline = crate::schema::pipelines::dsl::pipelines
+        .filter(crate::schema::pipelines::dsl::github_pr.eq(pr as i64))
+        .order(crate::schema::pipelines::dsl::id.desc())
+        .first::<Pipeline>(&mut conn)
+        .optional()?
+        .with_context(|| format!("No pipeline found for PR #{pr}"))?;
+
+    let jobs = latest_pipeline_jobs(&mut conn, pipeline.id)?;
+
+    Ok(PrCoverage {
+        pipeline_id: pipeline.id,
+        git_sha: pipeline.git_sha,
+        jobs: jobs
+            .into_iter()
+            .map(|job| PipelineListResponseJob {
+                job_id: job.id,
+                arch: job.arch,
+                status: job.status,
+            })
+            .collect(),
+    })
+}
+
+/// Changes to spec and defines files of an open PR against stable
+#[tracing::instrument]
+pub async fn pr_spec_diff(pr: u64) -> anyhow::Result<String> {
+    let pr = octocrab::instance()
+        .pulls(github_org(), github_repo())
+        .get(pr)
+        .await
+        .with_context(|| format!("Failed to get pull request #{pr}"))?;
+    if pr.merged_at.is_some() {
+        bail!("Pull request #{} has been merged", pr.number);
+    }
+    let git_branch = pr.head.ref_field.as_str();
 
-    Ok(res)
+    // unmerged head of a fork is only available from the fork itself
+    let mut git_remote = None;
+    if pr.head.repo.as_ref().and_then(|x| x.fork).unwrap_or(false) {
+        if git_branch == "stable" {
+            bail!("Refusing to fetch branch stable of a fork");
+        }
+        git_remote = Some(
+            pr.head
+                .repo
+                .as_ref()
+                .and_then(|repo| repo.clone_url.as_ref())
+                .context("Failed to get clone url of the fork")?
+                .to_string(),
+        );
+    }
+
+    let _lock = ABBS_REPO_LOCK.lock().await;
+    update_abbs_from_remote(
+        git_remote.as_deref().unwrap_or("origin"),
+        git_branch,
+        &ARGS.abbs_path,
+        false,
+    )
+    .await
+    .context("Failed to update ABBS tree")?;
+
+    let output = tokio::process::Command::new("git")
+        .args(["diff", "stable...HEAD", "--", "*/spec", "*/defines"])
+        .current_dir(&ARGS.abbs_path)
+        .output()
+        .await
+        .context("Failed to run git diff")?;
+    if !output.status.success() {
+        bail!(
+            "Failed to diff pull request #{}: {}",
+            pr.number,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Derive pipeline status from the latest job of each arch
+pub fn pipeline_status_from_jobs(jobs: &[Job]) -> &'static str {
+    let mut has_error = false;
+    let mut has_failed = false;
+    let mut has_unfinished = false;
+    let mut has_cancelled = false;
+    for job in jobs {
+        match job.status.as_str() {
+            "error" => has_error = true,
+            "success" => {
+                // success
+            }
+            "failed" => {
+                // failed
+                has_failed = true;
+            }
+            "created" => {
+                has_unfinished = true;
+            }
+            "running" => {
+                has_unfinished = true;
+            }
+            "cancelled" => {
+                has_cancelled = true;
+            }
+            _ => {
+                error!("Got job with unknown status: {:?}", job);
+            }
+        }
+    }
+
+    if has_error {
+        "error"
+    } else if has_failed {
+        "failed"
+    } else if has_unfinished {
+        "running"
+    } else if has_cancelled {
+        "cancelled"
+    } else {
+        "success"
+    }
 }
 
+pub const USER_PIPELINES_LIMIT: i64 = 20;
+
+#[derive(Serialize, Debug)]
+pub struct UserPipeline {
+    pub pipeline_id: i32,
+    pub git_branch: String,
+    pub packages: String,
+    pub status: String,
+}
+
+/// Recent pipelines created by user, looked up by telegram chat id or github login
 #[tracing::instrument(skip(pool))]
-pub async fn worker_status(pool: DbPool) -> anyhow::Result<Vec<Worker>> {
+pub async fn user_pipelines(pool: DbPool, user: &str) -> anyhow::Result<(User, Vec<UserPipeline>)> {
     let mut conn = pool
         .get()
         .context("Failed to get db connection from pool")?;
 
-    let workers = crate::schema::workers::dsl::workers.load::<Worker>(&mut conn)?;
-    Ok(workers)
+    let mut found = None;
+    if let Ok(chat_id) = user.parse::<i64>() {
+        found = crate::schema::users::dsl::users
+            .filter(crate::schema::users::dsl::telegram_chat_id.eq(chat_id))
+            .first::<User>(&mut conn)
+            .optional()?;
+    }
+    if found.is_none() {
+        found = crate::schema::users::dsl::users
+            .filter(crate::schema::users::dsl::github_login.eq(user))
+            .first::<User>(&mut conn)
+            .optional()?;
+    }
+    let found = found.with_context(|| format!("No user found for {user}"))?;
+
+    let pipelines = crate::schema::pipelines::dsl::pipelines
+        .filter(crate::schema::pipelines::dsl::creator_user_id.eq(found.id))
+        .order(crate::schema::pipelines::dsl::id.desc())
+        .limit(USER_PIPELINES_LIMIT)
+        .load::<Pipeline>(&mut conn)?;
+
+    let mut res = vec![];
+    for pipeline in pipelines {
+        let jobs = latest_pipeline_jobs(&mut conn, pipeline.id)?;
+        res.push(UserPipeline {
+            pipeline_id: pipeline.id,
+            git_branch: pipeline.git_branch,
+            packages: pipeline.packages,
+            status: pipeline_status_from_jobs(&jobs).to_string(),
+        });
+    }
+    Ok((found, res))
+}
+
+/// Open PR once all jobs of the pipeline succeed, see `bot::handle_deferred_pr`
+#[tracing::instrument(skip(pool))]
+pub async fn pipeline_defer_pr(pool: DbPool, pipeline_id: i32, title: &str) -> anyhow::Result<()> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    diesel::update(crate::schema::pipelines::dsl::pipelines.find(pipeline_id))
+        .set(crate::schema::pipelines::dsl::deferred_pr_title.eq(title))
+        .execute(&mut conn)?;
+    Ok(())
+}
+
+/// Take the deferred PR title of pipeline once all of its jobs have finished,
+/// along with the latest job of each arch. Only the first caller gets it.
+#[tracing::instrument(skip(pool))]
+pub async fn take_deferred_pr(
+    pool: DbPool,
+    pipeline_id: i32,
+) -> anyhow::Result<Option<(Pipeline, String, Vec<Job>)>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let pipeline = crate::schema::pipelines::dsl::pipelines
+        .find(pipeline_id)
+        .get_result::<Pipeline>(&mut conn)?;
+    let Some(title) = pipeline.deferred_pr_title.clone() else {
+        return Ok(None);
+    };
+
+    let jobs = latest_pipeline_jobs(&mut conn, pipeline_id)?;
+    if jobs
+        .iter()
+        .any(|job| job.status == "created" || job.status == "running")
+    {
+        return Ok(None);
+    }
+
+    // clear it so that concurrent job updates do not handle it twice
+    let updated = diesel::update(
+        crate::schema::pipelines::dsl::pipelines
+            .find(pipeline_id)
+            .filter(crate::schema::pipelines::dsl::deferred_pr_title.is_not_null()),
+    )
+    .set(crate::schema::pipelines::dsl::deferred_pr_title.eq(None::<String>))
+    .execute(&mut conn)?;
+    if updated == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some((pipeline, title, jobs)))
+}
+
+#[derive(Serialize, Debug)]
+pub struct RunningJob {
+    pub job_id: i32,
+    pub arch: String,
+    pub packages: String,
+    pub hostname: Option<String>,
+    pub running_secs: Option<i64>,
+    /// Running for longer than `BUILDIT_STUCK_JOB_SECS`
+    pub probably_stuck: bool,
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn running_jobs(pool: DbPool) -> anyhow::Result<Vec<RunningJob>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    use crate::schema::{jobs, workers};
+    let running = jobs::dsl::jobs
+        .left_join(workers::table.on(jobs::dsl::assigned_worker_id.eq(workers::dsl::id.nullable())))
+        .filter(jobs::dsl::status.eq("running"))
+        .order(jobs::dsl::assign_time.asc())
+        .select((Job::as_select(), workers::dsl::hostname.nullable()))
+        .load::<(Job, Option<String>)>(&mut conn)?;
+
+    let stuck_secs = ARGS.stuck_job_secs.unwrap_or(DEFAULT_STUCK_JOB_SECS);
+    let now = chrono::Utc::now();
+    Ok(running
+        .into_iter()
+        .map(|(job, hostname)| {
+            let running_secs = job
+                .assign_time
+                .map(|assign_time| (now - assign_time).num_seconds());
+            RunningJob {
+                job_id: job.id,
+                arch: job.arch,
+                packages: job.packages,
+                hostname,
+                running_secs,
+                probably_stuck: running_secs.is_some_and(|secs| secs > stuck_secs),
+            }
+        })
+        .collect())
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct JobTiming {
+    /// Time between job creation and assignment to worker
+    pub queued_secs: Option<i64>,
+    /// Time between assignment to worker and job completion
+    pub build_secs: Option<i64>,
+}
+
+/// Split the lifetime of job into queued and building phases,
+/// unfinished phases are counted until now
+pub fn compute_job_timing(job: &Job) -> JobTiming {
+    let now = chrono::Utc::now();
+    match job.assign_time {
+        Some(assign_time) => JobTiming {
+            queued_secs: Some((assign_time - job.creation_time).num_seconds()),
+            build_secs: match (job.status.as_str(), job.finish_time) {
+                (_, Some(finish_time)) => Some((finish_time - assign_time).num_seconds()),
+                ("running", None) => Some((now - assign_time).num_seconds()),
+                _ => None,
+            },
+        },
+        None => JobTiming {
+            queued_secs: if job.status == "created" {
+                Some((now - job.creation_time).num_seconds())
+            } else {
+                None
+            },
+            build_secs: None,
+        },
+    }
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn job_timing(pool: DbPool, job_id: i32) -> anyhow::Result<(Job, JobTiming)> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let job = crate::schema::jobs::dsl::jobs
+        .find(job_id)
+        .get_result::<Job>(&mut conn)?;
+    let timing = compute_job_timing(&job);
+    Ok((job, timing))
 }
 
-async fn job_restart_in_transaction(job_id: i32, conn: &mut PgConnection) -> anyhow::Result<Job> {
+async fn job_restart_in_transaction(
+    job_id: i32,
+    verbose: bool,
+    conn: &mut PgConnection,
+) -> anyhow::Result<Job> {
     let job = crate::schema::jobs::dsl::jobs
         .find(job_id)
         .get_result::<Job>(conn)?;
@@ -392,9 +2535,9 @@ async fn job_restart_in_transaction(job_id: i32, conn: &mut PgConnection) -> any
         .find(job.pipeline_id)
         .get_result::<Pipeline>(conn)?;
 
-    // job must be failed
-    if job.status != "failed" {
-        bail!("Cannot restart the job unless it was failed");
+    // job must be failed, or errored e.g. due to infra outage
+    if job.status != "failed" && job.status != "error" {
+        bail!("Cannot restart the job unless it was failed or errored");
     }
 
     // create a new job
@@ -410,6 +2553,13 @@ async fn job_restart_in_transaction(job_id: i32, conn: &mut PgConnection) -> any
         require_min_total_mem: job.require_min_total_mem,
         require_min_total_mem_per_core: job.require_min_total_mem_per_core,
         require_min_disk: job.require_min_disk,
+        built_by_worker_id: None,
+        repush_only: false,
+        verbose,
+        restarted_from: Some(job.id),
+        required_tags: job.required_tags.clone(),
+        preferred_machine_class: job.preferred_machine_class.clone(),
+        priority: job.priority,
     };
 
     // create new github check run if the restarted job has one
@@ -418,7 +2568,7 @@ async fn job_restart_in_transaction(job_id: i32, conn: &mut PgConnection) -> any
         match get_crab_github_installation().await {
             Ok(Some(crab)) => {
                 match crab
-                    .checks("AOSC-Dev", "aosc-os-abbs")
+                    .checks(github_org(), github_repo())
                     .create_check_run(format!("buildit {}", job.arch), &pipeline.git_sha)
                     .status(octocrab::params::checks::CheckRunStatus::Queued)
                     .send()
@@ -448,15 +2598,56 @@ async fn job_restart_in_transaction(job_id: i32, conn: &mut PgConnection) -> any
     Ok(new_job)
 }
 
+/// Describe the commands a worker runs for the job, see `worker/src/build.rs`
+#[tracing::instrument(skip(pool))]
+pub async fn job_build_command(pool: DbPool, job_id: i32) -> anyhow::Result<String> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let (job, pipeline) = crate::schema::jobs::dsl::jobs
+        .find(job_id)
+        .inner_join(crate::schema::pipelines::dsl::pipelines)
+        .get_result::<(Job, Pipeline)>(&mut conn)?;
+
+    if job.repush_only {
+        return Ok(format!(
+            "Job #{} only runs pushpkg on the previous build output",
+            job.id
+        ));
+    }
+
+    let mut res = format!(
+        "git fetch {} {}\n",
+        pipeline.git_remote.clone().unwrap_or_else(github_repo_url),
+        pipeline.git_branch
+    );
+    res += &format!("git checkout {}\n", pipeline.git_branch);
+    res += &format!("git reset {} --hard\n", pipeline.git_sha);
+    if pipeline.patch.is_some() {
+        res += &format!("git apply --index /tmp/buildit-{}.patch\n", job.id);
+    }
+    res += "ciel update-os\n";
+    // instance and verbose options are configured on each worker
+    let ciel_args = ciel_build_args(
+        "main",
+        &job.packages,
+        job.verbose.then_some("$BUILDIT_CIEL_VERBOSE_OPTIONS"),
+        pipeline.ciel_flags.as_deref(),
+    );
+    res += &format!("ciel {}\n", ciel_args.join(" "));
+    Ok(res)
+}
+
 #[tracing::instrument(skip(pool))]
-pub async fn job_restart(pool: DbPool, job_id: i32) -> anyhow::Result<Job> {
+pub async fn job_restart(pool: DbPool, job_id: i32, verbose: bool) -> anyhow::Result<Job> {
     let mut conn = pool
         .get()
         .context("Failed to get db connection from pool")?;
 
     // manually handle transaction, since we want to use async in transaction
     PoolTransactionManager::<AnsiTransactionManager>::begin_transaction(&mut conn)?;
-    match job_restart_in_transaction(job_id, &mut conn).await {
+    match job_restart_in_transaction(job_id, verbose, &mut conn).await {
         Ok(new_job) => {
             PoolTransactionManager::<AnsiTransactionManager>::commit_transaction(&mut conn)?;
             return Ok(new_job);
@@ -474,3 +2665,295 @@ pub async fn job_restart(pool: DbPool, job_id: i32) -> anyhow::Result<Job> {
         }
     }
 }
+
+/// Restart every failed or errored job of the pipeline that has not been restarted yet
+#[tracing::instrument(skip(pool))]
+pub async fn pipeline_restart_failed(pool: DbPool, pipeline_id: i32) -> anyhow::Result<Vec<Job>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let restarted = diesel::alias!(crate::schema::jobs as restarted);
+    let job_ids = crate::schema::jobs::dsl::jobs
+        .filter(crate::schema::jobs::dsl::pipeline_id.eq(pipeline_id))
+        .filter(crate::schema::jobs::dsl::status.eq_any(["failed", "error"]))
+        .filter(diesel::dsl::not(diesel::dsl::exists(
+            restarted.filter(
+                restarted
+                    .field(crate::schema::jobs::dsl::restarted_from)
+                    .eq(crate::schema::jobs::dsl::id.nullable()),
+            ),
+        )))
+        .order_by(crate::schema::jobs::dsl::id)
+        .select(crate::schema::jobs::dsl::id)
+        .load::<i32>(&mut conn)?;
+    if job_ids.is_empty() {
+        bail!("Pipeline #{pipeline_id} has no failed jobs to restart");
+    }
+
+    // manually handle transaction, since we want to use async in transaction
+    PoolTransactionManager::<AnsiTransactionManager>::begin_transaction(&mut conn)?;
+    let mut new_jobs = vec![];
+    for job_id in job_ids {
+        match job_restart_in_transaction(job_id, false, &mut conn).await {
+            Ok(new_job) => new_jobs.push(new_job),
+            Err(err) => {
+                return match PoolTransactionManager::<AnsiTransactionManager>::rollback_transaction(
+                    &mut conn,
+                ) {
+                    Ok(()) => Err(err),
+                    Err(rollback_err) => Err(err.context(rollback_err)),
+                };
+            }
+        }
+    }
+    PoolTransactionManager::<AnsiTransactionManager>::commit_transaction(&mut conn)?;
+    Ok(new_jobs)
+}
+
+/// Restart errored jobs created since `since` that have not been restarted yet,
+/// e.g. after a worker outage. Only the latest job of each pipeline and arch is restarted.
+#[tracing::instrument(skip(pool))]
+pub async fn restart_errored_jobs(
+    pool: DbPool,
+    since: chrono::DateTime<chrono::Utc>,
+) -> anyhow::Result<Vec<Job>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let restarted = diesel::alias!(crate::schema::jobs as restarted);
+    let errored = crate::schema::jobs::dsl::jobs
+        .filter(crate::schema::jobs::dsl::status.eq("error"))
+        .filter(crate::schema::jobs::dsl::creation_time.ge(since))
+        .filter(diesel::dsl::not(diesel::dsl::exists(
+            restarted.filter(
+                restarted
+                    .field(crate::schema::jobs::dsl::restarted_from)
+                    .eq(crate::schema::jobs::dsl::id.nullable()),
+            ),
+        )))
+        .order_by(crate::schema::jobs::dsl::id.desc())
+        .select((
+            crate::schema::jobs::dsl::id,
+            crate::schema::jobs::dsl::pipeline_id,
+            crate::schema::jobs::dsl::arch,
+        ))
+        .load::<(i32, i32, String)>(&mut conn)?;
+
+    restart_jobs(&mut conn, latest_per_pipeline_arch(errored)).await
+}
+
+/// Ids of the newest of `(job_id, pipeline_id, arch)` jobs for each pipeline and arch,
+/// sorted by id. `jobs` must be ordered newest first.
+fn latest_per_pipeline_arch(jobs: Vec<(i32, i32, String)>) -> Vec<i32> {
+    let mut seen = BTreeSet::new();
+    let mut job_ids = jobs
+        .into_iter()
+        .filter(|(_, job_pipeline_id, job_arch)| seen.insert((*job_pipeline_id, job_arch.clone())))
+        .map(|(job_id, _, _)| job_id)
+        .collect::<Vec<_>>();
+    job_ids.sort();
+    job_ids
+}
+
+/// Restart jobs in one transaction, either all or none of them are restarted
+async fn restart_jobs(conn: &mut DbConn, job_ids: Vec<i32>) -> anyhow::Result<Vec<Job>> {
+    // manually handle transaction, since we want to use async in transaction
+    PoolTransactionManager::<AnsiTransactionManager>::begin_transaction(conn)?;
+    let mut new_jobs = vec![];
+    for job_id in job_ids {
+        match job_restart_in_transaction(job_id, false, conn).await {
+            Ok(new_job) => new_jobs.push(new_job),
+            Err(err) => {
+                return match PoolTransactionManager::<AnsiTransactionManager>::rollback_transaction(
+                    conn,
+                ) {
+                    Ok(()) => Err(err),
+                    Err(rollback_err) => Err(err.context(rollback_err)),
+                };
+            }
+        }
+    }
+    PoolTransactionManager::<AnsiTransactionManager>::commit_transaction(conn)?;
+    Ok(new_jobs)
+}
+
+/// Failed or errored jobs of pipelines with the campaign tag, not restarted yet
+pub async fn campaign_failed_jobs(pool: DbPool, tag: &str) -> anyhow::Result<Vec<i32>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+    campaign_failed_jobs_in_conn(&mut conn, tag)
+}
+
+fn campaign_failed_jobs_in_conn(conn: &mut PgConnection, tag: &str) -> anyhow::Result<Vec<i32>> {
+    use crate::schema::{jobs, pipelines};
+
+    let restarted = diesel::alias!(crate::schema::jobs as restarted);
+    let failed = jobs::dsl::jobs
+        .inner_join(pipelines::table)
+        .filter(pipelines::dsl::tag.eq(tag))
+        .filter(jobs::dsl::status.eq_any(["failed", "error"]))
+        .filter(diesel::dsl::not(diesel::dsl::exists(
+            restarted.filter(
+                restarted
+                    .field(jobs::dsl::restarted_from)
+                    .eq(jobs::dsl::id.nullable()),
+            ),
+        )))
+        .order_by(jobs::dsl::id.desc())
+        .select((jobs::dsl::id, jobs::dsl::pipeline_id, jobs::dsl::arch))
+        .load::<(i32, i32, String)>(conn)?;
+    Ok(latest_per_pipeline_arch(failed))
+}
+
+/// Restart failed jobs of all pipelines with the campaign tag, e.g. after fixing a
+/// common root cause of a coordinated rebuild
+#[tracing::instrument(skip(pool))]
+pub async fn retry_campaign(pool: DbPool, tag: &str) -> anyhow::Result<Vec<Job>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let job_ids = campaign_failed_jobs_in_conn(&mut conn, tag)?;
+    restart_jobs(&mut conn, job_ids).await
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn job_repush(pool: DbPool, job_id: i32) -> anyhow::Result<Job> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let job = crate::schema::jobs::dsl::jobs
+        .find(job_id)
+        .get_result::<Job>(&mut conn)?;
+    let pipeline = crate::schema::pipelines::dsl::pipelines
+        .find(job.pipeline_id)
+        .get_result::<Pipeline>(&mut conn)?;
+
+    // only packages are built, but failed to push
+    if job.status != "failed"
+        || job.build_success != Some(true)
+        || job.pushpkg_success != Some(false)
+    {
+        bail!("Cannot repush the job unless it was built successfully but failed to push");
+    }
+
+    // the build output only lives on the worker that built it
+    let built_by_worker_id = job
+        .built_by_worker_id
+        .context("Cannot repush the job since the worker is unknown")?;
+
+    // create new github check run if the job has one
+    let github_check_run_id = if job.github_check_run_id.is_some() {
+        match get_crab_github_installation().await {
+            Ok(Some(crab)) => create_check_run(crab, job.arch.clone(), pipeline.git_sha.clone())
+                .await
+                .map(|id| id as i64),
+            Ok(None) => {
+                // github app unavailable
+                None
+            }
+            Err(err) => {
+                warn!("Failed to get installation token: {}", err);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    use crate::schema::jobs;
+    let new_job = NewJob {
+        pipeline_id: job.pipeline_id,
+        packages: job.packages,
+        arch: job.arch,
+        creation_time: chrono::Utc::now(),
+        status: "created".to_string(),
+        github_check_run_id,
+        require_min_core: job.require_min_core,
+        require_min_total_mem: job.require_min_total_mem,
+        require_min_total_mem_per_core: job.require_min_total_mem_per_core,
+        require_min_disk: job.require_min_disk,
+        built_by_worker_id: Some(built_by_worker_id),
+        repush_only: true,
+        verbose: false,
+        restarted_from: Some(job.id),
+        required_tags: job.required_tags,
+        preferred_machine_class: job.preferred_machine_class,
+        priority: job.priority,
+    };
+
+    let new_job: Job = diesel::insert_into(jobs::table)
+        .values(&new_job)
+        .get_result(&mut conn)
+        .context("Failed to create job")?;
+    Ok(new_job)
+}
+
+pub const API_TOKEN_PREFIX: &str = "aoscbldit1";
+
+/// Hash of the secret part of an API token, only the hash is stored
+pub fn hash_api_token_secret(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+/// Generate a new API token of the user, the old token stops validating
+#[tracing::instrument(skip(pool))]
+pub async fn user_token_rotate(pool: DbPool, user_id: i32) -> anyhow::Result<String> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let secret: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+
+    use crate::schema::users::dsl::*;
+    let updated = diesel::update(users.find(user_id))
+        .set(token_hash.eq(hash_api_token_secret(&secret)))
+        .execute(&mut conn)?;
+    if updated == 0 {
+        bail!("User #{user_id} not found");
+    }
+    info!("Rotated API token of user #{user_id}");
+    Ok(format!("{API_TOKEN_PREFIX}_{user_id}_{secret}"))
+}
+
+/// Find the user linked to the telegram chat
+#[tracing::instrument(skip(pool))]
+pub async fn user_by_telegram(pool: DbPool, chat_id: i64) -> anyhow::Result<User> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    use crate::schema::users::dsl::*;
+    users
+        .filter(telegram_chat_id.eq(chat_id))
+        .first::<User>(&mut conn)
+        .optional()?
+        .ok_or_else(|| anyhow!("No user linked to this chat, please /login first"))
+}
+
+/// Find the user whose API token has the secret
+#[tracing::instrument(skip(pool, secret))]
+pub async fn user_by_api_token(
+    pool: DbPool,
+    user_id: i32,
+    secret: &str,
+) -> anyhow::Result<Option<User>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    use crate::schema::users::dsl::*;
+    Ok(users
+        .find(user_id)
+        .filter(token_hash.eq(hash_api_token_secret(secret)))
+        .first::<User>(&mut conn)
+        .optional()?)
+}
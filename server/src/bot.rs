@@ -1,25 +1,45 @@
 use crate::{
-    api::{job_restart, pipeline_new, pipeline_new_pr, pipeline_status, worker_status, JobSource},
-    formatter::to_html_new_pipeline_summary,
+    api::{
+        arch_reliability, arch_wait_times, campaign_failed_jobs, env_req_override_clear,
+        env_req_override_get, env_req_override_set, job_artifacts, job_assigned_worker,
+        job_build_command, job_cancel, job_repush, job_restart, job_schedulability, job_timing,
+        package_spec_variables, package_versions, pipeline_critical_path, pipeline_defer_pr,
+        pipeline_expedite, pipeline_latest_jobs, pipeline_new, pipeline_new_pr,
+        pipeline_predict_secs, pipeline_restart_failed, pipeline_status, pipeline_verify,
+        pr_coverage, pr_spec_diff, restart_errored_jobs, retry_campaign, running_jobs,
+        stale_packages, take_deferred_pr, user_by_telegram, user_pipelines, user_token_rotate,
+        worker_packages, worker_reset, worker_status, JobSource, PipelineOptions, MAX_PATCH_SIZE,
+    },
+    base_url,
+    formatter::{to_html_new_pipeline_summary, to_markdown_pipeline_report, FAILED, SUCCESS},
     github::{get_github_token, login_github},
+    github_org, github_repo, github_repo_web_url,
     models::{NewUser, User},
-    DbPool, ALL_ARCH, ARGS,
+    routes::{job_log_stream, WSStateMap},
+    DbPool, ALL_ARCH, ARGS, GIT_COMMIT, HEARTBEAT_TIMEOUT,
 };
 use anyhow::{bail, Context, Result};
-use buildit_utils::{find_update_and_update_checksum, github::OpenPRRequest};
+use buildit_utils::{
+    find_updates_and_update_checksums,
+    github::{get_archs, OpenPRRequest},
+    preview_update, FindUpdate,
+};
 use chrono::{Datelike, Days, Local};
 use diesel::{Connection, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use futures::StreamExt;
+use once_cell::sync::Lazy;
 use rand::prelude::SliceRandom;
 use rand::thread_rng;
 use reqwest::ClientBuilder;
 use serde::{Deserialize, Serialize};
 use std::{
     borrow::{Borrow, Cow},
+    collections::{BTreeMap, HashSet},
     fmt::Display,
     future::Future,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, Mutex,
     },
     time::Duration,
 };
@@ -29,7 +49,22 @@ use teloxide::{
     utils::command::BotCommands,
 };
 use tokio::time::sleep;
-use tracing::{warn, Instrument};
+use tracing::{info, warn, Instrument};
+
+/// Details of /build, too long for /help
+const BUILD_USAGE: &str = "\
+Usage: /build branch[@sha] packages archs [options]
+
+- branch@sha: pin to a commit on the branch
+- packages: comma-separated, exclude group members with !package (e.g., groups/kde,!kwin), build part of a group with groups/name[start:end] (e.g., groups/kde[0:20])
+- archs: comma-separated (e.g., amd64,arm64)
+
+Options:
+- verbose: build with verbose output
+- notify-start: get notified when each job starts
+- tags=tag1,tag2: only workers with all the tags build it
+- ciel-flags=flag1,flag2: pass extra flags to ciel build (e.g., ciel-flags=--stage2)
+- campaign=tag: group pipelines for /retrycampaign";
 
 #[derive(BotCommands, Clone, Debug)]
 #[command(
@@ -40,35 +75,141 @@ pub enum Command {
     #[command(description = "Display usage: /help")]
     Help,
     #[command(
-        description = "Start a build job: /build branch packages archs (e.g., /build stable bash,fish amd64,arm64)"
+        description = "Start a build job: /build branch[@sha] packages archs [options] (e.g., /build stable bash,fish amd64,arm64), send /build alone for options"
     )]
     Build(String),
     #[command(
-        description = "Start one or more build jobs from GitHub PR: /pr pr-numbers [archs] (e.g., /pr 12,34 amd64,arm64)"
+        description = "Start one or more build jobs from GitHub PR: /pr pr-numbers [archs] [merge] [fork] (e.g., /pr 12,34 amd64,arm64), merge builds GitHub's test merge commit, fork builds a PR from a fork (admin only), /pr 1234 arm64 rebuilds one arch of a merged PR against stable"
     )]
     PR(String),
+    #[command(
+        description = "Show which archs have finished building in the latest pipeline of GitHub PR: /prcoverage pr-number"
+    )]
+    PRCoverage(String),
+    #[command(
+        description = "Show changes to spec and defines of an open GitHub PR against stable: /diff pr-number"
+    )]
+    Diff(String),
+    #[command(
+        description = "Upload markdown report of pipeline results to pastebin: /report pipeline-id"
+    )]
+    Report(String),
+    #[command(
+        description = "Check that every requested package was built on each arch of a pipeline: /verify pipeline-id"
+    )]
+    Verify(String),
+    #[command(
+        description = "Move pending jobs of a pipeline to the front of the queue, admin only: /expedite pipeline-id"
+    )]
+    Expedite(String),
+    #[command(description = "List package files produced by a successful job: /artifacts job-id")]
+    Artifacts(String),
+    #[command(description = "Show the latest live output of a running job: /logs job-id")]
+    Logs(String),
     #[command(description = "Show queue and server status: /status")]
     Status,
     #[command(
-        description = "Open Pull Request by git-ref: /openpr title;git-ref;packages;[labels];[architectures] (e.g., /openpr VSCode Survey 1.85.0;vscode-1.85.0;vscode,vscodium;;amd64,arm64"
+        description = "Show success rate of jobs per arch over the last day and week: /reliability"
+    )]
+    Reliability,
+    #[command(
+        description = "List packages not built on arch recently: /stalearch arch [days] (e.g., /stalearch riscv64 30d)"
+    )]
+    StaleArch(String),
+    #[command(
+        description = "Show average and p95 queue wait time per arch of jobs finished recently: /waittimes [days] (e.g., /waittimes 7d)"
+    )]
+    WaitTimes(String),
+    #[command(
+        description = "Show server and live worker versions, flagging workers running a different build: /versions"
+    )]
+    Versions,
+    #[command(
+        description = "Manage workers, reset puts jobs stuck on a worker back to the queue, packages lists what it built most recently: /worker reset hostname, /worker packages hostname [days]"
+    )]
+    Worker(String),
+    #[command(description = "Show running jobs and how long they have been building: /running")]
+    Running,
+    #[command(
+        description = "Open Pull Request by git-ref: /openpr title;git-ref;packages;[labels];[architectures];[security] (e.g., /openpr VSCode Survey 1.85.0;vscode-1.85.0;vscode,vscodium;;amd64,arm64;security"
     )]
     OpenPR(String),
     #[command(description = "Login to github")]
     Login,
+    #[command(
+        description = "Show the GitHub account linked to this chat and whether its token works: /whoami"
+    )]
+    WhoAmI,
+    #[command(
+        description = "Generate an API token for the logged in user, replacing the old one: /token"
+    )]
+    Token,
     #[command(description = "Start bot")]
     Start(String),
     #[command(description = "Let dickens generate report for GitHub PR: /dickens pr-number")]
     Dickens(String),
     #[command(
-        description = "Build lagging/missing packages for quality assurance: /qa arch lagging/missing"
+        description = "Build lagging/missing packages for quality assurance, optionally skipping packages unchanged since last successful build: /qa arch lagging/missing [skip-unchanged]"
     )]
     QA(String),
-    #[command(description = "Restart failed job: /restart job-id")]
+    #[command(
+        description = "Cancel created or running jobs, requires /login: /cancel job-ids (e.g., /cancel 123,456)"
+    )]
+    Cancel(String),
+    #[command(description = "Restart failed job: /restart job-id [verbose]")]
     Restart(String),
-    #[command(description = "Find update and bump package version: /bump package-name")]
+    #[command(
+        description = "Restart all failed jobs of a pipeline, successful ones are kept, requires /login: /restartpipeline pipeline-id"
+    )]
+    RestartPipeline(String),
+    #[command(
+        description = "Restart errored jobs across pipelines, e.g. after a worker outage, requires /login: /retryerrored [time-window] (e.g., /retryerrored 2h, defaults to 24h)"
+    )]
+    RetryErrored(String),
+    #[command(
+        description = "Restart failed jobs of all pipelines built with campaign=tag, requires /login, confirm restarting many jobs: /retrycampaign tag [confirm]"
+    )]
+    RetryCampaign(String),
+    #[command(
+        description = "Re-run pushpkg of job that failed to push, requires /login: /repush job-id"
+    )]
+    Repush(String),
+    #[command(
+        description = "Find update and bump package version, with --pr-on-success the PR is opened only after building on all archs: /bump package-names [version] [--pr-on-success] [--dry-run], comma-separated packages are bumped in one PR, with --dry-run the new version and spec diff are shown without pushing"
+    )]
     Bump(String),
     #[command(description = "Roll anicca 10 packages")]
     Roll,
+    #[command(description = "Show time a job spent queued and building: /timing job-id")]
+    Timing(String),
+    #[command(description = "Show commands a worker runs to build a job: /buildcmd job-id")]
+    BuildCmd(String),
+    #[command(description = "Explain why a job is not picked up by any worker: /whystuck job-id")]
+    WhyStuck(String),
+    #[command(
+        description = "Estimate which packages dominate the build time of a pipeline: /criticalpath pipeline-id"
+    )]
+    CriticalPath(String),
+    #[command(
+        description = "Show spec variables of a package as parsed by buildit: /spec package-name"
+    )]
+    Spec(String),
+    #[command(
+        description = "Show or override environment requirements of a package, taking precedence over ENVREQ in spec: /envreq show|set|clear package-name [requirements] (e.g., /envreq set llvm core=32 mem=64)"
+    )]
+    EnvReq(String),
+    #[command(
+        description = "Build with a git patch applied on top of branch: /patch branch packages archs https-patch-url"
+    )]
+    Patch(String),
+    #[command(
+        description = "List recent pipelines created by user (admin only): /userpipelines github-login/telegram-chat-id"
+    )]
+    UserPipelines(String),
+    #[command(
+        description = "Show versions and archs of packages in stable: /packages package1,package2"
+    )]
+    Packages(String),
 }
 
 async fn wait_with_send_typing<T, F: Future<Output = T>, B: Borrow<Bot>>(
@@ -101,6 +242,29 @@ async fn wait_with_send_typing<T, F: Future<Output = T>, B: Borrow<Bot>>(
     res
 }
 
+/// Packages being bumped, to reject concurrent /bump of the same package early
+static BUMPS_IN_PROGRESS: Lazy<Mutex<HashSet<String>>> = Lazy::new(Default::default);
+
+/// Marks a package as being bumped until dropped
+struct BumpGuard(String);
+
+impl BumpGuard {
+    /// Returns `None` if a bump of the package is already in progress
+    fn new(pkg: &str) -> Option<Self> {
+        BUMPS_IN_PROGRESS
+            .lock()
+            .unwrap()
+            .insert(pkg.to_string())
+            .then(|| BumpGuard(pkg.to_string()))
+    }
+}
+
+impl Drop for BumpGuard {
+    fn drop(&mut self) {
+        BUMPS_IN_PROGRESS.lock().unwrap().remove(&self.0);
+    }
+}
+
 fn handle_archs_args(archs: Vec<&str>) -> Vec<&str> {
     let mut archs = archs;
     if archs.contains(&"mainline") {
@@ -119,12 +283,17 @@ async fn status(pool: DbPool) -> anyhow::Result<String> {
     let mut res = String::from("__*Queue Status*__\n\n");
 
     for status in pipeline_status(pool.clone()).await? {
+        let wait = match status.estimated_wait_secs {
+            Some(secs) => format!(", \\~{}h{:02}m wait", secs / 3600, secs % 3600 / 60),
+            None => String::new(),
+        };
         res += &format!(
-            "*{}*: {} job\\(s\\) pending, {} job\\(s\\) running, {} available server\\(s\\)\n",
+            "*{}*: {} job\\(s\\) pending, {} job\\(s\\) running, {} available server\\(s\\){}\n",
             teloxide::utils::markdown::escape(&status.arch),
             status.pending,
             status.running,
-            status.available_servers
+            status.available_servers,
+            wait
         );
     }
 
@@ -144,6 +313,520 @@ async fn status(pool: DbPool) -> anyhow::Result<String> {
     Ok(res)
 }
 
+#[tracing::instrument(skip(pool))]
+async fn versions(pool: DbPool) -> anyhow::Result<String> {
+    let mut res = format!("Server: {} ({})\n\n", env!("CARGO_PKG_VERSION"), GIT_COMMIT);
+
+    let deadline = chrono::Utc::now() - chrono::Duration::try_seconds(HEARTBEAT_TIMEOUT).unwrap();
+    let mut outdated = 0;
+    for worker in worker_status(pool).await? {
+        if !worker.visible || worker.last_heartbeat_time < deadline {
+            continue;
+        }
+        if worker.git_commit == GIT_COMMIT {
+            res += &format!(
+                "{} ({}): {}\n",
+                worker.hostname, worker.arch, worker.git_commit
+            );
+        } else {
+            outdated += 1;
+            res += &format!(
+                "⚠️ {} ({}): {}\n",
+                worker.hostname, worker.arch, worker.git_commit
+            );
+        }
+    }
+
+    if outdated > 0 {
+        res += &format!("\n{outdated} live worker(s) running a different build than the server\n");
+    }
+    Ok(res)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn running(pool: DbPool) -> anyhow::Result<String> {
+    let jobs = running_jobs(pool).await?;
+    if jobs.is_empty() {
+        return Ok(String::from("No jobs are running"));
+    }
+
+    let mut res = String::from("__*Running Jobs*__\n\n");
+    for job in jobs {
+        let duration = match job.running_secs {
+            Some(secs) => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+            None => String::from("N/A"),
+        };
+        let line = teloxide::utils::markdown::escape(&format!(
+            "#{} {} on {}: {} for {}",
+            job.job_id,
+            job.arch,
+            job.hostname.as_deref().unwrap_or("unknown worker"),
+            job.packages,
+            duration
+        ));
+        if job.probably_stuck {
+            res += &format!("*{}* \\(probably stuck\\)\n", line);
+        } else {
+            res += &format!("{}\n", line);
+        }
+    }
+    Ok(res)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn reliability(pool: DbPool) -> anyhow::Result<String> {
+    let mut res = String::new();
+    for (window, days) in [("Last day", 1), ("Last week", 7)] {
+        res += &format!("{window}:\n");
+        let since = chrono::Utc::now() - Days::new(days);
+        let stats = arch_reliability(pool.clone(), since).await?;
+        if stats.is_empty() {
+            res += "No finished jobs\n";
+        }
+        for stat in stats {
+            res += &format!(
+                "{}: {:.1}% ({}/{})\n",
+                stat.arch,
+                stat.success as f64 * 100.0 / stat.total as f64,
+                stat.success,
+                stat.total
+            );
+        }
+        res += "\n";
+    }
+    Ok(res)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn wait_times(pool: DbPool, arguments: &str) -> anyhow::Result<String> {
+    let days = match arguments.trim() {
+        "" => 7,
+        days => days
+            .trim_end_matches('d')
+            .parse::<u64>()
+            .context("Invalid number of days")?,
+    };
+
+    let since = chrono::Utc::now() - Days::new(days);
+    let stats = arch_wait_times(pool, since).await?;
+    if stats.is_empty() {
+        return Ok(format!("No finished jobs in {days} days"));
+    }
+
+    let mut res = format!("Queue wait time of jobs finished in {days} days:\n");
+    for stat in stats {
+        res += &format!(
+            "{}: avg {}s, p95 {}s ({} jobs)\n",
+            stat.arch, stat.avg_secs, stat.p95_secs, stat.jobs
+        );
+    }
+    Ok(res)
+}
+
+const STALE_PACKAGES_LIMIT: usize = 50;
+
+#[tracing::instrument(skip(pool))]
+async fn stale_arch(pool: DbPool, arguments: &str) -> anyhow::Result<String> {
+    let mut parts = arguments.split_whitespace();
+    let arch = parts.next().context("Please specify an architecture")?;
+    let days = match parts.next() {
+        Some(days) => days
+            .trim_end_matches('d')
+            .parse::<u64>()
+            .context("Invalid number of days")?,
+        None => 30,
+    };
+
+    let pkgs = stale_packages(pool, arch, days).await?;
+    if pkgs.is_empty() {
+        return Ok(format!("All packages are built on {arch} in {days} days"));
+    }
+
+    let mut res = format!(
+        "{} package(s) not built on {arch} in {days} days:\n",
+        pkgs.len()
+    );
+    for pkg in pkgs.iter().take(STALE_PACKAGES_LIMIT) {
+        match pkg.last_built {
+            Some(time) => res += &format!("{}: {}\n", pkg.name, time.format("%Y-%m-%d")),
+            None => res += &format!("{}: never\n", pkg.name),
+        }
+    }
+    if pkgs.len() > STALE_PACKAGES_LIMIT {
+        res += "...\n";
+    }
+
+    let names: Vec<&str> = pkgs
+        .iter()
+        .take(STALE_PACKAGES_LIMIT)
+        .map(|pkg| pkg.name.as_str())
+        .collect();
+    res += &format!("\nTo build them: /build stable {} {arch}", names.join(","));
+    Ok(res)
+}
+
+const WORKER_PACKAGES_LIMIT: usize = 30;
+
+#[tracing::instrument(skip(pool))]
+async fn worker_packages_report(
+    pool: DbPool,
+    hostname: &str,
+    days: Option<&str>,
+) -> anyhow::Result<String> {
+    let days = match days {
+        Some(days) => days
+            .trim_end_matches('d')
+            .parse::<u64>()
+            .context("Invalid number of days")?,
+        None => 30,
+    };
+    let since = chrono::Utc::now() - Days::new(days);
+
+    let pkgs = worker_packages(pool, hostname, since).await?;
+    if pkgs.is_empty() {
+        return Ok(format!("{hostname} built no packages in {days} days"));
+    }
+
+    let mut res = format!(
+        "{} package(s) built by {hostname} in {days} days, most frequent first:\n",
+        pkgs.len()
+    );
+    for pkg in pkgs.iter().take(WORKER_PACKAGES_LIMIT) {
+        res += &format!("{}: {}\n", pkg.name, pkg.builds);
+    }
+    if pkgs.len() > WORKER_PACKAGES_LIMIT {
+        res += "...\n";
+    }
+    Ok(res)
+}
+
+const CRITICAL_PATH_TOP_PACKAGES: usize = 5;
+
+#[tracing::instrument(skip(pool))]
+async fn critical_path(pool: DbPool, pipeline_id: i32) -> anyhow::Result<String> {
+    let paths = pipeline_critical_path(pool, pipeline_id).await?;
+    let mut res = String::new();
+    if let Some(path) = paths.first() {
+        res += &format!(
+            "Critical path of pipeline #{pipeline_id}: {} (job #{}), estimated {}s\n",
+            path.arch, path.job_id, path.total_secs
+        );
+        let mut pkgs: Vec<_> = path
+            .packages
+            .iter()
+            .filter(|pkg| pkg.secs.is_some())
+            .collect();
+        pkgs.sort_by_key(|pkg| std::cmp::Reverse(pkg.secs));
+        for pkg in pkgs.iter().take(CRITICAL_PATH_TOP_PACKAGES) {
+            let secs = pkg.secs.unwrap_or_default();
+            res += &format!(
+                "{}: {}s ({:.1}%)\n",
+                pkg.name,
+                secs,
+                secs as f64 * 100.0 / path.total_secs.max(1) as f64
+            );
+        }
+    }
+
+    res += "\nEstimated time per arch:\n";
+    for path in &paths {
+        let unknown: Vec<&str> = path
+            .packages
+            .iter()
+            .filter(|pkg| pkg.secs.is_none())
+            .map(|pkg| pkg.name.as_str())
+            .collect();
+        res += &format!("{}: {}s", path.arch, path.total_secs);
+        if !unknown.is_empty() {
+            res += &format!(" (never built: {})", unknown.join(", "));
+        }
+        res += "\n";
+    }
+    Ok(res)
+}
+
+#[tracing::instrument]
+async fn spec(package: &str) -> anyhow::Result<String> {
+    let package = package.trim();
+    if package.is_empty() {
+        bail!("Please specify a package");
+    }
+
+    let mut res = String::new();
+    for file in package_spec_variables(package).await? {
+        res += &format!(
+            "{} ({}):\n",
+            file.path,
+            if file.fallback {
+                "fallback parser"
+            } else {
+                "apml"
+            }
+        );
+        if file.variables.is_empty() {
+            res += "No related variables\n";
+        }
+        for (name, value) in file.variables {
+            res += &format!("{name}={value}\n");
+        }
+        res += "\n";
+    }
+    Ok(res)
+}
+
+async fn packages(arguments: &str) -> anyhow::Result<String> {
+    let packages: Vec<String> = arguments
+        .split(',')
+        .map(|pkg| pkg.trim().to_string())
+        .filter(|pkg| !pkg.is_empty())
+        .collect();
+    if packages.is_empty() {
+        bail!("Please specify packages");
+    }
+
+    let mut res = String::new();
+    for package in package_versions(&packages).await? {
+        res += &format!("{}: {}\n", package.package, package.archs.join(", "));
+        for (name, version) in package.versions {
+            res += &format!("- {name}: {version}\n");
+        }
+    }
+    Ok(res)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn env_req_override_report(pool: DbPool, package: &str) -> anyhow::Result<String> {
+    Ok(match env_req_override_get(pool, package).await? {
+        Some(o) => format!(
+            "Environment requirements of {package} are overridden with: {}\nLast updated: {}",
+            o.envreq, o.update_time
+        ),
+        None => format!(
+            "No environment requirement override of {package}, ENVREQ in spec is used (see /spec {package})"
+        ),
+    })
+}
+
+#[tracing::instrument(skip(pool))]
+async fn pr_coverage_report(pool: DbPool, pr: u64) -> anyhow::Result<String> {
+    let coverage = pr_coverage(pool, pr).await?;
+    let finished = coverage
+        .jobs
+        .iter()
+        .filter(|job| job.status == "success" || job.status == "failed")
+        .count();
+
+    let mut res = format!(
+        "Pipeline #{} of PR #{pr} ({}): {finished}/{} arch(es) finished\n",
+        coverage.pipeline_id,
+        &coverage.git_sha[..8],
+        coverage.jobs.len()
+    );
+    for job in &coverage.jobs {
+        let icon = match job.status.as_str() {
+            "success" => SUCCESS,
+            "failed" | "error" => FAILED,
+            "cancelled" => "🚫",
+            _ => "⏳",
+        };
+        res += &format!(
+            "{icon} {}: {} (job #{})\n",
+            job.arch, job.status, job.job_id
+        );
+    }
+    Ok(res)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn verify_report(pool: DbPool, pipeline_id: i32) -> anyhow::Result<String> {
+    let (pipeline, archs) = pipeline_verify(pool, pipeline_id).await?;
+
+    let unfinished = archs
+        .iter()
+        .filter(|arch| arch.job_status == "created" || arch.job_status == "running")
+        .count();
+    let incomplete: Vec<_> = archs
+        .iter()
+        .filter(|arch| !arch.not_built.is_empty())
+        .collect();
+    if incomplete.is_empty() {
+        return Ok(format!(
+            "{SUCCESS} All requested packages of pipeline #{pipeline_id} ({}) were built on {} arch(es)",
+            pipeline.packages,
+            archs.len()
+        ));
+    }
+
+    let mut res = format!(
+        "{FAILED} Requested packages of pipeline #{pipeline_id} not built on {}/{} arch(es):\n",
+        incomplete.len(),
+        archs.len()
+    );
+    if unfinished > 0 {
+        res += &format!("{unfinished} job(s) not finished yet\n");
+    }
+    for arch in incomplete {
+        res += &format!(
+            "\n{} (job #{}, {}): {} built\n",
+            arch.arch, arch.job_id, arch.job_status, arch.built
+        );
+        for (pkg, reason) in &arch.not_built {
+            res += &format!("- {pkg}: {reason}\n");
+        }
+    }
+    Ok(res)
+}
+
+const LOGS_TAIL_LINES: usize = 30;
+
+/// `/retrycampaign` asks for confirmation before restarting more jobs than this
+const RETRY_CAMPAIGN_CONFIRM_JOBS: usize = 20;
+
+#[tracing::instrument(skip(pool, ws_state_map))]
+async fn logs_report(
+    pool: DbPool,
+    ws_state_map: WSStateMap,
+    job_id: i32,
+) -> anyhow::Result<String> {
+    let (job, worker) = job_assigned_worker(pool, job_id).await?;
+    let worker = match worker {
+        Some(worker) if job.status == "running" => worker,
+        _ => {
+            return Ok(format!(
+                "Job #{job_id} is {}, not running, see {}/jobs/{job_id} for its log",
+                job.status,
+                base_url()
+            ))
+        }
+    };
+
+    // several build slots of the worker stream separately
+    let stream = job_log_stream(&ws_state_map, job_id).unwrap_or(worker.hostname.clone());
+    let mut lines = ws_state_map
+        .lock()
+        .unwrap()
+        .get(&stream)
+        .map(|state| state.last_log_lines(LOGS_TAIL_LINES))
+        .unwrap_or_default();
+    if lines.is_empty() {
+        return Ok(format!(
+            "No live output of job #{job_id} from {} yet, try again later",
+            worker.hostname
+        ));
+    }
+
+    // drop oldest lines so the latest ones survive truncation
+    let header = format!("Latest output of job #{job_id} on {}:\n", worker.hostname);
+    while lines.len() > 1
+        && header.chars().count() + lines.iter().map(|l| l.chars().count() + 1).sum::<usize>()
+            > 1000
+    {
+        lines.remove(0);
+    }
+    Ok(header + &lines.join("\n"))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn artifacts_report(pool: DbPool, job_id: i32) -> anyhow::Result<String> {
+    let (job, pipeline, artifacts) = job_artifacts(pool, job_id).await?;
+    let Some(artifacts) = artifacts else {
+        return Ok(format!(
+            "Artifacts of job #{job_id} are not available, the job predates artifact tracking"
+        ));
+    };
+
+    let mut res = format!(
+        "{} artifact(s) of job #{job_id} ({}):\n",
+        artifacts.len(),
+        job.arch
+    );
+    for artifact in artifacts {
+        res += &format!(
+            "{} ({})",
+            artifact.path,
+            size::Size::from_bytes(artifact.size)
+        );
+        if job.pushpkg_success == Some(true) {
+            res += &format!(
+                " https://repo.aosc.io/debs/pool/{}/main/{}",
+                pipeline.git_branch, artifact.path
+            );
+        }
+        res += "\n";
+    }
+    Ok(res)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn pipeline_report(pool: DbPool, pipeline_id: i32) -> anyhow::Result<String> {
+    let (pipeline, jobs) = pipeline_latest_jobs(pool, pipeline_id).await?;
+    let report = to_markdown_pipeline_report(&github_repo_web_url(), &pipeline, &jobs);
+    let id = paste_to_aosc_io(&format!("Report for pipeline #{pipeline_id}"), &report).await?;
+    Ok(format!(
+        "Report of pipeline #{pipeline_id} has been uploaded to https://aosc.io/paste/detail?id={id}"
+    ))
+}
+
+/// Longest message telegram accepts
+const TELEGRAM_MESSAGE_LIMIT: usize = 4096;
+
+#[tracing::instrument]
+async fn pr_diff_report(pr: u64) -> anyhow::Result<String> {
+    let diff = pr_spec_diff(pr).await?;
+    if diff.is_empty() {
+        return Ok(format!(
+            "Pull request #{pr} does not change any spec or defines"
+        ));
+    }
+    if diff.chars().count() <= TELEGRAM_MESSAGE_LIMIT {
+        return Ok(diff);
+    }
+    match paste_to_aosc_io(&format!("Spec changes of PR #{pr}"), &diff).await {
+        Ok(id) => Ok(format!(
+            "Spec changes of pull request #{pr} have been uploaded to https://aosc.io/paste/detail?id={id}"
+        )),
+        Err(err) => {
+            warn!("Failed to upload diff to aosc.io pastebin: {err:?}");
+            Ok(console::truncate_str(&diff, TELEGRAM_MESSAGE_LIMIT, "...").into_owned())
+        }
+    }
+}
+
+fn is_admin(chat_id: ChatId) -> bool {
+    ARGS.admin_chat_ids.as_ref().is_some_and(|ids| {
+        ids.split(',')
+            .any(|id| id.trim().parse::<i64>() == Ok(chat_id.0))
+    })
+}
+
+#[tracing::instrument(skip(pool))]
+async fn user_pipelines_report(pool: DbPool, user: &str) -> anyhow::Result<String> {
+    let user = user.trim();
+    if user.is_empty() {
+        bail!("Please specify a GitHub login or Telegram chat id");
+    }
+
+    let (user, pipelines) = user_pipelines(pool, user).await?;
+    let mut res = format!(
+        "Recent pipelines created by {} (telegram chat id: {}):\n",
+        user.github_login.as_deref().unwrap_or("unknown"),
+        user.telegram_chat_id
+            .map(|id| id.to_string())
+            .as_deref()
+            .unwrap_or("unknown")
+    );
+    if pipelines.is_empty() {
+        res += "No pipelines found\n";
+    }
+    for pipeline in pipelines {
+        res += &format!(
+            "#{} {}: {} ({})\n",
+            pipeline.pipeline_id, pipeline.status, pipeline.packages, pipeline.git_branch
+        );
+    }
+    Ok(res)
+}
+
 #[derive(Deserialize)]
 pub struct QAResponsePackage {
     name: String,
@@ -159,20 +842,21 @@ async fn pipeline_new_and_report(
     bot: &Bot,
     pool: DbPool,
     git_branch: &str,
+    git_sha: Option<&str>,
     packages: &str,
     archs: &str,
+    options: PipelineOptions<'_>,
     msg: &Message,
 ) -> ResponseResult<()> {
     match wait_with_send_typing(
         pipeline_new(
-            pool,
+            pool.clone(),
             git_branch,
-            None,
-            None,
+            git_sha,
             packages,
             archs,
             JobSource::Telegram(msg.chat.id.0),
-            false,
+            options,
         ),
         bot,
         msg.chat.id.0,
@@ -180,16 +864,16 @@ async fn pipeline_new_and_report(
     .await
     {
         Ok(pipeline) => {
+            let estimated_secs =
+                pipeline_predict_secs(pool, &pipeline)
+                    .await
+                    .unwrap_or_else(|err| {
+                        warn!("Failed to predict build time: {err:?}");
+                        BTreeMap::new()
+                    });
             bot.send_message(
                 msg.chat.id,
-                to_html_new_pipeline_summary(
-                    pipeline.id,
-                    &pipeline.git_branch,
-                    &pipeline.git_sha,
-                    pipeline.github_pr.map(|n| n as u64),
-                    &pipeline.archs.split(',').collect::<Vec<_>>(),
-                    &pipeline.packages.split(',').collect::<Vec<_>>(),
-                ),
+                to_html_new_pipeline_summary(&github_repo_web_url(), &pipeline, &estimated_secs),
             )
             .parse_mode(ParseMode::Html)
             .disable_web_page_preview(true)
@@ -204,6 +888,140 @@ async fn pipeline_new_and_report(
     Ok(())
 }
 
+/// Build the bump branch on all archs, the PR is opened by `handle_deferred_pr`
+async fn bump_build_and_defer_pr(
+    bot: &Bot,
+    pool: DbPool,
+    f: FindUpdate,
+    msg: &Message,
+) -> ResponseResult<()> {
+    let packages: Vec<String> = f.package.split(',').map(|pkg| pkg.to_string()).collect();
+    let archs = get_archs(&ARGS.abbs_path, &packages).join(",");
+
+    let res = async {
+        let pipeline = pipeline_new(
+            pool.clone(),
+            &f.branch,
+            None,
+            &f.package,
+            &archs,
+            JobSource::Telegram(msg.chat.id.0),
+            PipelineOptions::default(),
+        )
+        .await?;
+        pipeline_defer_pr(pool, pipeline.id, &f.title).await?;
+        anyhow::Ok(pipeline)
+    };
+
+    match wait_with_send_typing(res, bot, msg.chat.id.0).await {
+        Ok(pipeline) => {
+            bot.send_message(
+                msg.chat.id,
+                format!(
+                    "Pipeline #{} is building {} on {}, the PR will be opened once all archs succeed",
+                    pipeline.id, f.branch, pipeline.archs
+                ),
+            )
+            .await?;
+        }
+        Err(err) => {
+            bot.send_message(
+                msg.chat.id,
+                truncate(&format!("Failed to create pipeline: {err:?}")),
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Open the PR deferred by `/bump --pr-on-success` once its pipeline has finished,
+/// or report the failed archs instead
+#[tracing::instrument(skip(bot, pool))]
+pub async fn handle_deferred_pr(bot: &Bot, pool: DbPool, pipeline_id: i32) -> anyhow::Result<()> {
+    let Some((pipeline, title, jobs)) = take_deferred_pr(pool, pipeline_id).await? else {
+        return Ok(());
+    };
+    let chat_id = ChatId(
+        pipeline
+            .telegram_user
+            .context("Deferred PR without telegram user")?,
+    );
+
+    let failed: Vec<String> = jobs
+        .iter()
+        .filter(|job| job.status != "success")
+        .map(|job| format!("{} ({})", job.arch, job.status))
+        .collect();
+    if !failed.is_empty() {
+        bot.send_message(
+            chat_id,
+            format!(
+                "Pipeline #{} of {} did not succeed on {}, not opening PR",
+                pipeline.id,
+                pipeline.git_branch,
+                failed.join(", ")
+            ),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let res = async {
+        let secret = ARGS
+            .github_secret
+            .as_ref()
+            .context("GITHUB_SECRET is not set")?;
+        let app_private_key = ARGS
+            .github_app_key
+            .as_ref()
+            .context("GITHUB_APP_KEY is not set")?;
+        let id = ARGS
+            .github_app_id
+            .as_ref()
+            .and_then(|x| x.parse::<u64>().ok())
+            .context("GITHUB_APP_ID is not set")?;
+        let token = get_github_token(&chat_id, secret).await?.access_token;
+        buildit_utils::github::open_pr(
+            app_private_key,
+            &token,
+            id,
+            OpenPRRequest {
+                git_ref: pipeline.git_branch.clone(),
+                abbs_path: ARGS.abbs_path.clone(),
+                packages: pipeline.packages.clone(),
+                title,
+                tags: None,
+                archs: None,
+                security: false,
+                pr_template: ARGS.pr_template.clone(),
+                github_org: github_org().to_string(),
+                github_repo: github_repo().to_string(),
+            },
+        )
+        .await
+    };
+
+    match res.await {
+        Ok((_, url)) => {
+            bot.send_message(
+                chat_id,
+                format!(
+                    "Pipeline #{} succeeded on all archs, opened PR: {url}",
+                    pipeline.id
+                ),
+            )
+            .await?;
+        }
+        Err(err) => {
+            bot.send_message(chat_id, truncate(&format!("Failed to open PR: {err:?}")))
+                .await?;
+        }
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct GitHubUser {
     pub login: String,
@@ -275,6 +1093,44 @@ async fn sync_github_info(pool: DbPool, telegram_chat_id: ChatId, access_token:
     }
 }
 
+/// GitHub OAuth page, redirects back to the bot with a /start code
+const LOGIN_URL: &str = "https://github.com/login/oauth/authorize?client_id=Iv1.bf26f3e9dd7883ae&redirect_uri=https://minzhengbu.aosc.io/login";
+
+/// Describe the GitHub identity linked to the telegram chat
+#[tracing::instrument(skip(pool))]
+async fn whoami(pool: DbPool, chat_id: ChatId) -> anyhow::Result<String> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let user = {
+        use crate::schema::users::dsl::*;
+        users
+            .filter(telegram_chat_id.eq(chat_id.0))
+            .first::<User>(&mut conn)
+            .optional()?
+    };
+    let Some(user) = user else {
+        return Ok(format!(
+            "This chat is not linked to a GitHub account, please login: {LOGIN_URL}"
+        ));
+    };
+
+    let token = match ARGS.github_secret.as_deref() {
+        Some(secret) => match get_github_token(&chat_id, secret).await {
+            Ok(_) => "valid".to_string(),
+            Err(err) => format!("unavailable ({err}), please login again: {LOGIN_URL}"),
+        },
+        None => "unknown, GITHUB_SECRET is not set".to_string(),
+    };
+
+    Ok(format!(
+        "GitHub login: {}\nGitHub name: {}\nGitHub token: {token}",
+        user.github_login.as_deref().unwrap_or("unknown"),
+        user.github_name.as_deref().unwrap_or("unknown"),
+    ))
+}
+
 #[tracing::instrument(skip(pool, access_token))]
 async fn get_user(pool: DbPool, chat_id: ChatId, access_token: String) -> anyhow::Result<User> {
     let mut conn = pool
@@ -305,31 +1161,99 @@ async fn get_user(pool: DbPool, chat_id: ChatId, access_token: String) -> anyhow
     bail!("Failed to get user info")
 }
 
+/// Parse time window like `30m`, `2h` or `1d`, defaults to a day if empty
+fn parse_time_window(window: &str) -> Option<chrono::Duration> {
+    if window.is_empty() {
+        return Some(chrono::Duration::days(1));
+    }
+    let unit = window.chars().last()?;
+    let count = window[..window.len() - unit.len_utf8()]
+        .parse::<i64>()
+        .ok()
+        .filter(|count| *count > 0)?;
+    match unit {
+        'm' => chrono::Duration::try_minutes(count),
+        'h' => chrono::Duration::try_hours(count),
+        'd' => chrono::Duration::try_days(count),
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct PrArguments<'a> {
+    pr_numbers: Vec<u64>,
+    /// Restrict the build, e.g. rebuild only the failed arch of a merged PR
+    archs: Option<&'a str>,
+    merge: bool,
+    fork: bool,
+}
+
+/// Parse `pr-numbers [archs] [merge] [fork]`
+fn parse_pr_arguments(arguments: &str) -> Option<PrArguments<'_>> {
+    let mut parts = arguments.split_ascii_whitespace().collect::<Vec<_>>();
+    let mut merge = false;
+    let mut fork = false;
+    while parts.len() > 1 {
+        match parts.last() {
+            Some(&"merge") => merge = true,
+            Some(&"fork") => fork = true,
+            _ => break,
+        }
+        parts.pop();
+    }
+    let (pr_numbers, archs) = match parts.as_slice() {
+        [pr_numbers] => (pr_numbers, None),
+        [pr_numbers, archs] => (pr_numbers, Some(*archs)),
+        _ => return None,
+    };
+    let pr_numbers = pr_numbers
+        .split(',')
+        .map(str::parse::<u64>)
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    Some(PrArguments {
+        pr_numbers,
+        archs,
+        merge,
+        fork,
+    })
+}
+
 async fn create_pipeline_from_pr(
     pool: DbPool,
     pr_number: u64,
     archs: Option<&str>,
+    merge: bool,
+    allow_fork: bool,
     msg: &Message,
     bot: &Bot,
 ) -> ResponseResult<()> {
     match wait_with_send_typing(
-        pipeline_new_pr(pool, pr_number, archs, JobSource::Telegram(msg.chat.id.0)),
+        pipeline_new_pr(
+            pool.clone(),
+            pr_number,
+            archs,
+            merge,
+            allow_fork,
+            JobSource::Telegram(msg.chat.id.0),
+            None,
+        ),
         bot,
         msg.chat.id.0,
     )
     .await
     {
         Ok(pipeline) => {
+            let estimated_secs =
+                pipeline_predict_secs(pool, &pipeline)
+                    .await
+                    .unwrap_or_else(|err| {
+                        warn!("Failed to predict build time: {err:?}");
+                        BTreeMap::new()
+                    });
             bot.send_message(
                 msg.chat.id,
-                to_html_new_pipeline_summary(
-                    pipeline.id,
-                    &pipeline.git_branch,
-                    &pipeline.git_sha,
-                    pipeline.github_pr.map(|n| n as u64),
-                    &pipeline.archs.split(',').collect::<Vec<_>>(),
-                    &pipeline.packages.split(',').collect::<Vec<_>>(),
-                ),
+                to_html_new_pipeline_summary(&github_repo_web_url(), &pipeline, &estimated_secs),
             )
             .parse_mode(ParseMode::Html)
             .disable_web_page_preview(true)
@@ -349,89 +1273,438 @@ async fn create_pipeline_from_pr(
     Ok(())
 }
 
-#[tracing::instrument(skip(bot, msg, pool))]
-pub async fn answer(bot: Bot, msg: Message, cmd: Command, pool: DbPool) -> ResponseResult<()> {
+#[tracing::instrument(skip(bot, msg, pool, ws_state_map))]
+pub async fn answer(
+    bot: Bot,
+    msg: Message,
+    cmd: Command,
+    pool: DbPool,
+    ws_state_map: WSStateMap,
+) -> ResponseResult<()> {
     match cmd {
         Command::Help => {
             bot.send_message(msg.chat.id, Command::descriptions().to_string())
                 .await?;
         }
-        Command::PR(arguments) => {
-            let parts = arguments.split_ascii_whitespace().collect::<Vec<_>>();
-            if !(1..=2).contains(&parts.len()) {
+        Command::PR(arguments) => match parse_pr_arguments(&arguments) {
+            Some(args) if args.fork && !is_admin(msg.chat.id) => {
+                bot.send_message(
+                    msg.chat.id,
+                    "Only admins can build pull requests from forks",
+                )
+                .await?;
+            }
+            Some(args) => {
+                for pr_number in args.pr_numbers {
+                    create_pipeline_from_pr(
+                        pool.clone(),
+                        pr_number,
+                        args.archs,
+                        args.merge,
+                        args.fork,
+                        &msg,
+                        &bot,
+                    )
+                    .await?;
+                }
+            }
+            None => {
+                bot.send_message(
+                    msg.chat.id,
+                    format!(
+                        "Got invalid pr description: {arguments}.\n\n{}",
+                        Command::descriptions()
+                    ),
+                )
+                .await?;
+            }
+        },
+        Command::Build(arguments) => {
+            let parts: Vec<&str> = arguments.split(' ').collect();
+            // optional trailing `verbose`, `notify-start`, `tags=tag1,tag2`, `ciel-flags=flag1,flag2`
+            // and `campaign=tag`
+            let mut verbose = false;
+            let mut notify_start = false;
+            let mut required_tags = None;
+            let mut ciel_flags = None;
+            let mut tag = None;
+            let options_valid = parts.len() >= 3
+                && parts[3..].iter().all(|part| {
+                    if *part == "verbose" {
+                        verbose = true;
+                    } else if *part == "notify-start" {
+                        notify_start = true;
+                    } else if let Some(tags) = part.strip_prefix("tags=") {
+                        required_tags = Some(tags);
+                    } else if let Some(flags) = part.strip_prefix("ciel-flags=") {
+                        ciel_flags = Some(flags.replace(',', " "));
+                    } else if let Some(campaign) = part.strip_prefix("campaign=") {
+                        tag = Some(campaign);
+                    } else {
+                        return false;
+                    }
+                    true
+                });
+            if options_valid {
+                // pin to a commit on the branch with branch@sha
+                let (git_branch, git_sha) = match parts[0].split_once('@') {
+                    Some((git_branch, git_sha)) => (git_branch, Some(git_sha)),
+                    None => (parts[0], None),
+                };
+                let packages = parts[1];
+                let archs = parts[2];
+
+                pipeline_new_and_report(
+                    &bot,
+                    pool,
+                    git_branch,
+                    git_sha,
+                    packages,
+                    archs,
+                    PipelineOptions {
+                        required_tags,
+                        verbose,
+                        notify_start,
+                        ciel_flags: ciel_flags.as_deref(),
+                        tag,
+                        ..Default::default()
+                    },
+                    &msg,
+                )
+                .await?;
+
+                return Ok(());
+            }
+
+            let res = if arguments.trim().is_empty() {
+                BUILD_USAGE.to_string()
+            } else {
+                format!("Got invalid job description: {arguments}. \n\n{BUILD_USAGE}")
+            };
+            bot.send_message(msg.chat.id, res).await?;
+        }
+        Command::Diff(arguments) => match str::parse::<u64>(arguments.trim()) {
+            Ok(pr) => match wait_with_send_typing(pr_diff_report(pr), &bot, msg.chat.id.0).await {
+                Ok(res) => {
+                    bot.send_message(msg.chat.id, res)
+                        .disable_web_page_preview(true)
+                        .await?;
+                }
+                Err(err) => {
+                    bot.send_message(
+                        msg.chat.id,
+                        truncate(&format!("Failed to get PR diff: {err:?}")),
+                    )
+                    .await?;
+                }
+            },
+            Err(err) => {
+                bot.send_message(msg.chat.id, truncate(&format!("Bad PR number: {err:?}")))
+                    .await?;
+            }
+        },
+        Command::PRCoverage(arguments) => match str::parse::<u64>(arguments.trim()) {
+            Ok(pr) => {
+                match wait_with_send_typing(pr_coverage_report(pool, pr), &bot, msg.chat.id.0).await
+                {
+                    Ok(res) => {
+                        bot.send_message(msg.chat.id, truncate(&res)).await?;
+                    }
+                    Err(err) => {
+                        bot.send_message(
+                            msg.chat.id,
+                            truncate(&format!("Failed to get PR build coverage: {err:?}")),
+                        )
+                        .await?;
+                    }
+                }
+            }
+            Err(err) => {
+                bot.send_message(msg.chat.id, truncate(&format!("Bad PR number: {err:?}")))
+                    .await?;
+            }
+        },
+        Command::Verify(arguments) => match str::parse::<i32>(arguments.trim()) {
+            Ok(pipeline_id) => {
+                match wait_with_send_typing(verify_report(pool, pipeline_id), &bot, msg.chat.id.0)
+                    .await
+                {
+                    Ok(res) => {
+                        bot.send_message(msg.chat.id, truncate(&res)).await?;
+                    }
+                    Err(err) => {
+                        bot.send_message(
+                            msg.chat.id,
+                            truncate(&format!("Failed to verify pipeline: {err:?}")),
+                        )
+                        .await?;
+                    }
+                }
+            }
+            Err(err) => {
+                bot.send_message(msg.chat.id, truncate(&format!("Bad pipeline ID: {err:?}")))
+                    .await?;
+            }
+        },
+        Command::Artifacts(arguments) => match str::parse::<i32>(arguments.trim()) {
+            Ok(job_id) => {
+                match wait_with_send_typing(artifacts_report(pool, job_id), &bot, msg.chat.id.0)
+                    .await
+                {
+                    Ok(res) => {
+                        bot.send_message(msg.chat.id, truncate(&res))
+                            .disable_web_page_preview(true)
+                            .await?;
+                    }
+                    Err(err) => {
+                        bot.send_message(
+                            msg.chat.id,
+                            truncate(&format!("Failed to get artifacts: {err:?}")),
+                        )
+                        .await?;
+                    }
+                }
+            }
+            Err(err) => {
+                bot.send_message(msg.chat.id, truncate(&format!("Bad job ID: {err:?}")))
+                    .await?;
+            }
+        },
+        Command::Expedite(arguments) => {
+            if !is_admin(msg.chat.id) {
+                bot.send_message(msg.chat.id, "Only admins can expedite pipelines")
+                    .await?;
+                return Ok(());
+            }
+
+            match str::parse::<i32>(arguments.trim()) {
+                Ok(pipeline_id) => {
+                    match wait_with_send_typing(
+                        pipeline_expedite(pool, pipeline_id),
+                        &bot,
+                        msg.chat.id.0,
+                    )
+                    .await
+                    {
+                        Ok(expedited) => {
+                            info!(
+                                "Chat {} ({}) expedited pipeline #{pipeline_id}: job(s) {:?} raised to priority {}",
+                                msg.chat.id,
+                                msg.chat.username().unwrap_or("unknown"),
+                                expedited.job_ids,
+                                expedited.priority
+                            );
+                            bot.send_message(
+                                msg.chat.id,
+                                format!(
+                                    "Moved {} job(s) of pipeline #{pipeline_id} to the front of the queue, ahead of {} other pending job(s)",
+                                    expedited.job_ids.len(),
+                                    expedited.overtaken
+                                ),
+                            )
+                            .await?;
+                        }
+                        Err(err) => {
+                            bot.send_message(
+                                msg.chat.id,
+                                truncate(&format!("Failed to expedite pipeline: {err:?}")),
+                            )
+                            .await?;
+                        }
+                    }
+                }
+                Err(err) => {
+                    bot.send_message(msg.chat.id, truncate(&format!("Bad pipeline ID: {err:?}")))
+                        .await?;
+                }
+            }
+        }
+        Command::Logs(arguments) => match str::parse::<i32>(arguments.trim()) {
+            Ok(job_id) => {
+                match wait_with_send_typing(
+                    logs_report(pool, ws_state_map, job_id),
+                    &bot,
+                    msg.chat.id.0,
+                )
+                .await
+                {
+                    Ok(res) => {
+                        bot.send_message(msg.chat.id, truncate(&res)).await?;
+                    }
+                    Err(err) => {
+                        bot.send_message(
+                            msg.chat.id,
+                            truncate(&format!("Failed to get logs: {err:?}")),
+                        )
+                        .await?;
+                    }
+                }
+            }
+            Err(err) => {
+                bot.send_message(msg.chat.id, truncate(&format!("Bad job ID: {err:?}")))
+                    .await?;
+            }
+        },
+        Command::Report(arguments) => match str::parse::<i32>(arguments.trim()) {
+            Ok(pipeline_id) => {
+                match wait_with_send_typing(pipeline_report(pool, pipeline_id), &bot, msg.chat.id.0)
+                    .await
+                {
+                    Ok(res) => {
+                        bot.send_message(msg.chat.id, res).await?;
+                    }
+                    Err(err) => {
+                        bot.send_message(
+                            msg.chat.id,
+                            truncate(&format!("Failed to generate report: {err:?}")),
+                        )
+                        .await?;
+                    }
+                }
+            }
+            Err(err) => {
+                bot.send_message(msg.chat.id, truncate(&format!("Bad pipeline ID: {err:?}")))
+                    .await?;
+            }
+        },
+        Command::Status => match wait_with_send_typing(status(pool), &bot, msg.chat.id.0).await {
+            Ok(status) => {
+                bot.send_message(msg.chat.id, status)
+                    .parse_mode(ParseMode::MarkdownV2)
+                    .await?;
+            }
+            Err(err) => {
                 bot.send_message(
                     msg.chat.id,
-                    format!(
-                        "Got invalid job description: {arguments}. \n\n{}",
-                        Command::descriptions()
-                    ),
+                    truncate(&format!("Failed to get status: {:?}", err)),
                 )
                 .await?;
-                return Ok(());
             }
-
-            let mut pr_numbers = vec![];
-            let mut parse_success = true;
-            for part in parts[0].split(',') {
-                if let Ok(pr_number) = str::parse::<u64>(part) {
-                    pr_numbers.push(pr_number);
-                } else {
-                    parse_success = false;
-
+        },
+        Command::Reliability => {
+            match wait_with_send_typing(reliability(pool), &bot, msg.chat.id.0).await {
+                Ok(res) => {
+                    bot.send_message(msg.chat.id, truncate(&res)).await?;
+                }
+                Err(err) => {
                     bot.send_message(
                         msg.chat.id,
-                        format!(
-                            "Got invalid pr description: {arguments}.\n\n{}",
-                            Command::descriptions()
-                        ),
+                        truncate(&format!("Failed to get reliability: {:?}", err)),
                     )
                     .await?;
-                    break;
                 }
             }
-
-            if parse_success {
-                let archs = if parts.len() == 1 {
-                    None
-                } else {
-                    Some(parts[1])
-                };
-                for pr_number in pr_numbers {
-                    create_pipeline_from_pr(pool.clone(), pr_number, archs, &msg, &bot).await?;
+        }
+        Command::StaleArch(arguments) => {
+            match wait_with_send_typing(stale_arch(pool, &arguments), &bot, msg.chat.id.0).await {
+                Ok(res) => {
+                    bot.send_message(msg.chat.id, res).await?;
+                }
+                Err(err) => {
+                    bot.send_message(
+                        msg.chat.id,
+                        truncate(&format!("Failed to list stale packages: {:?}", err)),
+                    )
+                    .await?;
                 }
             }
         }
-        Command::Build(arguments) => {
-            let parts: Vec<&str> = arguments.split(' ').collect();
-            if parts.len() == 3 {
-                let git_branch = parts[0];
-                let packages = parts[1];
-                let archs = parts[2];
-
-                pipeline_new_and_report(&bot, pool, git_branch, packages, archs, &msg).await?;
-
+        Command::WaitTimes(arguments) => {
+            match wait_with_send_typing(wait_times(pool, &arguments), &bot, msg.chat.id.0).await {
+                Ok(res) => {
+                    bot.send_message(msg.chat.id, res).await?;
+                }
+                Err(err) => {
+                    bot.send_message(
+                        msg.chat.id,
+                        truncate(&format!("Failed to get wait times: {:?}", err)),
+                    )
+                    .await?;
+                }
+            }
+        }
+        Command::Versions => {
+            match wait_with_send_typing(versions(pool), &bot, msg.chat.id.0).await {
+                Ok(res) => {
+                    bot.send_message(msg.chat.id, truncate(&res)).await?;
+                }
+                Err(err) => {
+                    bot.send_message(
+                        msg.chat.id,
+                        truncate(&format!("Failed to get versions: {:?}", err)),
+                    )
+                    .await?;
+                }
+            }
+        }
+        Command::Worker(arguments) => {
+            let parts: Vec<&str> = arguments.split_whitespace().collect();
+            if let ["reset", hostname] = parts.as_slice() {
+                match wait_with_send_typing(worker_reset(pool, hostname), &bot, msg.chat.id.0).await
+                {
+                    Ok(count) => {
+                        bot.send_message(
+                            msg.chat.id,
+                            format!("Released {count} job(s) assigned to {hostname}"),
+                        )
+                        .await?;
+                    }
+                    Err(err) => {
+                        bot.send_message(
+                            msg.chat.id,
+                            truncate(&format!("Failed to reset worker: {err:?}")),
+                        )
+                        .await?;
+                    }
+                }
+                return Ok(());
+            }
+            if let ["packages", hostname, days @ ..] = parts.as_slice() {
+                if days.len() > 1 {
+                    bot.send_message(msg.chat.id, "Usage: /worker packages hostname [days]")
+                        .await?;
+                    return Ok(());
+                }
+                match wait_with_send_typing(
+                    worker_packages_report(pool, hostname, days.first().copied()),
+                    &bot,
+                    msg.chat.id.0,
+                )
+                .await
+                {
+                    Ok(res) => {
+                        bot.send_message(msg.chat.id, truncate(&res)).await?;
+                    }
+                    Err(err) => {
+                        bot.send_message(
+                            msg.chat.id,
+                            truncate(&format!("Failed to get worker packages: {err:?}")),
+                        )
+                        .await?;
+                    }
+                }
                 return Ok(());
             }
 
             bot.send_message(
                 msg.chat.id,
                 format!(
-                    "Got invalid job description: {arguments}. \n\n{}",
+                    "Got invalid worker command: {arguments}. \n\n{}",
                     Command::descriptions()
                 ),
             )
             .await?;
         }
-        Command::Status => match wait_with_send_typing(status(pool), &bot, msg.chat.id.0).await {
-            Ok(status) => {
-                bot.send_message(msg.chat.id, status)
+        Command::Running => match wait_with_send_typing(running(pool), &bot, msg.chat.id.0).await {
+            Ok(running) => {
+                bot.send_message(msg.chat.id, running)
                     .parse_mode(ParseMode::MarkdownV2)
                     .await?;
             }
             Err(err) => {
                 bot.send_message(
                     msg.chat.id,
-                    truncate(&format!("Failed to get status: {:?}", err)),
+                    truncate(&format!("Failed to get running jobs: {:?}", err)),
                 )
                 .await?;
             }
@@ -474,7 +1747,7 @@ pub async fn answer(bot: Bot, msg: Message, cmd: Command, pool: DbPool) -> Respo
             // sync github info, but do not wait for result
             tokio::spawn(sync_github_info(pool, msg.chat.id, token.clone()));
 
-            if (3..=5).contains(&parts.len()) {
+            if (3..=6).contains(&parts.len()) {
                 let tags = if parts.len() >= 4 {
                     if parts[3].is_empty() {
                         None
@@ -490,7 +1763,7 @@ pub async fn answer(bot: Bot, msg: Message, cmd: Command, pool: DbPool) -> Respo
                     None
                 };
 
-                let archs = if parts.len() == 5 {
+                let archs = if parts.len() >= 5 && !parts[4].is_empty() {
                     let archs = parts[4].split(',').collect::<Vec<_>>();
                     Some(handle_archs_args(archs))
                 } else {
@@ -532,6 +1805,10 @@ pub async fn answer(bot: Bot, msg: Message, cmd: Command, pool: DbPool) -> Respo
                             title: parts[0].to_string(),
                             tags: tags.clone(),
                             archs: archs.clone(),
+                            security: parts.get(5) == Some(&"security"),
+                            pr_template: ARGS.pr_template.clone(),
+                            github_org: github_org().to_string(),
+                            github_repo: github_repo().to_string(),
                         },
                     ),
                     &bot,
@@ -562,7 +1839,49 @@ pub async fn answer(bot: Bot, msg: Message, cmd: Command, pool: DbPool) -> Respo
             .await?;
         }
         Command::Login => {
-            bot.send_message(msg.chat.id, "https://github.com/login/oauth/authorize?client_id=Iv1.bf26f3e9dd7883ae&redirect_uri=https://minzhengbu.aosc.io/login").await?;
+            bot.send_message(msg.chat.id, LOGIN_URL).await?;
+        }
+        Command::WhoAmI => {
+            match wait_with_send_typing(whoami(pool, msg.chat.id), &bot, msg.chat.id.0).await {
+                Ok(res) => {
+                    bot.send_message(msg.chat.id, res).await?;
+                }
+                Err(err) => {
+                    bot.send_message(
+                        msg.chat.id,
+                        truncate(&format!("Failed to get linked GitHub account: {err:?}")),
+                    )
+                    .await?;
+                }
+            }
+        }
+        Command::Token => {
+            if !msg.chat.is_private() {
+                bot.send_message(msg.chat.id, "Please use /token in a private chat")
+                    .await?;
+                return Ok(());
+            }
+
+            let res = async {
+                let user = user_by_telegram(pool.clone(), msg.chat.id.0).await?;
+                user_token_rotate(pool, user.id).await
+            };
+            match wait_with_send_typing(res, &bot, msg.chat.id.0).await {
+                Ok(token) => {
+                    bot.send_message(
+                        msg.chat.id,
+                        format!("Your new API token, the old one no longer works. It will not be shown again:\n\n{token}"),
+                    )
+                    .await?;
+                }
+                Err(err) => {
+                    bot.send_message(
+                        msg.chat.id,
+                        truncate(&format!("Failed to generate API token: {err:?}")),
+                    )
+                    .await?;
+                }
+            }
         }
         Command::Start(arguments) => {
             if arguments.len() != 20 {
@@ -605,7 +1924,7 @@ pub async fn answer(bot: Bot, msg: Message, cmd: Command, pool: DbPool) -> Respo
 
                 // get topic of pr
                 match wait_with_send_typing(
-                    crab.pulls("AOSC-Dev", "aosc-os-abbs").get(pr_number),
+                    crab.pulls(github_org(), github_repo()).get(pr_number),
                     &bot,
                     msg.chat.id.0,
                 )
@@ -640,7 +1959,7 @@ pub async fn answer(bot: Bot, msg: Message, cmd: Command, pool: DbPool) -> Respo
                             };
                             // post report as github comment
                             match wait_with_send_typing(
-                                crab.issues("AOSC-Dev", "aosc-os-abbs")
+                                crab.issues(github_org(), github_repo())
                                     .create_comment(pr_number, report),
                                 &bot,
                                 msg.chat.id.0,
@@ -692,12 +2011,13 @@ pub async fn answer(bot: Bot, msg: Message, cmd: Command, pool: DbPool) -> Respo
         },
         Command::QA(arguments) => {
             let parts: Vec<&str> = arguments.split(' ').collect();
-            if parts.len() == 2
+            if (parts.len() == 2 || (parts.len() == 3 && parts[2] == "skip-unchanged"))
                 && ALL_ARCH.contains(&parts[0])
                 && ["lagging", "missing"].contains(&parts[1])
             {
                 let arch = parts[0];
                 let ty = parts[1];
+                let skip_unchanged = parts.len() == 3;
                 let client = reqwest::Client::new();
 
                 match wait_with_send_typing(
@@ -719,8 +2039,13 @@ pub async fn answer(bot: Bot, msg: Message, cmd: Command, pool: DbPool) -> Respo
                                     &bot,
                                     pool.clone(),
                                     "stable",
+                                    None,
                                     &pkg.name,
                                     arch,
+                                    PipelineOptions {
+                                        skip_unchanged,
+                                        ..Default::default()
+                                    },
                                     &msg,
                                 )
                                 .await?;
@@ -754,31 +2079,349 @@ pub async fn answer(bot: Bot, msg: Message, cmd: Command, pool: DbPool) -> Respo
             )
             .await?;
         }
-        Command::Restart(arguments) => match str::parse::<i32>(&arguments) {
+        Command::Cancel(arguments) => {
+            match arguments
+                .split(',')
+                .map(|job_id| str::parse::<i32>(job_id.trim()))
+                .collect::<Result<Vec<_>, _>>()
+            {
+                Ok(job_ids) => {
+                    let res = async {
+                        // only known users may cancel builds of others
+                        let user = user_by_telegram(pool.clone(), msg.chat.id.0).await?;
+                        let (cancelled, skipped) = job_cancel(pool, &job_ids).await?;
+                        info!(
+                            "User #{} cancelled job(s) {:?}",
+                            user.id,
+                            cancelled.iter().map(|job| job.id).collect::<Vec<_>>()
+                        );
+                        anyhow::Ok((cancelled, skipped))
+                    };
+                    match wait_with_send_typing(res, &bot, msg.chat.id.0).await {
+                        Ok((cancelled, skipped)) => {
+                            let mut res = format!("Cancelled {} job(s)", cancelled.len());
+                            if !skipped.is_empty() {
+                                res += &format!(
+                                    ", skipped {} job(s) not created or running: {}",
+                                    skipped.len(),
+                                    skipped
+                                        .iter()
+                                        .map(|job_id| format!("#{job_id}"))
+                                        .collect::<Vec<_>>()
+                                        .join(", ")
+                                );
+                            }
+                            bot.send_message(msg.chat.id, truncate(&res)).await?;
+                        }
+                        Err(err) => {
+                            bot.send_message(
+                                msg.chat.id,
+                                truncate(&format!("Failed to cancel jobs: {err:?}")),
+                            )
+                            .await?;
+                        }
+                    }
+                }
+                Err(err) => {
+                    bot.send_message(msg.chat.id, truncate(&format!("Bad job ID: {err:?}")))
+                        .await?;
+                }
+            }
+        }
+        Command::Restart(arguments) => {
+            let (job_id, verbose) = match arguments.split_once(' ') {
+                Some((job_id, "verbose")) => (job_id, true),
+                _ => (arguments.as_str(), false),
+            };
+            match str::parse::<i32>(job_id) {
+                Ok(job_id) => {
+                    match wait_with_send_typing(
+                        job_restart(pool, job_id, verbose),
+                        &bot,
+                        msg.chat.id.0,
+                    )
+                    .await
+                    {
+                        Ok(new_job) => {
+                            bot.send_message(
+                                msg.chat.id,
+                                truncate(&format!("Restarted as job #{}", new_job.id)),
+                            )
+                            .await?;
+                        }
+                        Err(err) => {
+                            bot.send_message(
+                                msg.chat.id,
+                                truncate(&format!("Failed to restart job: {err:?}")),
+                            )
+                            .await?;
+                        }
+                    }
+                }
+                Err(err) => {
+                    bot.send_message(msg.chat.id, truncate(&format!("Bad job ID: {err:?}")))
+                        .await?;
+                }
+            }
+        }
+        Command::RestartPipeline(arguments) => match str::parse::<i32>(arguments.trim()) {
+            Ok(pipeline_id) => {
+                let res = async {
+                    // only known users may rebuild pipelines of others
+                    let user = user_by_telegram(pool.clone(), msg.chat.id.0).await?;
+                    let new_jobs = pipeline_restart_failed(pool, pipeline_id).await?;
+                    info!(
+                        "User #{} restarted {} failed job(s) of pipeline #{pipeline_id}",
+                        user.id,
+                        new_jobs.len()
+                    );
+                    anyhow::Ok(new_jobs)
+                };
+                match wait_with_send_typing(res, &bot, msg.chat.id.0).await {
+                    Ok(new_jobs) => {
+                        bot.send_message(
+                            msg.chat.id,
+                            truncate(&format!(
+                                "Restarted {} job(s): {}",
+                                new_jobs.len(),
+                                new_jobs
+                                    .iter()
+                                    .map(|job| format!("#{} ({})", job.id, job.arch))
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            )),
+                        )
+                        .await?;
+                    }
+                    Err(err) => {
+                        bot.send_message(
+                            msg.chat.id,
+                            truncate(&format!("Failed to restart pipeline: {err:?}")),
+                        )
+                        .await?;
+                    }
+                }
+            }
+            Err(err) => {
+                bot.send_message(msg.chat.id, truncate(&format!("Bad pipeline ID: {err:?}")))
+                    .await?;
+            }
+        },
+        Command::RetryErrored(arguments) => match parse_time_window(arguments.trim()) {
+            Some(window) => {
+                let res = async {
+                    // guard mass rebuilds against random users
+                    let user = user_by_telegram(pool.clone(), msg.chat.id.0).await?;
+                    let new_jobs = restart_errored_jobs(pool, chrono::Utc::now() - window).await?;
+                    info!(
+                        "User #{} restarted {} errored job(s) of the last {}",
+                        user.id,
+                        new_jobs.len(),
+                        arguments.trim()
+                    );
+                    anyhow::Ok(new_jobs)
+                };
+                match wait_with_send_typing(res, &bot, msg.chat.id.0).await {
+                    Ok(new_jobs) => {
+                        bot.send_message(
+                            msg.chat.id,
+                            truncate(&format!(
+                                "Restarted {} errored job(s): {}",
+                                new_jobs.len(),
+                                new_jobs
+                                    .iter()
+                                    .map(|job| format!("#{} ({})", job.id, job.arch))
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            )),
+                        )
+                        .await?;
+                    }
+                    Err(err) => {
+                        bot.send_message(
+                            msg.chat.id,
+                            truncate(&format!("Failed to restart errored jobs: {err:?}")),
+                        )
+                        .await?;
+                    }
+                }
+            }
+            None => {
+                bot.send_message(
+                    msg.chat.id,
+                    format!("Bad time window: {arguments}, expecting e.g. 30m, 2h or 1d"),
+                )
+                .await?;
+            }
+        },
+        Command::RetryCampaign(arguments) => {
+            let parts: Vec<&str> = arguments.split_whitespace().collect();
+            match parts.as_slice() {
+                [tag] | [tag, "confirm"] => {
+                    let confirmed = parts.len() == 2;
+                    let res = async {
+                        // guard mass rebuilds against random users
+                        let user = user_by_telegram(pool.clone(), msg.chat.id.0).await?;
+                        let job_ids = campaign_failed_jobs(pool.clone(), tag).await?;
+                        if job_ids.len() > RETRY_CAMPAIGN_CONFIRM_JOBS && !confirmed {
+                            return anyhow::Ok(Err(job_ids.len()));
+                        }
+                        let new_jobs = retry_campaign(pool, tag).await?;
+                        info!(
+                            "User #{} restarted {} failed job(s) of campaign {tag}",
+                            user.id,
+                            new_jobs.len()
+                        );
+                        anyhow::Ok(Ok(new_jobs))
+                    };
+                    match wait_with_send_typing(res, &bot, msg.chat.id.0).await {
+                        Ok(Ok(new_jobs)) => {
+                            bot.send_message(
+                                msg.chat.id,
+                                truncate(&format!(
+                                    "Restarted {} failed job(s) of campaign {tag}: {}",
+                                    new_jobs.len(),
+                                    new_jobs
+                                        .iter()
+                                        .map(|job| format!("#{} ({})", job.id, job.arch))
+                                        .collect::<Vec<_>>()
+                                        .join(", ")
+                                )),
+                            )
+                            .await?;
+                        }
+                        Ok(Err(count)) => {
+                            bot.send_message(
+                                msg.chat.id,
+                                format!(
+                                    "Campaign {tag} has {count} failed job(s), run /retrycampaign {tag} confirm to restart all of them"
+                                ),
+                            )
+                            .await?;
+                        }
+                        Err(err) => {
+                            bot.send_message(
+                                msg.chat.id,
+                                truncate(&format!("Failed to retry campaign: {err:?}")),
+                            )
+                            .await?;
+                        }
+                    }
+                }
+                _ => {
+                    bot.send_message(
+                        msg.chat.id,
+                        format!(
+                            "Bad arguments: {arguments}, expecting /retrycampaign tag [confirm]"
+                        ),
+                    )
+                    .await?;
+                }
+            }
+        }
+        Command::Repush(arguments) => match str::parse::<i32>(&arguments) {
             Ok(job_id) => {
-                match wait_with_send_typing(job_restart(pool, job_id), &bot, msg.chat.id.0).await {
+                let res = async {
+                    // pushing to the repo is reserved to known users
+                    let user = user_by_telegram(pool.clone(), msg.chat.id.0).await?;
+                    let new_job = job_repush(pool, job_id).await?;
+                    info!(
+                        "User #{} repushed job #{job_id} as job #{}",
+                        user.id, new_job.id
+                    );
+                    anyhow::Ok(new_job)
+                };
+                match wait_with_send_typing(res, &bot, msg.chat.id.0).await {
                     Ok(new_job) => {
                         bot.send_message(
                             msg.chat.id,
-                            truncate(&format!("Restarted as job #{}", new_job.id)),
+                            truncate(&format!("Repushing as job #{}", new_job.id)),
                         )
                         .await?;
                     }
                     Err(err) => {
                         bot.send_message(
                             msg.chat.id,
-                            truncate(&format!("Failed to restart job: {err:?}")),
+                            truncate(&format!("Failed to repush job: {err:?}")),
+                        )
+                        .await?;
+                    }
+                }
+            }
+            Err(err) => {
+                bot.send_message(msg.chat.id, truncate(&format!("Bad job ID: {err:?}")))
+                    .await?;
+            }
+        },
+        Command::Bump(package_and_version) => {
+            let mut split_args: Vec<&str> = package_and_version.split_ascii_whitespace().collect();
+            let pr_on_success = split_args.contains(&"--pr-on-success");
+            let dry_run = split_args.contains(&"--dry-run");
+            split_args.retain(|arg| *arg != "--pr-on-success" && *arg != "--dry-run");
+            let mut split_args = split_args.into_iter();
+            let pkg = split_args.next().context("Failed to parse argument");
+            let version = split_args.next();
+
+            let pkgs: Vec<&str> = match pkg {
+                Ok(pkg) => pkg.split(',').filter(|pkg| !pkg.is_empty()).collect(),
+                Err(e) => {
+                    bot.send_message(msg.chat.id, e.to_string()).await?;
+                    return Ok(());
+                }
+            };
+            if pkgs.is_empty() || (pkgs.len() > 1 && version.is_some()) {
+                bot.send_message(
+                    msg.chat.id,
+                    "Please specify one package with version, or packages separated by comma",
+                )
+                .await?;
+                return Ok(());
+            }
+
+            let mut _guards = vec![];
+            for pkg in &pkgs {
+                match BumpGuard::new(pkg) {
+                    Some(guard) => _guards.push(guard),
+                    None => {
+                        bot.send_message(
+                            msg.chat.id,
+                            format!("A bump for {pkg} is already in progress"),
                         )
                         .await?;
+                        return Ok(());
                     }
                 }
             }
-            Err(err) => {
-                bot.send_message(msg.chat.id, truncate(&format!("Bad job ID: {err:?}")))
-                    .await?;
+
+            if dry_run {
+                // nothing is pushed, no need to log in to GitHub
+                for pkg in &pkgs {
+                    let text = match wait_with_send_typing(
+                        preview_update(
+                            pkg,
+                            &ARGS.abbs_path,
+                            version,
+                            &ARGS
+                                .acbs_scratch_dir
+                                .clone()
+                                .unwrap_or_else(std::env::temp_dir),
+                        ),
+                        &bot,
+                        msg.chat.id.0,
+                    )
+                    .await
+                    {
+                        Ok(preview) => format!(
+                            "{}: {} -> {} (dry run, nothing pushed)\n\n{}",
+                            preview.package, preview.old_version, preview.new_version, preview.diff
+                        ),
+                        Err(e) => format!("Failed to find update of {pkg}: {:?}", e),
+                    };
+                    bot.send_message(msg.chat.id, truncate(&text)).await?;
+                }
+                return Ok(());
             }
-        },
-        Command::Bump(package_and_version) => {
+
             let app_private_key = match ARGS.github_app_key.as_ref() {
                 Some(p) => p,
                 None => {
@@ -855,26 +2498,54 @@ pub async fn answer(bot: Bot, msg: Message, cmd: Command, pool: DbPool) -> Respo
             }
             let coauthor = coauthor_parts.join(" ");
 
-            let mut split_args = package_and_version.split_ascii_whitespace();
-            let pkg = split_args.next().context("Failed to parse argument");
-            let version = split_args.next();
-
-            let pkg = match pkg {
-                Ok(pkg) => pkg,
-                Err(e) => {
-                    bot.send_message(msg.chat.id, e.to_string()).await?;
-                    return Ok(());
-                }
-            };
-
-            match wait_with_send_typing(
-                find_update_and_update_checksum(pkg, &ARGS.abbs_path, &coauthor, version),
+            let update = match wait_with_send_typing(
+                find_updates_and_update_checksums(
+                    &pkgs,
+                    &ARGS.abbs_path,
+                    &coauthor,
+                    version,
+                    &ARGS
+                        .acbs_scratch_dir
+                        .clone()
+                        .unwrap_or_else(std::env::temp_dir),
+                ),
                 &bot,
                 msg.chat.id.0,
             )
             .await
             {
-                Ok(f) => {
+                Ok(res) => {
+                    // a package without update does not stop the others
+                    if !res.failed.is_empty() {
+                        let failed = res
+                            .failed
+                            .iter()
+                            .map(|(pkg, err)| format!("{pkg}: {err}"))
+                            .collect::<Vec<_>>()
+                            .join("\n");
+                        bot.send_message(
+                            msg.chat.id,
+                            truncate(&format!("Failed to find update:\n{failed}")),
+                        )
+                        .await?;
+                    }
+                    res.update
+                }
+                Err(e) => {
+                    bot.send_message(
+                        msg.chat.id,
+                        truncate(&format!("Failed to find update: {:?}", e)),
+                    )
+                    .await?;
+                    None
+                }
+            };
+
+            match update {
+                Some(f) if pr_on_success => {
+                    bump_build_and_defer_pr(&bot, pool.clone(), f, &msg).await?;
+                }
+                Some(f) => {
                     match buildit_utils::github::open_pr(
                         app_private_key,
                         &token,
@@ -886,6 +2557,10 @@ pub async fn answer(bot: Bot, msg: Message, cmd: Command, pool: DbPool) -> Respo
                             title: f.title,
                             tags: None,
                             archs: None,
+                            security: false,
+                            pr_template: ARGS.pr_template.clone(),
+                            github_org: github_org().to_string(),
+                            github_repo: github_repo().to_string(),
                         },
                     )
                     .await
@@ -897,8 +2572,16 @@ pub async fn answer(bot: Bot, msg: Message, cmd: Command, pool: DbPool) -> Respo
                             )
                             .await?;
 
-                            create_pipeline_from_pr(pool.clone(), pr_number, None, &msg, &bot)
-                                .await?;
+                            create_pipeline_from_pr(
+                                pool.clone(),
+                                pr_number,
+                                None,
+                                false,
+                                false,
+                                &msg,
+                                &bot,
+                            )
+                            .await?;
                         }
                         Err(e) => {
                             bot.send_message(
@@ -909,15 +2592,298 @@ pub async fn answer(bot: Bot, msg: Message, cmd: Command, pool: DbPool) -> Respo
                         }
                     }
                 }
-                Err(e) => {
+                None => {}
+            };
+        }
+        Command::Patch(arguments) => {
+            let parts: Vec<&str> = arguments.split(' ').collect();
+            if parts.len() == 4 {
+                let git_branch = parts[0];
+                let packages = parts[1];
+                let archs = parts[2];
+                let url = parts[3];
+
+                match wait_with_send_typing(fetch_patch(url), &bot, msg.chat.id.0).await {
+                    Ok(patch) => {
+                        pipeline_new_and_report(
+                            &bot,
+                            pool,
+                            git_branch,
+                            None,
+                            packages,
+                            archs,
+                            PipelineOptions {
+                                patch: Some(&patch),
+                                ..Default::default()
+                            },
+                            &msg,
+                        )
+                        .await?;
+                    }
+                    Err(err) => {
+                        bot.send_message(
+                            msg.chat.id,
+                            truncate(&format!("Failed to fetch patch: {err:?}")),
+                        )
+                        .await?;
+                    }
+                }
+
+                return Ok(());
+            }
+
+            bot.send_message(
+                msg.chat.id,
+                format!(
+                    "Got invalid job description: {arguments}. \n\n{}",
+                    Command::descriptions()
+                ),
+            )
+            .await?;
+        }
+        Command::UserPipelines(arguments) => {
+            if !is_admin(msg.chat.id) {
+                bot.send_message(msg.chat.id, "Only admins can list pipelines of other users")
+                    .await?;
+                return Ok(());
+            }
+
+            match wait_with_send_typing(
+                user_pipelines_report(pool, &arguments),
+                &bot,
+                msg.chat.id.0,
+            )
+            .await
+            {
+                Ok(res) => {
+                    bot.send_message(msg.chat.id, truncate(&res)).await?;
+                }
+                Err(err) => {
                     bot.send_message(
                         msg.chat.id,
-                        truncate(&format!("Failed to find update: {:?}", e)),
+                        truncate(&format!("Failed to list user pipelines: {err:?}")),
+                    )
+                    .await?;
+                }
+            }
+        }
+        Command::Timing(arguments) => match str::parse::<i32>(&arguments) {
+            Ok(job_id) => match job_timing(pool, job_id).await {
+                Ok((job, timing)) => {
+                    let fmt_secs = |secs: Option<i64>| match secs {
+                        Some(secs) => format!("{}s", secs),
+                        None => String::from("N/A"),
+                    };
+                    bot.send_message(
+                        msg.chat.id,
+                        format!(
+                            "Job #{} ({}, {}): queued for {}, building for {}",
+                            job.id,
+                            job.arch,
+                            job.status,
+                            fmt_secs(timing.queued_secs),
+                            fmt_secs(timing.build_secs)
+                        ),
+                    )
+                    .await?;
+                }
+                Err(err) => {
+                    bot.send_message(
+                        msg.chat.id,
+                        truncate(&format!("Failed to get job timing: {err:?}")),
+                    )
+                    .await?;
+                }
+            },
+            Err(err) => {
+                bot.send_message(msg.chat.id, truncate(&format!("Bad job ID: {err:?}")))
+                    .await?;
+            }
+        },
+        Command::BuildCmd(arguments) => match str::parse::<i32>(&arguments) {
+            Ok(job_id) => match job_build_command(pool, job_id).await {
+                Ok(cmd) => {
+                    bot.send_message(msg.chat.id, truncate(&cmd)).await?;
+                }
+                Err(err) => {
+                    bot.send_message(
+                        msg.chat.id,
+                        truncate(&format!("Failed to get build command: {err:?}")),
+                    )
+                    .await?;
+                }
+            },
+            Err(err) => {
+                bot.send_message(msg.chat.id, truncate(&format!("Bad job ID: {err:?}")))
+                    .await?;
+            }
+        },
+        Command::CriticalPath(arguments) => match str::parse::<i32>(&arguments) {
+            Ok(pipeline_id) => {
+                match wait_with_send_typing(critical_path(pool, pipeline_id), &bot, msg.chat.id.0)
+                    .await
+                {
+                    Ok(res) => {
+                        bot.send_message(msg.chat.id, truncate(&res)).await?;
+                    }
+                    Err(err) => {
+                        bot.send_message(
+                            msg.chat.id,
+                            truncate(&format!("Failed to estimate critical path: {err:?}")),
+                        )
+                        .await?;
+                    }
+                }
+            }
+            Err(err) => {
+                bot.send_message(msg.chat.id, truncate(&format!("Bad pipeline ID: {err:?}")))
+                    .await?;
+            }
+        },
+        Command::Spec(arguments) => {
+            match wait_with_send_typing(spec(&arguments), &bot, msg.chat.id.0).await {
+                Ok(res) => {
+                    bot.send_message(msg.chat.id, truncate(&res)).await?;
+                }
+                Err(err) => {
+                    bot.send_message(
+                        msg.chat.id,
+                        truncate(&format!("Failed to read spec: {err:?}")),
+                    )
+                    .await?;
+                }
+            }
+        }
+        Command::Packages(arguments) => {
+            match wait_with_send_typing(packages(&arguments), &bot, msg.chat.id.0).await {
+                Ok(res) => {
+                    bot.send_message(msg.chat.id, truncate(&res)).await?;
+                }
+                Err(err) => {
+                    bot.send_message(
+                        msg.chat.id,
+                        truncate(&format!("Failed to get package versions: {err:?}")),
+                    )
+                    .await?;
+                }
+            }
+        }
+        Command::EnvReq(arguments) => {
+            let parts: Vec<&str> = arguments.split_whitespace().collect();
+            if matches!(parts.first(), Some(&"set") | Some(&"clear")) && !is_admin(msg.chat.id) {
+                bot.send_message(
+                    msg.chat.id,
+                    "Only admins can change environment requirement overrides",
+                )
+                .await?;
+                return Ok(());
+            }
+
+            let res = match parts.as_slice() {
+                ["show", package] => {
+                    wait_with_send_typing(env_req_override_report(pool, package), &bot, msg.chat.id.0)
+                        .await
+                }
+                ["set", package, reqs @ ..] => wait_with_send_typing(
+                    env_req_override_set(pool, package, &reqs.join(" ")),
+                    &bot,
+                    msg.chat.id.0,
+                )
+                .await
+                .map(|_| {
+                    format!(
+                        "Environment requirements of {package} are now overridden with: {}\nThis applies to pipelines created from now on",
+                        reqs.join(" ")
+                    )
+                }),
+                ["clear", package] => {
+                    wait_with_send_typing(env_req_override_clear(pool, package), &bot, msg.chat.id.0)
+                        .await
+                        .map(|existed| {
+                            if existed {
+                                format!("Removed environment requirement override of {package}")
+                            } else {
+                                format!("No environment requirement override of {package}")
+                            }
+                        })
+                }
+                _ => {
+                    bot.send_message(
+                        msg.chat.id,
+                        format!(
+                            "Got invalid envreq command: {arguments}. \n\n{}",
+                            Command::descriptions()
+                        ),
                     )
                     .await?;
+                    return Ok(());
                 }
             };
+
+            match res {
+                Ok(res) => {
+                    bot.send_message(msg.chat.id, truncate(&res)).await?;
+                }
+                Err(err) => {
+                    bot.send_message(
+                        msg.chat.id,
+                        truncate(&format!(
+                            "Failed to handle environment requirement override: {err:?}"
+                        )),
+                    )
+                    .await?;
+                }
+            }
         }
+        Command::WhyStuck(arguments) => match str::parse::<i32>(&arguments) {
+            Ok(job_id) => {
+                match wait_with_send_typing(job_schedulability(pool, job_id), &bot, msg.chat.id.0)
+                    .await
+                {
+                    Ok(res) => {
+                        let mut s = if res.status != "created" {
+                            format!(
+                                "Job #{} is not waiting to be scheduled: {}\n",
+                                res.job_id, res.status
+                            )
+                        } else if res.schedulable {
+                            format!(
+                                "Job #{} should be picked up soon by one of the eligible workers\n",
+                                res.job_id
+                            )
+                        } else {
+                            format!(
+                                "Job #{} cannot be picked up by any live {} worker\n",
+                                res.job_id, res.arch
+                            )
+                        };
+                        for worker in res.workers {
+                            if worker.reasons.is_empty() {
+                                s += &format!("{}: eligible\n", worker.hostname);
+                            } else {
+                                s += &format!(
+                                    "{}: {}\n",
+                                    worker.hostname,
+                                    worker.reasons.join("; ")
+                                );
+                            }
+                        }
+                        bot.send_message(msg.chat.id, truncate(&s)).await?;
+                    }
+                    Err(err) => {
+                        bot.send_message(
+                            msg.chat.id,
+                            truncate(&format!("Failed to check job: {err:?}")),
+                        )
+                        .await?;
+                    }
+                }
+            }
+            Err(err) => {
+                bot.send_message(msg.chat.id, truncate(&format!("Bad job ID: {err:?}")))
+                    .await?;
+            }
+        },
         Command::Roll => match wait_with_send_typing(roll(), &bot, msg.chat.id.0).await {
             Ok(pkgs) => {
                 let mut s = String::new();
@@ -982,6 +2948,39 @@ async fn roll() -> anyhow::Result<Vec<UpdatePkg>> {
     Ok(v)
 }
 
+/// Give up on slow patch servers, the user is waiting for the reply
+const FETCH_PATCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+async fn fetch_patch(url: &str) -> anyhow::Result<String> {
+    let url = reqwest::Url::parse(url).context("Bad patch URL")?;
+    if url.scheme() != "https" {
+        bail!("Patch URL must be https");
+    }
+    let client = ClientBuilder::new()
+        .user_agent("buildit")
+        .timeout(FETCH_PATCH_TIMEOUT)
+        .build()?;
+    let resp = client.get(url).send().await?.error_for_status()?;
+    if resp
+        .content_length()
+        .map(|len| len as usize > MAX_PATCH_SIZE)
+        .unwrap_or(false)
+    {
+        bail!("Patch is too large");
+    }
+
+    // content length might be missing or lying, stop reading once over the limit
+    let mut patch = vec![];
+    let mut stream = resp.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        patch.extend_from_slice(&chunk?);
+        if patch.len() > MAX_PATCH_SIZE {
+            bail!("Patch is too large");
+        }
+    }
+    String::from_utf8(patch).context("Patch is not valid UTF-8")
+}
+
 fn truncate<'a>(text: &'a str) -> Cow<'a, str> {
     let text = if text.chars().count() > 1000 {
         console::truncate_str(text, 1000, "...")
@@ -1073,3 +3072,67 @@ fn test_split_open_pr_message() {
         )
     );
 }
+
+#[test]
+fn test_parse_time_window() {
+    assert_eq!(parse_time_window(""), Some(chrono::Duration::days(1)));
+    assert_eq!(
+        parse_time_window("30m"),
+        Some(chrono::Duration::minutes(30))
+    );
+    assert_eq!(parse_time_window("2h"), Some(chrono::Duration::hours(2)));
+    assert_eq!(parse_time_window("7d"), Some(chrono::Duration::days(7)));
+    assert_eq!(parse_time_window("2"), None);
+    assert_eq!(parse_time_window("h"), None);
+    assert_eq!(parse_time_window("0h"), None);
+    assert_eq!(parse_time_window("-1h"), None);
+    assert_eq!(parse_time_window("2w"), None);
+    assert_eq!(parse_time_window("2时"), None);
+}
+
+#[test]
+fn test_parse_pr_arguments() {
+    let args = |pr_numbers: Vec<u64>, archs, merge, fork| PrArguments {
+        pr_numbers,
+        archs,
+        merge,
+        fork,
+    };
+    assert_eq!(
+        parse_pr_arguments("1234"),
+        Some(args(vec![1234], None, false, false))
+    );
+    // rebuild a single arch, e.g. of a merged PR
+    assert_eq!(
+        parse_pr_arguments("1234 arm64"),
+        Some(args(vec![1234], Some("arm64"), false, false))
+    );
+    assert_eq!(
+        parse_pr_arguments("12,34 amd64,arm64 merge"),
+        Some(args(vec![12, 34], Some("amd64,arm64"), true, false))
+    );
+    assert_eq!(
+        parse_pr_arguments("1234 merge"),
+        Some(args(vec![1234], None, true, false))
+    );
+    assert_eq!(
+        parse_pr_arguments("1234 fork"),
+        Some(args(vec![1234], None, false, true))
+    );
+    assert_eq!(
+        parse_pr_arguments("1234 arm64 fork merge"),
+        Some(args(vec![1234], Some("arm64"), true, true))
+    );
+    assert_eq!(parse_pr_arguments(""), None);
+    assert_eq!(parse_pr_arguments("12a arm64"), None);
+    assert_eq!(parse_pr_arguments("1234 arm64 riscv64"), None);
+}
+
+#[test]
+fn test_bump_guard() {
+    let guard = BumpGuard::new("fd").unwrap();
+    assert!(BumpGuard::new("fd").is_none());
+    assert!(BumpGuard::new("fish").is_some());
+    drop(guard);
+    assert!(BumpGuard::new("fd").is_some());
+}
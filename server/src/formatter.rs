@@ -1,44 +1,79 @@
 use crate::models::{Job, Pipeline};
 use common::JobOk;
-use std::borrow::Cow;
+use std::{borrow::Cow, collections::BTreeMap};
 
 pub const SUCCESS: &str = "✅️";
 pub const FAILED: &str = "❌";
 pub const SUCCESS_TEXT: &str = "successfully";
 pub const FAILED_TEXT: &str = "unsuccessfully";
+/// Max packages listed in messages and check runs, the full list is kept in database
+pub const DISPLAY_PACKAGES_LIMIT: usize = 100;
+
+/// Join packages with ", ", listing at most `DISPLAY_PACKAGES_LIMIT` of them
+pub fn display_packages<S: AsRef<str>>(packages: &[S]) -> String {
+    let mut res = packages
+        .iter()
+        .take(DISPLAY_PACKAGES_LIMIT)
+        .map(AsRef::as_ref)
+        .collect::<Vec<_>>()
+        .join(", ");
+    if packages.len() > DISPLAY_PACKAGES_LIMIT {
+        res += &format!(" and {} more", packages.len() - DISPLAY_PACKAGES_LIMIT);
+    }
+    res
+}
+
+/// Like `display_packages`, for comma-separated packages
+pub fn display_packages_str(packages: &str) -> String {
+    display_packages(&packages.split(',').collect::<Vec<_>>())
+}
 
 pub fn to_html_new_pipeline_summary(
-    pipeline_id: i32,
-    git_branch: &str,
-    git_sha: &str,
-    github_pr: Option<u64>,
-    archs: &[&str],
-    packages: &[&str],
+    repo_url: &str,
+    pipeline: &Pipeline,
+    estimated_secs: &BTreeMap<String, i64>,
 ) -> String {
     format!(
         r#"<b><u>New Pipeline Summary</u></b>
 
 <b>Pipeline</b>: <a href="https://buildit.aosc.io/pipelines/{}">#{}</a>
 <b>Git branch</b>: {}
-<b>Git commit</b>: <a href="https://github.com/AOSC-Dev/aosc-os-abbs/commit/{}">{}</a>{}
+<b>Git commit</b>: <a href="{}/commit/{}">{}</a>{}
 <b>Architecture(s)</b>: {}
-<b>Package(s)</b>: {}"#,
-        pipeline_id,
-        pipeline_id,
-        git_branch,
-        git_sha,
-        &git_sha[..8],
-        if let Some(pr) = github_pr {
-            format!("\n<b>GitHub PR</b>: <a href=\"https://github.com/AOSC-Dev/aosc-os-abbs/pull/{}\">#{}</a>", pr, pr)
+<b>Package(s)</b>: {}{}"#,
+        pipeline.id,
+        pipeline.id,
+        pipeline.git_branch,
+        repo_url,
+        pipeline.git_sha,
+        &pipeline.git_sha[..8],
+        if let Some(pr) = pipeline.github_pr {
+            format!(
+                "\n<b>GitHub PR</b>: <a href=\"{}/pull/{}\">#{}</a>",
+                repo_url, pr, pr
+            )
         } else {
             String::new()
         },
-        archs.join(", "),
-        packages.join(", "),
+        pipeline.archs.replace(',', ", "),
+        display_packages_str(&pipeline.packages),
+        if estimated_secs.is_empty() {
+            String::new()
+        } else {
+            format!(
+                "\n<b>Estimated build time</b>: {}",
+                estimated_secs
+                    .iter()
+                    .map(|(arch, secs)| format!("{arch} {secs}s"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        },
     )
 }
 
 pub fn to_html_build_result(
+    repo_url: &str,
     pipeline: &Pipeline,
     job: &Job,
     job_ok: &JobOk,
@@ -86,27 +121,28 @@ pub fn to_html_build_result(
         format!("{}", job.creation_time),
         format!("{}s", elapsed_secs),
         format!(
-            "<a href=\"https://github.com/AOSC-Dev/aosc-os-abbs/commit/{}\">{}</a>",
+            "<a href=\"{}/commit/{}\">{}</a>",
+            repo_url,
             pipeline.git_sha,
             &pipeline.git_sha[..8]
         ),
         format!(
-            "<a href=\"https://github.com/AOSC-Dev/aosc-os-abbs/tree/{}\">{}</a>",
-            pipeline.git_branch, &pipeline.git_branch
+            "<a href=\"{}/tree/{}\">{}</a>",
+            repo_url, pipeline.git_branch, &pipeline.git_branch
         ),
         if let Some(pr) = pipeline.github_pr {
             format!(
-                "<b>GitHub PR</b>: <a href=\"https://github.com/AOSC-Dev/aosc-os-abbs/pull/{}\">#{}</a>\n",
-                pr, pr
+                "<b>GitHub PR</b>: <a href=\"{}/pull/{}\">#{}</a>\n",
+                repo_url, pr, pr
             )
         } else {
             String::new()
         },
         job.arch,
-        job.packages.replace(",", ", "),
-        &successful_packages.join(", "),
+        display_packages_str(&job.packages),
+        display_packages(successful_packages),
         &failed_package.clone().unwrap_or(String::from("None")),
-        &skipped_packages.join(", "),
+        display_packages(skipped_packages),
         if let Some(log) = log_url {
             Cow::Owned(format!("<a href=\"{}\">Build Log >></a>", log))
         } else {
@@ -116,6 +152,7 @@ pub fn to_html_build_result(
 }
 
 pub fn to_markdown_build_result(
+    repo_url: &str,
     pipeline: &Pipeline,
     job: &Job,
     job_ok: &JobOk,
@@ -142,13 +179,13 @@ pub fn to_markdown_build_result(
         format!("[#{}](https://buildit.aosc.io/pipelines/{})", pipeline.id, pipeline.id),
         teloxide::utils::markdown::escape(&job.creation_time.to_string()),
         elapsed_secs,
-        format!("**Git commit**: [{}](https://github.com/AOSC-Dev/aosc-os-abbs/commit/{})\n", &pipeline.git_sha[..8], pipeline.git_sha),
-        format!("**Git branch**: [{}](https://github.com/AOSC-Dev/aosc-os-abbs/tree/{})\n", &pipeline.git_branch, pipeline.git_branch),
+        format!("**Git commit**: [{}]({}/commit/{})\n", &pipeline.git_sha[..8], repo_url, pipeline.git_sha),
+        format!("**Git branch**: [{}]({}/tree/{})\n", &pipeline.git_branch, repo_url, pipeline.git_branch),
         job.arch,
-        teloxide::utils::markdown::escape(&job.packages.replace(",", ", ")),
-        teloxide::utils::markdown::escape(&successful_packages.join(", ")),
+        teloxide::utils::markdown::escape(&display_packages_str(&job.packages)),
+        teloxide::utils::markdown::escape(&display_packages(successful_packages)),
         teloxide::utils::markdown::escape(&failed_package.clone().unwrap_or(String::from("None"))),
-        teloxide::utils::markdown::escape(&skipped_packages.join(", ")),
+        teloxide::utils::markdown::escape(&display_packages(skipped_packages)),
         if let Some(log) = log_url {
             Cow::Owned(format!("[Build Log \\>\\>]({})", log))
         } else {
@@ -157,14 +194,88 @@ pub fn to_markdown_build_result(
     )
 }
 
+/// Parse the job arch from a comment made by `to_markdown_build_result`
+pub fn build_result_arch(body: &str) -> Option<&str> {
+    body.lines()
+        .find_map(|line| line.strip_prefix("**Architecture**:"))
+        .map(|arch| arch.trim())
+}
+
+pub fn to_markdown_pipeline_report(repo_url: &str, pipeline: &Pipeline, jobs: &[Job]) -> String {
+    let mut s = format!(
+        "## Pipeline [#{}](https://buildit.aosc.io/pipelines/{})\n\n**Git commit**: [{}]({}/commit/{})\n**Git branch**: [{}]({}/tree/{})\n{}**Package\\(s\\)**: {}\n\n| Architecture | Status | Job | Time elapsed | Log |\n| --- | --- | --- | --- | --- |\n",
+        pipeline.id,
+        pipeline.id,
+        &pipeline.git_sha[..8],
+        repo_url,
+        pipeline.git_sha,
+        pipeline.git_branch,
+        repo_url,
+        pipeline.git_branch,
+        if let Some(pr) = pipeline.github_pr {
+            format!("**GitHub PR**: [#{}]({}/pull/{})\n", pr, repo_url, pr)
+        } else {
+            String::new()
+        },
+        display_packages_str(&pipeline.packages),
+    );
+
+    for job in jobs {
+        s += &format!(
+            "| {} | {} | [#{}](https://buildit.aosc.io/jobs/{}) | {} | {} |\n",
+            job.arch,
+            match job.status.as_str() {
+                "success" => format!("{} {}", SUCCESS, job.status),
+                "failed" | "error" => format!("{} {}", FAILED, job.status),
+                _ => job.status.clone(),
+            },
+            job.id,
+            job.id,
+            job.elapsed_secs
+                .map(|secs| format!("{}s", secs))
+                .unwrap_or_default(),
+            job.log_url
+                .as_ref()
+                .map(|log| format!("[Build Log \\>\\>]({})", log))
+                .unwrap_or_default(),
+        );
+    }
+
+    s
+}
+
 pub fn code_repr_string(s: &str) -> String {
     format!("<code>{s}</code>")
 }
 
+#[cfg(test)]
+const REPO_URL: &str = "https://github.com/AOSC-Dev/aosc-os-abbs";
+
 #[test]
 fn test_format_html_new_pipeline_summary() {
-    let s =
-        to_html_new_pipeline_summary(1, "fd-9.0.0", "123456789", Some(4992), &["amd64"], &["fd"]);
+    use chrono::DateTime;
+
+    let pipeline = Pipeline {
+        id: 1,
+        packages: "fd".to_string(),
+        archs: "amd64".to_string(),
+        git_branch: "fd-9.0.0".to_string(),
+        git_sha: "123456789".to_string(),
+        creation_time: DateTime::from_timestamp(61, 0).unwrap(),
+        source: "telegram".to_string(),
+        github_pr: Some(4992),
+        telegram_user: None,
+        creator_user_id: None,
+        patch: None,
+        deferred_pr_title: None,
+        git_ref: None,
+        notify_start: false,
+        git_remote: None,
+        idempotency_key: None,
+        ciel_flags: None,
+        tag: None,
+    };
+    let s = to_html_new_pipeline_summary(REPO_URL, &pipeline, &BTreeMap::new());
     assert_eq!(s, "<b><u>New Pipeline Summary</u></b>\n\n<b>Pipeline</b>: <a href=\"https://buildit.aosc.io/pipelines/1\">#1</a>\n<b>Git branch</b>: fd-9.0.0\n<b>Git commit</b>: <a href=\"https://github.com/AOSC-Dev/aosc-os-abbs/commit/123456789\">12345678</a>\n<b>GitHub PR</b>: <a href=\"https://github.com/AOSC-Dev/aosc-os-abbs/pull/4992\">#4992</a>\n<b>Architecture(s)</b>: amd64\n<b>Package(s)</b>: fd")
 }
 
@@ -184,6 +295,14 @@ fn test_format_html_build_result() {
         github_pr: Some(4992),
         telegram_user: None,
         creator_user_id: None,
+        patch: None,
+        deferred_pr_title: None,
+        git_ref: None,
+        notify_start: false,
+        git_remote: None,
+        idempotency_key: None,
+        ciel_flags: None,
+        tag: None,
     };
 
     let job = Job {
@@ -210,6 +329,15 @@ fn test_format_html_build_result() {
         require_min_disk: None,
         require_min_total_mem: None,
         require_min_total_mem_per_core: None,
+        repush_only: false,
+        verbose: false,
+        restarted_from: None,
+        artifacts: None,
+        required_tags: None,
+        priority: 0,
+        preferred_machine_class: None,
+        step_timings: None,
+        reclaim_count: 0,
     };
 
     let job_ok = JobOk {
@@ -220,12 +348,157 @@ fn test_format_html_build_result() {
         log_url: Some("https://pastebin.aosc.io/paste/c0rWzj4EsSC~CVXs2qXtFw".to_string()),
         elapsed_secs: 888,
         pushpkg_success: true,
+        failure_stage: None,
+        artifacts: None,
+        step_timings: vec![],
     };
 
     let worker_hostname = "Yerus";
     let worker_arch = "amd64";
 
-    let s = to_html_build_result(&pipeline, &job, &job_ok, worker_hostname, worker_arch, true);
+    let s = to_html_build_result(
+        REPO_URL,
+        &pipeline,
+        &job,
+        &job_ok,
+        worker_hostname,
+        worker_arch,
+        true,
+    );
+
+    assert_eq!(s, "✅\u{fe0f} Job successfully completed on Yerus (amd64)\n\n<b>Job</b>: <a href=\"https://buildit.aosc.io/jobs/1\">#1</a>\n<b>Pipeline</b>: <a href=\"https://buildit.aosc.io/pipelines/1\">#1</a>\n<b>Enqueue time</b>: 1970-01-01 00:01:01 UTC\n<b>Time elapsed</b>: 888s\n<b>Git commit</b>: <a href=\"https://github.com/AOSC-Dev/aosc-os-abbs/commit/34acef168fc5ec454d3825fc864964951b130b49\">34acef16</a>\n<b>Git branch</b>: <a href=\"https://github.com/AOSC-Dev/aosc-os-abbs/tree/fd-9.0.0\">fd-9.0.0</a>\n<b>GitHub PR</b>: <a href=\"https://github.com/AOSC-Dev/aosc-os-abbs/pull/4992\">#4992</a>\n<b>Architecture</b>: amd64\n<b>Package(s) to build</b>: fd, fd2\n<b>Package(s) successfully built</b>: fd\n<b>Package(s) failed to build</b>: None\n<b>Package(s) not built due to previous build failure</b>: \n\n<a href=\"https://pastebin.aosc.io/paste/c0rWzj4EsSC~CVXs2qXtFw\">Build Log >></a>");
+
+    // noarch jobs are built by amd64 workers, the job arch must win
+    let job = Job {
+        arch: "noarch".to_string(),
+        ..job
+    };
+    let s = to_markdown_build_result(
+        REPO_URL,
+        &pipeline,
+        &job,
+        &job_ok,
+        worker_hostname,
+        worker_arch,
+        true,
+    );
+    assert_eq!(build_result_arch(&s), Some("noarch"));
+}
+
+#[test]
+fn test_format_markdown_pipeline_report() {
+    use chrono::DateTime;
+
+    let pipeline = Pipeline {
+        id: 1,
+        packages: "fd,fd2".to_string(),
+        archs: "amd64,arm64".to_string(),
+        git_branch: "fd-9.0.0".to_string(),
+        git_sha: "34acef168fc5ec454d3825fc864964951b130b49".to_string(),
+        creation_time: DateTime::from_timestamp(61, 0).unwrap(),
+        source: "telegram".to_string(),
+        github_pr: None,
+        telegram_user: None,
+        creator_user_id: None,
+        patch: None,
+        deferred_pr_title: None,
+        git_ref: None,
+        notify_start: false,
+        git_remote: None,
+        idempotency_key: None,
+        ciel_flags: None,
+        tag: None,
+    };
+
+    let job = Job {
+        id: 1,
+        pipeline_id: 1,
+        packages: "fd,fd2".to_string(),
+        arch: "amd64".to_string(),
+        creation_time: DateTime::from_timestamp(61, 0).unwrap(),
+        status: "success".to_string(),
+        github_check_run_id: None,
+        build_success: Some(true),
+        pushpkg_success: Some(true),
+        successful_packages: Some("fd,fd2".to_string()),
+        failed_package: None,
+        skipped_packages: Some("".to_string()),
+        log_url: Some("https://pastebin.aosc.io/paste/c0rWzj4EsSC~CVXs2qXtFw".to_string()),
+        finish_time: Some(DateTime::from_timestamp(61, 0).unwrap()),
+        assign_time: Some(DateTime::from_timestamp(61, 0).unwrap()),
+        error_message: None,
+        elapsed_secs: Some(888),
+        assigned_worker_id: Some(1),
+        built_by_worker_id: Some(1),
+        require_min_core: None,
+        require_min_disk: None,
+        require_min_total_mem: None,
+        require_min_total_mem_per_core: None,
+        repush_only: false,
+        verbose: false,
+        restarted_from: None,
+        artifacts: None,
+        required_tags: None,
+        priority: 0,
+        preferred_machine_class: None,
+        step_timings: None,
+        reclaim_count: 0,
+    };
+    let running = Job {
+        id: 2,
+        pipeline_id: 1,
+        packages: "fd,fd2".to_string(),
+        arch: "arm64".to_string(),
+        creation_time: DateTime::from_timestamp(61, 0).unwrap(),
+        status: "running".to_string(),
+        github_check_run_id: None,
+        build_success: None,
+        pushpkg_success: None,
+        successful_packages: None,
+        failed_package: None,
+        skipped_packages: None,
+        log_url: None,
+        finish_time: None,
+        assign_time: Some(DateTime::from_timestamp(61, 0).unwrap()),
+        error_message: None,
+        elapsed_secs: None,
+        assigned_worker_id: Some(2),
+        built_by_worker_id: None,
+        require_min_core: None,
+        require_min_disk: None,
+        require_min_total_mem: None,
+        require_min_total_mem_per_core: None,
+        repush_only: false,
+        verbose: false,
+        restarted_from: None,
+        artifacts: None,
+        required_tags: None,
+        priority: 0,
+        preferred_machine_class: None,
+        step_timings: None,
+        reclaim_count: 0,
+    };
+
+    let s = to_markdown_pipeline_report(REPO_URL, &pipeline, &[job, running]);
+    assert_eq!(s, "## Pipeline [#1](https://buildit.aosc.io/pipelines/1)\n\n**Git commit**: [34acef16](https://github.com/AOSC-Dev/aosc-os-abbs/commit/34acef168fc5ec454d3825fc864964951b130b49)\n**Git branch**: [fd-9.0.0](https://github.com/AOSC-Dev/aosc-os-abbs/tree/fd-9.0.0)\n**Package\\(s\\)**: fd, fd2\n\n| Architecture | Status | Job | Time elapsed | Log |\n| --- | --- | --- | --- | --- |\n| amd64 | ✅\u{fe0f} success | [#1](https://buildit.aosc.io/jobs/1) | 888s | [Build Log \\>\\>](https://pastebin.aosc.io/paste/c0rWzj4EsSC~CVXs2qXtFw) |\n| arm64 | running | [#2](https://buildit.aosc.io/jobs/2) |  |  |\n");
+}
+
+#[test]
+fn test_display_packages() {
+    let packages = (0..DISPLAY_PACKAGES_LIMIT)
+        .map(|i| format!("pkg{i}"))
+        .collect::<Vec<_>>();
+    let res = display_packages(&packages);
+    assert!(res.starts_with("pkg0, pkg1, "));
+    assert!(res.ends_with(&format!("pkg{}", DISPLAY_PACKAGES_LIMIT - 1)));
+    assert!(!res.contains("more"));
+
+    let mut packages = packages;
+    packages.push(String::from("extra1"));
+    packages.push(String::from("extra2"));
+    let res = display_packages(&packages);
+    assert!(res.ends_with(&format!("pkg{} and 2 more", DISPLAY_PACKAGES_LIMIT - 1)));
+    assert!(!res.contains("extra"));
 
-    assert_eq!(s, "✅\u{fe0f} Job successfully completed on Yerus (amd64)\n\n<b>Job</b>: <a href=\"https://buildit.aosc.io/jobs/1\">#1</a>\n<b>Pipeline</b>: <a href=\"https://buildit.aosc.io/pipelines/1\">#1</a>\n<b>Enqueue time</b>: 1970-01-01 00:01:01 UTC\n<b>Time elapsed</b>: 888s\n<b>Git commit</b>: <a href=\"https://github.com/AOSC-Dev/aosc-os-abbs/commit/34acef168fc5ec454d3825fc864964951b130b49\">34acef16</a>\n<b>Git branch</b>: <a href=\"https://github.com/AOSC-Dev/aosc-os-abbs/tree/fd-9.0.0\">fd-9.0.0</a>\n<b>GitHub PR</b>: <a href=\"https://github.com/AOSC-Dev/aosc-os-abbs/pull/4992\">#4992</a>\n<b>Architecture</b>: amd64\n<b>Package(s) to build</b>: fd, fd2\n<b>Package(s) successfully built</b>: fd\n<b>Package(s) failed to build</b>: None\n<b>Package(s) not built due to previous build failure</b>: \n\n<a href=\"https://pastebin.aosc.io/paste/c0rWzj4EsSC~CVXs2qXtFw\">Build Log >></a>")
+    assert_eq!(display_packages_str("fd,fd2"), "fd, fd2");
 }
@@ -1,12 +1,14 @@
+use anyhow::Context;
 use axum::{extract::connect_info, serve::IncomingStream};
 use clap::Parser;
 use diesel::{
-    r2d2::{ConnectionManager, Pool},
+    r2d2::{ConnectionManager, Pool, PooledConnection},
     PgConnection,
 };
 use once_cell::sync::Lazy;
-use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 use tokio::net::{unix::UCred, UnixStream};
+use tracing::{error, warn};
 
 pub mod api;
 pub mod bot;
@@ -18,6 +20,45 @@ pub mod routes;
 pub mod schema;
 
 pub type DbPool = Pool<ConnectionManager<PgConnection>>;
+pub type DbConn = PooledConnection<ConnectionManager<PgConnection>>;
+
+/// Timeout of each attempt in `get_conn_with_retry`
+const DB_RETRY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Backoff between attempts in `get_conn_with_retry`
+const DB_RETRY_BACKOFF: &[Duration] = &[
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(2),
+];
+
+/// Get db connection from pool, retrying with backoff so that a brief database
+/// restart does not fail the request. Only use it where nothing has been done
+/// before, e.g. read-only endpoints and worker polling, writes should fail fast
+/// with `pool.get()`.
+pub async fn get_conn_with_retry(pool: &DbPool) -> anyhow::Result<DbConn> {
+    for (attempt, backoff) in DB_RETRY_BACKOFF.iter().enumerate() {
+        match pool.get_timeout(DB_RETRY_TIMEOUT) {
+            Ok(conn) => return Ok(conn),
+            Err(err) => {
+                warn!(
+                    "Failed to get db connection from pool (attempt {}): {}",
+                    attempt + 1,
+                    err
+                );
+                tokio::time::sleep(*backoff).await;
+            }
+        }
+    }
+
+    let res = pool.get_timeout(DB_RETRY_TIMEOUT);
+    if let Err(err) = &res {
+        error!(
+            "Failed to get db connection from pool after retries: {}",
+            err
+        );
+    }
+    res.context("Failed to get db connection from pool")
+}
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -61,21 +102,154 @@ pub struct Args {
     /// Listen to unix socket if set
     #[arg(env = "BUILDIT_LISTEN_SOCKET_PATH")]
     pub unix_socket: Option<PathBuf>,
+
+    /// Max number of created + running jobs per arch, e.g. `riscv64=200,default=1000`
+    #[arg(env = "BUILDIT_QUEUE_LIMITS")]
+    pub queue_limits: Option<String>,
+
+    /// Running jobs exceeding this duration are considered probably stuck
+    #[arg(env = "BUILDIT_STUCK_JOB_SECS")]
+    pub stuck_job_secs: Option<i64>,
+
+    /// PR body template, see `buildit_utils::github::PrTemplate`
+    #[arg(env = "BUILDIT_PR_TEMPLATE")]
+    pub pr_template: Option<PathBuf>,
+
+    /// Scratch directory for acbs-build outside of the ABBS tree, defaults to system temp dir
+    #[arg(env = "BUILDIT_ACBS_SCRATCH_DIR")]
+    pub acbs_scratch_dir: Option<PathBuf>,
+
+    /// Telegram chat ids allowed to run admin commands, e.g. `12345,67890`
+    #[arg(env = "BUILDIT_ADMIN_CHAT_IDS")]
+    pub admin_chat_ids: Option<String>,
+
+    /// Adjust archs of PR builds by labels, see `buildit_utils::github::apply_label_archs`
+    #[arg(env = "BUILDIT_PR_LABEL_ARCHS")]
+    pub pr_label_archs: Option<String>,
+
+    /// Local directory of logs uploaded by workers, served at `https://buildit.aosc.io/logs`,
+    /// also enables `/api/job/upload_log` for workers failing to scp logs
+    #[arg(env = "BUILDIT_LOGS_PATH")]
+    pub logs_path: Option<PathBuf>,
+
+    /// Store uploaded logs gzip-compressed as `<name>.txt.gz`, defaults to false.
+    /// The web server of `BUILDIT_LOGS_PATH` should serve them for `<name>.txt`,
+    /// e.g. with `gzip_static always` and `gunzip on` of nginx
+    #[arg(env = "BUILDIT_COMPRESS_LOGS")]
+    pub compress_logs: Option<bool>,
+
+    /// Max attempts to report a job result to telegram and github, defaults to 5
+    #[arg(env = "BUILDIT_JOB_UPDATE_MAX_RETRY")]
+    pub job_update_max_retry: Option<u8>,
+
+    /// Read replica database for reporting queries, keeping them off the primary database
+    #[arg(env = "DATABASE_REPLICA_URL")]
+    pub database_replica_url: Option<String>,
+
+    /// Max packages of a pipeline after resolving groups, defaults to 1000
+    #[arg(env = "BUILDIT_MAX_PIPELINE_PACKAGES")]
+    pub max_pipeline_packages: Option<usize>,
+
+    /// Max lines of live log kept per worker for websocket viewers, defaults to 1000
+    #[arg(env = "BUILDIT_WS_LOG_BUFFER_LINES")]
+    pub ws_log_buffer_lines: Option<usize>,
+
+    /// Noarch jobs waiting longer than this can be claimed by workers of any arch,
+    /// not only amd64, defaults to 1800
+    #[arg(env = "BUILDIT_NOARCH_FALLBACK_SECS")]
+    pub noarch_fallback_secs: Option<i64>,
+
+    /// Adding this label to a PR triggers a build, defaults to `build-please`
+    #[arg(env = "BUILDIT_GITHUB_BUILD_LABEL")]
+    pub github_build_label: Option<String>,
+
+    /// Fail jobs instead of requeueing them once their workers disappeared this many times,
+    /// defaults to 3
+    #[arg(env = "BUILDIT_MAX_JOB_RECLAIMS")]
+    pub max_job_reclaims: Option<i32>,
+
+    /// GitHub organization of the ABBS repo, defaults to `AOSC-Dev`
+    #[arg(env = "BUILDIT_GITHUB_ORG")]
+    pub github_org: Option<String>,
+
+    /// GitHub repository name of the ABBS repo, defaults to `aosc-os-abbs`
+    #[arg(env = "BUILDIT_GITHUB_REPO")]
+    pub github_repo: Option<String>,
+
+    /// Public url of the web frontend, used in links to jobs, defaults to `https://buildit.aosc.io`
+    #[arg(env = "BUILDIT_BASE_URL")]
+    pub base_url: Option<String>,
+}
+
+/// GitHub organization of the ABBS repo
+pub fn github_org() -> &'static str {
+    ARGS.github_org.as_deref().unwrap_or(DEFAULT_GITHUB_ORG)
+}
+
+/// GitHub repository name of the ABBS repo
+pub fn github_repo() -> &'static str {
+    ARGS.github_repo.as_deref().unwrap_or(DEFAULT_GITHUB_REPO)
+}
+
+/// Url of the ABBS repo on GitHub, e.g. `https://github.com/AOSC-Dev/aosc-os-abbs`
+pub fn github_repo_web_url() -> String {
+    format!("https://github.com/{}/{}", github_org(), github_repo())
+}
+
+/// Public url of the web frontend, without trailing slash
+pub fn base_url() -> &'static str {
+    ARGS.base_url
+        .as_deref()
+        .unwrap_or(DEFAULT_BASE_URL)
+        .trim_end_matches('/')
+}
+
+/// Url to clone the ABBS repo from GitHub
+pub fn github_repo_url() -> String {
+    format!("{}.git", github_repo_web_url())
 }
 
 pub static ARGS: Lazy<Args> = Lazy::new(Args::parse);
 pub const HEARTBEAT_TIMEOUT: i64 = 600; // 10 minutes
+pub const DEFAULT_QUEUE_LIMIT: i64 = 1000;
+pub const DEFAULT_STUCK_JOB_SECS: i64 = 86400; // 1 day
+pub const DEFAULT_JOB_UPDATE_MAX_RETRY: u8 = 5;
+pub const JOB_UPDATE_MAX_RETRY_DELAY_SECS: u64 = 60;
+pub const DEFAULT_MAX_PIPELINE_PACKAGES: usize = 1000;
+pub const DEFAULT_WS_LOG_BUFFER_LINES: usize = 1000;
+pub const DEFAULT_NOARCH_FALLBACK_SECS: i64 = 1800; // 30 minutes
+pub const DEFAULT_BUILD_LABEL: &str = "build-please";
+pub const DEFAULT_MAX_JOB_RECLAIMS: i32 = 3;
+pub const DEFAULT_GITHUB_ORG: &str = "AOSC-Dev";
+pub const DEFAULT_GITHUB_REPO: &str = "aosc-os-abbs";
+pub const DEFAULT_BASE_URL: &str = "https://buildit.aosc.io";
+/// Git commit the server is built from, comparable to `git_commit` reported by workers
+pub const GIT_COMMIT: &str = env!("VERGEN_GIT_DESCRIBE");
 
-// follow https://github.com/AOSC-Dev/autobuild3/blob/master/sets/arch_groups/mainline
-pub(crate) const ALL_ARCH: &[&str] = &[
-    "amd64",
-    "arm64",
-    "loongarch64",
-    "loongson3",
-    "ppc64el",
-    "riscv64",
+/// Optional API features supported by this server, reported by `/api/version`
+pub const API_FEATURES: &[&str] = &[
+    "patch",
+    "repush",
+    "verbose",
+    "queue_limits",
+    "supersede",
+    "requirements",
+    "schedulability",
+    "job_log",
+    "pipeline_events",
+    "priority",
+    "tokens",
+    "export",
+    "idempotency",
+    "ciel_flags",
+    "log_upload",
+    "pagination_cursor",
+    "health",
+    "log_compression",
 ];
 
+pub(crate) use buildit_utils::ALL_ARCH;
+
 // https://github.com/tokio-rs/axum/blob/main/examples/unix-domain-socket/src/main.rs
 #[derive(Clone, Debug)]
 pub enum RemoteAddr {
@@ -1,7 +1,7 @@
 use axum::extract::MatchedPath;
 use axum::http::Method;
-use axum::routing::post;
-use axum::{http::Request, routing::get, Router};
+use axum::{http::Request, Router};
+use buildit_utils::github::{check_abbs_repo, PrTemplate};
 use diesel::pg::PgConnection;
 use diesel::r2d2::ConnectionManager;
 use diesel::r2d2::Pool;
@@ -13,13 +13,7 @@ use opentelemetry_sdk::trace;
 use opentelemetry_sdk::Resource;
 use server::bot::{answer, Command};
 use server::recycler::recycler_worker;
-use server::routes::{
-    dashboard_status, job_info, job_list, job_restart, ping, pipeline_info, pipeline_list,
-    pipeline_new_pr, webhook_handler, worker_info, worker_job_update, worker_list, worker_poll,
-    ws_viewer_handler, ws_worker_handler, AppState, WSStateMap,
-};
-use server::routes::{pipeline_new, worker_heartbeat};
-use server::routes::{pipeline_status, worker_status};
+use server::routes::{api_routes, AppState, PipelineEventsMap, WSStateMap};
 use server::{DbPool, RemoteAddr, ARGS};
 use std::collections::HashMap;
 use std::os::unix::fs::PermissionsExt;
@@ -66,9 +60,24 @@ async fn main() -> anyhow::Result<()> {
         tracing_subscriber::fmt::init();
     }
 
+    // fail early on invalid PR template
+    PrTemplate::load(ARGS.pr_template.as_deref())?;
+    // and misconfigured ABBS tree
+    check_abbs_repo(&ARGS.abbs_path)?;
+
     tracing::info!("Connecting to database");
     let manager = ConnectionManager::<PgConnection>::new(&ARGS.database_url);
     let pool = Pool::builder().test_on_check_out(true).build(manager)?;
+    let replica_pool = match &ARGS.database_replica_url {
+        Some(database_replica_url) => {
+            tracing::info!("Connecting to read replica database");
+            let manager = ConnectionManager::<PgConnection>::new(database_replica_url);
+            Pool::builder().test_on_check_out(true).build(manager)?
+        }
+        None => pool.clone(),
+    };
+
+    let ws_state_map = WSStateMap::new(Mutex::new(HashMap::new()));
 
     let mut handles = vec![];
     let bot = if std::env::var("TELOXIDE_TOKEN").is_ok() {
@@ -76,15 +85,21 @@ async fn main() -> anyhow::Result<()> {
         let bot = Bot::from_env();
 
         let handler =
-            Update::filter_message().branch(dptree::entry().filter_command::<Command>().endpoint(
-                |bot: Bot, pool: DbPool, msg: Message, cmd: Command| async move {
-                    answer(bot, msg, cmd, pool).await
-                },
-            ));
+            Update::filter_message().branch(
+                dptree::entry().filter_command::<Command>().endpoint(
+                    |bot: Bot,
+                     pool: DbPool,
+                     ws_state_map: WSStateMap,
+                     msg: Message,
+                     cmd: Command| async move {
+                        answer(bot, msg, cmd, pool, ws_state_map).await
+                    },
+                ),
+            );
 
         let mut telegram = Dispatcher::builder(bot.clone(), handler)
             // Pass the shared state to the handler as a dependency.
-            .dependencies(dptree::deps![pool.clone()])
+            .dependencies(dptree::deps![pool.clone(), ws_state_map.clone()])
             .enable_ctrlc_handler()
             .build();
 
@@ -98,30 +113,18 @@ async fn main() -> anyhow::Result<()> {
     // build our application with a route
     let state = AppState {
         pool: pool.clone(),
+        replica_pool,
         bot,
-        ws_state_map: WSStateMap::new(Mutex::new(HashMap::new())),
+        ws_state_map,
+        pipeline_events_map: PipelineEventsMap::new(Mutex::new(HashMap::new())),
     };
 
-    let mut app = Router::new()
-        .route("/api/ping", get(ping))
-        .route("/api/pipeline/new", post(pipeline_new))
-        .route("/api/pipeline/new_pr", post(pipeline_new_pr))
-        .route("/api/pipeline/status", get(pipeline_status))
-        .route("/api/pipeline/list", get(pipeline_list))
-        .route("/api/pipeline/info", get(pipeline_info))
-        .route("/api/job/list", get(job_list))
-        .route("/api/job/info", get(job_info))
-        .route("/api/job/restart", post(job_restart))
-        .route("/api/worker/heartbeat", post(worker_heartbeat))
-        .route("/api/worker/poll", post(worker_poll))
-        .route("/api/worker/job_update", post(worker_job_update))
-        .route("/api/worker/status", get(worker_status))
-        .route("/api/worker/list", get(worker_list))
-        .route("/api/worker/info", get(worker_info))
-        .route("/api/dashboard/status", get(dashboard_status))
-        .route("/api/ws/viewer/:hostname", get(ws_viewer_handler))
-        .route("/api/ws/worker/:hostname", get(ws_worker_handler))
-        .route("/api/webhook", post(webhook_handler))
+    let recycler_state = state.clone();
+    let mut app = Router::new();
+    for (path, method_router) in api_routes() {
+        app = app.route(path, method_router);
+    }
+    let mut app = app
         .nest_service("/assets", ServeDir::new("frontend/dist/assets"))
         .route_service("/favicon.ico", ServeFile::new("frontend/dist/favicon.ico"))
         .fallback_service(ServeFile::new("frontend/dist/index.html"))
@@ -209,7 +212,7 @@ async fn main() -> anyhow::Result<()> {
         }));
     }
 
-    handles.push(tokio::spawn(recycler_worker(pool)));
+    handles.push(tokio::spawn(recycler_worker(recycler_state)));
 
     for handle in handles {
         handle.await?;
@@ -1,7 +1,7 @@
 use diesel::prelude::*;
 use serde::Serialize;
 
-#[derive(Queryable, Selectable, Identifiable, Debug)]
+#[derive(Queryable, Selectable, Identifiable, Debug, Clone)]
 #[diesel(table_name = crate::schema::pipelines)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct Pipeline {
@@ -15,6 +15,21 @@ pub struct Pipeline {
     pub github_pr: Option<i64>,
     pub telegram_user: Option<i64>,
     pub creator_user_id: Option<i32>,
+    pub patch: Option<String>,
+    /// Title of PR to open once all jobs succeed
+    pub deferred_pr_title: Option<String>,
+    /// Git ref for workers to fetch instead of git_branch, e.g. refs/pull/N/merge
+    pub git_ref: Option<String>,
+    /// Notify the telegram user when each job is picked up by a worker
+    pub notify_start: bool,
+    /// Git remote for workers to fetch from instead of the configured ABBS repo, e.g. PR from a fork
+    pub git_remote: Option<String>,
+    /// Client-supplied key making retried submissions return this pipeline
+    pub idempotency_key: Option<String>,
+    /// Extra space-separated `ciel build` flags, see `common::ALLOWED_CIEL_FLAGS`
+    pub ciel_flags: Option<String>,
+    /// Campaign tag grouping pipelines of a coordinated rebuild, see `/retrycampaign`
+    pub tag: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -30,9 +45,16 @@ pub struct NewPipeline {
     pub github_pr: Option<i64>,
     pub telegram_user: Option<i64>,
     pub creator_user_id: Option<i32>,
+    pub patch: Option<String>,
+    pub git_ref: Option<String>,
+    pub notify_start: bool,
+    pub git_remote: Option<String>,
+    pub idempotency_key: Option<String>,
+    pub ciel_flags: Option<String>,
+    pub tag: Option<String>,
 }
 
-#[derive(Queryable, Selectable, Associations, Identifiable, Debug)]
+#[derive(Queryable, Selectable, Associations, Identifiable, Debug, Clone)]
 #[diesel(belongs_to(Pipeline))]
 #[diesel(table_name = crate::schema::jobs)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
@@ -60,6 +82,22 @@ pub struct Job {
     pub require_min_total_mem_per_core: Option<f32>,
     pub require_min_disk: Option<i64>,
     pub assign_time: Option<chrono::DateTime<chrono::Utc>>,
+    pub repush_only: bool,
+    pub verbose: bool,
+    /// The job this one was restarted from
+    pub restarted_from: Option<i32>,
+    /// JSON list of `common::Artifact`, None for jobs predating artifact tracking
+    pub artifacts: Option<String>,
+    /// Comma-separated tags a worker must have to build the job
+    pub required_tags: Option<String>,
+    /// Jobs with higher priority are assigned first
+    pub priority: i32,
+    /// Machine class the job is preferably assigned to, see `common::job_machine_class`
+    pub preferred_machine_class: Option<String>,
+    /// JSON list of step name and seconds spent, see `common::JobOk::step_timings`
+    pub step_timings: Option<String>,
+    /// Times the job was requeued after its worker disappeared mid-build
+    pub reclaim_count: i32,
 }
 
 #[derive(Insertable)]
@@ -76,6 +114,16 @@ pub struct NewJob {
     pub require_min_total_mem: Option<i64>,
     pub require_min_total_mem_per_core: Option<f32>,
     pub require_min_disk: Option<i64>,
+    pub built_by_worker_id: Option<i32>,
+    pub repush_only: bool,
+    pub verbose: bool,
+    /// The job this one was restarted from
+    pub restarted_from: Option<i32>,
+    /// Comma-separated tags a worker must have to build the job
+    pub required_tags: Option<String>,
+    pub preferred_machine_class: Option<String>,
+    /// Jobs with higher priority are assigned first
+    pub priority: i32,
 }
 
 #[derive(Queryable, Selectable, Serialize, Debug)]
@@ -93,6 +141,15 @@ pub struct Worker {
     pub performance: Option<i64>,
     pub visible: bool,
     pub internet_connectivity: bool,
+    /// Comma-separated ciel instance names
+    pub ciel_instances: Option<String>,
+    /// Comma-separated tags, e.g. `bigdisk,gpu`
+    pub tags: Option<String>,
+    /// Machine class set explicitly by the worker, derived from cores and memory if None
+    pub machine_class: Option<String>,
+    /// Websocket reconnections within the last hour, as of the last heartbeat
+    pub websocket_reconnects: Option<i32>,
+    pub websocket_last_error: Option<String>,
 }
 
 #[derive(Insertable, AsChangeset)]
@@ -108,6 +165,15 @@ pub struct NewWorker {
     pub disk_free_space_bytes: i64,
     pub performance: Option<i64>,
     pub internet_connectivity: bool,
+    /// Comma-separated ciel instance names
+    pub ciel_instances: Option<String>,
+    /// Comma-separated tags, e.g. `bigdisk,gpu`
+    pub tags: Option<String>,
+    /// Machine class set explicitly by the worker, derived from cores and memory if None
+    pub machine_class: Option<String>,
+    /// Websocket reconnections within the last hour, as of the last heartbeat
+    pub websocket_reconnects: Option<i32>,
+    pub websocket_last_error: Option<String>,
 }
 
 #[derive(Queryable, Selectable)]
@@ -121,6 +187,8 @@ pub struct User {
     pub github_avatar_url: Option<String>,
     pub github_email: Option<String>,
     pub telegram_chat_id: Option<i64>,
+    /// SHA-256 of the secret part of the API token
+    pub token_hash: Option<String>,
 }
 
 #[derive(Insertable, AsChangeset)]
@@ -134,3 +202,23 @@ pub struct NewUser {
     pub github_email: Option<String>,
     pub telegram_chat_id: Option<i64>,
 }
+
+/// Runtime override of environment requirements of a package
+#[derive(Queryable, Selectable, Insertable, AsChangeset)]
+#[diesel(table_name = crate::schema::env_req_overrides)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct EnvReqOverride {
+    pub package: String,
+    /// Requirements in ENVREQ syntax, e.g. `core=8 total_mem=16`
+    pub envreq: String,
+    pub update_time: chrono::DateTime<chrono::Utc>,
+}
+
+/// Last time a running job was reported by its worker
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::job_last_seen)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct JobLastSeen {
+    pub job_id: i32,
+    pub last_seen_time: chrono::DateTime<chrono::Utc>,
+}
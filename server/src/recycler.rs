@@ -1,14 +1,17 @@
 use crate::{
-    models::{Job, Worker},
-    DbPool, HEARTBEAT_TIMEOUT,
+    models::{Job, Pipeline, Worker},
+    routes::{worker::complete_job, AppState},
+    ARGS, DEFAULT_MAX_JOB_RECLAIMS, HEARTBEAT_TIMEOUT,
 };
 use anyhow::Context;
 use chrono::Utc;
+use common::{JobOk, JobResult, WorkerJobUpdateRequest};
 use diesel::{ExpressionMethods, JoinOnDsl, NullableExpressionMethods, QueryDsl, RunQueryDsl};
 use std::time::Duration;
 use tracing::{info, warn};
 
-pub async fn recycler_worker_inner(pool: DbPool) -> anyhow::Result<()> {
+pub async fn recycler_worker_inner(state: &AppState) -> anyhow::Result<()> {
+    let pool = &state.pool;
     loop {
         // recycle jobs whose worker is dead
         use crate::schema::{jobs, workers};
@@ -24,17 +27,75 @@ pub async fn recycler_worker_inner(pool: DbPool) -> anyhow::Result<()> {
                     .eq(jobs::dsl::assigned_worker_id)),
             )
             .filter(workers::dsl::last_heartbeat_time.lt(deadline))
+            .filter(jobs::dsl::status.eq("running"))
             .load::<(Job, Worker)>(&mut conn)?;
 
+        let max_reclaims = ARGS.max_job_reclaims.unwrap_or(DEFAULT_MAX_JOB_RECLAIMS);
         for (job, worker) in res {
+            let reclaim_count = job.reclaim_count + 1;
+            if reclaim_count > max_reclaims {
+                // probably the job itself takes the workers down, stop rescheduling it
+                warn!(
+                    "Job {} was assigned to worker {}, but the worker disappeared, \
+                     failing it after {} reclamations",
+                    job.id, worker.id, job.reclaim_count
+                );
+                let pipeline = crate::schema::pipelines::dsl::pipelines
+                    .find(job.pipeline_id)
+                    .first::<Pipeline>(&mut conn)?;
+                // fail it like a failed build, so that check run and users are notified
+                let job_id = job.id;
+                let elapsed_secs = job
+                    .assign_time
+                    .map(|time| (Utc::now() - time).num_seconds())
+                    .unwrap_or_default();
+                let payload = WorkerJobUpdateRequest {
+                    hostname: worker.hostname.clone(),
+                    arch: worker.arch.clone(),
+                    job_id,
+                    result: JobResult::Ok(JobOk {
+                        build_success: false,
+                        successful_packages: vec![],
+                        failed_package: None,
+                        skipped_packages: vec![],
+                        log_url: None,
+                        elapsed_secs,
+                        pushpkg_success: false,
+                        failure_stage: None,
+                        artifacts: None,
+                        step_timings: vec![],
+                    }),
+                    worker_secret: String::new(),
+                };
+                complete_job(
+                    pool,
+                    &state.bot,
+                    &state.pipeline_events_map,
+                    job,
+                    pipeline,
+                    &worker,
+                    payload,
+                )
+                .await?;
+                diesel::update(jobs::dsl::jobs.find(job_id))
+                    .set(jobs::dsl::error_message.eq(format!(
+                        "Workers disappeared while building the job {} times",
+                        reclaim_count
+                    )))
+                    .execute(&mut conn)?;
+                continue;
+            }
+
             info!(
-                "Job {} was assigned to worker {}, but the worker disappeared",
-                job.id, worker.id
+                "Job {} was assigned to worker {}, but the worker disappeared, \
+                 reclaiming it ({}/{})",
+                job.id, worker.id, reclaim_count, max_reclaims
             );
             diesel::update(jobs::dsl::jobs.find(job.id))
                 .set((
                     jobs::dsl::status.eq("created"),
                     jobs::dsl::assigned_worker_id.eq(None::<i32>),
+                    jobs::dsl::reclaim_count.eq(reclaim_count),
                 ))
                 .execute(&mut conn)?;
         }
@@ -43,10 +104,10 @@ pub async fn recycler_worker_inner(pool: DbPool) -> anyhow::Result<()> {
     }
 }
 
-pub async fn recycler_worker(pool: DbPool) {
+pub async fn recycler_worker(state: AppState) {
     loop {
         info!("Starting recycler worker");
-        if let Err(err) = recycler_worker_inner(pool.clone()).await {
+        if let Err(err) = recycler_worker_inner(&state).await {
             warn!("Got error running recycler worker: {}", err);
         }
         tokio::time::sleep(Duration::from_secs(5)).await;
@@ -1,49 +1,60 @@
+use crate::api::{compute_job_timing, JobSchedulability};
 use crate::models::{Job, Pipeline, User, Worker};
-use crate::routes::{AnyhowError, AppState};
-use anyhow::Context;
+use crate::routes::{job_log_stream, AnyhowError, AppState};
+use crate::ARGS;
+use anyhow::{anyhow, bail, Context};
+use axum::body::Bytes;
 use axum::extract::{Json, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use common::api::{
+    JobInfoResponse, JobListResponse, JobListResponseItem, JobRestartRequest, JobRestartResponse,
+};
+use common::{
+    JobUploadLogRequest, JobUploadLogResponse, MAX_UPLOADED_LOG_BYTES, WORKER_SECRET_HEADER,
+};
 use diesel::{
-    Connection, ExpressionMethods, JoinOnDsl, NullableExpressionMethods, QueryDsl, RunQueryDsl,
+    Connection, ExpressionMethods, JoinOnDsl, NullableExpressionMethods, OptionalExtension,
+    PgConnection, QueryDsl, QueryResult, RunQueryDsl,
 };
-use serde::{Deserialize, Serialize};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 #[derive(Deserialize)]
 pub struct JobListRequest {
+    #[serde(default = "default_page")]
     page: i64,
     items_per_page: i64,
+    /// Keyset pagination: list jobs older than this job id, instead of using `page`
+    before_id: Option<i32>,
+    /// Keyset pagination: list jobs newer than this job id, instead of using `page`
+    after_id: Option<i32>,
 }
 
-#[derive(Serialize)]
-pub struct JobListResponseItem {
-    id: i32,
-    pipeline_id: i32,
-    packages: String,
-    arch: String,
-    status: String,
-    elapsed_secs: Option<i64>,
-    creation_time: chrono::DateTime<chrono::Utc>,
-    log_url: Option<String>,
-    build_success: Option<bool>,
-
-    // from pipeline
-    git_branch: String,
-    git_sha: String,
-    github_pr: Option<i64>,
-
-    // from pipeline creator
-    creator_github_login: Option<String>,
-    creator_github_avatar_url: Option<String>,
+fn default_page() -> i64 {
+    1
 }
 
-#[derive(Serialize)]
-pub struct JobListResponse {
-    total_items: i64,
-    items: Vec<JobListResponseItem>,
+/// Cursor to continue in the same direction, given job ids in query order.
+/// None if the last page has been reached.
+fn job_list_next_cursor(ids: &[i32], items_per_page: i64) -> Option<i32> {
+    if items_per_page < 0 || (ids.len() as i64) < items_per_page {
+        None
+    } else {
+        ids.last().copied()
+    }
 }
 
 pub async fn job_list(
     Query(query): Query<JobListRequest>,
-    State(AppState { pool, .. }): State<AppState>,
+    State(AppState {
+        replica_pool: pool, ..
+    }): State<AppState>,
 ) -> Result<Json<JobListResponse>, AnyhowError> {
     let mut conn = pool
         .get()
@@ -53,24 +64,52 @@ pub async fn job_list(
         conn.transaction::<JobListResponse, anyhow::Error, _>(|conn| {
             let total_items = crate::schema::jobs::dsl::jobs.count().get_result(conn)?;
 
-            let sql = crate::schema::jobs::dsl::jobs
+            let mut sql = crate::schema::jobs::dsl::jobs
                 .inner_join(crate::schema::pipelines::dsl::pipelines)
                 .left_join(
                     crate::schema::users::dsl::users
                         .on(crate::schema::pipelines::dsl::creator_user_id
                             .eq(crate::schema::users::dsl::id.nullable())),
                 )
-                .order(crate::schema::jobs::dsl::id.desc());
+                .into_boxed();
+
+            match (query.before_id, query.after_id) {
+                (Some(_), Some(_)) => {
+                    anyhow::bail!("before_id and after_id cannot be used together")
+                }
+                (Some(before_id), None) => {
+                    sql = sql
+                        .filter(crate::schema::jobs::dsl::id.lt(before_id))
+                        .order(crate::schema::jobs::dsl::id.desc());
+                }
+                (None, Some(after_id)) => {
+                    // walk upwards from after_id, reversed below to keep newest first
+                    sql = sql
+                        .filter(crate::schema::jobs::dsl::id.gt(after_id))
+                        .order(crate::schema::jobs::dsl::id.asc());
+                }
+                (None, None) => {
+                    sql = sql.order(crate::schema::jobs::dsl::id.desc());
+                    if query.items_per_page != -1 {
+                        sql = sql.offset((query.page - 1) * query.items_per_page);
+                    }
+                }
+            }
 
             // all
-            let res = if query.items_per_page == -1 {
+            let mut res = if query.items_per_page == -1 {
                 sql.load::<(Job, Pipeline, Option<User>)>(conn)?
             } else {
-                sql.offset((query.page - 1) * query.items_per_page)
-                    .limit(query.items_per_page)
+                sql.limit(query.items_per_page)
                     .load::<(Job, Pipeline, Option<User>)>(conn)?
             };
 
+            let ids = res.iter().map(|(job, _, _)| job.id).collect::<Vec<_>>();
+            let next_cursor = job_list_next_cursor(&ids, query.items_per_page);
+            if query.after_id.is_some() {
+                res.reverse();
+            }
+
             let mut items = vec![];
             for (job, pipeline, creator) in res {
                 items.push(JobListResponseItem {
@@ -99,7 +138,11 @@ pub async fn job_list(
                 });
             }
 
-            Ok(JobListResponse { total_items, items })
+            Ok(JobListResponse {
+                total_items,
+                items,
+                next_cursor,
+            })
         })?,
     ))
 }
@@ -109,125 +152,457 @@ pub struct JobInfoRequest {
     job_id: i32,
 }
 
-#[derive(Serialize)]
-pub struct JobInfoResponse {
-    // from job
-    job_id: i32,
-    pipeline_id: i32,
-    packages: String,
-    arch: String,
-    creation_time: chrono::DateTime<chrono::Utc>,
-    status: String,
-    build_success: Option<bool>,
-    pushpkg_success: Option<bool>,
-    successful_packages: Option<String>,
-    failed_package: Option<String>,
-    skipped_packages: Option<String>,
-    log_url: Option<String>,
-    finish_time: Option<chrono::DateTime<chrono::Utc>>,
-    error_message: Option<String>,
-    elapsed_secs: Option<i64>,
-    assigned_worker_id: Option<i32>,
-    built_by_worker_id: Option<i32>,
-    require_min_core: Option<i32>,
-    require_min_total_mem: Option<i64>,
-    require_min_total_mem_per_core: Option<f32>,
-    require_min_disk: Option<i64>,
-    assign_time: Option<chrono::DateTime<chrono::Utc>>,
-
-    // from pipeline
-    git_branch: String,
-    git_sha: String,
-    github_pr: Option<i64>,
-
-    // from worker
-    assigned_worker_hostname: Option<String>,
-    built_by_worker_hostname: Option<String>,
-}
-
 pub async fn job_info(
     Query(query): Query<JobInfoRequest>,
-    State(AppState { pool, .. }): State<AppState>,
+    State(AppState {
+        pool, ws_state_map, ..
+    }): State<AppState>,
 ) -> Result<Json<JobInfoResponse>, AnyhowError> {
     let mut conn = pool
         .get()
         .context("Failed to get db connection from pool")?;
 
+    let mut info = conn.transaction::<JobInfoResponse, diesel::result::Error, _>(|conn| {
+        load_job_info(conn, query.job_id)
+    })?;
+    info.live_log_stream = job_log_stream(&ws_state_map, query.job_id);
+    Ok(Json(info))
+}
+
+/// Job details along with its pipeline and workers
+pub fn load_job_info(conn: &mut PgConnection, job_id: i32) -> QueryResult<JobInfoResponse> {
+    // use alias to allow joining workers table twice
+    // https://github.com/diesel-rs/diesel/issues/2569
+    // https://github.com/diesel-rs/diesel/pull/2254
+    // https://docs.rs/diesel/latest/diesel/macro.alias.html
+    let assigned_workers = diesel::alias!(crate::schema::workers as assigned_workers);
+    let (job, pipeline, assigned_worker, built_by_worker) = crate::schema::jobs::dsl::jobs
+        .find(job_id)
+        .inner_join(crate::schema::pipelines::dsl::pipelines)
+        .left_join(
+            assigned_workers.on(
+                crate::schema::jobs::dsl::assigned_worker_id.eq(assigned_workers
+                    .field(crate::schema::workers::dsl::id)
+                    .nullable()),
+            ),
+        )
+        .left_join(
+            crate::schema::workers::dsl::workers.on(crate::schema::jobs::dsl::built_by_worker_id
+                .eq(crate::schema::workers::dsl::id.nullable())),
+        )
+        .get_result::<(Job, Pipeline, Option<Worker>, Option<Worker>)>(conn)?;
+
+    let timing = compute_job_timing(&job);
+
+    let restarted_to = crate::schema::jobs::dsl::jobs
+        .filter(crate::schema::jobs::dsl::restarted_from.eq(job.id))
+        .order(crate::schema::jobs::dsl::id.desc())
+        .select(crate::schema::jobs::dsl::id)
+        .first::<i32>(conn)
+        .optional()?;
+
+    Ok(JobInfoResponse {
+        job_id: job.id,
+        pipeline_id: job.pipeline_id,
+        packages: job.packages,
+        arch: job.arch,
+        creation_time: job.creation_time,
+        status: job.status,
+        build_success: job.build_success,
+        pushpkg_success: job.pushpkg_success,
+        successful_packages: job.successful_packages,
+        failed_package: job.failed_package,
+        skipped_packages: job.skipped_packages,
+        log_url: job.log_url,
+        finish_time: job.finish_time,
+        error_message: job.error_message,
+        elapsed_secs: job.elapsed_secs,
+        assigned_worker_id: job.assigned_worker_id,
+        built_by_worker_id: job.built_by_worker_id,
+        require_min_core: job.require_min_core,
+        require_min_total_mem: job.require_min_total_mem,
+        require_min_total_mem_per_core: job.require_min_total_mem_per_core,
+        require_min_disk: job.require_min_disk,
+        required_tags: job.required_tags,
+        assign_time: job.assign_time,
+        restarted_from: job.restarted_from,
+        restarted_to,
+        priority: job.priority,
+        step_timings: job
+            .step_timings
+            .as_deref()
+            .and_then(|timings| serde_json::from_str(timings).ok()),
+
+        // timing
+        queued_secs: timing.queued_secs,
+        build_secs: timing.build_secs,
+
+        // from pipeline
+        git_branch: pipeline.git_branch,
+        git_sha: pipeline.git_sha,
+        github_pr: pipeline.github_pr,
+
+        // from worker
+        assigned_worker_hostname: assigned_worker.map(|w| w.hostname),
+        built_by_worker_hostname: built_by_worker.map(|w| w.hostname),
+
+        // filled in by `job_info`, only known in memory
+        live_log_stream: None,
+    })
+}
+
+pub async fn job_restart(
+    State(AppState { pool, .. }): State<AppState>,
+    Json(payload): Json<JobRestartRequest>,
+) -> Result<Json<JobRestartResponse>, AnyhowError> {
+    let new_job = crate::api::job_restart(pool, payload.job_id, payload.verbose).await?;
+    return Ok(Json(JobRestartResponse { job_id: new_job.id }));
+}
+
+pub async fn job_repush(
+    State(AppState { pool, .. }): State<AppState>,
+    Json(payload): Json<JobRestartRequest>,
+) -> Result<Json<JobRestartResponse>, AnyhowError> {
+    let new_job = crate::api::job_repush(pool, payload.job_id).await?;
+    Ok(Json(JobRestartResponse { job_id: new_job.id }))
+}
+
+pub async fn job_schedulability(
+    Query(query): Query<JobInfoRequest>,
+    State(AppState { pool, .. }): State<AppState>,
+) -> Result<Json<JobSchedulability>, AnyhowError> {
     Ok(Json(
-        conn.transaction::<JobInfoResponse, diesel::result::Error, _>(|conn| {
-            // use alias to allow joining workers table twice
-            // https://github.com/diesel-rs/diesel/issues/2569
-            // https://github.com/diesel-rs/diesel/pull/2254
-            // https://docs.rs/diesel/latest/diesel/macro.alias.html
-            let assigned_workers = diesel::alias!(crate::schema::workers as assigned_workers);
-            let (job, pipeline, assigned_worker, built_by_worker) = crate::schema::jobs::dsl::jobs
-                .find(query.job_id)
-                .inner_join(crate::schema::pipelines::dsl::pipelines)
-                .left_join(
-                    assigned_workers.on(crate::schema::jobs::dsl::assigned_worker_id.eq(
-                        assigned_workers
-                            .field(crate::schema::workers::dsl::id)
-                            .nullable(),
-                    )),
-                )
-                .left_join(
-                    crate::schema::workers::dsl::workers
-                        .on(crate::schema::jobs::dsl::built_by_worker_id
-                            .eq(crate::schema::workers::dsl::id.nullable())),
-                )
-                .get_result::<(Job, Pipeline, Option<Worker>, Option<Worker>)>(conn)?;
-
-            Ok(JobInfoResponse {
-                job_id: job.id,
-                pipeline_id: job.pipeline_id,
-                packages: job.packages,
-                arch: job.arch,
-                creation_time: job.creation_time,
-                status: job.status,
-                build_success: job.build_success,
-                pushpkg_success: job.pushpkg_success,
-                successful_packages: job.successful_packages,
-                failed_package: job.failed_package,
-                skipped_packages: job.skipped_packages,
-                log_url: job.log_url,
-                finish_time: job.finish_time,
-                error_message: job.error_message,
-                elapsed_secs: job.elapsed_secs,
-                assigned_worker_id: job.assigned_worker_id,
-                built_by_worker_id: job.built_by_worker_id,
-                require_min_core: job.require_min_core,
-                require_min_total_mem: job.require_min_total_mem,
-                require_min_total_mem_per_core: job.require_min_total_mem_per_core,
-                require_min_disk: job.require_min_disk,
-                assign_time: job.assign_time,
-
-                // from pipeline
-                git_branch: pipeline.git_branch,
-                git_sha: pipeline.git_sha,
-                github_pr: pipeline.github_pr,
-
-                // from worker
-                assigned_worker_hostname: assigned_worker.map(|w| w.hostname),
-                built_by_worker_hostname: built_by_worker.map(|w| w.hostname),
-            })
-        })?,
+        crate::api::job_schedulability(pool, query.job_id).await?,
     ))
 }
 
 #[derive(Deserialize)]
-pub struct JobRestartRequest {
+pub struct JobLogRequest {
     job_id: i32,
+    /// Only return the last N lines
+    tail: Option<usize>,
 }
 
-#[derive(Serialize)]
-pub struct JobRestartResponse {
-    job_id: i32,
+/// Prefix of log urls of logs uploaded by workers
+const LOGS_URL_PREFIX: &str = "https://buildit.aosc.io/logs/";
+
+pub async fn job_log(
+    headers: HeaderMap,
+    Query(query): Query<JobLogRequest>,
+    State(AppState { pool, .. }): State<AppState>,
+) -> Result<Response, AnyhowError> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let log_url = crate::schema::jobs::dsl::jobs
+        .find(query.job_id)
+        .select(crate::schema::jobs::dsl::log_url)
+        .get_result::<Option<String>>(&mut conn)
+        .optional()?
+        .flatten();
+    let Some(log_url) = log_url else {
+        return Ok((StatusCode::NOT_FOUND, "Job log not found").into_response());
+    };
+
+    // read the file directly if logs are stored locally
+    let local_path = log_url
+        .strip_prefix(LOGS_URL_PREFIX)
+        .filter(|file_name| !file_name.contains('/'))
+        .zip(ARGS.logs_path.as_ref())
+        .map(|(file_name, logs_path)| logs_path.join(file_name));
+    let compressed = match &local_path {
+        Some(path) => tokio::fs::try_exists(compressed_log_path(path))
+            .await
+            .unwrap_or(false),
+        None => false,
+    };
+    let log: Vec<u8> = match local_path {
+        Some(path) if compressed => {
+            let content =
+                tokio::task::spawn_blocking(move || std::fs::read(compressed_log_path(&path)))
+                    .await?
+                    .context("Failed to read job log")?;
+            // pass the compressed log through if the whole log is requested
+            if query.tail.is_none() && accepts_gzip(&headers) {
+                return Ok((
+                    [
+                        (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+                        (header::CONTENT_ENCODING, "gzip"),
+                    ],
+                    content,
+                )
+                    .into_response());
+            }
+            tokio::task::spawn_blocking(move || gunzip(&content))
+                .await?
+                .context("Failed to decompress job log")?
+        }
+        Some(path) => tokio::task::spawn_blocking(move || std::fs::read(path))
+            .await?
+            .context("Failed to read job log")?,
+        None => fetch_remote_log(&log_url, query.tail).await?,
+    };
+
+    let log = match query.tail {
+        Some(n) => tail_lines(&log, n),
+        None => &log,
+    };
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        String::from_utf8_lossy(log).into_owned(),
+    )
+        .into_response())
 }
 
-pub async fn job_restart(
+/// Client for fetching remote logs, e.g. pastes, without hanging on slow servers
+static LOG_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .user_agent("buildit")
+        .timeout(Duration::from_secs(60))
+        .build()
+        .unwrap()
+});
+
+/// Largest remote log read into memory
+const MAX_REMOTE_LOG_BYTES: usize = 4 * MAX_UPLOADED_LOG_BYTES;
+
+/// Fetch a remote log, keeping only the last `tail` lines while reading if set
+async fn fetch_remote_log(log_url: &str, tail: Option<usize>) -> anyhow::Result<Vec<u8>> {
+    let resp = LOG_CLIENT
+        .get(log_url)
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .context("Failed to fetch job log")?;
+    let mut log = vec![];
+    let mut stream = resp.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        log.extend_from_slice(&chunk.context("Failed to fetch job log")?);
+        if let Some(n) = tail {
+            let start = log.len() - tail_lines(&log, n).len();
+            log.drain(..start);
+        }
+        if log.len() > MAX_REMOTE_LOG_BYTES {
+            bail!("Job log is larger than {MAX_REMOTE_LOG_BYTES} bytes, try with tail");
+        }
+    }
+    Ok(log)
+}
+
+/// Store a log uploaded by a worker that failed to scp it, returning its url
+pub async fn job_upload_log(
+    headers: HeaderMap,
+    Query(query): Query<JobUploadLogRequest>,
     State(AppState { pool, .. }): State<AppState>,
-    Json(payload): Json<JobRestartRequest>,
-) -> Result<Json<JobRestartResponse>, AnyhowError> {
-    let new_job = crate::api::job_restart(pool, payload.job_id).await?;
-    return Ok(Json(JobRestartResponse { job_id: new_job.id }));
+    body: Bytes,
+) -> Result<Json<JobUploadLogResponse>, AnyhowError> {
+    if headers
+        .get(WORKER_SECRET_HEADER)
+        .and_then(|v| v.to_str().ok())
+        != Some(ARGS.worker_secret.as_str())
+    {
+        return Err(anyhow!("Invalid worker secret").into());
+    }
+    let Some(logs_path) = &ARGS.logs_path else {
+        return Err(anyhow!("Log upload is not enabled, BUILDIT_LOGS_PATH is not set").into());
+    };
+    if !valid_log_file_name(&query.file_name, query.job_id) {
+        return Err(anyhow!("Invalid log file name: {}", query.file_name).into());
+    }
+    if body.len() > MAX_UPLOADED_LOG_BYTES {
+        return Err(anyhow!("Log is too large: {} bytes", body.len()).into());
+    }
+
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    let job = crate::schema::jobs::dsl::jobs
+        .find(query.job_id)
+        .first::<Job>(&mut conn)?;
+    let worker = crate::schema::workers::dsl::workers
+        .filter(crate::schema::workers::dsl::hostname.eq(&query.hostname))
+        .filter(crate::schema::workers::dsl::arch.eq(&query.arch))
+        .first::<Worker>(&mut conn)?;
+    if job.status != "running" || job.assigned_worker_id != Some(worker.id) {
+        return Err(anyhow!("Worker not assigned to the job").into());
+    }
+
+    let path = logs_path.join(&query.file_name);
+    if ARGS.compress_logs.unwrap_or(false) {
+        let content = tokio::task::spawn_blocking(move || gzip(&body))
+            .await?
+            .context("Failed to compress job log")?;
+        tokio::fs::write(compressed_log_path(&path), content)
+            .await
+            .context("Failed to save job log")?;
+    } else {
+        tokio::fs::write(path, &body)
+            .await
+            .context("Failed to save job log")?;
+    }
+    Ok(Json(JobUploadLogResponse {
+        log_url: format!("{LOGS_URL_PREFIX}{}", query.file_name),
+    }))
+}
+
+/// Log file names are chosen by workers, make sure they stay in the logs directory
+fn valid_log_file_name(file_name: &str, job_id: i32) -> bool {
+    file_name.starts_with(&format!("{job_id}-"))
+        && file_name.ends_with(".txt")
+        && !file_name.contains("..")
+        && file_name
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || "-_.:+".contains(ch))
+}
+
+/// Log stored compressed in place of `path`, see `Args::compress_logs`
+fn compressed_log_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".gz");
+    path.into()
+}
+
+fn gzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+fn gunzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut res = vec![];
+    GzDecoder::new(data).read_to_end(&mut res)?;
+    Ok(res)
+}
+
+/// Whether `Accept-Encoding` of the request allows gzip, e.g. `gzip, deflate`
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|encoding| {
+            let mut parts = encoding.split(';').map(|part| part.trim());
+            matches!(parts.next(), Some("gzip" | "*"))
+                && !parts.any(|param| {
+                    param.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0)
+                })
+        })
+}
+
+/// Last `n` lines of `text`
+fn tail_lines(text: &[u8], n: usize) -> &[u8] {
+    if n == 0 {
+        return &[];
+    }
+    let trimmed = text.strip_suffix(b"\n").unwrap_or(text);
+    match trimmed
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, c)| **c == b'\n')
+        .nth(n - 1)
+    {
+        Some((idx, _)) => &text[idx + 1..],
+        None => text,
+    }
+}
+
+#[test]
+fn test_tail_lines() {
+    let text = b"a\nb\nc\n";
+    assert_eq!(tail_lines(text, 0), b"");
+    assert_eq!(tail_lines(text, 1), b"c\n");
+    assert_eq!(tail_lines(text, 2), b"b\nc\n");
+    assert_eq!(tail_lines(text, 3), text);
+    assert_eq!(tail_lines(text, 10), text);
+    assert_eq!(tail_lines(b"a\nb", 1), b"b");
+
+    // keeping the tail chunk by chunk while streaming gives the same result
+    let mut log = vec![];
+    for chunk in [&b"a\nb"[..], b"b\nc", b"\nd\n", b"e"] {
+        log.extend_from_slice(chunk);
+        let start = log.len() - tail_lines(&log, 2).len();
+        log.drain(..start);
+    }
+    assert_eq!(log, b"d\ne");
+}
+
+#[test]
+fn test_valid_log_file_name() {
+    assert!(valid_log_file_name(
+        "42-stable-amd64-builder1-2025-01-25-08:34:12.txt",
+        42
+    ));
+    assert!(!valid_log_file_name("43-stable.txt", 42));
+    assert!(!valid_log_file_name("42-stable.log", 42));
+    assert!(!valid_log_file_name("42-../../etc/passwd.txt", 42));
+    assert!(!valid_log_file_name("42-..txt", 42));
+}
+
+#[test]
+fn test_job_list_next_cursor() {
+    // job ids with gaps, e.g. from deleted jobs
+    let table = [1, 2, 3, 5, 8, 9, 10, 14, 15, 20];
+    for items_per_page in 1..=12 {
+        // before_id: newest to oldest, same as `id < before_id ORDER BY id DESC LIMIT n`
+        let mut walked = vec![];
+        let mut cursor = None;
+        loop {
+            let ids = table
+                .iter()
+                .rev()
+                .copied()
+                .filter(|id| cursor.is_none_or(|before_id| *id < before_id))
+                .take(items_per_page as usize)
+                .collect::<Vec<_>>();
+            walked.extend_from_slice(&ids);
+            cursor = job_list_next_cursor(&ids, items_per_page);
+            if cursor.is_none() {
+                break;
+            }
+        }
+        assert_eq!(walked, table.iter().rev().copied().collect::<Vec<_>>());
+
+        // after_id: oldest to newest, same as `id > after_id ORDER BY id ASC LIMIT n`
+        let mut walked = vec![];
+        let mut cursor = Some(0);
+        while let Some(after_id) = cursor {
+            let ids = table
+                .iter()
+                .copied()
+                .filter(|id| *id > after_id)
+                .take(items_per_page as usize)
+                .collect::<Vec<_>>();
+            walked.extend_from_slice(&ids);
+            cursor = job_list_next_cursor(&ids, items_per_page);
+        }
+        assert_eq!(walked, table);
+    }
+    assert_eq!(job_list_next_cursor(&[3, 2, 1], -1), None);
+}
+
+#[test]
+fn test_compressed_log() {
+    let log = b"[INFO]:  Building fish...\n".repeat(100);
+    let compressed = gzip(&log).unwrap();
+    assert!(compressed.len() < log.len());
+    assert_eq!(gunzip(&compressed).unwrap(), log);
+
+    assert_eq!(
+        compressed_log_path(Path::new("/srv/logs/1-fish-amd64.txt")),
+        Path::new("/srv/logs/1-fish-amd64.txt.gz")
+    );
+
+    let mut headers = HeaderMap::new();
+    assert!(!accepts_gzip(&headers));
+    headers.insert(
+        header::ACCEPT_ENCODING,
+        "deflate, gzip;q=0.8".parse().unwrap(),
+    );
+    assert!(accepts_gzip(&headers));
+    headers.insert(header::ACCEPT_ENCODING, "br, gzip;q=0".parse().unwrap());
+    assert!(!accepts_gzip(&headers));
 }
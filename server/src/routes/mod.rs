@@ -1,39 +1,166 @@
-use crate::{DbPool, RemoteAddr, HEARTBEAT_TIMEOUT};
-use anyhow::Context;
+use crate::{
+    api::{user_by_api_token, API_TOKEN_PREFIX},
+    get_conn_with_retry, github_repo_web_url,
+    models::User,
+    DbPool, RemoteAddr, ALL_ARCH, API_FEATURES, GIT_COMMIT, HEARTBEAT_TIMEOUT,
+};
 use axum::{
-    extract::{Json, State},
-    http::StatusCode,
+    async_trait,
+    extract::{DefaultBodyLimit, FromRequestParts, Json, State},
+    http::{
+        header::{self, AUTHORIZATION},
+        request::Parts,
+        HeaderMap, StatusCode,
+    },
     response::{IntoResponse, Response},
+    routing::{get, post, MethodRouter},
 };
 use chrono::Utc;
+use common::api::{DashboardStatusResponse, DashboardStatusResponseByArch, PipelineJobEvent};
+use common::MAX_UPLOADED_LOG_BYTES;
 use diesel::dsl::{count, sum};
 use diesel::{Connection, ExpressionMethods, QueryDsl, RunQueryDsl};
 use futures::channel::mpsc::UnboundedSender;
 use serde::Serialize;
 use std::{
     collections::{BTreeMap, HashMap, VecDeque},
+    hash::{DefaultHasher, Hash, Hasher},
     sync::{Arc, Mutex},
 };
 
 use teloxide::prelude::*;
-use tracing::info;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
 
 pub mod job;
 pub mod pipeline;
+pub mod user;
 pub mod webhook;
 pub mod websocket;
 pub mod worker;
 
 pub use job::*;
 pub use pipeline::*;
+pub use user::*;
 pub use webhook::*;
 pub use websocket::*;
 pub use worker::*;
 
+/// Routes of the api, served along with the frontend
+pub fn api_routes() -> Vec<(&'static str, MethodRouter<AppState>)> {
+    vec![
+        ("/api/ping", get(ping)),
+        ("/api/health", get(health)),
+        ("/api/version", get(version)),
+        ("/api/pipeline/new", post(pipeline_new)),
+        ("/api/pipeline/new_pr", post(pipeline_new_pr)),
+        ("/api/pipeline/restart", post(pipeline_restart)),
+        ("/api/pipeline/status", get(pipeline_status)),
+        ("/api/pipeline/list", get(pipeline_list)),
+        ("/api/pipeline/info", get(pipeline_info)),
+        ("/api/pipeline/export", get(pipeline_export)),
+        ("/api/pipeline/requirements", get(pipeline_requirements)),
+        ("/api/pipeline/events", get(pipeline_events)),
+        ("/api/job/list", get(job_list)),
+        ("/api/job/info", get(job_info)),
+        ("/api/job/log", get(job_log)),
+        (
+            "/api/job/upload_log",
+            post(job_upload_log).layer(DefaultBodyLimit::max(MAX_UPLOADED_LOG_BYTES)),
+        ),
+        ("/api/job/restart", post(job_restart)),
+        ("/api/job/repush", post(job_repush)),
+        ("/api/job/schedulability", get(job_schedulability)),
+        ("/api/worker/heartbeat", post(worker_heartbeat)),
+        ("/api/worker/poll", post(worker_poll)),
+        ("/api/worker/job_update", post(worker_job_update)),
+        ("/api/worker/status", get(worker_status)),
+        ("/api/worker/list", get(worker_list)),
+        ("/api/worker/info", get(worker_info)),
+        ("/api/user/token/rotate", post(user_token_rotate)),
+        ("/api/dashboard/status", get(dashboard_status)),
+        ("/api/ws/viewer/:hostname", get(ws_viewer_handler)),
+        ("/api/ws/worker/:hostname", get(ws_worker_handler)),
+        ("/api/webhook", post(webhook_handler)),
+    ]
+}
+
 pub async fn ping() -> &'static str {
     "PONG"
 }
 
+#[derive(Serialize)]
+pub struct HealthResponse {
+    db: String,
+    live_worker_count_by_arch: BTreeMap<String, i64>,
+}
+
+/// Readiness check: unlike `ping`, fails with 503 if the database is unreachable
+pub async fn health(State(AppState { pool, .. }): State<AppState>) -> Response {
+    let res = pool
+        .get_timeout(std::time::Duration::from_secs(5))
+        .map_err(anyhow::Error::from)
+        .and_then(|mut conn| {
+            diesel::sql_query("SELECT 1").execute(&mut conn)?;
+            let deadline = Utc::now() - chrono::Duration::try_seconds(HEARTBEAT_TIMEOUT).unwrap();
+            let counts = crate::schema::workers::dsl::workers
+                .filter(crate::schema::workers::last_heartbeat_time.gt(deadline))
+                .filter(crate::schema::workers::dsl::visible.eq(true))
+                .group_by(crate::schema::workers::dsl::arch)
+                .select((
+                    crate::schema::workers::dsl::arch,
+                    count(crate::schema::workers::dsl::id),
+                ))
+                .load::<(String, i64)>(&mut conn)?;
+            Ok(counts)
+        });
+
+    match res {
+        Ok(counts) => {
+            // list every arch so that alerts can fire on zero live workers
+            let mut live_worker_count_by_arch: BTreeMap<String, i64> =
+                ALL_ARCH.iter().map(|arch| (arch.to_string(), 0)).collect();
+            live_worker_count_by_arch.extend(counts);
+            Json(HealthResponse {
+                db: "ok".to_string(),
+                live_worker_count_by_arch,
+            })
+            .into_response()
+        }
+        Err(err) => {
+            warn!("Health check failed: {err:?}");
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(HealthResponse {
+                    db: format!("{err}"),
+                    live_worker_count_by_arch: BTreeMap::new(),
+                }),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct VersionResponse {
+    version: &'static str,
+    git_commit: &'static str,
+    features: &'static [&'static str],
+    archs: &'static [&'static str],
+    /// ABBS repo on GitHub, for links to commits and PRs
+    repo_url: String,
+}
+
+pub async fn version() -> Json<VersionResponse> {
+    Json(VersionResponse {
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: GIT_COMMIT,
+        features: API_FEATURES,
+        archs: ALL_ARCH,
+        repo_url: github_repo_web_url(),
+    })
+}
+
 pub struct Viewer {
     remote_addr: RemoteAddr,
     sender: UnboundedSender<axum::extract::ws::Message>,
@@ -43,16 +170,124 @@ pub struct Viewer {
 pub struct WSState {
     last_logs: VecDeque<axum::extract::ws::Message>,
     viewers: Vec<Arc<Viewer>>,
+    /// Job being built by the build slot streaming here
+    job_id: Option<i32>,
 }
 
-// map from hostname to ws state
+impl WSState {
+    /// Up to `count` latest buffered log lines
+    pub fn last_log_lines(&self, count: usize) -> Vec<String> {
+        let lines = self
+            .last_logs
+            .iter()
+            .filter_map(|msg| match msg {
+                axum::extract::ws::Message::Text(line) => Some(line.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        lines[lines.len().saturating_sub(count)..].to_vec()
+    }
+}
+
+// map from live log stream, i.e. hostname or `<hostname>-<slot>`, to ws state
 pub type WSStateMap = Arc<Mutex<HashMap<String, WSState>>>;
 
+/// Live log stream of the running job, see `WorkerPollRequest::log_stream`
+pub fn job_log_stream(ws_state_map: &WSStateMap, job_id: i32) -> Option<String> {
+    ws_state_map
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(_, state)| state.job_id == Some(job_id))
+        .map(|(stream, _)| stream.clone())
+}
+
+/// The job is taken by the build slot streaming to `stream`, drop output of its previous job
+pub fn start_job_log(ws_state_map: &WSStateMap, stream: &str, job_id: i32) {
+    let mut map = ws_state_map.lock().unwrap();
+    let state = map.entry(stream.to_string()).or_default();
+    state.last_logs.clear();
+    state.job_id = Some(job_id);
+}
+
+/// Build of the job finished, do not show its output to viewers of the next job of the stream,
+/// while other build slots of the worker keep streaming
+pub fn finish_job_log(ws_state_map: &WSStateMap, job_id: i32) {
+    let mut map = ws_state_map.lock().unwrap();
+    for state in map.values_mut() {
+        if state.job_id == Some(job_id) {
+            state.last_logs.clear();
+            state.job_id = None;
+        }
+    }
+}
+
+// map from pipeline id to sender of job state changes, see `pipeline_events`
+pub type PipelineEventsMap = Arc<Mutex<HashMap<i32, broadcast::Sender<PipelineJobEvent>>>>;
+
+/// Push a job state change to subscribers of its pipeline, if any
+pub fn publish_pipeline_event(map: &PipelineEventsMap, pipeline_id: i32, event: PipelineJobEvent) {
+    let mut map = map.lock().unwrap();
+    if let Some(tx) = map.get(&pipeline_id) {
+        if tx.send(event).is_err() {
+            // all subscribers have disconnected
+            map.remove(&pipeline_id);
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct AppState {
     pub pool: DbPool,
+    /// Pool of read replica for reporting handlers, same as `pool` if no replica is configured
+    pub replica_pool: DbPool,
     pub bot: Option<Bot>,
     pub ws_state_map: WSStateMap,
+    pub pipeline_events_map: PipelineEventsMap,
+}
+
+/// Split `aoscbldit1_{uid}_{secret}` into user id and secret
+pub fn parse_api_token(token: &str) -> Option<(i32, &str)> {
+    let rest = token.strip_prefix(API_TOKEN_PREFIX)?.strip_prefix('_')?;
+    let (user_id, secret) = rest.split_once('_')?;
+    if secret.is_empty() {
+        return None;
+    }
+    Some((user_id.parse().ok()?, secret))
+}
+
+/// User authenticated by `Authorization: Bearer <api token>`, see `/token` of the bot
+pub struct ApiAuth(pub User);
+
+#[async_trait]
+impl FromRequestParts<AppState> for ApiAuth {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or((StatusCode::UNAUTHORIZED, "Missing API token"))?;
+        let (user_id, secret) =
+            parse_api_token(token).ok_or((StatusCode::UNAUTHORIZED, "Invalid API token"))?;
+
+        match user_by_api_token(state.pool.clone(), user_id, secret).await {
+            Ok(Some(user)) => Ok(ApiAuth(user)),
+            Ok(None) => Err((StatusCode::UNAUTHORIZED, "Invalid API token")),
+            Err(err) => {
+                warn!("Failed to verify API token: {err:?}");
+                Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to verify API token",
+                ))
+            }
+        }
+    }
 }
 
 // learned from https://github.com/tokio-rs/axum/blob/main/examples/anyhow-error-response/src/main.rs
@@ -74,174 +309,281 @@ where
     }
 }
 
-#[derive(Serialize, Default)]
-pub struct DashboardStatusResponseByArch {
-    total_worker_count: i64,
-    live_worker_count: i64,
-    total_logical_cores: i64,
-    total_memory_bytes: bigdecimal::BigDecimal,
+pub async fn dashboard_status(
+    headers: HeaderMap,
+    State(AppState {
+        replica_pool: pool, ..
+    }): State<AppState>,
+) -> Result<Response, AnyhowError> {
+    let mut conn = get_conn_with_retry(&pool).await?;
 
-    total_job_count: i64,
-    pending_job_count: i64,
-    running_job_count: i64,
-}
+    let status = conn.transaction::<DashboardStatusResponse, diesel::result::Error, _>(|conn| {
+        let total_pipeline_count = crate::schema::pipelines::dsl::pipelines
+            .count()
+            .get_result(conn)?;
+        let total_job_count = crate::schema::jobs::dsl::jobs.count().get_result(conn)?;
+        let pending_job_count = crate::schema::jobs::dsl::jobs
+            .filter(crate::schema::jobs::dsl::status.eq("created"))
+            .count()
+            .get_result(conn)?;
+        let running_job_count = crate::schema::jobs::dsl::jobs
+            .filter(crate::schema::jobs::dsl::status.eq("running"))
+            .count()
+            .get_result(conn)?;
+        let finished_job_count = crate::schema::jobs::dsl::jobs
+            .filter(crate::schema::jobs::dsl::status.eq("success"))
+            .or_filter(crate::schema::jobs::dsl::status.eq("failed"))
+            .count()
+            .get_result(conn)?;
+        let cancelled_job_count = crate::schema::jobs::dsl::jobs
+            .filter(crate::schema::jobs::dsl::status.eq("cancelled"))
+            .count()
+            .get_result(conn)?;
+        let total_worker_count = crate::schema::workers::dsl::workers
+            .filter(crate::schema::workers::dsl::visible.eq(true))
+            .count()
+            .get_result(conn)?;
+        let (total_logical_cores, total_memory_bytes) = crate::schema::workers::dsl::workers
+            .select((
+                sum(crate::schema::workers::dsl::logical_cores),
+                sum(crate::schema::workers::dsl::memory_bytes),
+            ))
+            .filter(crate::schema::workers::dsl::visible.eq(true))
+            .get_result::<(Option<i64>, Option<bigdecimal::BigDecimal>)>(conn)?;
 
-#[derive(Serialize)]
-pub struct DashboardStatusResponse {
-    total_pipeline_count: i64,
+        let deadline = Utc::now() - chrono::Duration::try_seconds(HEARTBEAT_TIMEOUT).unwrap();
+        let live_worker_count = crate::schema::workers::dsl::workers
+            .filter(crate::schema::workers::last_heartbeat_time.gt(deadline))
+            .filter(crate::schema::workers::dsl::visible.eq(true))
+            .count()
+            .get_result(conn)?;
+
+        // collect information by arch
+        let mut by_arch: BTreeMap<String, DashboardStatusResponseByArch> = BTreeMap::new();
+
+        for (arch, count, cores, bytes) in crate::schema::workers::dsl::workers
+            .group_by(crate::schema::workers::dsl::arch)
+            .select((
+                crate::schema::workers::dsl::arch,
+                count(crate::schema::workers::dsl::id),
+                sum(crate::schema::workers::dsl::logical_cores),
+                sum(crate::schema::workers::dsl::memory_bytes),
+            ))
+            .filter(crate::schema::workers::dsl::visible.eq(true))
+            .load::<(String, i64, Option<i64>, Option<bigdecimal::BigDecimal>)>(conn)?
+        {
+            by_arch.entry(arch.clone()).or_default().total_worker_count = count;
+            by_arch.entry(arch.clone()).or_default().total_logical_cores =
+                cores.unwrap_or_default();
+            by_arch.entry(arch).or_default().total_memory_bytes = bytes.unwrap_or_default();
+        }
+
+        for (arch, count) in crate::schema::workers::dsl::workers
+            .filter(crate::schema::workers::last_heartbeat_time.gt(deadline))
+            .group_by(crate::schema::workers::dsl::arch)
+            .select((
+                crate::schema::workers::dsl::arch,
+                count(crate::schema::workers::dsl::id),
+            ))
+            .load::<(String, i64)>(conn)?
+        {
+            by_arch.entry(arch).or_default().live_worker_count = count;
+        }
 
-    total_job_count: i64,
-    pending_job_count: i64,
-    running_job_count: i64,
-    finished_job_count: i64,
+        for (arch, count) in crate::schema::jobs::dsl::jobs
+            .group_by(crate::schema::jobs::dsl::arch)
+            .select((
+                crate::schema::jobs::dsl::arch,
+                count(crate::schema::jobs::dsl::id),
+            ))
+            .load::<(String, i64)>(conn)?
+        {
+            let arch = if arch == "noarch" || arch == "optenv32" {
+                "amd64".to_string()
+            } else {
+                arch
+            };
+            by_arch.entry(arch).or_default().total_job_count += count;
+        }
 
-    total_worker_count: i64,
-    live_worker_count: i64,
-    total_logical_cores: i64,
-    total_memory_bytes: bigdecimal::BigDecimal,
+        for (arch, count) in crate::schema::jobs::dsl::jobs
+            .filter(crate::schema::jobs::dsl::status.eq("created"))
+            .group_by(crate::schema::jobs::dsl::arch)
+            .select((
+                crate::schema::jobs::dsl::arch,
+                count(crate::schema::jobs::dsl::id),
+            ))
+            .load::<(String, i64)>(conn)?
+        {
+            let arch = if arch == "noarch" || arch == "optenv32" {
+                "amd64".to_string()
+            } else {
+                arch
+            };
+            by_arch.entry(arch).or_default().pending_job_count += count;
+        }
 
-    by_arch: BTreeMap<String, DashboardStatusResponseByArch>,
+        for (arch, count) in crate::schema::jobs::dsl::jobs
+            .filter(crate::schema::jobs::dsl::status.eq("running"))
+            .group_by(crate::schema::jobs::dsl::arch)
+            .select((
+                crate::schema::jobs::dsl::arch,
+                count(crate::schema::jobs::dsl::id),
+            ))
+            .load::<(String, i64)>(conn)?
+        {
+            let arch = if arch == "noarch" || arch == "optenv32" {
+                "amd64".to_string()
+            } else {
+                arch
+            };
+            by_arch.entry(arch).or_default().running_job_count += count;
+        }
+
+        Ok(DashboardStatusResponse {
+            total_pipeline_count,
+            total_job_count,
+            pending_job_count,
+            running_job_count,
+            finished_job_count,
+            cancelled_job_count,
+            total_worker_count,
+            live_worker_count,
+            total_logical_cores: total_logical_cores.unwrap_or(0),
+            total_memory_bytes: total_memory_bytes.unwrap_or_default(),
+            by_arch,
+        })
+    })?;
+
+    // dashboards poll this endpoint, answer with 304 if nothing changed
+    let etag = dashboard_etag(&status);
+    let cache_headers = [
+        (header::ETAG, etag.clone()),
+        (header::CACHE_CONTROL, "no-cache".to_string()),
+    ];
+    if headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| etag_matches(v, &etag))
+    {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+    Ok((cache_headers, Json(status)).into_response())
 }
 
-pub async fn dashboard_status(
-    State(AppState { pool, .. }): State<AppState>,
-) -> Result<Json<DashboardStatusResponse>, AnyhowError> {
-    let mut conn = pool
-        .get()
-        .context("Failed to get db connection from pool")?;
-
-    Ok(Json(
-        conn.transaction::<DashboardStatusResponse, diesel::result::Error, _>(|conn| {
-            let total_pipeline_count = crate::schema::pipelines::dsl::pipelines
-                .count()
-                .get_result(conn)?;
-            let total_job_count = crate::schema::jobs::dsl::jobs.count().get_result(conn)?;
-            let pending_job_count = crate::schema::jobs::dsl::jobs
-                .filter(crate::schema::jobs::dsl::status.eq("created"))
-                .count()
-                .get_result(conn)?;
-            let running_job_count = crate::schema::jobs::dsl::jobs
-                .filter(crate::schema::jobs::dsl::status.eq("running"))
-                .count()
-                .get_result(conn)?;
-            let finished_job_count = crate::schema::jobs::dsl::jobs
-                .filter(crate::schema::jobs::dsl::status.eq("success"))
-                .or_filter(crate::schema::jobs::dsl::status.eq("failed"))
-                .count()
-                .get_result(conn)?;
-            let total_worker_count = crate::schema::workers::dsl::workers
-                .filter(crate::schema::workers::dsl::visible.eq(true))
-                .count()
-                .get_result(conn)?;
-            let (total_logical_cores, total_memory_bytes) = crate::schema::workers::dsl::workers
-                .select((
-                    sum(crate::schema::workers::dsl::logical_cores),
-                    sum(crate::schema::workers::dsl::memory_bytes),
-                ))
-                .filter(crate::schema::workers::dsl::visible.eq(true))
-                .get_result::<(Option<i64>, Option<bigdecimal::BigDecimal>)>(conn)?;
+/// Strong ETag over every value in the dashboard status,
+/// including live worker counts that change as heartbeats expire
+fn dashboard_etag(status: &DashboardStatusResponse) -> String {
+    let mut hasher = DefaultHasher::new();
+    status.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
 
-            let deadline = Utc::now() - chrono::Duration::try_seconds(HEARTBEAT_TIMEOUT).unwrap();
-            let live_worker_count = crate::schema::workers::dsl::workers
-                .filter(crate::schema::workers::last_heartbeat_time.gt(deadline))
-                .filter(crate::schema::workers::dsl::visible.eq(true))
-                .count()
-                .get_result(conn)?;
+/// Check an `If-None-Match` header value against `etag`
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
 
-            // collect information by arch
-            let mut by_arch: BTreeMap<String, DashboardStatusResponseByArch> = BTreeMap::new();
+#[test]
+fn test_parse_api_token() {
+    assert_eq!(
+        parse_api_token("aoscbldit1_42_abcDEF123"),
+        Some((42, "abcDEF123"))
+    );
+    // secret may contain the separator
+    assert_eq!(parse_api_token("aoscbldit1_42_a_b"), Some((42, "a_b")));
+    assert_eq!(parse_api_token("aoscbldit1_42_"), None);
+    assert_eq!(parse_api_token("aoscbldit1_x_abc"), None);
+    assert_eq!(parse_api_token("aoscbldit2_42_abc"), None);
+    assert_eq!(parse_api_token("aoscbldit142_abc"), None);
+}
 
-            for (arch, count, cores, bytes) in crate::schema::workers::dsl::workers
-                .group_by(crate::schema::workers::dsl::arch)
-                .select((
-                    crate::schema::workers::dsl::arch,
-                    count(crate::schema::workers::dsl::id),
-                    sum(crate::schema::workers::dsl::logical_cores),
-                    sum(crate::schema::workers::dsl::memory_bytes),
-                ))
-                .filter(crate::schema::workers::dsl::visible.eq(true))
-                .load::<(String, i64, Option<i64>, Option<bigdecimal::BigDecimal>)>(conn)?
-            {
-                by_arch.entry(arch.clone()).or_default().total_worker_count = count;
-                by_arch.entry(arch.clone()).or_default().total_logical_cores =
-                    cores.unwrap_or_default();
-                by_arch.entry(arch).or_default().total_memory_bytes = bytes.unwrap_or_default();
-            }
+#[test]
+fn test_etag_matches() {
+    assert!(etag_matches("\"abc\"", "\"abc\""));
+    assert!(etag_matches("W/\"abc\"", "\"abc\""));
+    assert!(etag_matches("\"x\", \"abc\"", "\"abc\""));
+    assert!(etag_matches("*", "\"abc\""));
+    assert!(!etag_matches("\"abd\"", "\"abc\""));
+    assert!(!etag_matches("abc", "\"abc\""));
+}
 
-            for (arch, count) in crate::schema::workers::dsl::workers
-                .filter(crate::schema::workers::last_heartbeat_time.gt(deadline))
-                .group_by(crate::schema::workers::dsl::arch)
-                .select((
-                    crate::schema::workers::dsl::arch,
-                    count(crate::schema::workers::dsl::id),
-                ))
-                .load::<(String, i64)>(conn)?
-            {
-                by_arch.entry(arch).or_default().live_worker_count = count;
-            }
+#[test]
+fn test_job_log_per_slot() {
+    let map = WSStateMap::default();
+    start_job_log(&map, "host-0", 1);
+    start_job_log(&map, "host-1", 2);
+    for stream in ["host-0", "host-1"] {
+        map.lock()
+            .unwrap()
+            .get_mut(stream)
+            .unwrap()
+            .last_logs
+            .push_back(axum::extract::ws::Message::Text(stream.to_string()));
+    }
+    assert_eq!(job_log_stream(&map, 2).as_deref(), Some("host-1"));
 
-            for (arch, count) in crate::schema::jobs::dsl::jobs
-                .group_by(crate::schema::jobs::dsl::arch)
-                .select((
-                    crate::schema::jobs::dsl::arch,
-                    count(crate::schema::jobs::dsl::id),
-                ))
-                .load::<(String, i64)>(conn)?
-            {
-                let arch = if arch == "noarch" || arch == "optenv32" {
-                    "amd64".to_string()
-                } else {
-                    arch
-                };
-                by_arch.entry(arch).or_default().total_job_count += count;
-            }
+    // only the buffer of the finished job is cleared
+    finish_job_log(&map, 1);
+    assert_eq!(job_log_stream(&map, 1), None);
+    let map = map.lock().unwrap();
+    assert!(map["host-0"].last_logs.is_empty());
+    assert_eq!(map["host-1"].last_logs.len(), 1);
+}
 
-            for (arch, count) in crate::schema::jobs::dsl::jobs
-                .filter(crate::schema::jobs::dsl::status.eq("created"))
-                .group_by(crate::schema::jobs::dsl::arch)
-                .select((
-                    crate::schema::jobs::dsl::arch,
-                    count(crate::schema::jobs::dsl::id),
-                ))
-                .load::<(String, i64)>(conn)?
-            {
-                let arch = if arch == "noarch" || arch == "optenv32" {
-                    "amd64".to_string()
-                } else {
-                    arch
-                };
-                by_arch.entry(arch).or_default().pending_job_count += count;
-            }
+#[test]
+fn test_api_features_match_routes() {
+    // every route, with the feature it was introduced by, if any
+    let routes = [
+        ("/api/ping", None),
+        ("/api/health", Some("health")),
+        ("/api/version", None),
+        ("/api/pipeline/new", None),
+        ("/api/pipeline/new_pr", None),
+        ("/api/pipeline/restart", None),
+        ("/api/pipeline/status", None),
+        ("/api/pipeline/list", None),
+        ("/api/pipeline/info", None),
+        ("/api/pipeline/export", Some("export")),
+        ("/api/pipeline/requirements", Some("requirements")),
+        ("/api/pipeline/events", Some("pipeline_events")),
+        ("/api/job/list", None),
+        ("/api/job/info", None),
+        ("/api/job/log", Some("job_log")),
+        ("/api/job/upload_log", Some("log_upload")),
+        ("/api/job/restart", None),
+        ("/api/job/repush", Some("repush")),
+        ("/api/job/schedulability", Some("schedulability")),
+        ("/api/worker/heartbeat", None),
+        ("/api/worker/poll", None),
+        ("/api/worker/job_update", None),
+        ("/api/worker/status", None),
+        ("/api/worker/list", None),
+        ("/api/worker/info", None),
+        ("/api/user/token/rotate", Some("tokens")),
+        ("/api/dashboard/status", None),
+        ("/api/ws/viewer/:hostname", None),
+        ("/api/ws/worker/:hostname", None),
+        ("/api/webhook", None),
+    ];
 
-            for (arch, count) in crate::schema::jobs::dsl::jobs
-                .filter(crate::schema::jobs::dsl::status.eq("running"))
-                .group_by(crate::schema::jobs::dsl::arch)
-                .select((
-                    crate::schema::jobs::dsl::arch,
-                    count(crate::schema::jobs::dsl::id),
-                ))
-                .load::<(String, i64)>(conn)?
-            {
-                let arch = if arch == "noarch" || arch == "optenv32" {
-                    "amd64".to_string()
-                } else {
-                    arch
-                };
-                by_arch.entry(arch).or_default().running_job_count += count;
-            }
+    // a new route must be listed above, and its feature reported
+    assert_eq!(
+        api_routes()
+            .iter()
+            .map(|(route, _)| *route)
+            .collect::<Vec<_>>(),
+        routes.iter().map(|(route, _)| *route).collect::<Vec<_>>()
+    );
+    for feature in routes.iter().filter_map(|(_, feature)| *feature) {
+        assert!(API_FEATURES.contains(&feature), "{feature} is not reported");
+    }
 
-            Ok(DashboardStatusResponse {
-                total_pipeline_count,
-                total_job_count,
-                pending_job_count,
-                running_job_count,
-                finished_job_count,
-                total_worker_count,
-                live_worker_count,
-                total_logical_cores: total_logical_cores.unwrap_or(0),
-                total_memory_bytes: total_memory_bytes.unwrap_or_default(),
-                by_arch,
-            })
-        })?,
-    ))
+    let mut features = API_FEATURES.to_vec();
+    features.sort();
+    features.dedup();
+    assert_eq!(features.len(), API_FEATURES.len());
 }
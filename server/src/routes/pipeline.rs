@@ -1,29 +1,26 @@
 use crate::models::User;
-use crate::routes::{AnyhowError, AppState};
+use crate::routes::{load_job_info, AnyhowError, AppState, PipelineEventsMap};
 use crate::{
-    api::{self, JobSource, PipelineStatus},
+    api::{self, JobRequirements, JobSource, PipelineOptions, PipelineStatus},
     models::{Job, Pipeline},
 };
 use anyhow::Context;
 use axum::extract::{Json, Query, State};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use common::api::{
+    PipelineExportResponse, PipelineInfoResponse, PipelineInfoResponseJob, PipelineJobEvent,
+    PipelineListResponse, PipelineListResponseItem, PipelineListResponseJob, PipelineNewRequest,
+    PipelineNewResponse, PipelineRestartRequest, PipelineRestartResponse,
+};
 use diesel::{
     BelongingToDsl, Connection, ExpressionMethods, GroupedBy, QueryDsl, RunQueryDsl,
     SelectableHelper,
 };
-use serde::{Deserialize, Serialize};
-use tracing::error;
-
-#[derive(Deserialize)]
-pub struct PipelineNewRequest {
-    git_branch: String,
-    packages: String,
-    archs: String,
-}
-
-#[derive(Serialize)]
-pub struct PipelineNewResponse {
-    id: i32,
-}
+use futures::Stream;
+use serde::Deserialize;
+use std::convert::Infallible;
+use tokio::sync::broadcast;
+use tracing::warn;
 
 pub async fn pipeline_new(
     State(AppState { pool, .. }): State<AppState>,
@@ -33,11 +30,18 @@ pub async fn pipeline_new(
         pool,
         &payload.git_branch,
         None,
-        None,
         &payload.packages,
         &payload.archs,
         JobSource::Manual,
-        false,
+        PipelineOptions {
+            patch: payload.patch.as_deref(),
+            required_tags: payload.required_tags.as_deref(),
+            verbose: payload.verbose,
+            idempotency_key: payload.idempotency_key.as_deref(),
+            ciel_flags: payload.ciel_flags.as_deref(),
+            tag: payload.tag.as_deref(),
+            ..Default::default()
+        },
     )
     .await?;
     Ok(Json(PipelineNewResponse { id: pipeline.id }))
@@ -47,6 +51,15 @@ pub async fn pipeline_new(
 pub struct PipelineNewPRRequest {
     pr: u64,
     archs: Option<String>,
+    /// Build the test merge commit with stable instead of PR head
+    #[serde(default)]
+    merge: bool,
+    /// Build PR from a fork, fetching from the fork instead of the configured ABBS repo
+    #[serde(default)]
+    allow_fork: bool,
+    /// See `PipelineNewRequest::idempotency_key`
+    #[serde(default)]
+    idempotency_key: Option<String>,
 }
 
 pub async fn pipeline_new_pr(
@@ -57,36 +70,28 @@ pub async fn pipeline_new_pr(
         pool,
         payload.pr,
         payload.archs.as_deref(),
+        payload.merge,
+        payload.allow_fork,
         JobSource::Manual,
+        payload.idempotency_key.as_deref(),
     )
     .await?;
     Ok(Json(PipelineNewResponse { id: pipeline.id }))
 }
 
-#[derive(Deserialize)]
-pub struct PipelineInfoRequest {
-    pipeline_id: i32,
-}
-
-#[derive(Serialize)]
-pub struct PipelineInfoResponseJob {
-    job_id: i32,
-    arch: String,
+pub async fn pipeline_restart(
+    State(AppState { pool, .. }): State<AppState>,
+    Json(payload): Json<PipelineRestartRequest>,
+) -> Result<Json<PipelineRestartResponse>, AnyhowError> {
+    let new_jobs = api::pipeline_restart_failed(pool, payload.pipeline_id).await?;
+    Ok(Json(PipelineRestartResponse {
+        job_ids: new_jobs.iter().map(|job| job.id).collect(),
+    }))
 }
 
-#[derive(Serialize)]
-pub struct PipelineInfoResponse {
-    // from pipeline
+#[derive(Deserialize)]
+pub struct PipelineInfoRequest {
     pipeline_id: i32,
-    packages: String,
-    archs: String,
-    git_branch: String,
-    git_sha: String,
-    creation_time: chrono::DateTime<chrono::Utc>,
-    github_pr: Option<i64>,
-
-    // related jobs
-    jobs: Vec<PipelineInfoResponseJob>,
 }
 
 pub async fn pipeline_info(
@@ -120,6 +125,7 @@ pub async fn pipeline_info(
                 archs: pipeline.archs,
                 git_branch: pipeline.git_branch,
                 git_sha: pipeline.git_sha,
+                git_ref: pipeline.git_ref,
                 creation_time: pipeline.creation_time,
                 github_pr: pipeline.github_pr,
                 jobs,
@@ -128,48 +134,75 @@ pub async fn pipeline_info(
     ))
 }
 
-#[derive(Deserialize)]
-pub struct PipelineListRequest {
-    page: i64,
-    items_per_page: i64,
-    stable_only: bool,
-    github_pr_only: bool,
+pub async fn pipeline_export(
+    Query(query): Query<PipelineInfoRequest>,
+    State(AppState {
+        replica_pool: pool, ..
+    }): State<AppState>,
+) -> Result<Json<PipelineExportResponse>, AnyhowError> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    Ok(Json(
+        conn.transaction::<PipelineExportResponse, diesel::result::Error, _>(|conn| {
+            let (pipeline, creator) = crate::schema::pipelines::dsl::pipelines
+                .find(query.pipeline_id)
+                .left_join(crate::schema::users::dsl::users)
+                .get_result::<(Pipeline, Option<User>)>(conn)?;
+
+            let jobs = crate::schema::jobs::dsl::jobs
+                .filter(crate::schema::jobs::dsl::pipeline_id.eq(pipeline.id))
+                .order(crate::schema::jobs::dsl::id.asc())
+                .select(crate::schema::jobs::dsl::id)
+                .load::<i32>(conn)?
+                .into_iter()
+                .map(|job_id| load_job_info(conn, job_id))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(PipelineExportResponse {
+                pipeline_id: pipeline.id,
+                packages: pipeline.packages,
+                archs: pipeline.archs,
+                git_branch: pipeline.git_branch,
+                git_sha: pipeline.git_sha,
+                git_ref: pipeline.git_ref,
+                creation_time: pipeline.creation_time,
+                github_pr: pipeline.github_pr,
+                creator_github_login: creator.and_then(|user| user.github_login),
+                jobs,
+            })
+        })?,
+    ))
 }
 
-#[derive(Serialize)]
-pub struct PipelineListResponseJob {
-    job_id: i32,
-    arch: String,
-    status: String,
+#[derive(Deserialize)]
+pub struct PipelineRequirementsRequest {
+    pipeline_id: i32,
 }
 
-#[derive(Serialize)]
-pub struct PipelineListResponseItem {
-    id: i32,
-    git_branch: String,
-    git_sha: String,
-    creation_time: chrono::DateTime<chrono::Utc>,
-    github_pr: Option<i64>,
-    packages: String,
-    archs: String,
-    status: &'static str,
-
-    // from pipeline creator
-    creator_github_login: Option<String>,
-    creator_github_avatar_url: Option<String>,
-
-    jobs: Vec<PipelineListResponseJob>,
+pub async fn pipeline_requirements(
+    Query(query): Query<PipelineRequirementsRequest>,
+    State(AppState { pool, .. }): State<AppState>,
+) -> Result<Json<Vec<JobRequirements>>, AnyhowError> {
+    Ok(Json(
+        api::pipeline_requirements(pool, query.pipeline_id).await?,
+    ))
 }
 
-#[derive(Serialize)]
-pub struct PipelineListResponse {
-    total_items: i64,
-    items: Vec<PipelineListResponseItem>,
+#[derive(Deserialize)]
+pub struct PipelineListRequest {
+    page: i64,
+    items_per_page: i64,
+    stable_only: bool,
+    github_pr_only: bool,
 }
 
 pub async fn pipeline_list(
     Query(query): Query<PipelineListRequest>,
-    State(AppState { pool, .. }): State<AppState>,
+    State(AppState {
+        replica_pool: pool, ..
+    }): State<AppState>,
 ) -> Result<Json<PipelineListResponse>, AnyhowError> {
     let mut conn = pool
         .get()
@@ -235,40 +268,7 @@ pub async fn pipeline_list(
                 jobs.sort_by(|a, b| a.arch.cmp(&b.arch));
                 jobs.dedup_by(|a, b| a.arch.eq(&b.arch));
 
-                let mut has_error = false;
-                let mut has_failed = false;
-                let mut has_unfinished = false;
-                for job in &jobs {
-                    match job.status.as_str() {
-                        "error" => has_error = true,
-                        "success" => {
-                            // success
-                        }
-                        "failed" => {
-                            // failed
-                            has_failed = true;
-                        }
-                        "created" => {
-                            has_unfinished = true;
-                        }
-                        "running" => {
-                            has_unfinished = true;
-                        }
-                        _ => {
-                            error!("Got job with unknown status: {:?}", job);
-                        }
-                    }
-                }
-
-                let status = if has_error {
-                    "error"
-                } else if has_failed {
-                    "failed"
-                } else if has_unfinished {
-                    "running"
-                } else {
-                    "success"
-                };
+                let status = api::pipeline_status_from_jobs(&jobs);
 
                 // compute pipeline status based on job status
                 items.push(PipelineListResponseItem {
@@ -279,7 +279,7 @@ pub async fn pipeline_list(
                     archs: pipeline.archs,
                     creation_time: pipeline.creation_time,
                     github_pr: pipeline.github_pr,
-                    status,
+                    status: status.to_string(),
 
                     creator_github_login: creator
                         .as_ref()
@@ -306,7 +306,54 @@ pub async fn pipeline_list(
 }
 
 pub async fn pipeline_status(
-    State(AppState { pool, .. }): State<AppState>,
+    State(AppState {
+        replica_pool: pool, ..
+    }): State<AppState>,
 ) -> Result<Json<Vec<PipelineStatus>>, AnyhowError> {
     Ok(Json(api::pipeline_status(pool).await?))
 }
+
+/// Buffered events per pipeline, slow subscribers skip older events
+const PIPELINE_EVENTS_CAPACITY: usize = 64;
+
+#[derive(Deserialize)]
+pub struct PipelineEventsRequest {
+    pipeline_id: i32,
+}
+
+/// Server-sent events of job state changes of a pipeline
+pub async fn pipeline_events(
+    Query(query): Query<PipelineEventsRequest>,
+    State(AppState {
+        pipeline_events_map,
+        ..
+    }): State<AppState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let rx = subscribe_pipeline_events(&pipeline_events_map, query.pipeline_id);
+    let stream = futures::stream::unfold(rx, |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => match Event::default().json_data(event) {
+                    Ok(event) => return Some((Ok(event), rx)),
+                    Err(err) => warn!("Failed to serialize pipeline event: {}", err),
+                },
+                // the client refetches the whole pipeline on the next event
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    Sse::new(stream).keep_alive(KeepAlive::new().text("heartbeat"))
+}
+
+fn subscribe_pipeline_events(
+    map: &PipelineEventsMap,
+    pipeline_id: i32,
+) -> broadcast::Receiver<PipelineJobEvent> {
+    let mut map = map.lock().unwrap();
+    // drop channels of pipelines whose subscribers have all disconnected
+    map.retain(|_, tx| tx.receiver_count() > 0);
+    map.entry(pipeline_id)
+        .or_insert_with(|| broadcast::channel(PIPELINE_EVENTS_CAPACITY).0)
+        .subscribe()
+}
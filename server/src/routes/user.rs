@@ -0,0 +1,13 @@
+use super::{AnyhowError, ApiAuth, AppState};
+use crate::api;
+use axum::extract::{Json, State};
+use common::api::UserTokenRotateResponse;
+
+/// Replace the API token of the authenticated user
+pub async fn user_token_rotate(
+    State(AppState { pool, .. }): State<AppState>,
+    ApiAuth(user): ApiAuth,
+) -> Result<Json<UserTokenRotateResponse>, AnyhowError> {
+    let token = api::user_token_rotate(pool, user.id).await?;
+    Ok(Json(UserTokenRotateResponse { token }))
+}
@@ -1,12 +1,18 @@
-use anyhow::{anyhow, bail};
-use axum::{extract::State, Json};
+use anyhow::{anyhow, bail, Context};
+use axum::{body::Bytes, extract::State};
+use hmac::{Hmac, Mac};
 use hyper::HeaderMap;
 use reqwest::StatusCode;
 use serde::Deserialize;
 use serde_json::Value;
+use sha2::Sha256;
+use std::collections::BTreeMap;
 use tracing::{info, warn};
 
-use crate::{api, formatter::to_html_new_pipeline_summary, DbPool, ARGS};
+use crate::{
+    api, formatter::to_html_new_pipeline_summary, github_org, github_repo, github_repo_web_url,
+    DbPool, ARGS, DEFAULT_BUILD_LABEL,
+};
 
 use super::{AnyhowError, AppState};
 
@@ -28,11 +34,25 @@ struct User {
     login: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct WebhookPullRequest {
+    action: String,
+    number: u64,
+    label: Option<Label>,
+    sender: User,
+}
+
+#[derive(Debug, Deserialize)]
+struct Label {
+    name: String,
+}
+
 pub async fn webhook_handler(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Json(json): Json<Value>,
+    body: Bytes,
 ) -> Result<(), AnyhowError> {
+    let json: Value = serde_json::from_slice(&body)?;
     info!("Got Github webhook request: {}", json);
 
     match headers.get("X-GitHub-Event").and_then(|x| x.to_str().ok()) {
@@ -49,6 +69,44 @@ pub async fn webhook_handler(
                 });
             }
         }
+        Some("pull_request") => {
+            let webhook_pr: WebhookPullRequest = serde_json::from_value(json)?;
+            let build_label = ARGS
+                .github_build_label
+                .as_deref()
+                .unwrap_or(DEFAULT_BUILD_LABEL);
+
+            if webhook_pr.action == "labeled"
+                && webhook_pr
+                    .label
+                    .is_some_and(|label| label.name == build_label)
+            {
+                // anyone can send requests here, only act on those signed by github
+                let secret = ARGS
+                    .github_secret
+                    .as_ref()
+                    .context("GITHUB_SECRET is not set")?;
+                let signature = headers
+                    .get("X-Hub-Signature-256")
+                    .and_then(|x| x.to_str().ok())
+                    .context("Missing webhook signature")?;
+                if !verify_signature(secret.as_bytes(), &body, signature) {
+                    return Err(anyhow!("Invalid webhook signature").into());
+                }
+
+                info!(
+                    "PR #{} labeled {build_label} by {}, creating pipeline",
+                    webhook_pr.number, webhook_pr.sender.login
+                );
+                let pool = state.pool;
+                tokio::spawn(async move {
+                    let res = pipeline_new_pr_impl(pool, webhook_pr.number, None).await;
+                    if let Err(err) = res {
+                        warn!("Failed to handle webhook label: {}", err);
+                    }
+                });
+            }
+        }
         x => {
             warn!("Unsupported Github event: {:?}", x);
         }
@@ -105,39 +163,73 @@ async fn pipeline_new_pr_impl(
     num: u64,
     archs: Option<&str>,
 ) -> Result<(), anyhow::Error> {
-    let res = api::pipeline_new_pr(pool, num, archs, api::JobSource::Github(num)).await;
+    let res = api::pipeline_new_pr(
+        pool.clone(),
+        num,
+        archs,
+        false,
+        false,
+        api::JobSource::Github(num),
+        None,
+    )
+    .await;
 
     let crab = octocrab::Octocrab::builder()
         .user_access_token(ARGS.github_access_token.clone())
         .build()?;
 
     let msg = match res {
-        Ok(res) => to_html_new_pipeline_summary(
-            res.id,
-            &res.git_branch,
-            &res.git_sha,
-            res.github_pr.map(|n| n as u64),
-            &res.archs.split(',').collect::<Vec<_>>(),
-            &res.packages.split(',').collect::<Vec<_>>(),
-        ),
+        Ok(res) => {
+            let estimated_secs =
+                api::pipeline_predict_secs(pool, &res)
+                    .await
+                    .unwrap_or_else(|err| {
+                        warn!("Failed to predict build time: {err:?}");
+                        BTreeMap::new()
+                    });
+            to_html_new_pipeline_summary(&github_repo_web_url(), &res, &estimated_secs)
+        }
         Err(e) => {
             format!("Failed to create pipeline: {e}")
         }
     };
 
-    crab.issues("aosc-dev", "aosc-os-abbs")
+    crab.issues(github_org(), github_repo())
         .create_comment(num, msg)
         .await?;
 
     Ok(())
 }
 
+/// Check `X-Hub-Signature-256` of a webhook request, i.e. `sha256=` followed by
+/// the hex HMAC-SHA256 digest of the body
+fn verify_signature(secret: &[u8], body: &[u8], signature: &str) -> bool {
+    let Some(signature) = signature.strip_prefix("sha256=").and_then(decode_hex) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret) else {
+        return false;
+    };
+    mac.update(body);
+    // compares in constant time
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    // an odd trailing digit fails `get`
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
 async fn is_org_user(user: &str) -> anyhow::Result<bool> {
     let client = reqwest::Client::builder().user_agent("buildit").build()?;
 
     let resp = client
         .get(format!(
-            "https://api.github.com/orgs/aosc-dev/public_members/{}",
+            "https://api.github.com/orgs/{}/public_members/{}",
+            github_org(),
             user
         ))
         .send()
@@ -152,3 +244,26 @@ async fn is_org_user(user: &str) -> anyhow::Result<bool> {
         },
     }
 }
+
+#[test]
+fn test_verify_signature() {
+    // example from github webhook documentation
+    let secret = b"It's a Secret to Everybody";
+    let body = b"Hello, World!";
+    assert!(verify_signature(
+        secret,
+        body,
+        "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17"
+    ));
+    assert!(!verify_signature(
+        secret,
+        body,
+        "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e18"
+    ));
+    assert!(!verify_signature(
+        secret,
+        body,
+        "757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17"
+    ));
+    assert!(!verify_signature(secret, body, "sha256="));
+}
@@ -1,5 +1,5 @@
 use super::{AppState, WSStateMap};
-use crate::{routes::Viewer, RemoteAddr};
+use crate::{routes::Viewer, RemoteAddr, ARGS, DEFAULT_WS_LOG_BUFFER_LINES};
 use axum::{
     extract::{ws::WebSocket, ConnectInfo, Path, State, WebSocketUpgrade},
     response::IntoResponse,
@@ -24,6 +24,9 @@ async fn handle_worker_socket(
     state_map: WSStateMap,
 ) {
     info!("{:?} connected as worker with hostname {}", who, hostname);
+    let buffer_lines = ARGS
+        .ws_log_buffer_lines
+        .unwrap_or(DEFAULT_WS_LOG_BUFFER_LINES);
 
     let (_outgoing, incoming) = socket.split();
 
@@ -37,9 +40,9 @@ async fn handle_worker_socket(
                     recp.sender.unbounded_send(msg.clone()).ok();
                 }
 
-                // save last 1000 entries
+                // save last entries for new viewers
                 state.last_logs.push_back(msg.clone());
-                if state.last_logs.len() > 1000 {
+                while state.last_logs.len() > buffer_lines {
                     state.last_logs.pop_front();
                 }
             }
@@ -1,31 +1,43 @@
-use crate::routes::{AnyhowError, AppState};
-use crate::HEARTBEAT_TIMEOUT;
+use crate::routes::{
+    finish_job_log, publish_pipeline_event, start_job_log, AnyhowError, AppState, PipelineEventsMap,
+};
 use crate::{
     api::{self},
-    formatter::{to_html_build_result, to_markdown_build_result, FAILED, SUCCESS},
+    formatter::{
+        build_result_arch, to_html_build_result, to_markdown_build_result, FAILED, SUCCESS,
+    },
+    get_conn_with_retry,
     github::get_crab_github_installation,
-    models::{Job, NewWorker, Pipeline, Worker},
-    ARGS,
+    models::{Job, JobLastSeen, NewWorker, Pipeline, Worker},
+    ALL_ARCH, ARGS,
+};
+use crate::{
+    base_url, github_org, github_repo, github_repo_url, github_repo_web_url, DbPool,
+    DEFAULT_JOB_UPDATE_MAX_RETRY, HEARTBEAT_TIMEOUT, JOB_UPDATE_MAX_RETRY_DELAY_SECS,
 };
-use anyhow::anyhow;
 use anyhow::Context;
+use anyhow::{anyhow, bail};
 use axum::extract::{Json, Query, State};
-use buildit_utils::{AMD64, ARM64, LOONGSON3, PPC64EL, RISCV64};
-use buildit_utils::{LOONGARCH64, NOARCH};
+use buildit_utils::github::update_arch_checklist;
 
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 use common::{
-    JobOk, JobResult, WorkerHeartbeatRequest, WorkerJobUpdateRequest, WorkerPollRequest,
-    WorkerPollResponse,
+    api::{PipelineJobEvent, WorkerInfoResponse, WorkerListResponse, WorkerListResponseItem},
+    parse_tags, JobOk, JobResult, WorkerHeartbeatRequest, WorkerHeartbeatResponse,
+    WorkerJobUpdateRequest, WorkerPollRequest, WorkerPollResponse, MACHINE_CLASSES,
 };
 
-use diesel::{BoolExpressionMethods, JoinOnDsl, NullableExpressionMethods};
-use diesel::{Connection, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use diesel::sql_types::{Array, Bool, Text};
+use diesel::{BoolExpressionMethods, NullableExpressionMethods, PgSortExpressionMethods};
+use diesel::{
+    Connection, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl,
+};
 use octocrab::models::CheckRunId;
 use octocrab::params::checks::CheckRunConclusion;
 use octocrab::params::checks::CheckRunOutput;
 use once_cell::sync::Lazy;
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
+use std::{collections::HashMap, time::Duration};
 
 use teloxide::types::ChatId;
 use teloxide::{prelude::*, types::ParseMode};
@@ -37,31 +49,11 @@ pub struct WorkerListRequest {
     items_per_page: i64,
 }
 
-#[derive(Serialize)]
-pub struct WorkerListResponseItem {
-    id: i32,
-    hostname: String,
-    arch: String,
-    logical_cores: i32,
-    memory_bytes: i64,
-    disk_free_space_bytes: i64,
-    is_live: bool,
-    last_heartbeat_time: DateTime<Utc>,
-    internet_connectivity: bool,
-    // status
-    running_job_id: Option<i32>,
-    running_job_assign_time: Option<chrono::DateTime<chrono::Utc>>,
-}
-
-#[derive(Serialize)]
-pub struct WorkerListResponse {
-    total_items: i64,
-    items: Vec<WorkerListResponseItem>,
-}
-
 pub async fn worker_list(
     Query(query): Query<WorkerListRequest>,
-    State(AppState { pool, .. }): State<AppState>,
+    State(AppState {
+        replica_pool: pool, ..
+    }): State<AppState>,
 ) -> Result<Json<WorkerListResponse>, AnyhowError> {
     let mut conn = pool
         .get()
@@ -74,39 +66,41 @@ pub async fn worker_list(
                 .count()
                 .get_result(conn)?;
 
-            let workers = if query.items_per_page == -1 {
-                crate::schema::workers::dsl::workers
-                    .filter(crate::schema::workers::dsl::visible.eq(true))
-                    .order_by((
-                        crate::schema::workers::dsl::arch,
-                        crate::schema::workers::dsl::hostname,
-                    ))
-                    .left_join(
-                        crate::schema::jobs::dsl::jobs
-                            .on(crate::schema::jobs::dsl::assigned_worker_id
-                                .eq(crate::schema::workers::dsl::id.nullable())),
-                    )
-                    .load::<(Worker, Option<Job>)>(conn)?
-            } else {
-                crate::schema::workers::dsl::workers
-                    .filter(crate::schema::workers::dsl::visible.eq(true))
-                    .order_by((
-                        crate::schema::workers::dsl::arch,
-                        crate::schema::workers::dsl::hostname,
-                    ))
+            let mut sql = crate::schema::workers::dsl::workers
+                .filter(crate::schema::workers::dsl::visible.eq(true))
+                .order_by((
+                    crate::schema::workers::dsl::arch,
+                    crate::schema::workers::dsl::hostname,
+                ))
+                .into_boxed();
+            if query.items_per_page != -1 {
+                sql = sql
                     .offset((query.page - 1) * query.items_per_page)
-                    .limit(query.items_per_page)
-                    .left_join(
-                        crate::schema::jobs::dsl::jobs
-                            .on(crate::schema::jobs::dsl::assigned_worker_id
-                                .eq(crate::schema::workers::dsl::id.nullable())),
-                    )
-                    .load::<(Worker, Option<Job>)>(conn)?
-            };
+                    .limit(query.items_per_page);
+            }
+            let workers = sql.load::<Worker>(conn)?;
+            let worker_ids = workers.iter().map(|worker| worker.id).collect::<Vec<_>>();
+
+            // a worker with several build slots may run several jobs, show the earliest one
+            let running_jobs = crate::schema::jobs::dsl::jobs
+                .filter(crate::schema::jobs::dsl::assigned_worker_id.eq_any(&worker_ids))
+                .order_by(crate::schema::jobs::dsl::assign_time.asc())
+                .load::<Job>(conn)?;
+            let mut running_job_of_worker: HashMap<i32, Job> = HashMap::new();
+            for job in running_jobs {
+                if let Some(worker_id) = job.assigned_worker_id {
+                    running_job_of_worker.entry(worker_id).or_insert(job);
+                }
+            }
+
+            let stats = worker_job_stats(conn, &worker_ids)?;
 
             let mut items = vec![];
             let deadline = Utc::now() - chrono::Duration::try_seconds(HEARTBEAT_TIMEOUT).unwrap();
-            for (worker, job) in workers {
+            for worker in workers {
+                let job = running_job_of_worker.remove(&worker.id);
+                let (successful_job_count, failed_job_count) =
+                    stats.get(&worker.id).copied().unwrap_or_default();
                 items.push(WorkerListResponseItem {
                     id: worker.id,
                     hostname: worker.hostname,
@@ -117,8 +111,15 @@ pub async fn worker_list(
                     is_live: worker.last_heartbeat_time > deadline,
                     last_heartbeat_time: worker.last_heartbeat_time,
                     internet_connectivity: worker.internet_connectivity,
+                    tags: parse_tags(worker.tags.as_deref().unwrap_or(""))
+                        .into_iter()
+                        .map(String::from)
+                        .collect(),
                     running_job_id: job.as_ref().map(|job| job.id),
                     running_job_assign_time: job.and_then(|job| job.assign_time),
+                    successful_job_count,
+                    failed_job_count,
+                    success_rate: success_rate(successful_job_count, failed_job_count),
                 });
             }
 
@@ -127,28 +128,151 @@ pub async fn worker_list(
     ))
 }
 
+/// Whether the failure of the job would happen on other archs too,
+/// so that created jobs of its pipeline should be cancelled
+fn cancels_other_archs(res: &JobOk) -> bool {
+    res.failure_stage
+        .is_some_and(|stage| stage.is_arch_independent())
+}
+
+/// Whether a job assigned to the polling worker is no longer being built:
+/// still running, but not reported by the worker, and neither assigned nor
+/// reported by any process of the host within the heartbeat window
+fn is_orphaned_job(
+    job_id: i32,
+    job_status: &str,
+    job_assign_time: Option<chrono::DateTime<Utc>>,
+    job_last_seen_time: Option<chrono::DateTime<Utc>>,
+    running_job_ids: &[i32],
+    deadline: chrono::DateTime<Utc>,
+) -> bool {
+    job_status == "running"
+        && !running_job_ids.contains(&job_id)
+        && job_assign_time
+            .max(job_last_seen_time)
+            .is_none_or(|time| time < deadline)
+}
+
+/// Record that jobs are still being built, as reported on poll or heartbeat
+fn refresh_job_last_seen(conn: &mut PgConnection, job_ids: &[i32]) -> diesel::QueryResult<()> {
+    if job_ids.is_empty() {
+        return Ok(());
+    }
+    // skip ids of jobs that no longer exist
+    let now = Utc::now();
+    let rows: Vec<JobLastSeen> = crate::schema::jobs::dsl::jobs
+        .filter(crate::schema::jobs::dsl::id.eq_any(job_ids))
+        .select(crate::schema::jobs::dsl::id)
+        .load::<i32>(conn)?
+        .into_iter()
+        .map(|id| JobLastSeen {
+            job_id: id,
+            last_seen_time: now,
+        })
+        .collect();
+    use crate::schema::job_last_seen::dsl;
+    diesel::insert_into(dsl::job_last_seen)
+        .values(&rows)
+        .on_conflict(dsl::job_id)
+        .do_update()
+        .set(dsl::last_seen_time.eq(now))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Count successful and failed jobs built by each of the workers
+fn worker_job_stats(
+    conn: &mut PgConnection,
+    worker_ids: &[i32],
+) -> diesel::QueryResult<HashMap<i32, (i64, i64)>> {
+    let mut res: HashMap<i32, (i64, i64)> = HashMap::new();
+    for (worker_id, job_status, count) in crate::schema::jobs::dsl::jobs
+        .filter(crate::schema::jobs::dsl::built_by_worker_id.eq_any(worker_ids))
+        .filter(crate::schema::jobs::dsl::status.eq_any(["success", "failed"]))
+        .group_by((
+            crate::schema::jobs::dsl::built_by_worker_id,
+            crate::schema::jobs::dsl::status,
+        ))
+        .select((
+            crate::schema::jobs::dsl::built_by_worker_id,
+            crate::schema::jobs::dsl::status,
+            diesel::dsl::count(crate::schema::jobs::dsl::id),
+        ))
+        .load::<(Option<i32>, String, i64)>(conn)?
+    {
+        let Some(worker_id) = worker_id else {
+            continue;
+        };
+        let entry = res.entry(worker_id).or_default();
+        if job_status == "success" {
+            entry.0 += count;
+        } else {
+            entry.1 += count;
+        }
+    }
+    Ok(res)
+}
+
+fn success_rate(successful: i64, failed: i64) -> Option<f64> {
+    let total = successful + failed;
+    (total > 0).then(|| successful as f64 / total as f64)
+}
+
 pub async fn worker_heartbeat(
     State(AppState { pool, .. }): State<AppState>,
     Json(payload): Json<WorkerHeartbeatRequest>,
-) -> Result<(), AnyhowError> {
+) -> Result<Json<WorkerHeartbeatResponse>, AnyhowError> {
     if payload.worker_secret != ARGS.worker_secret {
         return Err(anyhow!("Invalid worker secret").into());
     }
+    validate_worker_arch(&payload.arch)?;
+    validate_machine_class(payload.machine_class.as_deref())?;
 
     // insert or update worker
     let mut conn = pool
         .get()
         .context("Failed to get db connection from pool")?;
 
+    let instances = payload
+        .ciel_instances
+        .as_ref()
+        .map(|instances| instances.join(","));
+    let worker_tags = normalize_tags(payload.tags.as_deref());
+
     conn.transaction::<(), diesel::result::Error, _>(|conn| {
         use crate::schema::workers::dsl::*;
-        match workers
+        let rows = workers
             .filter(hostname.eq(&payload.hostname))
-            .filter(arch.eq(&payload.arch))
-            .first::<Worker>(conn)
-            .optional()?
-        {
-            Some(worker) => {
+            .load::<Worker>(conn)?;
+        match match_worker_row(&rows, &payload.arch) {
+            WorkerRow::ArchChanged(worker) => {
+                // worker reconfigured to another arch, reuse its row and
+                // release jobs assigned under the old arch
+                info!(
+                    "Worker {} changed arch from {} to {}",
+                    worker.hostname, worker.arch, payload.arch
+                );
+                diesel::update(workers.find(worker.id))
+                    .set((
+                        arch.eq(&payload.arch),
+                        git_commit.eq(&payload.git_commit),
+                        memory_bytes.eq(payload.memory_bytes),
+                        logical_cores.eq(payload.logical_cores),
+                        disk_free_space_bytes.eq(payload.disk_free_space_bytes),
+                        last_heartbeat_time.eq(chrono::Utc::now()),
+                        performance.eq(payload.performance),
+                        internet_connectivity.eq(payload.internet_connectivity.unwrap_or(false)),
+                        ciel_instances.eq(&instances),
+                        tags.eq(&worker_tags),
+                        machine_class.eq(&payload.machine_class),
+                        websocket_reconnects.eq(payload.websocket_reconnects),
+                        websocket_last_error.eq(&payload.websocket_last_error),
+                    ))
+                    .execute(conn)?;
+
+                api::release_worker_jobs(conn, worker.id)?;
+            }
+            WorkerRow::Existing(worker) => {
                 // existing worker, update it
                 diesel::update(workers.find(worker.id))
                     .set((
@@ -159,10 +283,15 @@ pub async fn worker_heartbeat(
                         last_heartbeat_time.eq(chrono::Utc::now()),
                         performance.eq(payload.performance),
                         internet_connectivity.eq(payload.internet_connectivity.unwrap_or(false)),
+                        ciel_instances.eq(&instances),
+                        tags.eq(&worker_tags),
+                        machine_class.eq(&payload.machine_class),
+                        websocket_reconnects.eq(payload.websocket_reconnects),
+                        websocket_last_error.eq(&payload.websocket_last_error),
                     ))
                     .execute(conn)?;
             }
-            None => {
+            WorkerRow::New => {
                 let new_worker = NewWorker {
                     hostname: payload.hostname.clone(),
                     arch: payload.arch.clone(),
@@ -173,52 +302,224 @@ pub async fn worker_heartbeat(
                     last_heartbeat_time: chrono::Utc::now(),
                     performance: payload.performance,
                     internet_connectivity: payload.internet_connectivity.unwrap_or(false),
+                    ciel_instances: instances,
+                    tags: worker_tags,
+                    machine_class: payload.machine_class.clone(),
+                    websocket_reconnects: payload.websocket_reconnects,
+                    websocket_last_error: payload.websocket_last_error.clone(),
                 };
                 diesel::insert_into(crate::schema::workers::table)
                     .values(&new_worker)
                     .execute(conn)?;
             }
         }
-        Ok(())
+        refresh_job_last_seen(conn, &payload.running_job_ids)
     })?;
-    Ok(())
+
+    // tell the worker to stop building jobs cancelled in the meantime
+    let cancelled_job_ids = {
+        use crate::schema::jobs::dsl::*;
+        jobs.filter(id.eq_any(&payload.running_job_ids))
+            .filter(status.eq("cancelled"))
+            .select(id)
+            .load::<i32>(&mut conn)?
+    };
+    Ok(Json(WorkerHeartbeatResponse { cancelled_job_ids }))
+}
+
+/// Reject workers of unknown arch, which would never match any job
+fn validate_worker_arch(worker_arch: &str) -> anyhow::Result<()> {
+    if ALL_ARCH.contains(&worker_arch) {
+        return Ok(());
+    }
+    if worker_arch == "noarch" || worker_arch == "optenv32" {
+        bail!(
+            "Unsupported worker arch {worker_arch}: {worker_arch} jobs are built by amd64 workers"
+        );
+    }
+    bail!(
+        "Unsupported worker arch {worker_arch}, expected one of: {}",
+        ALL_ARCH.join(", ")
+    );
+}
+
+#[derive(Debug)]
+enum WorkerRow<'a> {
+    /// Row with the same hostname and arch
+    Existing(&'a Worker),
+    /// The only row of the hostname, but with a different arch
+    ArchChanged(&'a Worker),
+    New,
+}
+
+/// Find the row to update for a worker heartbeat among rows of the same hostname.
+/// Hosts running workers of several archs keep one row per arch.
+fn match_worker_row<'a>(rows: &'a [Worker], new_arch: &str) -> WorkerRow<'a> {
+    if let Some(worker) = rows.iter().find(|worker| worker.arch == new_arch) {
+        return WorkerRow::Existing(worker);
+    }
+    match rows {
+        [worker] => WorkerRow::ArchChanged(worker),
+        _ => WorkerRow::New,
+    }
+}
+
+/// Worker row registered on poll, fields not in the poll request are left for heartbeat
+fn new_worker_from_poll(payload: &WorkerPollRequest) -> NewWorker {
+    NewWorker {
+        hostname: payload.hostname.clone(),
+        arch: payload.arch.clone(),
+        git_commit: "unknown".to_string(),
+        memory_bytes: payload.memory_bytes,
+        logical_cores: payload.logical_cores,
+        last_heartbeat_time: chrono::Utc::now(),
+        disk_free_space_bytes: payload.disk_free_space_bytes,
+        performance: None,
+        internet_connectivity: false,
+        ciel_instances: None,
+        tags: normalize_tags(payload.tags.as_deref()),
+        machine_class: payload.machine_class.clone(),
+        websocket_reconnects: None,
+        websocket_last_error: None,
+    }
+}
+
+fn validate_machine_class(class: Option<&str>) -> anyhow::Result<()> {
+    match class {
+        Some(class) if !MACHINE_CLASSES.contains(&class) => bail!(
+            "Unknown machine class {class}, expected one of: {}",
+            MACHINE_CLASSES.join(", ")
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Tags stored in workers table, `None` if there is none
+fn normalize_tags(worker_tags: Option<&str>) -> Option<String> {
+    worker_tags
+        .map(|worker_tags| parse_tags(worker_tags).join(","))
+        .filter(|worker_tags| !worker_tags.is_empty())
 }
 
 pub async fn worker_poll(
-    State(AppState { pool, .. }): State<AppState>,
+    State(AppState {
+        pool,
+        bot,
+        ws_state_map,
+        pipeline_events_map,
+        ..
+    }): State<AppState>,
     Json(payload): Json<WorkerPollRequest>,
 ) -> Result<Json<Option<WorkerPollResponse>>, AnyhowError> {
     if payload.worker_secret != ARGS.worker_secret {
         return Err(anyhow!("Invalid worker secret").into());
     }
+    validate_worker_arch(&payload.arch)?;
+    validate_machine_class(payload.machine_class.as_deref())?;
+    let worker_rank = api::machine_class_rank(
+        payload.machine_class.as_deref(),
+        payload.logical_cores,
+        payload.memory_bytes,
+    );
 
     // find a job that can be assigned to the worker
-    let mut conn = pool
-        .get()
-        .context("Failed to get db connection from pool")?;
+    let mut conn = get_conn_with_retry(&pool).await?;
 
     match conn.transaction::<Option<(Pipeline, Job)>, diesel::result::Error, _>(|conn| {
         use crate::schema::jobs::dsl::*;
 
         // find worker id
-        let worker = crate::schema::workers::dsl::workers
+        let worker = match crate::schema::workers::dsl::workers
             .filter(crate::schema::workers::dsl::hostname.eq(&payload.hostname))
             .filter(crate::schema::workers::dsl::arch.eq(&payload.arch))
-            .first::<Worker>(conn)?;
+            .first::<Worker>(conn)
+            .optional()?
+        {
+            Some(worker) => worker,
+            None => {
+                // worker row deleted while the worker keeps running, re-register it
+                // and let the next heartbeat fill in the rest
+                info!(
+                    "Re-registering worker {} ({}) on poll",
+                    payload.hostname, payload.arch
+                );
+                diesel::insert_into(crate::schema::workers::table)
+                    .values(&new_worker_from_poll(&payload))
+                    .get_result::<Worker>(conn)?
+            }
+        };
+
+        // requeue orphaned jobs allocated to the worker, e.g. after a crash-restart,
+        // while jobs of other build slots or processes on the host keep running
+        refresh_job_last_seen(conn, &payload.running_job_ids)?;
+        let deadline = Utc::now() - chrono::Duration::try_seconds(HEARTBEAT_TIMEOUT).unwrap();
+        let orphaned: Vec<i32> = jobs
+            .left_join(crate::schema::job_last_seen::table)
+            .filter(assigned_worker_id.eq(worker.id))
+            .select((
+                id,
+                status,
+                assign_time,
+                crate::schema::job_last_seen::dsl::last_seen_time.nullable(),
+            ))
+            .load::<(
+                i32,
+                String,
+                Option<chrono::DateTime<Utc>>,
+                Option<chrono::DateTime<Utc>>,
+            )>(conn)?
+            .into_iter()
+            .filter(
+                |(job_id, job_status, job_assign_time, job_last_seen_time)| {
+                    is_orphaned_job(
+                        *job_id,
+                        job_status,
+                        *job_assign_time,
+                        *job_last_seen_time,
+                        &payload.running_job_ids,
+                        deadline,
+                    )
+                },
+            )
+            .map(|(job_id, _, _, _)| job_id)
+            .collect();
+        if !orphaned.is_empty() {
+            info!(
+                "Requeueing orphaned job(s) {:?} of worker {}",
+                orphaned, worker.id
+            );
+            diesel::update(jobs.filter(id.eq_any(&orphaned)))
+                .set((status.eq("created"), assigned_worker_id.eq(None::<i32>)))
+                .execute(conn)?;
+        }
 
-        // remove if any job is already allocated to the worker
-        diesel::update(jobs.filter(assigned_worker_id.eq(worker.id)))
-            .set((status.eq("created"), assigned_worker_id.eq(None::<i32>)))
-            .execute(conn)?;
+        // largest machine class among other live workers of the arch
+        let max_live_rank = api::max_machine_class_rank(
+            &crate::schema::workers::dsl::workers
+                .filter(crate::schema::workers::dsl::arch.eq(&payload.arch))
+                .filter(crate::schema::workers::dsl::id.ne(worker.id))
+                .filter(crate::schema::workers::dsl::visible.eq(true))
+                .filter(crate::schema::workers::dsl::last_heartbeat_time.gt(deadline))
+                .load::<Worker>(conn)?,
+        );
 
-        // prioritize jobs on stable branch
+        // prioritize expedited or security jobs, then jobs preferring the machine class,
+        // then jobs on stable branch, then oldest first
         let mut sql = jobs
             .inner_join(crate::schema::pipelines::dsl::pipelines)
-            .order_by(
+            .order_by(priority.desc())
+            .then_order_by(
+                preferred_machine_class
+                    .eq(MACHINE_CLASSES[worker_rank])
+                    .desc()
+                    .nulls_last(),
+            )
+            .then_order_by(
                 crate::schema::pipelines::dsl::git_branch
                     .eq("stable")
                     .desc(),
             )
+            .then_order_by(id.asc())
             .filter(status.eq("created"))
             .into_boxed();
         if payload.arch == "amd64" {
@@ -229,10 +530,30 @@ pub async fn worker_poll(
                     .or(arch.eq("optenv32")),
             );
         } else {
-            sql = sql.filter(arch.eq(&payload.arch));
+            // take noarch jobs that amd64 workers failed to pick up in time
+            sql = sql.filter(
+                arch.eq(&payload.arch).or(arch
+                    .eq("noarch")
+                    .and(creation_time.lt(api::noarch_fallback_deadline()))),
+            );
         }
 
-        // handle filters
+        // repush jobs can only run on the worker that holds the build output
+        sql = sql.filter(repush_only.eq(false).or(built_by_worker_id.eq(worker.id)));
+
+        // leave jobs for larger machines to them, unless none is live
+        sql = sql.filter(
+            preferred_machine_class
+                .is_null()
+                .or(
+                    preferred_machine_class.ne_all(api::machine_classes_left_to_others(
+                        worker_rank,
+                        max_live_rank,
+                    )),
+                ),
+        );
+
+        // handle filters, keep in sync with api::job_unmet_requirements
         sql = sql
             .filter(
                 require_min_core
@@ -254,6 +575,17 @@ pub async fn worker_poll(
                 require_min_disk
                     .is_null()
                     .or(require_min_disk.le(payload.disk_free_space_bytes)),
+            )
+            .filter(
+                required_tags.is_null().or(diesel::dsl::sql::<Bool>(
+                    "string_to_array(jobs.required_tags, ',') <@ ",
+                )
+                .bind::<Array<Text>, _>(
+                    parse_tags(payload.tags.as_deref().unwrap_or(""))
+                        .into_iter()
+                        .map(String::from)
+                        .collect::<Vec<_>>(),
+                )),
             );
 
         let res = sql.first::<(Job, Pipeline)>(conn).optional()?;
@@ -274,6 +606,45 @@ pub async fn worker_poll(
         }
     })? {
         Some((pipeline, job)) => {
+            start_job_log(
+                &ws_state_map,
+                payload.log_stream.as_deref().unwrap_or(&payload.hostname),
+                job.id,
+            );
+            publish_pipeline_event(
+                &pipeline_events_map,
+                pipeline.id,
+                PipelineJobEvent {
+                    job_id: job.id,
+                    arch: job.arch.clone(),
+                    status: "running".to_string(),
+                },
+            );
+
+            // tell the user who opted in with notify-start
+            if let (true, Some(bot), Some(telegram_user)) =
+                (pipeline.notify_start, bot, pipeline.telegram_user)
+            {
+                let text = format!(
+                    "Job #{} ({}) of pipeline #{} started on {}: {}/jobs/{}",
+                    job.id,
+                    job.arch,
+                    pipeline.id,
+                    payload.hostname,
+                    base_url(),
+                    job.id
+                );
+                tokio::spawn(async move {
+                    if let Err(err) = bot
+                        .send_message(ChatId(telegram_user), text)
+                        .disable_web_page_preview(true)
+                        .await
+                    {
+                        warn!("Failed to send job start notification: {}", err);
+                    }
+                });
+            }
+
             // update github check run status to in-progress
             if let Some(github_check_run_id) = job.github_check_run_id {
                 tokio::spawn(async move {
@@ -286,11 +657,11 @@ pub async fn worker_poll(
                             images: vec![],
                         };
                         if let Err(err) = crab
-                            .checks("AOSC-Dev", "aosc-os-abbs")
+                            .checks(github_org(), github_repo())
                             .update_check_run(CheckRunId(github_check_run_id as u64))
                             .status(octocrab::params::checks::CheckRunStatus::InProgress)
                             .output(output)
-                            .details_url(format!("https://buildit.aosc.io/jobs/{}", job.id))
+                            .details_url(format!("{}/jobs/{}", base_url(), job.id))
                             .send()
                             .await
                         {
@@ -305,7 +676,14 @@ pub async fn worker_poll(
                 job_id: job.id,
                 git_branch: pipeline.git_branch,
                 git_sha: pipeline.git_sha,
+                git_ref: pipeline.git_ref,
+                git_remote: Some(pipeline.git_remote.unwrap_or_else(github_repo_url)),
                 packages: job.packages,
+                patch: pipeline.patch,
+                repush_only: job.repush_only,
+                repush_from: job.restarted_from.filter(|_| job.repush_only),
+                verbose: job.verbose,
+                ciel_flags: pipeline.ciel_flags,
             })))
         }
         None => Ok(Json(None)),
@@ -313,7 +691,13 @@ pub async fn worker_poll(
 }
 
 pub async fn worker_job_update(
-    State(AppState { pool, bot, .. }): State<AppState>,
+    State(AppState {
+        pool,
+        bot,
+        ws_state_map,
+        pipeline_events_map,
+        ..
+    }): State<AppState>,
     Json(payload): Json<WorkerJobUpdateRequest>,
 ) -> Result<(), AnyhowError> {
     if payload.worker_secret != ARGS.worker_secret {
@@ -333,6 +717,9 @@ pub async fn worker_job_update(
         .filter(crate::schema::workers::dsl::arch.eq(&payload.arch))
         .first::<Worker>(&mut conn)?;
 
+    if job.status == "cancelled" {
+        return Err(anyhow!("Job has been cancelled").into());
+    }
     if job.status != "running" || job.assigned_worker_id != Some(worker.id) {
         return Err(anyhow!("Worker not assigned to the job").into());
     }
@@ -341,34 +728,67 @@ pub async fn worker_job_update(
         .find(job.pipeline_id)
         .first::<Pipeline>(&mut conn)?;
 
-    let mut retry = None;
-    loop {
-        if retry.map(|x| x < 5).unwrap_or(true) {
-            match handle_success_message(&job, &pipeline, &payload, &bot, retry).await {
-                HandleSuccessResult::Ok | HandleSuccessResult::DoNotRetry => {
-                    break;
-                }
-                HandleSuccessResult::Retry(x) => {
-                    info!("Retrying handlE_success_message");
-                    retry = Some(x);
-                    continue;
-                }
-            }
-        } else {
-            break;
-        }
-    }
+    // build finished, do not show its output to viewers of the next job
+    finish_job_log(&ws_state_map, payload.job_id);
+
+    complete_job(
+        &pool,
+        &bot,
+        &pipeline_events_map,
+        job,
+        pipeline,
+        &worker,
+        payload,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Record the result of a job and notify users of it, for results reported by
+/// workers as well as jobs failed by the recycler
+pub(crate) async fn complete_job(
+    pool: &DbPool,
+    bot: &Option<Bot>,
+    pipeline_events_map: &PipelineEventsMap,
+    job: Job,
+    pipeline: Pipeline,
+    worker: &Worker,
+    payload: WorkerJobUpdateRequest,
+) -> anyhow::Result<()> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get db connection from pool")?;
+
+    // retries back off up to a minute, do not keep the worker waiting
+    tokio::spawn(handle_success_message_with_retry(
+        job.clone(),
+        pipeline.clone(),
+        payload.clone(),
+        bot.clone(),
+    ));
 
     use crate::schema::jobs::dsl::*;
+    let mut changed = vec![];
     match payload.result {
         JobResult::Ok(res) => {
+            let arch_independent_failure = cancels_other_archs(&res);
+            let job_artifacts = res
+                .artifacts
+                .as_ref()
+                .map(serde_json::to_string)
+                .transpose()?;
+            let job_step_timings = Some(&res.step_timings)
+                .filter(|timings| !timings.is_empty())
+                .map(serde_json::to_string)
+                .transpose()?;
+            let new_status = if res.build_success && res.pushpkg_success {
+                "success"
+            } else {
+                "failed"
+            };
             diesel::update(jobs.filter(id.eq(payload.job_id)))
                 .set((
-                    status.eq(if res.build_success && res.pushpkg_success {
-                        "success"
-                    } else {
-                        "failed"
-                    }),
+                    status.eq(new_status),
                     build_success.eq(res.build_success),
                     pushpkg_success.eq(res.pushpkg_success),
                     successful_packages.eq(res.successful_packages.join(",")),
@@ -379,8 +799,34 @@ pub async fn worker_job_update(
                     elapsed_secs.eq(res.elapsed_secs),
                     assigned_worker_id.eq(None::<i32>),
                     built_by_worker_id.eq(Some(worker.id)),
+                    artifacts.eq(job_artifacts),
+                    step_timings.eq(job_step_timings),
                 ))
                 .execute(&mut conn)?;
+            changed.push((job.id, job.arch.clone(), new_status.to_string()));
+
+            // no need to try other archs if they are going to fail the same way
+            if arch_independent_failure {
+                let cancelled = api::pipeline_cancel_created_jobs(
+                    pool.clone(),
+                    pipeline.id,
+                    &format!(
+                        "Cancelled due to arch-independent failure of job #{}",
+                        job.id
+                    ),
+                )
+                .await?;
+                if !cancelled.is_empty() {
+                    info!(
+                        "Cancelled {} job(s) of pipeline #{} due to arch-independent failure",
+                        cancelled.len(),
+                        pipeline.id
+                    );
+                }
+                for cancelled_job in cancelled {
+                    changed.push((cancelled_job.id, cancelled_job.arch, cancelled_job.status));
+                }
+            }
         }
         JobResult::Error(err) => {
             diesel::update(jobs.filter(id.eq(payload.job_id)))
@@ -390,6 +836,27 @@ pub async fn worker_job_update(
                     built_by_worker_id.eq(Some(worker.id)),
                 ))
                 .execute(&mut conn)?;
+            changed.push((job.id, job.arch.clone(), "error".to_string()));
+        }
+    }
+    for (changed_job_id, changed_arch, changed_status) in changed {
+        publish_pipeline_event(
+            pipeline_events_map,
+            pipeline.id,
+            PipelineJobEvent {
+                job_id: changed_job_id,
+                arch: changed_arch,
+                status: changed_status,
+            },
+        );
+    }
+
+    if let Some(bot) = bot {
+        if let Err(err) = crate::bot::handle_deferred_pr(bot, pool.clone(), pipeline.id).await {
+            warn!(
+                "Failed to handle deferred PR of pipeline #{}: {}",
+                pipeline.id, err
+            );
         }
     }
     Ok(())
@@ -400,10 +867,55 @@ static GITHUB_PR_CHECKLIST_LOCK: Lazy<tokio::sync::Mutex<()>> =
 
 pub enum HandleSuccessResult {
     Ok,
-    Retry(u8),
+    /// Number of failed attempts so far and the error of the last one
+    Retry(u8, String),
     DoNotRetry,
 }
 
+/// Delay before the next attempt after `retry` failed attempts
+fn job_update_retry_delay(retry: u8) -> Duration {
+    Duration::from_secs(
+        2u64.saturating_pow(u32::from(retry.saturating_sub(1)))
+            .min(JOB_UPDATE_MAX_RETRY_DELAY_SECS),
+    )
+}
+
+async fn handle_success_message_with_retry(
+    job: Job,
+    pipeline: Pipeline,
+    req: WorkerJobUpdateRequest,
+    bot: Option<Bot>,
+) {
+    let max_retry = ARGS
+        .job_update_max_retry
+        .unwrap_or(DEFAULT_JOB_UPDATE_MAX_RETRY);
+    let mut retry = None;
+    loop {
+        match handle_success_message(&job, &pipeline, &req, &bot, retry).await {
+            HandleSuccessResult::Ok | HandleSuccessResult::DoNotRetry => {
+                break;
+            }
+            HandleSuccessResult::Retry(x, err) => {
+                if x >= max_retry {
+                    error!(
+                        "Giving up handle_success_message of job #{} after {x} attempt(s): {err}",
+                        job.id
+                    );
+                    break;
+                }
+                let delay = job_update_retry_delay(x);
+                warn!(
+                    "Retrying handle_success_message of job #{} in {}s (attempt {x}/{max_retry} failed): {err}",
+                    job.id,
+                    delay.as_secs()
+                );
+                tokio::time::sleep(delay).await;
+                retry = Some(x);
+            }
+        }
+    }
+}
+
 #[tracing::instrument(skip(bot))]
 pub async fn handle_success_message(
     job: &Job,
@@ -428,6 +940,7 @@ pub async fn handle_success_message(
                 if let Some(bot) = bot {
                     info!("Sending result to telegram");
                     let s = to_html_build_result(
+                        &github_repo_web_url(),
                         pipeline,
                         job,
                         job_ok,
@@ -442,8 +955,10 @@ pub async fn handle_success_message(
                         .disable_web_page_preview(true)
                         .await
                     {
-                        error!("Failed to send build result to telegram: {}", e);
-                        return update_retry(retry);
+                        return update_retry(
+                            retry,
+                            format!("Failed to send build result to telegram: {}", e),
+                        );
                     }
                 } else {
                     error!("Telegram bot not configured");
@@ -452,8 +967,15 @@ pub async fn handle_success_message(
             }
 
             // if associated with github pr, update comments
-            let new_content =
-                to_markdown_build_result(pipeline, job, job_ok, &req.hostname, &req.arch, success);
+            let new_content = to_markdown_build_result(
+                &github_repo_web_url(),
+                pipeline,
+                job,
+                job_ok,
+                &req.hostname,
+                &req.arch,
+                success,
+            );
             if let Some(pr_num) = pipeline.github_pr {
                 info!("Updating GitHub PR comments");
                 let crab = match octocrab::Octocrab::builder()
@@ -468,7 +990,7 @@ pub async fn handle_success_message(
                 };
 
                 let comments = crab
-                    .issues("AOSC-Dev", "aosc-os-abbs")
+                    .issues(github_org(), github_repo())
                     .list_comments(pr_num as u64)
                     .send()
                     .await;
@@ -476,8 +998,7 @@ pub async fn handle_success_message(
                 let comments = match comments {
                     Ok(c) => c,
                     Err(e) => {
-                        error!("Failed to list comments of pr: {e}");
-                        return update_retry(retry);
+                        return update_retry(retry, format!("Failed to list comments of pr: {e}"));
                     }
                 };
 
@@ -493,17 +1014,16 @@ pub async fn handle_success_message(
                             continue;
                         }
 
-                        for line in body.split('\n') {
-                            let arch = line.strip_prefix("Architecture:").map(|x| x.trim());
-                            if arch.map(|x| x == job.arch).unwrap_or(false) {
-                                if let Err(e) = crab
-                                    .issues("AOSC-Dev", "aosc-os-abbs")
-                                    .delete_comment(c.id)
-                                    .await
-                                {
-                                    error!("Failed to delete comment from pr: {e}");
-                                    return update_retry(retry);
-                                }
+                        if build_result_arch(&body) == Some(job.arch.as_str()) {
+                            if let Err(e) = crab
+                                .issues(github_org(), github_repo())
+                                .delete_comment(c.id)
+                                .await
+                            {
+                                return update_retry(
+                                    retry,
+                                    format!("Failed to delete comment from pr: {e}"),
+                                );
                             }
                         }
                     }
@@ -512,12 +1032,11 @@ pub async fn handle_success_message(
                 // Disable comment posting, since we have check run reporting
                 /*
                 if let Err(e) = crab
-                    .issues("AOSC-Dev", "aosc-os-abbs")
+                    .issues(github_org(), github_repo())
                     .create_comment(pr_num, new_content.clone())
                     .await
                 {
-                    error!("{e}");
-                    return update_retry(retry);
+                    return update_retry(retry, e.to_string());
                 }
                 */
 
@@ -526,14 +1045,13 @@ pub async fn handle_success_message(
                 info!("Updating GitHub PR checklist");
                 let _lock = GITHUB_PR_CHECKLIST_LOCK.lock().await;
                 let pr = match crab
-                    .pulls("AOSC-Dev", "aosc-os-abbs")
+                    .pulls(github_org(), github_repo())
                     .get(pr_num as u64)
                     .await
                 {
                     Ok(pr) => pr,
                     Err(e) => {
-                        error!("Failed to get pr info: {e:?}");
-                        return update_retry(retry);
+                        return update_retry(retry, format!("Failed to get pr info: {e:?}"));
                     }
                 };
 
@@ -543,35 +1061,22 @@ pub async fn handle_success_message(
                     return HandleSuccessResult::DoNotRetry;
                 };
 
-                let pr_arch = match job.arch.as_str() {
-                    "noarch" => NOARCH,
-                    "amd64" => AMD64,
-                    "arm64" => ARM64,
-                    "loongson3" => LOONGSON3,
-                    "ppc64el" => PPC64EL,
-                    "riscv64" => RISCV64,
-                    "loongarch64" => LOONGARCH64,
-                    x => {
-                        error!("Unknown architecture: {x}");
+                let body = match update_arch_checklist(&body, &job.arch, success) {
+                    Some(body) => body,
+                    None => {
+                        error!("Unknown architecture: {}", job.arch);
                         return HandleSuccessResult::DoNotRetry;
                     }
                 };
 
-                let body = if success {
-                    body.replace(&format!("- [ ] {pr_arch}"), &format!("- [x] {pr_arch}"))
-                } else {
-                    body.replace(&format!("- [x] {pr_arch}"), &format!("- [ ] {pr_arch}"))
-                };
-
                 if let Err(e) = crab
-                    .pulls("AOSC-Dev", "aosc-os-abbs")
+                    .pulls(github_org(), github_repo())
                     .update(pr_num as u64)
                     .body(body)
                     .send()
                     .await
                 {
-                    error!("Failed to update pr body: {e}");
-                    return update_retry(retry);
+                    return update_retry(retry, format!("Failed to update pr body: {e}"));
                 }
             }
 
@@ -581,7 +1086,7 @@ pub async fn handle_success_message(
                 // authenticate with github app
                 match get_crab_github_installation().await {
                     Ok(Some(crab)) => {
-                        let handler = crab.checks("AOSC-Dev", "aosc-os-abbs");
+                        let handler = crab.checks(github_org(), github_repo());
                         let output = CheckRunOutput {
                             title: format!(
                                 "Built {} packages in {}s",
@@ -602,19 +1107,23 @@ pub async fn handle_success_message(
                             } else {
                                 CheckRunConclusion::Failure
                             })
-                            .details_url(format!("https://buildit.aosc.io/jobs/{}", job.id));
+                            .details_url(format!("{}/jobs/{}", base_url(), job.id));
 
                         if let Err(e) = builder.send().await {
-                            error!("Failed to update github check run: {e}");
-                            return update_retry(retry);
+                            return update_retry(
+                                retry,
+                                format!("Failed to update github check run: {e}"),
+                            );
                         }
                     }
                     Ok(None) => {
                         // github app unavailable
                     }
                     Err(err) => {
-                        warn!("Failed to get installation token: {}", err);
-                        return update_retry(retry);
+                        return update_retry(
+                            retry,
+                            format!("Failed to get installation token: {}", err),
+                        );
                     }
                 }
             }
@@ -632,8 +1141,10 @@ pub async fn handle_success_message(
                         )
                         .await
                     {
-                        error!("Failed to send message to telegram: {e}");
-                        return update_retry(retry);
+                        return update_retry(
+                            retry,
+                            format!("Failed to send message to telegram: {e}"),
+                        );
                     }
                 } else {
                     error!("Telegram bot not configured");
@@ -652,7 +1163,7 @@ pub async fn handle_success_message(
                 };
 
                 if let Err(e) = crab
-                    .issues("AOSC-Dev", "aosc-os-abbs")
+                    .issues(github_org(), github_repo())
                     .create_comment(
                         pipeline.github_pr.unwrap() as u64,
                         format!(
@@ -662,8 +1173,7 @@ pub async fn handle_success_message(
                     )
                     .await
                 {
-                    error!("Failed to create comment on github: {e}");
-                    return update_retry(retry);
+                    return update_retry(retry, format!("Failed to create comment on github: {e}"));
                 }
             }
         }
@@ -672,15 +1182,17 @@ pub async fn handle_success_message(
     HandleSuccessResult::Ok
 }
 
-pub fn update_retry(retry: Option<u8>) -> HandleSuccessResult {
+pub fn update_retry(retry: Option<u8>, err: String) -> HandleSuccessResult {
     match retry {
-        Some(retry) => HandleSuccessResult::Retry(retry + 1),
-        None => HandleSuccessResult::Retry(1),
+        Some(retry) => HandleSuccessResult::Retry(retry.saturating_add(1), err),
+        None => HandleSuccessResult::Retry(1, err),
     }
 }
 
 pub async fn worker_status(
-    State(AppState { pool, .. }): State<AppState>,
+    State(AppState {
+        replica_pool: pool, ..
+    }): State<AppState>,
 ) -> Result<Json<Vec<Worker>>, AnyhowError> {
     Ok(Json(api::worker_status(pool).await?))
 }
@@ -690,28 +1202,11 @@ pub struct WorkerInfoRequest {
     worker_id: i32,
 }
 
-#[derive(Serialize)]
-pub struct WorkerInfoResponse {
-    // from worker
-    worker_id: i32,
-    hostname: String,
-    arch: String,
-    git_commit: String,
-    memory_bytes: i64,
-    logical_cores: i32,
-    last_heartbeat_time: chrono::DateTime<chrono::Utc>,
-    disk_free_space_bytes: i64,
-
-    // status
-    running_job_id: Option<i32>,
-
-    // statistics
-    built_job_count: i64,
-}
-
 pub async fn worker_info(
     Query(query): Query<WorkerInfoRequest>,
-    State(AppState { pool, .. }): State<AppState>,
+    State(AppState {
+        replica_pool: pool, ..
+    }): State<AppState>,
 ) -> Result<Json<WorkerInfoResponse>, AnyhowError> {
     let mut conn = pool
         .get()
@@ -732,6 +1227,9 @@ pub async fn worker_info(
                 .filter(crate::schema::jobs::dsl::built_by_worker_id.eq(worker.id))
                 .count()
                 .get_result::<i64>(conn)?;
+            let (successful_job_count, failed_job_count) = worker_job_stats(conn, &[worker.id])?
+                .remove(&worker.id)
+                .unwrap_or_default();
 
             Ok(WorkerInfoResponse {
                 worker_id: worker.id,
@@ -742,10 +1240,241 @@ pub async fn worker_info(
                 logical_cores: worker.logical_cores,
                 disk_free_space_bytes: worker.disk_free_space_bytes,
                 last_heartbeat_time: worker.last_heartbeat_time,
+                ciel_instances: worker
+                    .ciel_instances
+                    .map(|s| s.split(',').map(String::from).collect())
+                    .unwrap_or_default(),
+                tags: parse_tags(worker.tags.as_deref().unwrap_or(""))
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                websocket_reconnects: worker.websocket_reconnects,
+                websocket_last_error: worker.websocket_last_error,
 
                 running_job_id: running_job.map(|job| job.id),
                 built_job_count,
+                successful_job_count,
+                failed_job_count,
+                success_rate: success_rate(successful_job_count, failed_job_count),
             })
         })?,
     ))
 }
+
+#[test]
+fn test_match_worker_row() {
+    let worker = |id: i32, worker_arch: &str| Worker {
+        id,
+        hostname: "Yerus".to_string(),
+        arch: worker_arch.to_string(),
+        git_commit: "abcdef".to_string(),
+        memory_bytes: 0,
+        logical_cores: 1,
+        last_heartbeat_time: chrono::DateTime::from_timestamp(61, 0).unwrap(),
+        disk_free_space_bytes: 0,
+        performance: None,
+        visible: true,
+        internet_connectivity: true,
+        ciel_instances: None,
+        tags: None,
+        machine_class: None,
+        websocket_reconnects: None,
+        websocket_last_error: None,
+    };
+
+    assert!(matches!(match_worker_row(&[], "amd64"), WorkerRow::New));
+
+    // heartbeat of a known worker
+    let rows = [worker(1, "amd64")];
+    assert!(matches!(
+        match_worker_row(&rows, "amd64"),
+        WorkerRow::Existing(w) if w.id == 1
+    ));
+
+    // worker reconfigured to another arch, its jobs get rescheduled
+    assert!(matches!(
+        match_worker_row(&rows, "loongarch64"),
+        WorkerRow::ArchChanged(w) if w.id == 1
+    ));
+
+    // several workers of different archs on one host
+    let rows = [worker(1, "loongson3"), worker(2, "loongarch64")];
+    assert!(matches!(
+        match_worker_row(&rows, "loongarch64"),
+        WorkerRow::Existing(w) if w.id == 2
+    ));
+    assert!(matches!(match_worker_row(&rows, "amd64"), WorkerRow::New));
+}
+
+#[test]
+fn test_validate_worker_arch() {
+    assert!(validate_worker_arch("amd64").is_ok());
+    assert!(validate_worker_arch("loongarch64").is_ok());
+    assert!(validate_worker_arch("noarch").is_err());
+    assert!(validate_worker_arch("amd46").is_err());
+}
+
+#[test]
+fn test_new_worker_from_poll() {
+    let payload = WorkerPollRequest {
+        hostname: "Yerus".to_string(),
+        arch: "amd64".to_string(),
+        worker_secret: "secret".to_string(),
+        memory_bytes: 1024,
+        logical_cores: 8,
+        disk_free_space_bytes: 2048,
+        tags: Some("gpu, bigdisk,".to_string()),
+        machine_class: None,
+        running_job_ids: vec![],
+        log_stream: None,
+    };
+    let worker = new_worker_from_poll(&payload);
+    assert_eq!(worker.tags.as_deref(), Some("gpu,bigdisk"));
+    assert_eq!(worker.hostname, "Yerus");
+    assert_eq!(worker.arch, "amd64");
+    assert_eq!(worker.memory_bytes, 1024);
+    assert_eq!(worker.logical_cores, 8);
+    assert_eq!(worker.disk_free_space_bytes, 2048);
+    assert_eq!(worker.performance, None);
+}
+
+#[test]
+fn test_job_update_retry_delay() {
+    assert_eq!(job_update_retry_delay(1), Duration::from_secs(1));
+    assert_eq!(job_update_retry_delay(2), Duration::from_secs(2));
+    assert_eq!(job_update_retry_delay(5), Duration::from_secs(16));
+    assert_eq!(job_update_retry_delay(7), Duration::from_secs(60));
+    assert_eq!(job_update_retry_delay(u8::MAX), Duration::from_secs(60));
+}
+
+#[test]
+fn test_machine_classes_left_to_others() {
+    use api::{machine_class_rank, machine_classes_left_to_others};
+    const GIB: i64 = 1 << 30;
+    assert_eq!(machine_class_rank(None, 8, 16 * GIB), 0);
+    // needs both cores and memory of the class
+    assert_eq!(machine_class_rank(None, 64, 32 * GIB), 1);
+    assert_eq!(machine_class_rank(None, 64, 128 * GIB), 2);
+    assert_eq!(machine_class_rank(Some("xlarge"), 8, 16 * GIB), 2);
+
+    // no other live worker, every job falls back to this one
+    assert!(machine_classes_left_to_others(0, None).is_empty());
+    assert!(machine_classes_left_to_others(0, Some(0)).is_empty());
+    assert_eq!(machine_classes_left_to_others(0, Some(1)), ["large"]);
+    assert_eq!(
+        machine_classes_left_to_others(0, Some(2)),
+        ["large", "xlarge"]
+    );
+    assert_eq!(machine_classes_left_to_others(1, Some(2)), ["xlarge"]);
+    assert!(machine_classes_left_to_others(2, Some(1)).is_empty());
+}
+
+#[test]
+fn test_success_rate() {
+    assert_eq!(success_rate(0, 0), None);
+    assert_eq!(success_rate(3, 1), Some(0.75));
+    assert_eq!(success_rate(0, 2), Some(0.0));
+}
+
+#[test]
+fn test_is_orphaned_job() {
+    let now = Utc::now();
+    let deadline = now - chrono::Duration::try_seconds(HEARTBEAT_TIMEOUT).unwrap();
+    let long_ago = deadline - chrono::Duration::try_seconds(1).unwrap();
+    let hours_ago = now - chrono::Duration::try_hours(5).unwrap();
+
+    // freshly running job of another process on the host survives a poll
+    assert!(!is_orphaned_job(
+        1,
+        "running",
+        Some(now),
+        None,
+        &[],
+        deadline
+    ));
+    // reported by another build slot
+    assert!(!is_orphaned_job(
+        1,
+        "running",
+        Some(long_ago),
+        None,
+        &[1],
+        deadline
+    ));
+    assert!(!is_orphaned_job(
+        1,
+        "created",
+        Some(long_ago),
+        None,
+        &[],
+        deadline
+    ));
+    assert!(is_orphaned_job(
+        1,
+        "running",
+        Some(long_ago),
+        None,
+        &[2],
+        deadline
+    ));
+    assert!(is_orphaned_job(1, "running", None, None, &[], deadline));
+
+    // long build of another process on the host, still reported by its heartbeats
+    assert!(!is_orphaned_job(
+        1,
+        "running",
+        Some(hours_ago),
+        Some(now),
+        &[2],
+        deadline
+    ));
+    // no longer reported by any process for a while
+    assert!(is_orphaned_job(
+        1,
+        "running",
+        Some(hours_ago),
+        Some(long_ago),
+        &[2],
+        deadline
+    ));
+    assert!(is_orphaned_job(
+        1,
+        "running",
+        None,
+        Some(long_ago),
+        &[],
+        deadline
+    ));
+}
+
+#[test]
+fn test_cancels_other_archs() {
+    use common::FailureStage;
+
+    let failed = |output: &str| JobOk {
+        build_success: false,
+        successful_packages: vec![],
+        failed_package: Some("fish".to_string()),
+        skipped_packages: vec![],
+        log_url: None,
+        elapsed_secs: 60,
+        pushpkg_success: false,
+        failure_stage: Some(FailureStage::from_build_output(output)),
+        artifacts: None,
+        step_timings: vec![],
+    };
+
+    // the mirror might be down for this worker only, keep other archs going
+    let fetch = "\
+[INFO]:  [1/1] Fetching source (tarball)...
+curl: (7) Failed to connect to example.com port 443: Connection refused
+[ERROR]: Failed to fetch source from https://example.com/fish-3.7.1.tar.xz
+";
+    assert!(!cancels_other_archs(&failed(fetch)));
+
+    let spec = "[ERROR]: Failed to parse spec of package app-utils/fish: line 3: unexpected EOF\n";
+    assert!(cancels_other_archs(&failed(spec)));
+
+    let build = "[ERROR]: Build error.\n";
+    assert!(!cancels_other_archs(&failed(build)));
+}
@@ -1,5 +1,20 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    env_req_overrides (package) {
+        package -> Text,
+        envreq -> Text,
+        update_time -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    job_last_seen (job_id) {
+        job_id -> Int4,
+        last_seen_time -> Timestamptz,
+    }
+}
+
 diesel::table! {
     jobs (id) {
         id -> Int4,
@@ -25,6 +40,15 @@ diesel::table! {
         require_min_total_mem_per_core -> Nullable<Float4>,
         require_min_disk -> Nullable<Int8>,
         assign_time -> Nullable<Timestamptz>,
+        repush_only -> Bool,
+        verbose -> Bool,
+        restarted_from -> Nullable<Int4>,
+        artifacts -> Nullable<Text>,
+        required_tags -> Nullable<Text>,
+        priority -> Int4,
+        preferred_machine_class -> Nullable<Text>,
+        step_timings -> Nullable<Text>,
+        reclaim_count -> Int4,
     }
 }
 
@@ -40,6 +64,14 @@ diesel::table! {
         github_pr -> Nullable<Int8>,
         telegram_user -> Nullable<Int8>,
         creator_user_id -> Nullable<Int4>,
+        patch -> Nullable<Text>,
+        deferred_pr_title -> Nullable<Text>,
+        git_ref -> Nullable<Text>,
+        notify_start -> Bool,
+        git_remote -> Nullable<Text>,
+        idempotency_key -> Nullable<Text>,
+        ciel_flags -> Nullable<Text>,
+        tag -> Nullable<Text>,
     }
 }
 
@@ -52,6 +84,7 @@ diesel::table! {
         github_avatar_url -> Nullable<Text>,
         github_email -> Nullable<Text>,
         telegram_chat_id -> Nullable<Int8>,
+        token_hash -> Nullable<Text>,
     }
 }
 
@@ -68,10 +101,23 @@ diesel::table! {
         performance -> Nullable<Int8>,
         visible -> Bool,
         internet_connectivity -> Bool,
+        ciel_instances -> Nullable<Text>,
+        tags -> Nullable<Text>,
+        machine_class -> Nullable<Text>,
+        websocket_reconnects -> Nullable<Int4>,
+        websocket_last_error -> Nullable<Text>,
     }
 }
 
+diesel::joinable!(job_last_seen -> jobs (job_id));
 diesel::joinable!(jobs -> pipelines (pipeline_id));
 diesel::joinable!(pipelines -> users (creator_user_id));
 
-diesel::allow_tables_to_appear_in_same_query!(jobs, pipelines, users, workers,);
+diesel::allow_tables_to_appear_in_same_query!(
+    env_req_overrides,
+    job_last_seen,
+    jobs,
+    pipelines,
+    users,
+    workers,
+);
@@ -1,18 +1,25 @@
-use crate::{get_memory_bytes, Args};
+use crate::{get_memory_bytes, server_client, slot_ciel_paths, websocket::LogSender, Args, Slot};
+use anyhow::bail;
 use chrono::Local;
-use common::{JobOk, WorkerJobUpdateRequest, WorkerPollRequest, WorkerPollResponse};
-use flume::Sender;
+use common::{
+    ciel_build_args, Artifact, FailureStage, JobOk, JobUploadLogRequest, JobUploadLogResponse,
+    WorkerJobUpdateRequest, WorkerPollRequest, WorkerPollResponse, MAX_UPLOADED_LOG_BYTES,
+    WORKER_SECRET_HEADER,
+};
 use futures_util::future::try_join3;
 use log::{error, info, warn};
 use std::{
-    path::Path,
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
     process::{Output, Stdio},
-    time::{Duration, Instant},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
 };
 use tokio::{
     fs,
     io::{AsyncBufReadExt, AsyncRead, BufReader},
     process::Command,
+    sync::Notify,
     time::sleep,
 };
 use tokio_tungstenite::tungstenite::Message;
@@ -22,8 +29,21 @@ async fn get_output_logged(
     args: &[&str],
     cwd: &Path,
     logs: &mut Vec<u8>,
-    tx: Sender<Message>,
+    tx: LogSender,
 ) -> anyhow::Result<Output> {
+    let output = get_output_logged_cancellable(cmd, args, cwd, logs, tx, None).await?;
+    Ok(output.expect("command without cancel cannot be cancelled"))
+}
+
+/// Like `get_output_logged`, but kill the command and return None once `cancel` is notified
+async fn get_output_logged_cancellable(
+    cmd: &str,
+    args: &[&str],
+    cwd: &Path,
+    logs: &mut Vec<u8>,
+    tx: LogSender,
+    cancel: Option<&Notify>,
+) -> anyhow::Result<Option<Output>> {
     let begin = Instant::now();
     let msg = format!(
         "{}: Running `{} {}` in `{}`\n",
@@ -45,7 +65,7 @@ async fn get_output_logged(
     // learn from tokio wait_with_output
     async fn read_and_send<A: AsyncRead + Unpin>(
         io: &mut Option<A>,
-        tx: Sender<Message>,
+        tx: LogSender,
     ) -> tokio::io::Result<String> {
         let mut res = String::new();
         if let Some(io) = io.as_mut() {
@@ -67,7 +87,7 @@ async fn get_output_logged(
 
                         // convert \r to \n
                         for line in String::from_utf8_lossy(&buffer).split("\r") {
-                            tx.send_async(Message::Text(line.to_string())).await.ok();
+                            tx.send(Message::Text(line.to_string()));
                             res += &line;
                             res += "\n";
                         }
@@ -88,7 +108,30 @@ async fn get_output_logged(
     let mut stderr_pipe = output.stderr.take();
     let stderr_future = read_and_send(&mut stderr_pipe, tx.clone());
 
-    let (status, stdout, stderr) = try_join3(output.wait(), stdout_future, stderr_future).await?;
+    let cancelled = async {
+        match cancel {
+            Some(cancel) => cancel.notified().await,
+            None => std::future::pending().await,
+        }
+    };
+    let res = tokio::select! {
+        res = try_join3(output.wait(), stdout_future, stderr_future) => Some(res),
+        _ = cancelled => None,
+    };
+    let Some(res) = res else {
+        output.kill().await?;
+        logs.extend(
+            format!(
+                "{}: `{} {}` killed since the job was cancelled\n",
+                Local::now(),
+                cmd,
+                args.join(" ")
+            )
+            .as_bytes(),
+        );
+        return Ok(None);
+    };
+    let (status, stdout, stderr) = res?;
 
     let elapsed = begin.elapsed();
 
@@ -108,11 +151,11 @@ async fn get_output_logged(
     logs.extend("STDERR:\n".as_bytes());
     logs.extend(stderr.as_bytes());
 
-    Ok(Output {
+    Ok(Some(Output {
         status,
         stdout: stdout.into_bytes(),
         stderr: stderr.into_bytes(),
-    })
+    }))
 }
 
 /// Run command and retry until it succeeds
@@ -121,7 +164,7 @@ async fn run_logged_with_retry(
     args: &[&str],
     cwd: &Path,
     logs: &mut Vec<u8>,
-    tx: Sender<Message>,
+    tx: LogSender,
 ) -> anyhow::Result<bool> {
     for i in 0..5 {
         if i > 0 {
@@ -150,11 +193,159 @@ async fn run_logged_with_retry(
     Ok(false)
 }
 
+/// Upload built packages, returns false if pushpkg failed or is unavailable
+async fn pushpkg(
+    job: &WorkerPollResponse,
+    args: &Args,
+    output_path: &Path,
+    logs: &mut Vec<u8>,
+    tx: LogSender,
+) -> anyhow::Result<bool> {
+    if let Some(upload_ssh_key) = &args.upload_ssh_key {
+        let mut pushpkg_args = vec![
+            "--host",
+            &args.rsync_host,
+            "-i",
+            upload_ssh_key,
+            "maintainers",
+            &job.git_branch,
+        ];
+        if &args.pushpkg_options != "" {
+            pushpkg_args.insert(0, &args.pushpkg_options);
+        }
+        if &job.git_branch != "stable" {
+            // allow force push if noarch and non stable
+            pushpkg_args.insert(0, "--force-push-noarch-package");
+        }
+        run_logged_with_retry("pushpkg", &pushpkg_args, output_path, logs, tx).await
+    } else {
+        Ok(false)
+    }
+}
+
+/// Whether there is any file under `dir` recursively
+fn has_files(dir: &Path) -> bool {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return false;
+    };
+    entries.flatten().any(|entry| {
+        let path = entry.path();
+        if path.is_dir() {
+            has_files(&path)
+        } else {
+            true
+        }
+    })
+}
+
+/// List files under `debs` output directory recursively
+fn list_artifacts(debs: &Path, dir: &Path, res: &mut Vec<Artifact>) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            list_artifacts(debs, &path, res)?;
+        } else {
+            res.push(Artifact {
+                path: path.strip_prefix(debs)?.to_string_lossy().to_string(),
+                size: path.metadata()?.len(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Collect artifacts before pushpkg, failures are logged and not fatal
+fn collect_artifacts(output_path: &Path, logs: &mut Vec<u8>) -> Option<Vec<Artifact>> {
+    let debs = output_path.join("debs");
+    let mut res = vec![];
+    match list_artifacts(&debs, &debs, &mut res) {
+        Ok(()) => {
+            res.sort_by(|a, b| a.path.cmp(&b.path));
+            Some(res)
+        }
+        Err(err) => {
+            logs.extend(format!("Failed to list artifacts: {err}\n").as_bytes());
+            None
+        }
+    }
+}
+
+/// State shared by build slots
+#[derive(Default)]
+pub struct Slots {
+    /// Jobs being built, reported on poll so that the server keeps them assigned
+    running_jobs: tokio::sync::Mutex<BTreeSet<i32>>,
+    /// `TREE` of each ciel workspace, held from checkout until `ciel build` exits,
+    /// since slots building in instances of the same workspace share it
+    trees: std::sync::Mutex<HashMap<PathBuf, Arc<tokio::sync::Mutex<()>>>>,
+    /// `OUTPUT-<branch>` directories in use, held until the packages are pushed,
+    /// since repush and cleanup may touch the output of another slot
+    outputs: std::sync::Mutex<HashMap<PathBuf, Arc<tokio::sync::Mutex<()>>>>,
+    /// Notified to kill `ciel build` of jobs cancelled on the server
+    cancels: std::sync::Mutex<HashMap<i32, Arc<Notify>>>,
+}
+
+impl Slots {
+    /// Ids of jobs being built, reported on heartbeat
+    pub fn running_job_ids(&self) -> Vec<i32> {
+        let mut res: Vec<i32> = self.cancels.lock().unwrap().keys().copied().collect();
+        res.sort();
+        res
+    }
+
+    /// Stop building a job cancelled on the server
+    pub fn cancel(&self, job_id: i32) {
+        if let Some(cancel) = self.cancels.lock().unwrap().get(&job_id) {
+            info!("Cancelling job {}", job_id);
+            cancel.notify_one();
+        }
+    }
+
+    fn output_lock(&self, output_path: &Path) -> Arc<tokio::sync::Mutex<()>> {
+        self.outputs
+            .lock()
+            .unwrap()
+            .entry(output_path.to_path_buf())
+            .or_default()
+            .clone()
+    }
+
+    fn tree_lock(&self, ciel_path: &Path) -> Arc<tokio::sync::Mutex<()>> {
+        self.trees
+            .lock()
+            .unwrap()
+            .entry(ciel_path.to_path_buf())
+            .or_default()
+            .clone()
+    }
+}
+
+/// File in `OUTPUT-<branch>` recording the job that produced the packages
+const OUTPUT_JOB_ID_FILE: &str = "buildit-job-id";
+
+fn output_job_id(output_path: &Path) -> Option<i32> {
+    std::fs::read_to_string(output_path.join(OUTPUT_JOB_ID_FILE))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Find `OUTPUT-<branch>` produced by the given job in the workspaces of all slots
+fn find_previous_output(args: &Args, git_branch: &str, job_id: i32) -> Option<PathBuf> {
+    slot_ciel_paths(args)
+        .into_iter()
+        .map(|ciel_path| ciel_path.join(format!("OUTPUT-{git_branch}")))
+        .find(|output_path| output_job_id(output_path) == Some(job_id))
+}
+
 async fn build(
     job: &WorkerPollResponse,
-    tree_path: &Path,
+    slot: &Slot,
     args: &Args,
-    tx: Sender<Message>,
+    slots: &Slots,
+    cancel: &Notify,
+    tx: LogSender,
 ) -> anyhow::Result<WorkerJobUpdateRequest> {
     let begin = Instant::now();
     let mut successful_packages = vec![];
@@ -163,37 +354,89 @@ async fn build(
     let mut build_success = false;
     let mut logs = vec![];
 
-    let mut output_path = args.ciel_path.clone();
-    output_path.push(format!("OUTPUT-{}", job.git_branch));
+    let ciel_path = slot.ciel_path.as_path();
+    let tree_path = ciel_path.join("TREE");
+    let mut output_path = ciel_path.join(format!("OUTPUT-{}", job.git_branch));
 
-    // clear output directory
-    if output_path.exists() {
-        get_output_logged("rm", &["-rf", "debs"], &output_path, &mut logs, tx.clone()).await?;
+    let mut pushpkg_success = false;
+    let mut artifacts = None;
+    let mut step_timings = vec![];
+    let mut failure_stage = None;
+    // stdout and stderr of `ciel build`, to tell the stage of a failed build
+    let mut build_output = String::new();
+
+    if let Some(repush_from) = job.repush_from {
+        // packages were built by the previous job, possibly in another slot
+        if let Some(path) = find_previous_output(args, &job.git_branch, repush_from) {
+            output_path = path;
+        }
     }
+    let output_lock = slots.output_lock(&output_path);
+    let output_guard = output_lock.lock().await;
 
-    // switch to git ref
-    let git_fetch_succeess = run_logged_with_retry(
-        "git",
-        &[
-            "fetch",
-            "https://github.com/AOSC-Dev/aosc-os-abbs.git",
-            &job.git_branch,
-        ],
-        tree_path,
-        &mut logs,
-        tx.clone(),
-    )
-    .await?;
+    if job.repush_only {
+        // packages were built by the previous job, only push them again
+        if !output_path.join("debs").exists() {
+            bail!("Output of the previous build is no longer available");
+        }
+        // the output may have been overwritten by another build of the same branch
+        let built_by = output_job_id(&output_path);
+        if built_by.is_none() || built_by != job.repush_from {
+            bail!(
+                "Output of job {:?} has been replaced by job {:?}",
+                job.repush_from,
+                built_by
+            );
+        }
+        // repushing this job again needs the output as well
+        fs::write(output_path.join(OUTPUT_JOB_ID_FILE), job.job_id.to_string()).await?;
+        build_success = true;
+        artifacts = collect_artifacts(&output_path, &mut logs);
+        let step = Instant::now();
+        pushpkg_success = pushpkg(job, args, &output_path, &mut logs, tx.clone()).await?;
+        step_timings.push(("pushpkg".to_string(), step.elapsed().as_secs() as i64));
+    } else {
+        // clear output directory
+        if output_path.exists() {
+            get_output_logged(
+                "rm",
+                &["-rf", "debs", OUTPUT_JOB_ID_FILE],
+                &output_path,
+                &mut logs,
+                tx.clone(),
+            )
+            .await?;
+        }
 
-    let mut pushpkg_success = false;
+        // switch to git ref
+        let tree_lock = slots.tree_lock(ciel_path);
+        let tree_guard = tree_lock.lock().await;
+        let git_ref = job.git_ref.as_deref().unwrap_or(&job.git_branch);
+        let Some(git_remote) = job.git_remote.as_deref() else {
+            bail!("Server did not tell which git remote to fetch from");
+        };
+        let step = Instant::now();
+        let git_fetch_succeess = run_logged_with_retry(
+            "git",
+            &["fetch", git_remote, git_ref],
+            &tree_path,
+            &mut logs,
+            tx.clone(),
+        )
+        .await?;
+        step_timings.push(("git fetch".to_string(), step.elapsed().as_secs() as i64));
+
+        if !git_fetch_succeess {
+            // infrastructure failure, report as error rather than a failed build
+            bail!("Failed to fetch git ref {} from {}", git_ref, git_remote);
+        }
 
-    if git_fetch_succeess {
         // try to switch branch, but allow it to fail:
         // ensure branch exists
         get_output_logged(
             "git",
             &["checkout", "-b", &job.git_branch],
-            tree_path,
+            &tree_path,
             &mut logs,
             tx.clone(),
         )
@@ -202,7 +445,7 @@ async fn build(
         get_output_logged(
             "git",
             &["checkout", &job.git_branch],
-            tree_path,
+            &tree_path,
             &mut logs,
             tx.clone(),
         )
@@ -213,31 +456,96 @@ async fn build(
         let output = get_output_logged(
             "git",
             &["reset", &job.git_sha, "--hard"],
-            tree_path,
+            &tree_path,
             &mut logs,
             tx.clone(),
         )
         .await?;
+        if !output.status.success() {
+            bail!("Failed to reset git tree to {}", job.git_sha);
+        }
 
-        if output.status.success() {
-            // update container
-            get_output_logged(
-                "ciel",
-                &["update-os"],
-                &args.ciel_path,
+        let mut tree_ready = true;
+        if let Some(patch) = &job.patch {
+            // apply patch to index as well, so that the next `git reset --hard`
+            // removes newly added files
+            let patch_path = format!("/tmp/buildit-{}.patch", job.job_id);
+            fs::write(&patch_path, patch).await?;
+            let output = get_output_logged(
+                "git",
+                &["apply", "--index", "--verbose", &patch_path],
+                &tree_path,
                 &mut logs,
                 tx.clone(),
             )
             .await?;
+            fs::remove_file(&patch_path).await?;
+            tree_ready = output.status.success();
+        }
+        if !tree_ready {
+            failure_stage = Some(FailureStage::Checkout);
+        }
+
+        if tree_ready {
+            // update container
+            let step = Instant::now();
+            get_output_logged("ciel", &["update-os"], ciel_path, &mut logs, tx.clone()).await?;
+            step_timings.push((
+                "ciel update-os".to_string(),
+                step.elapsed().as_secs() as i64,
+            ));
 
             // build packages
-            let mut ciel_args = vec!["build", "-i", &args.ciel_instance];
-            ciel_args.extend(job.packages.split(','));
-            let output =
-                get_output_logged("ciel", &ciel_args, &args.ciel_path, &mut logs, tx.clone())
-                    .await?;
+            if job.verbose && args.ciel_verbose_options.is_empty() {
+                logs.extend(
+                    "Verbose build requested, but BUILDIT_CIEL_VERBOSE_OPTIONS is not set\n"
+                        .as_bytes(),
+                );
+            }
+            let ciel_args = ciel_build_args(
+                &slot.ciel_instance,
+                &job.packages,
+                job.verbose.then_some(args.ciel_verbose_options.as_str()),
+                job.ciel_flags.as_deref(),
+            );
+            let step = Instant::now();
+            let Some(output) = get_output_logged_cancellable(
+                "ciel",
+                &ciel_args,
+                ciel_path,
+                &mut logs,
+                tx.clone(),
+                Some(cancel),
+            )
+            .await?
+            else {
+                // the container keeps running after ciel is killed
+                get_output_logged(
+                    "ciel",
+                    &["stop", "-i", &slot.ciel_instance],
+                    ciel_path,
+                    &mut logs,
+                    tx.clone(),
+                )
+                .await?;
+                bail!("Job cancelled");
+            };
+            step_timings.push(("ciel build".to_string(), step.elapsed().as_secs() as i64));
+            // other slots may check out their trees while this one pushes packages
+            drop(tree_guard);
 
             build_success = output.status.success();
+            build_output = format!(
+                "{}{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+
+            // ciel might succeed without building anything, e.g. all packages skipped
+            if build_success && !args.allow_empty_output && !has_files(&output_path.join("debs")) {
+                logs.extend("ciel build succeeded, but produced no packages\n".as_bytes());
+                build_success = false;
+            }
 
             // parse output
             // match acbs/acbs/util.py
@@ -287,34 +595,24 @@ async fn build(
             }
 
             if build_success {
-                if let Some(upload_ssh_key) = &args.upload_ssh_key {
-                    let mut pushpkg_args = vec![
-                        "--host",
-                        &args.rsync_host,
-                        "-i",
-                        upload_ssh_key,
-                        "maintainers",
-                        &job.git_branch,
-                    ];
-                    if &args.pushpkg_options != "" {
-                        pushpkg_args.insert(0, &args.pushpkg_options);
-                    }
-                    if &job.git_branch != "stable" {
-                        // allow force push if noarch and non stable
-                        pushpkg_args.insert(0, "--force-push-noarch-package");
-                    }
-                    pushpkg_success = run_logged_with_retry(
-                        "pushpkg",
-                        &pushpkg_args,
-                        &output_path,
-                        &mut logs,
-                        tx.clone(),
-                    )
-                    .await?;
-                }
+                // for repush to tell whether the output is still the one built by this job
+                fs::write(output_path.join(OUTPUT_JOB_ID_FILE), job.job_id.to_string()).await?;
+                artifacts = collect_artifacts(&output_path, &mut logs);
+                let step = Instant::now();
+                pushpkg_success = pushpkg(job, args, &output_path, &mut logs, tx.clone()).await?;
+                step_timings.push(("pushpkg".to_string(), step.elapsed().as_secs() as i64));
             }
         }
     }
+    drop(output_guard);
+
+    if failure_stage.is_none() {
+        if !build_success {
+            failure_stage = Some(FailureStage::from_build_output(&build_output));
+        } else if !pushpkg_success {
+            failure_stage = Some(FailureStage::Pushpkg);
+        }
+    }
 
     let file_name = format!(
         "{}-{}-{}-{}-{}.txt",
@@ -326,7 +624,7 @@ async fn build(
     );
 
     let path = format!("/tmp/{file_name}");
-    fs::write(&path, logs).await?;
+    fs::write(&path, &logs).await?;
 
     let mut log_url = None;
     if let Some(upload_ssh_key) = &args.upload_ssh_key {
@@ -355,6 +653,16 @@ async fn build(
         };
     }
 
+    if log_url.is_none() {
+        match upload_log_to_server(args, job.job_id, &file_name, &logs).await {
+            Ok(url) => {
+                fs::remove_file(&path).await?;
+                log_url = Some(url);
+            }
+            Err(err) => warn!("Failed to upload log to server: {:?}", err),
+        }
+    }
+
     if log_url.is_none() {
         let dir = Path::new("./push_failed_logs");
         let to = dir.join(file_name);
@@ -375,34 +683,187 @@ async fn build(
             log_url,
             elapsed_secs: begin.elapsed().as_secs() as i64,
             pushpkg_success,
+            failure_stage,
+            artifacts,
+            step_timings,
         }),
     };
 
     Ok(result)
 }
 
-async fn build_worker_inner(args: &Args, tx: Sender<Message>) -> anyhow::Result<()> {
-    let mut tree_path = args.ciel_path.clone();
-    tree_path.push("TREE");
+/// Upload the log to the server when scp is unavailable, returning its url
+async fn upload_log_to_server(
+    args: &Args,
+    job_id: i32,
+    file_name: &str,
+    logs: &[u8],
+) -> anyhow::Result<String> {
+    // keep the end of oversized logs, where the failure usually is
+    let logs = &logs[logs.len().saturating_sub(MAX_UPLOADED_LOG_BYTES)..];
+    let resp = server_client(args)?
+        .post(format!("{}/api/job/upload_log", args.server))
+        .header(WORKER_SECRET_HEADER, &args.worker_secret)
+        .query(&JobUploadLogRequest {
+            hostname: gethostname::gethostname().to_string_lossy().to_string(),
+            arch: args.arch.clone(),
+            job_id,
+            file_name: file_name.to_string(),
+        })
+        .body(logs.to_vec())
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<JobUploadLogResponse>()
+        .await?;
+    Ok(resp.log_url)
+}
 
+/// Total size of files under path, without following symlinks
+fn dir_size(path: &Path) -> u64 {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    std::fs::read_dir(path)
+        .map(|entries| entries.flatten().map(|entry| dir_size(&entry.path())).sum())
+        .unwrap_or(0)
+}
+
+/// Modification time of the newest file under path, without following symlinks
+fn newest_mtime(path: &Path) -> Option<SystemTime> {
+    let metadata = std::fs::symlink_metadata(path).ok()?;
+    if !metadata.is_dir() {
+        return metadata.modified().ok();
+    }
+    std::fs::read_dir(path)
+        .ok()?
+        .flatten()
+        .filter_map(|entry| newest_mtime(&entry.path()))
+        .max()
+}
+
+/// Remove `OUTPUT-*` directories whose packages were not built within `retention`,
+/// returning removed directories and bytes reclaimed
+fn cleanup_outputs(
+    ciel_path: &Path,
+    retention: Duration,
+    slots: &Slots,
+) -> anyhow::Result<Vec<(PathBuf, u64)>> {
+    let mut removed = vec![];
+    for entry in std::fs::read_dir(ciel_path)? {
+        let entry = entry?;
+        if !entry.file_name().to_string_lossy().starts_with("OUTPUT-") {
+            continue;
+        }
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+        if !metadata.is_dir() {
+            continue;
+        }
+        // directories are touched by every `rm -rf debs`, go by the packages instead
+        let modified = newest_mtime(&path.join("debs")).unwrap_or(metadata.modified()?);
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        if age <= retention {
+            continue;
+        }
+        // in use by a build or repush
+        let lock = slots.output_lock(&path);
+        let Ok(_guard) = lock.try_lock() else {
+            continue;
+        };
+        let size = dir_size(&path);
+        std::fs::remove_dir_all(&path)?;
+        removed.push((path, size));
+    }
+    Ok(removed)
+}
+
+/// Free disk space of ciel workspace if below `min_free_space_gib`
+fn low_free_space(args: &Args, ciel_path: &Path) -> anyhow::Result<Option<u64>> {
+    let free = fs2::free_space(ciel_path)?;
+    Ok(args
+        .min_free_space_gib
+        .filter(|min| free < min * 1024 * 1024 * 1024)
+        .map(|_| free))
+}
+
+/// Like `low_free_space`, but failing to check free space does not stop the worker
+fn check_free_space(args: &Args, ciel_path: &Path) -> Option<u64> {
+    low_free_space(args, ciel_path).unwrap_or_else(|err| {
+        warn!("Failed to check free disk space: {:?}", err);
+        None
+    })
+}
+
+async fn build_worker_inner(
+    args: &Args,
+    slot: &Slot,
+    slots: &Arc<Slots>,
+    tx: LogSender,
+) -> anyhow::Result<()> {
     info!("Receiving new messages");
+    let ciel_path = slot.ciel_path.as_path();
 
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(30))
-        .build()
-        .unwrap();
+    let client = server_client(args)?;
 
     let hostname = gethostname::gethostname().to_string_lossy().to_string();
-    let req = WorkerPollRequest {
+    let mut req = WorkerPollRequest {
         hostname: hostname.clone(),
         arch: args.arch.clone(),
         worker_secret: args.worker_secret.clone(),
         memory_bytes: get_memory_bytes(),
         disk_free_space_bytes: fs2::free_space(std::env::current_dir()?)? as i64,
         logical_cores: num_cpus::get() as i32,
+        tags: args.tags.clone(),
+        machine_class: args.machine_class.clone(),
+        running_job_ids: vec![],
+        log_stream: Some(slot.log_stream.clone()),
     };
 
     loop {
+        if let Some(hours) = args.output_retention_hours {
+            let ciel_path = ciel_path.to_path_buf();
+            let slots = slots.clone();
+            let removed = tokio::task::spawn_blocking(move || {
+                cleanup_outputs(&ciel_path, Duration::from_secs(hours * 3600), &slots)
+            })
+            .await?;
+            match removed {
+                Ok(removed) => {
+                    for (path, size) in &removed {
+                        info!("Removed {} to reclaim {} bytes", path.display(), size);
+                    }
+                    if !removed.is_empty() {
+                        info!(
+                            "Reclaimed {} bytes from {} old output directories",
+                            removed.iter().map(|(_, size)| size).sum::<u64>(),
+                            removed.len()
+                        );
+                    }
+                }
+                Err(err) => warn!("Failed to clean up old output directories: {:?}", err),
+            }
+        }
+
+        // do not take jobs that are going to fail for lack of space
+        if let Some(free) = check_free_space(args, ciel_path) {
+            warn!(
+                "Not polling for jobs, only {} bytes of disk space left",
+                free
+            );
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            continue;
+        }
+
+        req.disk_free_space_bytes = fs2::free_space(std::env::current_dir()?)? as i64;
+        // poll one slot at a time, so that the server sees every job taken by other slots
+        let mut running_jobs = slots.running_jobs.lock().await;
+        req.running_job_ids = running_jobs.iter().copied().collect();
         if let Some(job) = client
             .post(format!("{}/api/worker/poll", args.server))
             .json(&req)
@@ -411,44 +872,87 @@ async fn build_worker_inner(args: &Args, tx: Sender<Message>) -> anyhow::Result<
             .json::<Option<WorkerPollResponse>>()
             .await?
         {
-            info!("Processing job {:?}", job);
-
-            match build(&job, &tree_path, args, tx.clone()).await {
-                Ok(result) => {
-                    // post result
-                    info!("Finished to run job {:?} with result {:?}", job, result);
-                    client
-                        .post(format!("{}/api/worker/job_update", args.server))
-                        .json(&result)
-                        .send()
-                        .await?;
-                }
-                Err(err) => {
-                    warn!("Failed to run job {:?} with err {:?}", job, err);
-                    client
-                        .post(format!("{}/api/worker/job_update", args.server))
-                        .json(&WorkerJobUpdateRequest {
-                            hostname: gethostname::gethostname().to_string_lossy().to_string(),
-                            arch: args.arch.clone(),
-                            worker_secret: args.worker_secret.clone(),
-                            job_id: job.job_id,
-                            result: common::JobResult::Error(err.to_string()),
-                        })
-                        .send()
-                        .await?;
+            running_jobs.insert(job.job_id);
+            drop(running_jobs);
+            let cancel = Arc::new(Notify::new());
+            slots
+                .cancels
+                .lock()
+                .unwrap()
+                .insert(job.job_id, cancel.clone());
+            info!(
+                "Processing job {:?} in ciel instance {} of {}",
+                job,
+                slot.ciel_instance,
+                ciel_path.display()
+            );
+
+            let res = async {
+                // report instead of failing mid-build, so that the job can be restarted elsewhere
+                let res = match check_free_space(args, ciel_path) {
+                    Some(free) => Err(anyhow::anyhow!(
+                        "Not enough disk space to start the build: {} bytes left",
+                        free
+                    )),
+                    None => build(&job, slot, args, slots, &cancel, tx.clone()).await,
+                };
+                match res {
+                    Ok(result) => {
+                        // post result
+                        info!("Finished to run job {:?} with result {:?}", job, result);
+                        client
+                            .post(format!("{}/api/worker/job_update", args.server))
+                            .json(&result)
+                            .send()
+                            .await?;
+                    }
+                    Err(err) => {
+                        warn!("Failed to run job {:?} with err {:?}", job, err);
+                        client
+                            .post(format!("{}/api/worker/job_update", args.server))
+                            .json(&WorkerJobUpdateRequest {
+                                hostname: gethostname::gethostname().to_string_lossy().to_string(),
+                                arch: args.arch.clone(),
+                                worker_secret: args.worker_secret.clone(),
+                                job_id: job.job_id,
+                                result: common::JobResult::Error(err.to_string()),
+                            })
+                            .send()
+                            .await?;
+                    }
                 }
+                anyhow::Ok(())
             }
+            .await;
+            // no longer ours, even if reporting failed: let the server requeue it on next poll
+            slots.running_jobs.lock().await.remove(&job.job_id);
+            slots.cancels.lock().unwrap().remove(&job.job_id);
+            res?;
         }
         tokio::time::sleep(Duration::from_secs(5)).await;
     }
 }
 
-pub async fn build_worker(args: Args, tx: Sender<Message>) -> ! {
+async fn build_slot(args: Args, slot: Slot, slots: Arc<Slots>, tx: LogSender) -> ! {
     loop {
-        info!("Starting build worker");
-        if let Err(err) = build_worker_inner(&args, tx.clone()).await {
-            warn!("Got error running heartbeat worker: {}", err);
+        info!(
+            "Starting build worker in ciel instance {} of {}",
+            slot.ciel_instance,
+            slot.ciel_path.display()
+        );
+        if let Err(err) = build_worker_inner(&args, &slot, &slots, tx.clone()).await {
+            warn!("Got error running build worker: {}", err);
         }
         tokio::time::sleep(Duration::from_secs(5)).await;
     }
 }
+
+/// Run build slots, each streaming its live log with its own sender, see `build_slots`
+pub async fn build_worker(args: Args, slots: Arc<Slots>, slot_logs: Vec<(Slot, LogSender)>) -> ! {
+    let mut slot_logs = slot_logs.into_iter();
+    let (first, first_tx) = slot_logs.next().expect("Worker has no build slot");
+    for (slot, tx) in slot_logs {
+        tokio::spawn(build_slot(args.clone(), slot, slots.clone(), tx));
+    }
+    build_slot(args, first, slots, first_tx).await
+}
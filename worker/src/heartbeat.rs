@@ -1,8 +1,14 @@
-use crate::{get_memory_bytes, Args};
-use common::WorkerHeartbeatRequest;
+use crate::{
+    build::Slots, build_slots, get_ciel_instances, get_memory_bytes, server_client,
+    slot_ciel_paths, websocket::websocket_stats, Args,
+};
+use common::{WorkerHeartbeatRequest, WorkerHeartbeatResponse};
 use log::{info, warn};
 use std::{
-    sync::atomic::{AtomicBool, Ordering},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 
@@ -26,14 +32,48 @@ pub async fn internet_connectivity_worker() -> ! {
     }
 }
 
-pub async fn heartbeat_worker_inner(args: &Args) -> anyhow::Result<()> {
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(30))
-        .build()
-        .unwrap();
+pub async fn heartbeat_worker_inner(args: &Args, slots: &Slots) -> anyhow::Result<()> {
+    let client = server_client(args)?;
+    let mut ciel_instances = Some(vec![]);
+    for ciel_path in slot_ciel_paths(args) {
+        match get_ciel_instances(&ciel_path) {
+            Ok(instances) => {
+                info!(
+                    "Found ciel instances in {}: {}",
+                    ciel_path.display(),
+                    instances.join(", ")
+                );
+                for slot in build_slots(args) {
+                    if slot.ciel_path == ciel_path && !instances.contains(&slot.ciel_instance) {
+                        warn!(
+                            "Configured ciel instance {} not found in {}",
+                            slot.ciel_instance,
+                            ciel_path.display()
+                        );
+                    }
+                }
+                if let Some(all) = &mut ciel_instances {
+                    for instance in instances {
+                        if !all.contains(&instance) {
+                            all.push(instance);
+                        }
+                    }
+                }
+            }
+            Err(err) => {
+                warn!(
+                    "Failed to list ciel instances in {}: {}",
+                    ciel_path.display(),
+                    err
+                );
+                ciel_instances = None;
+            }
+        }
+    }
     loop {
         // info!("Sending heartbeat");
-        client
+        let (websocket_reconnects, websocket_last_error) = websocket_stats();
+        let res = client
             .post(format!("{}/api/worker/heartbeat", args.server))
             .json(&WorkerHeartbeatRequest {
                 hostname: gethostname::gethostname().to_string_lossy().to_string(),
@@ -45,18 +85,36 @@ pub async fn heartbeat_worker_inner(args: &Args) -> anyhow::Result<()> {
                 logical_cores: num_cpus::get() as i32,
                 performance: args.worker_performance,
                 internet_connectivity: Some(INTERNET_CONNECTIVITY.load(Ordering::SeqCst)),
+                ciel_instances: ciel_instances.clone(),
+                tags: args.tags.clone(),
+                machine_class: args.machine_class.clone(),
+                websocket_reconnects: Some(websocket_reconnects),
+                websocket_last_error,
+                running_job_ids: slots.running_job_ids(),
             })
             .send()
             .await?;
+        if !res.status().is_success() {
+            warn!("Heartbeat rejected by server: {}", res.text().await?);
+        } else {
+            // older servers respond with an empty body
+            let res = res
+                .json::<WorkerHeartbeatResponse>()
+                .await
+                .unwrap_or_default();
+            for job_id in res.cancelled_job_ids {
+                slots.cancel(job_id);
+            }
+        }
         tokio::time::sleep(Duration::from_secs(60)).await;
     }
 }
 
-pub async fn heartbeat_worker(args: Args) -> ! {
+pub async fn heartbeat_worker(args: Args, slots: Arc<Slots>) -> ! {
     tokio::spawn(internet_connectivity_worker());
     loop {
         info!("Starting heartbeat worker");
-        if let Err(err) = heartbeat_worker_inner(&args).await {
+        if let Err(err) = heartbeat_worker_inner(&args, &slots).await {
             warn!("Got error running heartbeat worker: {}", err);
         }
         tokio::time::sleep(Duration::from_secs(5)).await;
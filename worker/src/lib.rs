@@ -1,9 +1,14 @@
+use anyhow::{bail, Context};
 use clap::Parser;
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use sysinfo::System;
 
 pub mod build;
 pub mod heartbeat;
+pub mod tls;
 pub mod websocket;
 
 #[derive(Parser, Debug, Clone)]
@@ -55,9 +60,178 @@ pub struct Args {
     )]
     pub pushpkg_options: String,
 
+    /// Extra options passed to `ciel build` when verbose output is requested
+    #[arg(long, default_value = "", env = "BUILDIT_CIEL_VERBOSE_OPTIONS")]
+    pub ciel_verbose_options: String,
+
+    /// Treat successful builds producing no packages as success instead of failure
+    #[arg(long, env = "BUILDIT_ALLOW_EMPTY_OUTPUT")]
+    pub allow_empty_output: bool,
+
+    /// Comma-separated tags of special hardware, jobs requiring tags only go to workers having all of them
+    #[arg(long, env = "BUILDIT_WORKER_TAGS")]
+    pub tags: Option<String>,
+
+    /// Machine class (medium, large or xlarge) for routing large packages, derived from cores and memory if not set
+    #[arg(long, env = "BUILDIT_MACHINE_CLASS")]
+    pub machine_class: Option<String>,
+
     /// Performance number of the worker (smaller is better)
     #[arg(short = 'p', long, env = "BUILDIT_WORKER_PERFORMANCE")]
     pub worker_performance: Option<i64>,
+
+    /// Pin the SHA-256 fingerprint of the server TLS certificate, for both http and websocket
+    #[arg(long, env = "BUILDIT_SERVER_CERT_SHA256")]
+    pub server_cert_sha256: Option<String>,
+
+    /// Max number of log lines buffered for live streaming, oldest lines are dropped when full
+    #[arg(long, default_value_t = 4096, env = "BUILDIT_LOG_STREAM_BUFFER")]
+    pub log_stream_buffer: usize,
+
+    /// Remove OUTPUT-* directories of ciel workspace whose packages were not built for this many hours
+    #[arg(long, env = "BUILDIT_OUTPUT_RETENTION_HOURS")]
+    pub output_retention_hours: Option<u64>,
+
+    /// Refuse to start builds when ciel workspace has less free disk space in GiB
+    #[arg(long, env = "BUILDIT_MIN_FREE_SPACE_GIB")]
+    pub min_free_space_gib: Option<u64>,
+
+    /// Number of jobs to build concurrently, each in its own ciel instance, see `build_slots`
+    #[arg(long, default_value_t = 1, env = "BUILDIT_MAX_JOBS")]
+    pub max_jobs: usize,
+
+    /// Give each build slot its own ciel workspace `<ciel_path>-<index>`, instead of
+    /// instance `<ciel_instance>-<index>` in the configured workspace
+    #[arg(long, env = "BUILDIT_SLOT_WORKSPACES")]
+    pub slot_workspaces: bool,
+}
+
+/// Where a build slot builds, see `build_slots`
+#[derive(Clone, Debug)]
+pub struct Slot {
+    pub index: usize,
+    pub ciel_path: PathBuf,
+    pub ciel_instance: String,
+    /// Name of the live log stream of the slot on the server
+    pub log_stream: String,
+}
+
+/// Build slots of the worker. Building one job at a time, the only slot uses the configured
+/// workspace and instance. Otherwise slot `<index>` builds in instance `<ciel_instance>-<index>`
+/// of the configured workspace, e.g. `main-0` and `main-1` for `--max-jobs 2`, taking turns
+/// to check out and build from the shared `TREE`. With `--slot-workspaces`, it builds in
+/// instance `<ciel_instance>` of workspace `<ciel_path>-<index>` instead, e.g.
+/// `/buildroots/buildit-0` and `/buildroots/buildit-1`, building independently.
+pub fn build_slots(args: &Args) -> Vec<Slot> {
+    let hostname = gethostname::gethostname().to_string_lossy().to_string();
+    if args.max_jobs <= 1 {
+        return vec![Slot {
+            index: 0,
+            ciel_path: args.ciel_path.clone(),
+            ciel_instance: args.ciel_instance.clone(),
+            log_stream: hostname,
+        }];
+    }
+
+    (0..args.max_jobs)
+        .map(|index| {
+            let (ciel_path, ciel_instance) = if args.slot_workspaces {
+                // normalize away trailing slash
+                let path: PathBuf = args.ciel_path.components().collect();
+                let mut path = path.into_os_string();
+                path.push(format!("-{index}"));
+                (path.into(), args.ciel_instance.clone())
+            } else {
+                (
+                    args.ciel_path.clone(),
+                    format!("{}-{index}", args.ciel_instance),
+                )
+            };
+            Slot {
+                index,
+                ciel_path,
+                ciel_instance,
+                log_stream: format!("{hostname}-{index}"),
+            }
+        })
+        .collect()
+}
+
+/// Ciel workspaces of all build slots
+pub fn slot_ciel_paths(args: &Args) -> Vec<PathBuf> {
+    let mut res: Vec<PathBuf> = vec![];
+    for slot in build_slots(args) {
+        if !res.contains(&slot.ciel_path) {
+            res.push(slot.ciel_path);
+        }
+    }
+    res
+}
+
+/// Make sure the workspace and instance of the slot exist, for checking configuration at startup
+pub fn check_slot(args: &Args, slot: &Slot) -> anyhow::Result<()> {
+    let hint = if args.max_jobs <= 1 {
+        ""
+    } else if args.slot_workspaces {
+        ", each build slot needs its own workspace with --slot-workspaces"
+    } else {
+        ", each build slot needs its own instance, or use --slot-workspaces"
+    };
+    check_ciel_tree(&slot.ciel_path).with_context(|| format!("Bad ciel workspace{hint}"))?;
+    if args.max_jobs > 1 {
+        let instances = get_ciel_instances(&slot.ciel_path)?;
+        if !instances.contains(&slot.ciel_instance) {
+            bail!(
+                "ciel instance {} not found in {}{hint}",
+                slot.ciel_instance,
+                slot.ciel_path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Create http client to talk to buildit server, respecting certificate pinning
+pub fn server_client(args: &Args) -> anyhow::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder().timeout(Duration::from_secs(30));
+    if let Some(pin) = &args.server_cert_sha256 {
+        builder = builder.use_preconfigured_tls(tls::pinned_tls_config(pin)?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Make sure the ciel TREE is a git repo, for checking configuration at startup
+pub fn check_ciel_tree(ciel_path: &Path) -> anyhow::Result<()> {
+    let tree_path = ciel_path.join("TREE");
+    if !tree_path.is_dir() {
+        bail!(
+            "ciel TREE {} does not exist, is {} a ciel workspace?",
+            tree_path.display(),
+            ciel_path.display()
+        );
+    }
+    let output = std::process::Command::new("git")
+        .args(["rev-parse", "--is-inside-work-tree"])
+        .current_dir(&tree_path)
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        bail!("ciel TREE {} is not a git repo", tree_path.display());
+    }
+    Ok(())
+}
+
+/// List ciel instances in the workspace, sorted by name
+pub fn get_ciel_instances(ciel_path: &Path) -> anyhow::Result<Vec<String>> {
+    let mut res = vec![];
+    for entry in std::fs::read_dir(ciel_path.join(".ciel/container/instances"))? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            res.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+    res.sort();
+    Ok(res)
 }
 
 pub fn get_memory_bytes() -> i64 {
@@ -1,8 +1,14 @@
 use clap::Parser;
-use flume::unbounded;
 use log::info;
+use std::sync::Arc;
 use sysinfo::System;
-use worker::{build::build_worker, heartbeat::heartbeat_worker, websocket::websocket_worker, Args};
+use worker::{
+    build::{build_worker, Slots},
+    build_slots, check_slot,
+    heartbeat::heartbeat_worker,
+    websocket::{log_channel, websocket_worker},
+    Args,
+};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -11,13 +17,25 @@ async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
     info!("Starting AOSC BuildIt! worker");
 
+    // fail early on misconfigured ciel workspace
+    let build_slots = build_slots(&args);
+    for slot in &build_slots {
+        check_slot(&args, slot)?;
+    }
+
     // Refresh memory usage for get_memory_bytes()
     let mut s = System::new();
     s.refresh_memory();
 
-    let (tx, rx) = unbounded();
-    tokio::spawn(websocket_worker(args.clone(), rx));
-    tokio::spawn(heartbeat_worker(args.clone()));
-    build_worker(args.clone(), tx).await;
+    // stream live log of each slot separately, so that concurrent builds are not mixed up
+    let mut slot_logs = vec![];
+    for slot in build_slots {
+        let (tx, rx) = log_channel(args.log_stream_buffer);
+        tokio::spawn(websocket_worker(args.clone(), slot.log_stream.clone(), rx));
+        slot_logs.push((slot, tx));
+    }
+    let slots = Arc::new(Slots::default());
+    tokio::spawn(heartbeat_worker(args.clone(), slots.clone()));
+    build_worker(args.clone(), slots, slot_logs).await;
     Ok(())
 }
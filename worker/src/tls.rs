@@ -0,0 +1,62 @@
+use anyhow::{bail, Context};
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    Certificate, ClientConfig, ServerName,
+};
+use sha2::{Digest, Sha256};
+use std::{sync::Arc, time::SystemTime};
+
+/// Accept the server only if the SHA-256 of its leaf certificate matches the pin
+struct PinnedCertVerifier {
+    pin: [u8; 32],
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let digest = Sha256::digest(&end_entity.0);
+        if digest.as_slice() == self.pin {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(format!(
+                "Server certificate sha256 {} does not match the pinned one",
+                to_hex(&digest)
+            )))
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parse sha256 fingerprint, e.g. `ab:cd:...` or `abcd...`
+pub fn parse_cert_pin(pin: &str) -> anyhow::Result<[u8; 32]> {
+    let hex: String = pin.chars().filter(|ch| *ch != ':').collect();
+    if hex.len() != 64 || !hex.chars().all(|ch| ch.is_ascii_hexdigit()) {
+        bail!("Invalid sha256 fingerprint: {pin}");
+    }
+
+    let mut res = [0u8; 32];
+    for (i, byte) in res.iter_mut().enumerate() {
+        *byte =
+            u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).context("Invalid sha256 fingerprint")?;
+    }
+    Ok(res)
+}
+
+/// Build rustls config that only trusts the certificate with the given fingerprint
+pub fn pinned_tls_config(pin: &str) -> anyhow::Result<ClientConfig> {
+    let pin = parse_cert_pin(pin)?;
+    Ok(ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier { pin }))
+        .with_no_client_auth())
+}
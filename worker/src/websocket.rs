@@ -1,35 +1,160 @@
-use crate::Args;
-use flume::Receiver;
+use crate::{tls::pinned_tls_config, Args};
+use anyhow::Context;
+use flume::{Receiver, Sender, TrySendError};
 use futures_util::StreamExt;
 use log::{info, warn};
 use reqwest::Url;
-use std::time::Duration;
-use tokio_tungstenite::{connect_async, tungstenite::Message};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_rustls::{client::TlsStream, TlsConnector};
+use tokio_tungstenite::{client_async, connect_async, tungstenite::Message, WebSocketStream};
 
-pub async fn websocket_worker(args: Args, rx: Receiver<Message>) -> anyhow::Result<()> {
+/// Sending end of the live log stream, dropping the oldest lines when the
+/// buffer is full so that a slow or absent viewer never blocks the build
+#[derive(Clone)]
+pub struct LogSender {
+    tx: Sender<Message>,
+    rx: Receiver<Message>,
+}
+
+impl LogSender {
+    pub fn send(&self, mut msg: Message) {
+        loop {
+            match self.tx.try_send(msg) {
+                Ok(()) | Err(TrySendError::Disconnected(_)) => return,
+                Err(TrySendError::Full(m)) => {
+                    // drop oldest
+                    self.rx.try_recv().ok();
+                    msg = m;
+                }
+            }
+        }
+    }
+}
+
+/// Create live log stream buffering at most `capacity` lines
+pub fn log_channel(capacity: usize) -> (LogSender, Receiver<Message>) {
+    let (tx, rx) = flume::bounded(capacity.max(1));
+    (LogSender { tx, rx: rx.clone() }, rx)
+}
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(300);
+
+/// Reconnections are reported for this long, see `websocket_stats`
+const RECONNECT_WINDOW: Duration = Duration::from_secs(3600);
+
+struct ReconnectStats {
+    reconnects: VecDeque<Instant>,
+    last_error: Option<String>,
+}
+
+static RECONNECT_STATS: Mutex<ReconnectStats> = Mutex::new(ReconnectStats {
+    reconnects: VecDeque::new(),
+    last_error: None,
+});
+
+/// Websocket reconnections within the last hour and the last connection error,
+/// reported in heartbeats to diagnose flapping connections
+pub fn websocket_stats() -> (i32, Option<String>) {
+    let mut stats = RECONNECT_STATS.lock().unwrap();
+    while stats
+        .reconnects
+        .front()
+        .is_some_and(|time| time.elapsed() > RECONNECT_WINDOW)
+    {
+        stats.reconnects.pop_front();
+    }
+    (stats.reconnects.len() as i32, stats.last_error.clone())
+}
+
+fn record_websocket_error(err: String) {
+    RECONNECT_STATS.lock().unwrap().last_error = Some(err);
+}
+
+/// Connect to the websocket over TLS, only trusting the pinned server certificate
+async fn connect_pinned(
+    ws: &Url,
+    pin: &str,
+) -> anyhow::Result<WebSocketStream<TlsStream<TcpStream>>> {
+    let host = ws.host_str().context("Websocket url has no host")?;
+    let port = ws.port_or_known_default().unwrap_or(443);
+    let server_name = rustls::ServerName::try_from(host)?;
+    let connector = TlsConnector::from(Arc::new(pinned_tls_config(pin)?));
+
+    let stream = TcpStream::connect((host, port)).await?;
+    let stream = connector.connect(server_name, stream).await?;
+    let (ws_stream, _) = client_async(ws.as_str(), stream).await?;
+    Ok(ws_stream)
+}
+
+/// Send the live log to the websocket until the connection breaks
+async fn forward_logs<S>(ws_stream: WebSocketStream<S>, rx: &Receiver<Message>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (write, _) = ws_stream.split();
+    let rx = rx.clone().into_stream();
+    if let Err(e) = rx.map(Ok).forward(write).await {
+        warn!("Failed to forward message to websocket: {e}");
+        record_websocket_error(e.to_string());
+    }
+}
+
+/// Stream live log to the server as `log_stream`, see `Slot::log_stream`
+pub async fn websocket_worker(
+    args: Args,
+    log_stream: String,
+    rx: Receiver<Message>,
+) -> anyhow::Result<()> {
     // wss://hostname/api/ws/worker/:hostname
-    let hostname = gethostname::gethostname().to_string_lossy().to_string();
     let ws = Url::parse(&args.server.replace("http", "ws"))?
         .join("api/")?
         .join("ws/")?
         .join("worker/")?
-        .join(&hostname)?;
+        .join(&log_stream)?;
 
+    let mut delay = MIN_RECONNECT_DELAY;
     loop {
         info!("Starting websocket connect to {:?}", ws);
-        match connect_async(ws.as_str()).await {
-            Ok((ws_stream, _)) => {
-                let (write, _) = ws_stream.split();
-                let rx = rx.clone().into_stream();
-                if let Err(e) = rx.map(Ok).forward(write).await {
-                    warn!("Failed to forward message to websocket: {e}");
+        // pin the certificate for websocket as well as for http requests
+        let res = match &args.server_cert_sha256 {
+            Some(pin) if ws.scheme() == "wss" => match connect_pinned(&ws, pin).await {
+                Ok(ws_stream) => {
+                    delay = MIN_RECONNECT_DELAY;
+                    forward_logs(ws_stream, &rx).await;
+                    Ok(())
                 }
-            }
-            Err(err) => {
-                warn!("Got error connecting to websocket: {}", err);
-            }
+                Err(err) => Err(err.to_string()),
+            },
+            _ => match connect_async(ws.as_str()).await {
+                Ok((ws_stream, _)) => {
+                    delay = MIN_RECONNECT_DELAY;
+                    forward_logs(ws_stream, &rx).await;
+                    Ok(())
+                }
+                Err(err) => Err(err.to_string()),
+            },
+        };
+        if let Err(err) = res {
+            warn!("Got error connecting to websocket: {}", err);
+            record_websocket_error(err);
         }
 
-        tokio::time::sleep(Duration::from_secs(5)).await;
+        // back off exponentially while the server stays unreachable
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        RECONNECT_STATS
+            .lock()
+            .unwrap()
+            .reconnects
+            .push_back(Instant::now());
     }
 }